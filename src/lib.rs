@@ -1,4 +1,26 @@
 mod zmachine;
 
+pub use crate::zmachine::new_dumb_story_processor;
 pub use crate::zmachine::new_story_processor;
+#[cfg(feature = "terminal")]
+pub use crate::zmachine::new_terminal_story_processor;
+pub use crate::zmachine::story_dictionary;
+pub use crate::zmachine::story_disassembly;
+pub use crate::zmachine::story_header;
+pub use crate::zmachine::story_objects;
+pub use crate::zmachine::story_problems;
+pub use crate::zmachine::story_strings;
+pub use crate::zmachine::ErrorPolicy;
 pub use crate::zmachine::Result;
+pub use crate::zmachine::SaveHandler;
+pub use crate::zmachine::ZDictionary;
+pub use crate::zmachine::ZKey;
+pub use crate::zmachine::ZOptions;
+pub use crate::zmachine::ZProblem;
+pub use crate::zmachine::ZSnapshot;
+pub use crate::zmachine::{
+    compress_memory, decompress_memory, memory_chunk, read_quetzal, restore_memory, Chunk,
+    MemoryFormat, QuetzalSave, SaveHeader,
+};
+pub use crate::zmachine::{FileSaveHandler, SaveEntry, SaveInfo};
+pub use crate::zmachine::{ZAccess, ZAuditLog};
@@ -1,13 +1,201 @@
 use std::fs::File;
+use std::io;
+use std::iter;
+use std::path::PathBuf;
+use std::process;
 
-use rzm2::{new_story_processor, Result};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use rzm2::new_dumb_story_processor;
+#[cfg(not(feature = "terminal"))]
+use rzm2::new_story_processor;
+#[cfg(feature = "terminal")]
+use rzm2::new_terminal_story_processor as new_story_processor;
+use rzm2::{
+    story_dictionary, story_disassembly, story_header, story_objects, story_problems,
+    story_strings, FileSaveHandler, Result, ZOptions,
+};
 
 fn run() -> Result<()> {
-    // TODO: add some cmd line args.
-    // TODO: read a filename.
-    let mut rdr = File::open("Zork1.z3")?;
-    let mut machine = new_story_processor(&mut rdr)?;
-    machine.run()
+    let matches = App::new("rzm2")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Plays Z-machine stories")
+        // The subcommands examine a story rather than play it.
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .setting(AppSettings::VersionlessSubcommands)
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Checks the story for problems without playing it")
+                .arg(story_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("disasm")
+                .about("Disassembles the routines reachable from the start, like txd")
+                .arg(story_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("header")
+                .about("Describes the story's header, like infodump -i")
+                .arg(story_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("dictionary")
+                .about("Lists the story's dictionary, like infodump -d")
+                .arg(story_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("objects")
+                .about("Lists the story's objects, like infodump -o")
+                .arg(story_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("strings")
+                .about("Lists the strings in the story's high memory, with their addresses")
+                .arg(story_arg()),
+        )
+        .arg(
+            Arg::with_name("dumb")
+                .long("dumb")
+                .help("Writes plain text with no escapes, for piping walkthroughs"),
+        )
+        .arg(
+            Arg::with_name("debug")
+                .long("debug")
+                .help("Starts in the debugger, before the first instruction"),
+        )
+        .arg(
+            Arg::with_name("transcript")
+                .long("transcript")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Writes a transcript of the game to FILE from the start"),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Records every command typed to FILE, one per line"),
+        )
+        .arg(
+            Arg::with_name("replay")
+                .long("replay")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Plays back the commands in FILE, then reads the keyboard"),
+        )
+        .arg(
+            Arg::with_name("save-dir")
+                .long("save-dir")
+                .value_name("DIR")
+                .takes_value(true)
+                .help("Keeps saved games in DIR, rather than the current directory"),
+        )
+        .arg(
+            Arg::with_name("strict")
+                .long("strict")
+                .value_name("POLICY")
+                .takes_value(true)
+                .possible_values(&["ignore", "warn", "once", "fatal"])
+                .help("Ignores, warns about (always or once), or stops on spec violations"),
+        )
+        .arg(
+            Arg::with_name("story")
+                .help("The story file to play")
+                .required(true)
+                .index(1),
+        )
+        .get_matches();
+
+    if let (name, Some(sub)) = matches.subcommand() {
+        return examine(name, sub);
+    }
+
+    // clap has already insisted on a story.
+    let filename = matches.value_of("story").unwrap();
+    let mut options = ZOptions::new();
+    options.transcript = matches.value_of("transcript").map(PathBuf::from);
+    options.command_script = matches.value_of("record").map(PathBuf::from);
+    options.command_file = matches.value_of("replay").map(PathBuf::from);
+    if let Some(policy) = matches.value_of("strict") {
+        options.strict = policy.parse()?;
+    }
+
+    // The debugger talks on stderr, to keep out of the game's way.
+    let debug = matches.is_present("debug");
+    // Saves are named for the story, as "zork1-0042.sav".
+    let saves = FileSaveHandler::new(matches.value_of("save-dir").unwrap_or("."), filename);
+    let mut rdr = File::open(filename)?;
+    if matches.is_present("dumb") {
+        let mut processor = new_dumb_story_processor(&mut rdr, options)?;
+        processor.save_handler = Some(Box::new(saves));
+        if debug {
+            processor.debug(stdin_lines(), io::stderr())
+        } else {
+            processor.run()
+        }
+    } else {
+        let mut processor = new_story_processor(&mut rdr, options)?;
+        processor.save_handler = Some(Box::new(saves));
+        if debug {
+            processor.debug(stdin_lines(), io::stderr())
+        } else {
+            processor.run()
+        }
+    }
+}
+
+// Debugger commands from stdin. Each line takes the stdin lock only while it
+// is read, so that the game can read stdin in between.
+fn stdin_lines() -> impl Iterator<Item = io::Result<String>> {
+    iter::from_fn(|| {
+        let mut line = String::new();
+        match io::stdin().read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => Some(Ok(line)),
+            Err(e) => Some(Err(e)),
+        }
+    })
+}
+
+fn story_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("story")
+        .help("The story file to examine")
+        .required(true)
+        .index(1)
+}
+
+fn examine(subcommand: &str, matches: &ArgMatches) -> Result<()> {
+    let mut rdr = File::open(matches.value_of("story").unwrap())?;
+    match subcommand {
+        "check" => {
+            let problems = story_problems(&mut rdr)?;
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            match problems.len() {
+                0 => println!("No problems found."),
+                1 => println!("1 problem found."),
+                count => println!("{} problems found.", count),
+            }
+            // A failing status lets scripts use check as a validator.
+            if !problems.is_empty() {
+                process::exit(1);
+            }
+        }
+        "disasm" => print!("{}", story_disassembly(&mut rdr)?),
+        "header" => print!("{}", story_header(&mut rdr)?),
+        "dictionary" => print!("{}", story_dictionary(&mut rdr)?),
+        "objects" => print!("{}", story_objects(&mut rdr)?),
+        // Newlines are shown as ^, as they are written in Inform.
+        "strings" => {
+            for (address, text) in story_strings(&mut rdr)? {
+                println!("{:05x}: \"{}\"", address, text.replace('\n', "^"));
+            }
+        }
+        _ => unreachable!("clap allows only known subcommands"),
+    }
+    Ok(())
 }
 
 fn main() {
@@ -15,7 +203,9 @@ fn main() {
 
     match run() {
         Ok(_) => (),
-        //        Err(ItoolsError::Clap(err)) => println!("{}", err.description()),
-        Err(e) => eprintln!("Error: {}", e),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
     }
 }
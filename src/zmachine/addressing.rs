@@ -24,8 +24,10 @@ impl ByteAddress {
         ByteAddress(word)
     }
 
+    // Byte addresses are 16 bits, so arithmetic on them wraps. Anything past
+    // the first 64K is reached through a ZOffset instead.
     pub fn inc_by(self, by: u16) -> ByteAddress {
-        ByteAddress(self.0 + by)
+        ByteAddress(self.0.wrapping_add(by))
     }
 }
 
@@ -50,19 +52,24 @@ impl From<WordAddress> for ZOffset {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct PackedAddress {
     val: u16,
     multiplier: u8,
-    offset: u16, // for V6 only, other versions set this to zero.
+    offset: u16, // for V6-7 only, other versions set this to zero.
 }
 
 impl PackedAddress {
     pub fn new(val: u16, multiplier: u8) -> PackedAddress {
+        PackedAddress::with_offset(val, multiplier, 0)
+    }
+
+    // The offset is in units of 8 bytes. (ZSpec 1.2.3)
+    pub fn with_offset(val: u16, multiplier: u8, offset: u16) -> PackedAddress {
         PackedAddress {
             val,
             multiplier,
-            offset: 0,
+            offset,
         }
     }
 }
@@ -76,7 +83,7 @@ impl From<PackedAddress> for usize {
 
 impl From<PackedAddress> for ZOffset {
     fn from(pa: PackedAddress) -> ZOffset {
-        ZOffset(usize::from(pa.val) * usize::from(pa.multiplier) + usize::from(pa.offset))
+        ZOffset(usize::from(pa.val) * usize::from(pa.multiplier) + usize::from(pa.offset) * 8)
     }
 }
 
@@ -86,6 +93,62 @@ impl fmt::Display for PackedAddress {
     }
 }
 
+// Routines and strings are unpacked with different offsets in V6-7, so the
+// two kinds of packed address are kept apart. (ZSpec 1.2.3)
+#[derive(Clone, Copy, Debug)]
+pub struct RoutinePackedAddress(PackedAddress);
+
+impl RoutinePackedAddress {
+    pub fn new(pa: PackedAddress) -> RoutinePackedAddress {
+        RoutinePackedAddress(pa)
+    }
+}
+
+impl From<RoutinePackedAddress> for usize {
+    fn from(rpa: RoutinePackedAddress) -> usize {
+        usize::from(rpa.0)
+    }
+}
+
+impl From<RoutinePackedAddress> for ZOffset {
+    fn from(rpa: RoutinePackedAddress) -> ZOffset {
+        ZOffset::from(rpa.0)
+    }
+}
+
+impl fmt::Display for RoutinePackedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "r{:x}", usize::from(*self))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StringPackedAddress(PackedAddress);
+
+impl StringPackedAddress {
+    pub fn new(pa: PackedAddress) -> StringPackedAddress {
+        StringPackedAddress(pa)
+    }
+}
+
+impl From<StringPackedAddress> for usize {
+    fn from(spa: StringPackedAddress) -> usize {
+        usize::from(spa.0)
+    }
+}
+
+impl From<StringPackedAddress> for ZOffset {
+    fn from(spa: StringPackedAddress) -> ZOffset {
+        ZOffset::from(spa.0)
+    }
+}
+
+impl fmt::Display for StringPackedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "s{:x}", usize::from(*self))
+    }
+}
+
 pub struct ZPC<M>
 where
     M: Memory,
@@ -161,6 +224,9 @@ mod test {
         let ba = ByteAddress::from_raw(58);
         assert_eq!(58, ZOffset::from(ba).value());
         assert_eq!(65, ZOffset::from(ba.inc_by(7)).value());
+
+        let top = ByteAddress::from_raw(0xfffe);
+        assert_eq!(0x0001, ZOffset::from(top.inc_by(3)).value());
     }
 
     #[test]
@@ -171,13 +237,45 @@ mod test {
 
     #[test]
     fn test_packed_address() {
-        let pa3 = ZVersion::V3.make_packed_address(53);
+        let pa3 = ZVersion::V3.make_packed_address(53, 0);
         assert_eq!(106, usize::from(pa3));
         assert_eq!(106, ZOffset::from(pa3).value());
 
-        let pa5 = ZVersion::V5.make_packed_address(53);
+        let pa4 = ZVersion::V4.make_packed_address(53, 0);
+        assert_eq!(212, usize::from(pa4));
+
+        let pa5 = ZVersion::V5.make_packed_address(53, 0);
         assert_eq!(212, usize::from(pa5));
         assert_eq!(212, ZOffset::from(pa5).value());
+
+        // The offset is ignored, except in V6-7.
+        let pa5 = ZVersion::V5.make_packed_address(53, 3);
+        assert_eq!(212, usize::from(pa5));
+
+        let pa7 = ZVersion::V7.make_packed_address(53, 3);
+        assert_eq!(236, usize::from(pa7));
+
+        let pa8 = ZVersion::V8.make_packed_address(53, 3);
+        assert_eq!(424, usize::from(pa8));
+
+        // Packed addresses reach past the first 64K.
+        let big5 = ZVersion::V5.make_packed_address(0xfff0, 0);
+        assert_eq!(0x3ffc0, usize::from(big5));
+        let big8 = ZVersion::V8.make_packed_address(0xfff0, 0);
+        assert_eq!(0x7ff80, ZOffset::from(big8).value());
+    }
+
+    #[test]
+    fn test_routine_and_string_addresses() {
+        let rpa = RoutinePackedAddress::new(ZVersion::V7.make_packed_address(53, 3));
+        assert_eq!(236, usize::from(rpa));
+        assert_eq!(236, ZOffset::from(rpa).value());
+        assert_eq!("rec", format!("{}", rpa));
+
+        let spa = StringPackedAddress::new(ZVersion::V7.make_packed_address(53, 5));
+        assert_eq!(252, usize::from(spa));
+        assert_eq!(252, ZOffset::from(spa).value());
+        assert_eq!("sfc", format!("{}", spa));
     }
 
     #[test]
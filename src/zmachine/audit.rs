@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+// One read or write of dynamic memory, and the instruction that made it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ZAccess {
+    pub pc: usize,
+    pub offset: usize,
+    pub value: u8,
+    pub write: bool,
+}
+
+impl fmt::Display for ZAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:05x}: {} {:05x} {:02x}",
+            self.pc,
+            if self.write { "write" } else { "read " },
+            self.offset,
+            self.value
+        )
+    }
+}
+
+// The most recent accesses to dynamic memory, oldest first. Once the limit is
+// reached, each new access drops the oldest. When a game stops with an error,
+// the log shows what led up to it, which helps find the instruction that
+// corrupted memory.
+pub struct ZAuditLog {
+    accesses: VecDeque<ZAccess>,
+    limit: usize,
+    pc: usize,
+}
+
+impl ZAuditLog {
+    pub fn new(limit: usize) -> ZAuditLog {
+        ZAuditLog {
+            accesses: VecDeque::with_capacity(limit),
+            limit,
+            pc: 0,
+        }
+    }
+
+    // The pc of the instruction now running, which is noted with each access.
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    pub fn record(&mut self, offset: usize, value: u8, write: bool) {
+        if self.limit == 0 {
+            return;
+        }
+        if self.accesses.len() == self.limit {
+            self.accesses.pop_front();
+        }
+        self.accesses.push_back(ZAccess {
+            pc: self.pc,
+            offset,
+            value,
+            write,
+        });
+    }
+
+    pub fn accesses(&self) -> impl Iterator<Item = &ZAccess> {
+        self.accesses.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ring() {
+        let mut log = ZAuditLog::new(3);
+        log.set_pc(0x4f05);
+        log.record(0x100, 1, false);
+        log.record(0x101, 2, true);
+        log.set_pc(0x4f09);
+        log.record(0x102, 3, false);
+        log.record(0x103, 4, true);
+
+        let accesses = log.accesses().cloned().collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ZAccess {
+                    pc: 0x4f05,
+                    offset: 0x101,
+                    value: 2,
+                    write: true
+                },
+                ZAccess {
+                    pc: 0x4f09,
+                    offset: 0x102,
+                    value: 3,
+                    write: false
+                },
+                ZAccess {
+                    pc: 0x4f09,
+                    offset: 0x103,
+                    value: 4,
+                    write: true
+                },
+            ],
+            accesses
+        );
+        assert_eq!("04f09: read  00102 03", accesses[1].to_string());
+        assert_eq!("04f09: write 00103 04", accesses[2].to_string());
+    }
+
+    #[test]
+    fn test_zero_limit() {
+        let mut log = ZAuditLog::new(0);
+        log.record(0x100, 1, false);
+        assert_eq!(0, log.accesses().count());
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use super::addressing::{ByteAddress, ZOffset};
+use super::dictionary::ZDictionary;
+use super::disasm;
+use super::handle::{new_handle, Handle};
+use super::header::{
+    ZHeader, HEADER_SIZE, HOF_ABBREV_LOCATION, HOF_CHECKSUM, HOF_DICTIONARY_LOCATION,
+    HOF_GLOBAL_LOCATION, HOF_HIGH_MEMORY_BASE, HOF_OTABLE_LOCATION, HOF_START_PC,
+    HOF_STATIC_MEMORY_BASE,
+};
+use super::memory::ZMemory;
+use super::objects::{ObjectNumber, ObjectTable, ZObjectTable};
+use super::traits::{Header, Memory};
+use super::version::ZVersion;
+use super::zscii::ZTextDecoder;
+
+// The globals table holds 240 words. (ZSpec 6.2)
+const GLOBALS_SIZE: usize = 240 * 2;
+// The abbreviations table holds 96 words. (ZSpec 3.3)
+const ABBREVIATIONS_SIZE: usize = 96 * 2;
+
+// Something wrong with a story, and where it is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZProblem {
+    pub address: usize,
+    pub description: String,
+}
+
+impl ZProblem {
+    fn new<S: Into<String>>(address: usize, description: S) -> ZProblem {
+        ZProblem {
+            address,
+            description: description.into(),
+        }
+    }
+}
+
+impl fmt::Display for ZProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:05x}: {}", self.address, self.description)
+    }
+}
+
+// Look over a story without running it, as an author might before release:
+// the header's fields and checksum, the code reachable from the start, the
+// object tree, and the order of the dictionary. An empty list means that
+// nothing was found wrong.
+pub fn check(header: &ZHeader, memory: &Handle<ZMemory>) -> Vec<ZProblem> {
+    let mut problems = check_header(header, memory);
+    problems.extend(check_code(header, memory));
+
+    let text = new_handle(ZTextDecoder::new(header, memory));
+    let objects = ZObjectTable::new(header, memory, &text);
+    for problem in objects.validate() {
+        let address = objects
+            .get_object(ObjectNumber::from(problem.object()))
+            .map_or(0, |object| offset(object.address()));
+        problems.push(ZProblem::new(address, problem.to_string()));
+    }
+
+    let dictionary = ZDictionary::from_header(header, &*memory.borrow());
+    for entry in dictionary.misplaced_entries(header, &*memory.borrow()) {
+        problems.push(ZProblem::new(
+            offset(entry),
+            "Dictionary entry is out of order, so lookups may miss it",
+        ));
+    }
+    problems
+}
+
+fn offset(address: ByteAddress) -> usize {
+    ZOffset::from(address).value()
+}
+
+// Each table must lie within the story, and the tables that the game writes
+// within dynamic memory. (ZSpec 1.1, 11.1)
+fn check_header(header: &ZHeader, memory: &Handle<ZMemory>) -> Vec<ZProblem> {
+    let mut problems = Vec::new();
+    let length = memory.borrow().memory_size();
+    let static_base = offset(header.static_memory_base());
+
+    if static_base < HEADER_SIZE || static_base > length {
+        problems.push(ZProblem::new(
+            usize::from(HOF_STATIC_MEMORY_BASE),
+            format!(
+                "Static memory base {:04x} is outside the story",
+                static_base
+            ),
+        ));
+    }
+    let high_base = offset(header.high_memory_base());
+    if high_base < static_base || high_base > length {
+        problems.push(ZProblem::new(
+            usize::from(HOF_HIGH_MEMORY_BASE),
+            format!(
+                "High memory base {:04x} is in dynamic memory, or outside the story",
+                high_base
+            ),
+        ));
+    }
+    if header.version_number() != ZVersion::V6 {
+        let start = offset(header.start_pc());
+        if start < HEADER_SIZE || start >= length {
+            problems.push(ZProblem::new(
+                usize::from(HOF_START_PC),
+                format!("Start PC {:04x} is outside the story", start),
+            ));
+        }
+    }
+
+    let tables = [
+        (
+            HOF_DICTIONARY_LOCATION,
+            "Dictionary",
+            header.dictionary_location(),
+            1,
+            length,
+        ),
+        (
+            HOF_ABBREV_LOCATION,
+            "Abbreviations table",
+            header.abbrev_location(),
+            ABBREVIATIONS_SIZE,
+            length,
+        ),
+        (
+            HOF_OTABLE_LOCATION,
+            "Object table",
+            header.otable_location(),
+            1,
+            static_base,
+        ),
+        (
+            HOF_GLOBAL_LOCATION,
+            "Globals table",
+            header.global_location(),
+            GLOBALS_SIZE,
+            static_base,
+        ),
+    ];
+    for &(field, name, address, size, end) in &tables {
+        let address = offset(address);
+        if address < HEADER_SIZE || address + size > end {
+            let region = if end == length {
+                "the story"
+            } else {
+                "dynamic memory"
+            };
+            problems.push(ZProblem::new(
+                usize::from(field),
+                format!("{} at {:04x} is not within {}", name, address, region),
+            ));
+        }
+    }
+
+    // The earliest stories have no checksum.
+    let checksum = header.checksum();
+    let computed = memory.borrow().checksum();
+    if checksum != 0 && checksum != computed {
+        problems.push(ZProblem::new(
+            usize::from(HOF_CHECKSUM),
+            format!(
+                "Checksum is {:04x}, but the story sums to {:04x}",
+                checksum, computed
+            ),
+        ));
+    }
+    problems
+}
+
+// Decode the routines reachable from the start. Opcodes that aren't legal in
+// the story's version, and calls to addresses that don't hold a routine, are
+// problems.
+fn check_code(header: &ZHeader, memory: &Handle<ZMemory>) -> Vec<ZProblem> {
+    let routines = match disasm::disassemble(header, memory) {
+        Ok(routines) => routines,
+        Err(err) => {
+            return vec![ZProblem::new(
+                usize::from(HOF_START_PC),
+                format!("Can't decode the main routine: {}", err),
+            )]
+        }
+    };
+
+    let mut problems = Vec::new();
+    let found = routines
+        .iter()
+        .map(|routine| routine.address)
+        .collect::<HashSet<_>>();
+    for routine in &routines {
+        if let Some((address, ref error)) = routine.error {
+            problems.push(ZProblem::new(address, error.clone()));
+        }
+        for instruction in &routine.instructions {
+            if let Some(called) = instruction.called_routine() {
+                if !found.contains(&called) {
+                    problems.push(ZProblem::new(
+                        instruction.address,
+                        format!("Call to r{:x}, which is not a routine", called),
+                    ));
+                }
+            }
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    // A V3 story with everything in place: the object table at 0x40 with
+    // one object, the globals after it, then the dictionary and
+    // abbreviations, and a main routine that quits in high memory.
+    fn story_bytes() -> Vec<u8> {
+        let mut bytes = vec![0; 0x400];
+        bytes[0x00] = 3;
+        bytes[0x04..0x06].copy_from_slice(&[0x03, 0x00]); // High memory.
+        bytes[0x06..0x08].copy_from_slice(&[0x03, 0x01]); // Start PC.
+        bytes[0x08..0x0a].copy_from_slice(&[0x02, 0x00]); // Dictionary.
+        bytes[0x0a..0x0c].copy_from_slice(&[0x00, 0x40]); // Object table.
+        bytes[0x0c..0x0e].copy_from_slice(&[0x00, 0x90]); // Globals.
+        bytes[0x0e..0x10].copy_from_slice(&[0x03, 0x00]); // Static memory.
+        bytes[0x18..0x1a].copy_from_slice(&[0x02, 0x80]); // Abbreviations.
+
+        // Object 1, after 31 default property words, with its property table
+        // (no name, no properties) right after it.
+        bytes[0x7e + 7..0x7e + 9].copy_from_slice(&[0x00, 0x87]);
+
+        // No separators, 7-byte entries, and two of them, in order.
+        bytes[0x200..0x204].copy_from_slice(&[0, 7, 0, 2]);
+        bytes[0x204..0x208].copy_from_slice(&[0x10, 0x00, 0x80, 0x00]);
+        bytes[0x20b..0x20f].copy_from_slice(&[0x2a, 0x00, 0x80, 0x00]);
+
+        // The main routine, with no locals: quit.
+        bytes[0x301] = 0xba;
+        bytes
+    }
+
+    fn problems(bytes: Vec<u8>) -> Vec<String> {
+        let (memory, header) = ZMemory::new(&mut Cursor::new(bytes)).unwrap();
+        check(&header, &memory)
+            .iter()
+            .map(|problem| problem.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_sound_story() {
+        assert_eq!(Vec::<String>::new(), problems(story_bytes()));
+    }
+
+    #[test]
+    fn test_header_problems() {
+        let mut bytes = story_bytes();
+        bytes[0x0c..0x0e].copy_from_slice(&[0x02, 0xf0]); // Globals.
+        bytes[0x1c..0x1e].copy_from_slice(&[0x12, 0x34]); // Checksum.
+        assert_eq!(
+            vec![
+                "0000c: Globals table at 02f0 is not within dynamic memory",
+                "0001c: Checksum is 1234, but the story sums to 0284",
+            ],
+            problems(bytes)
+        );
+    }
+
+    #[test]
+    fn test_code_problems() {
+        let mut bytes = story_bytes();
+        // call r3f0 -> sp, where r3f0 has too many locals, then an opcode
+        // that V3 doesn't have (call_1s).
+        bytes[0x301..0x308].copy_from_slice(&[0xe0, 0x3f, 0x01, 0xf8, 0x00, 0x88, 0x00]);
+        bytes[0x3f0] = 0x10;
+        assert_eq!(
+            vec![
+                "00306: Unknown 1OP opcode 8 (0x08) in V3 story at pc 0x306",
+                "00301: Call to r3f0, which is not a routine",
+            ],
+            problems(bytes)
+        );
+    }
+
+    #[test]
+    fn test_object_and_dictionary_problems() {
+        let mut bytes = story_bytes();
+        bytes[0x7e + 4] = 1; // Object 1 is its own parent.
+        bytes[0x20b..0x20f].copy_from_slice(&[0x01, 0x00, 0x80, 0x00]);
+        assert_eq!(
+            vec![
+                "0007e: Object 1 has parent 1, but is not among its children",
+                "0020b: Dictionary entry is out of order, so lookups may miss it",
+            ],
+            problems(bytes)
+        );
+    }
+}
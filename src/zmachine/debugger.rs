@@ -0,0 +1,252 @@
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+use super::addressing::{ByteAddress, ZOffset};
+use super::opcode::ZVariable;
+use super::processor::ZProcessor;
+use super::result::Result;
+use super::traits::{
+    Header, InputStreams, Memory, OutputStreams, Screen, SoundPlayer, Stack, Variables, PC,
+};
+
+const HELP: &str = "\
+Commands (addresses and variable numbers are in hex):
+  step [count]     run one instruction, or count of them
+  continue         run until a breakpoint or the end of the story
+  break addr       stop before the instruction at addr
+  delete addr      remove the breakpoint at addr
+  where            show the pc and the routine depth
+  x addr [count]   show count bytes of memory, 16 by default
+  var number       show a variable (0 is the top of the stack)
+  quit             leave the debugger, ending the story
+";
+
+// The number of bytes that x shows, and on each line.
+const DUMP_LENGTH: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Command {
+    Step(usize),
+    Continue,
+    Break(usize),
+    Delete(usize),
+    Where,
+    Examine(usize, usize),
+    Variable(u8),
+    Help,
+    Quit,
+}
+
+impl Command {
+    fn parse(line: &str) -> std::result::Result<Command, String> {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Err("No command".to_string()),
+        };
+        let args = words.collect::<Vec<_>>();
+        match (command, args.as_slice()) {
+            ("s", []) | ("step", []) => Ok(Command::Step(1)),
+            ("s", [count]) | ("step", [count]) => count
+                .parse()
+                .map(Command::Step)
+                .map_err(|_| format!("Bad count: {}", count)),
+            ("c", []) | ("continue", []) => Ok(Command::Continue),
+            ("b", [addr]) | ("break", [addr]) => parse_hex(addr).map(Command::Break),
+            ("d", [addr]) | ("delete", [addr]) => parse_hex(addr).map(Command::Delete),
+            ("w", []) | ("where", []) => Ok(Command::Where),
+            ("x", [addr]) => parse_hex(addr).map(|addr| Command::Examine(addr, DUMP_LENGTH)),
+            ("x", [addr, count]) => {
+                let count = count.parse().map_err(|_| format!("Bad count: {}", count))?;
+                parse_hex(addr).map(|addr| Command::Examine(addr, count))
+            }
+            ("v", [number]) | ("var", [number]) => match parse_hex(number)? {
+                number if number <= 0xff => Ok(Command::Variable(number as u8)),
+                _ => Err(format!("No such variable: {}", number)),
+            },
+            ("h", []) | ("help", []) | ("?", []) => Ok(Command::Help),
+            ("q", []) | ("quit", []) => Ok(Command::Quit),
+            _ => Err(format!("Unknown command: {} (try help)", line.trim())),
+        }
+    }
+}
+
+fn parse_hex(word: &str) -> std::result::Result<usize, String> {
+    let digits = word.trim_start_matches("0x");
+    usize::from_str_radix(digits, 16).map_err(|_| format!("Bad number: {}", word))
+}
+
+impl<A, H, I, M, O, P, S, Scr, V> ZProcessor<A, H, I, M, O, P, S, Scr, V>
+where
+    A: SoundPlayer,
+    H: Header,
+    I: InputStreams,
+    M: Memory,
+    O: OutputStreams,
+    P: PC,
+    S: Stack + Clone,
+    Scr: Screen,
+    V: Variables,
+{
+    // Run the story under a simple line-based debugger, stopped before the
+    // first instruction. Commands come a line at a time from `input`, and the
+    // debugger's own output goes to `output`, apart from the game's screen.
+    // When the game shares the debugger's input, `input` must not hold it
+    // locked between lines, or the game's first read will wait forever.
+    pub fn debug<L, W>(&mut self, input: L, mut output: W) -> Result<()>
+    where
+        L: IntoIterator<Item = io::Result<String>>,
+        W: Write,
+    {
+        let mut breakpoints = HashSet::new();
+        let mut last = Command::Step(1);
+        let mut lines = input.into_iter();
+        loop {
+            write!(output, "{:05x}> ", self.pc.current_pc())?;
+            output.flush()?;
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+            // An empty line repeats the last command, as in gdb.
+            let command = if line.trim().is_empty() {
+                last
+            } else {
+                match Command::parse(&line) {
+                    Ok(command) => command,
+                    Err(msg) => {
+                        writeln!(output, "{}", msg)?;
+                        continue;
+                    }
+                }
+            };
+            last = command;
+
+            let running = match command {
+                Command::Step(count) => self.debug_run(count, &HashSet::new(), &mut output)?,
+                Command::Continue => self.debug_run(usize::MAX, &breakpoints, &mut output)?,
+                Command::Break(addr) => {
+                    breakpoints.insert(addr);
+                    true
+                }
+                Command::Delete(addr) => {
+                    if !breakpoints.remove(&addr) {
+                        writeln!(output, "No breakpoint at {:05x}", addr)?;
+                    }
+                    true
+                }
+                Command::Where => {
+                    writeln!(
+                        output,
+                        "pc {:05x}, routine depth {}",
+                        self.pc.current_pc(),
+                        self.stack.borrow().frame_depth()
+                    )?;
+                    true
+                }
+                Command::Examine(addr, count) => {
+                    self.examine(addr, count, &mut output)?;
+                    true
+                }
+                Command::Variable(number) => {
+                    match self.variables.peek_variable(ZVariable::from(number)) {
+                        Ok(value) => writeln!(output, "{:04x} ({})", value, value as i16)?,
+                        Err(err) => writeln!(output, "{}", err)?,
+                    }
+                    true
+                }
+                Command::Help => {
+                    write!(output, "{}", HELP)?;
+                    true
+                }
+                Command::Quit => false,
+            };
+            if !running {
+                return Ok(());
+            }
+        }
+    }
+
+    // Run up to count instructions, stopping early at a breakpoint. Errors
+    // are reported rather than returned, so that the state that led to them
+    // can be examined. Returns false when the story has ended.
+    fn debug_run<W>(
+        &mut self,
+        count: usize,
+        breakpoints: &HashSet<usize>,
+        output: &mut W,
+    ) -> Result<bool>
+    where
+        W: Write,
+    {
+        for step in 0..count {
+            if step > 0 && breakpoints.contains(&self.pc.current_pc()) {
+                writeln!(output, "Breakpoint at {:05x}", self.pc.current_pc())?;
+                break;
+            }
+            match self.execute_opcode() {
+                Ok(true) => (),
+                Ok(false) => {
+                    self.screen.borrow_mut().flush()?;
+                    writeln!(output, "The story has ended.")?;
+                    return Ok(false);
+                }
+                Err(err) => {
+                    writeln!(output, "Error: {}", err)?;
+                    break;
+                }
+            }
+        }
+        self.screen.borrow_mut().flush()?;
+        Ok(true)
+    }
+
+    fn examine<W>(&self, addr: usize, count: usize, output: &mut W) -> Result<()>
+    where
+        W: Write,
+    {
+        let memory = self.memory.borrow();
+        let origin = ZOffset::from(ByteAddress::from_raw(0));
+        for line in (addr..addr + count).step_by(DUMP_LENGTH) {
+            write!(output, "{:05x}:", line)?;
+            for offset in line..(line + DUMP_LENGTH).min(addr + count) {
+                match memory.try_read_byte(origin.inc_by(offset)) {
+                    Ok(byte) => write!(output, " {:02x}", byte)?,
+                    Err(_) => write!(output, " --")?,
+                }
+            }
+            writeln!(output)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Ok(Command::Step(1)), Command::parse("s"));
+        assert_eq!(Ok(Command::Step(20)), Command::parse("step 20"));
+        assert_eq!(Ok(Command::Continue), Command::parse("  c  "));
+        assert_eq!(Ok(Command::Break(0x4f05)), Command::parse("break 4f05"));
+        assert_eq!(Ok(Command::Delete(0x4f05)), Command::parse("d 0x4f05"));
+        assert_eq!(Ok(Command::Where), Command::parse("where"));
+        assert_eq!(Ok(Command::Examine(0x40, 16)), Command::parse("x 40"));
+        assert_eq!(Ok(Command::Examine(0x40, 3)), Command::parse("x 40 3"));
+        assert_eq!(Ok(Command::Variable(0x10)), Command::parse("var 10"));
+        assert_eq!(Ok(Command::Help), Command::parse("?"));
+        assert_eq!(Ok(Command::Quit), Command::parse("quit"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Command::parse("").is_err());
+        assert!(Command::parse("step many").is_err());
+        assert!(Command::parse("break").is_err());
+        assert!(Command::parse("break zz").is_err());
+        assert!(Command::parse("var 100").is_err());
+        assert!(Command::parse("frobozz").is_err());
+    }
+}
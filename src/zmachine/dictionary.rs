@@ -0,0 +1,345 @@
+use std::cmp::Ordering;
+
+use super::addressing::{ByteAddress, ZOffset};
+use super::handle::Handle;
+use super::result::Result;
+use super::traits::{Header, Memory};
+use super::version::ZVersion;
+use super::zscii::ZTextDecoder;
+
+// The dictionary's header. (ZSpec 13.2)
+//
+//   n                  the number of word separators
+//   n bytes            the separators, as ZSCII codes
+//   1 byte             the length of each entry
+//   2 bytes            the number of entries (signed)
+//   entries...
+//
+// Each entry starts with the encoded word, and the rest is for the game's
+// own use.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZDictionary {
+    separators: Vec<u8>,
+    entry_length: u8,
+    entry_count: i16,
+    entries: ByteAddress,
+}
+
+impl ZDictionary {
+    // The story's standard dictionary, from the address in the header.
+    pub fn from_header<H, M>(header: &H, memory: &M) -> ZDictionary
+    where
+        H: Header,
+        M: Memory,
+    {
+        ZDictionary::new(memory, header.dictionary_location())
+    }
+
+    pub fn new<M>(memory: &M, address: ByteAddress) -> ZDictionary
+    where
+        M: Memory,
+    {
+        let num_separators = memory.read_byte(address);
+        let separators = (0..u16::from(num_separators))
+            .map(|idx| memory.read_byte(address.inc_by(1 + idx)))
+            .collect();
+        let header = address.inc_by(1 + u16::from(num_separators));
+        ZDictionary {
+            separators,
+            entry_length: memory.read_byte(header),
+            entry_count: memory.read_word(header.inc_by(1)) as i16,
+            entries: header.inc_by(3),
+        }
+    }
+
+    pub fn separators(&self) -> &[u8] {
+        &self.separators
+    }
+
+    pub fn entry_length(&self) -> u8 {
+        self.entry_length
+    }
+
+    // A negative count in the header marks an unsorted dictionary. Either
+    // way, the number of entries is the absolute value. (ZSpec 15 tokenise)
+    pub fn entry_count(&self) -> u16 {
+        self.entry_count.unsigned_abs()
+    }
+
+    pub fn is_sorted(&self) -> bool {
+        self.entry_count >= 0
+    }
+
+    pub fn entry_address(&self, idx: u16) -> ByteAddress {
+        self.entries.inc_by(idx * u16::from(self.entry_length))
+    }
+
+    // Find the entry for an encoded word, of 4 bytes in V1-3 or 6 in V4+.
+    //
+    // Entries are sorted by their encoded words, read as a sequence of bytes
+    // (or, the same thing, of big-endian words), so a binary search finds
+    // them. Unsorted dictionaries are searched from the start. (ZSpec 13.5)
+    pub fn lookup<M>(&self, memory: &M, encoded: &[u8]) -> Option<ByteAddress>
+    where
+        M: Memory,
+    {
+        if !self.is_sorted() {
+            return (0..self.entry_count())
+                .map(|idx| self.entry_address(idx))
+                .find(|&entry| self.compare_entry(memory, entry, encoded) == Ordering::Equal);
+        }
+
+        let mut low = 0;
+        let mut high = self.entry_count();
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let entry = self.entry_address(mid);
+            match self.compare_entry(memory, entry, encoded) {
+                Ordering::Equal => return Some(entry),
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+            }
+        }
+        None
+    }
+
+    // A listing of the separators and every entry, with the word decoded and
+    // the data after it in hex, laid out like the dictionary section of
+    // infodump's output.
+    pub fn report<H, M>(&self, header: &H, memory: &Handle<M>) -> Result<String>
+    where
+        H: Header,
+        M: Memory,
+    {
+        let separators = self
+            .separators
+            .iter()
+            .map(|&sep| format!("\"{}\"", char::from(sep)))
+            .collect::<Vec<_>>();
+        let mut report = format!(
+            "    **** Dictionary ****\n\n  Word separators = {}\n",
+            separators.join(" ")
+        );
+        report.push_str(&format!(
+            "  Word count = {}{}, word size = {}\n\n",
+            self.entry_count(),
+            if self.is_sorted() { "" } else { " (unsorted)" },
+            self.entry_length
+        ));
+
+        let word_bytes = encoded_length(header.version_number());
+        let mut text = ZTextDecoder::new(header, memory);
+        for idx in 0..self.entry_count() {
+            let entry = self.entry_address(idx);
+            let word = text.decode_from_memory(entry)?;
+            let data = (word_bytes..usize::from(self.entry_length))
+                .map(|offset| entry.inc_by(offset as u16))
+                .map(|at| format!("{:02x}", memory.borrow().read_byte(at)))
+                .collect::<Vec<_>>();
+            let line = format!(
+                "  [{:4}] {:04x} {:<9} {}",
+                idx + 1,
+                ZOffset::from(entry).value(),
+                word,
+                data.join(" ")
+            );
+            report.push_str(line.trim_end());
+            report.push('\n');
+        }
+        Ok(report)
+    }
+
+    // The entries of a sorted dictionary that don't come after the entry
+    // before them, so that lookup may not find them. (ZSpec 13.5)
+    pub fn misplaced_entries<H, M>(&self, header: &H, memory: &M) -> Vec<ByteAddress>
+    where
+        H: Header,
+        M: Memory,
+    {
+        if !self.is_sorted() {
+            return Vec::new();
+        }
+        let word_bytes = encoded_length(header.version_number());
+        let encoded = |entry: ByteAddress| {
+            (0..word_bytes)
+                .map(|idx| memory.read_byte(entry.inc_by(idx as u16)))
+                .collect::<Vec<_>>()
+        };
+        (1..self.entry_count())
+            .filter(|&idx| {
+                let previous = encoded(self.entry_address(idx - 1));
+                self.compare_entry(memory, self.entry_address(idx), &previous) != Ordering::Greater
+            })
+            .map(|idx| self.entry_address(idx))
+            .collect()
+    }
+
+    fn compare_entry<M>(&self, memory: &M, entry: ByteAddress, encoded: &[u8]) -> Ordering
+    where
+        M: Memory,
+    {
+        encoded
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| memory.read_byte(entry.inc_by(i as u16)).cmp(byte))
+            .find(|ordering| *ordering != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+// The length in bytes of an encoded word, at three z-chars to a word.
+fn encoded_length(version: ZVersion) -> usize {
+    version.dictionary_word_length() / 3 * 2
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::fixtures::{TestHeader, TestMemory};
+    use super::super::handle::new_handle;
+    use super::super::version::ZVersion;
+    use super::super::zscii::encode_zstr;
+    use super::*;
+
+    #[test]
+    fn test_dictionary_header() {
+        let mut memory = TestMemory::new(0x200);
+        memory.bytes[0x100..0x108].copy_from_slice(&[3, b'.', b',', b'"', 7, 0x01, 0x02, 0]);
+        let mut header = TestHeader::new(ZVersion::V3);
+        header.dictionary = ByteAddress::from_raw(0x100);
+
+        let dictionary = ZDictionary::from_header(&header, &memory);
+        assert_eq!(b".,\"", dictionary.separators());
+        assert_eq!(7, dictionary.entry_length());
+        assert_eq!(0x102, dictionary.entry_count());
+        assert!(dictionary.is_sorted());
+        assert_eq!(ByteAddress::from_raw(0x107), dictionary.entry_address(0));
+        assert_eq!(
+            ByteAddress::from_raw(0x107 + 14),
+            dictionary.entry_address(2)
+        );
+    }
+
+    #[test]
+    fn test_unsorted_dictionary() {
+        let mut memory = TestMemory::new(0x20);
+        // No separators, and -2 entries.
+        memory.bytes[0x10..0x14].copy_from_slice(&[0, 9, 0xff, 0xfe]);
+
+        let dictionary = ZDictionary::new(&memory, ByteAddress::from_raw(0x10));
+        assert!(dictionary.separators().is_empty());
+        assert_eq!(2, dictionary.entry_count());
+        assert!(!dictionary.is_sorted());
+        assert_eq!(ByteAddress::from_raw(0x14), dictionary.entry_address(0));
+    }
+
+    // A dictionary at 0x10 with no separators and 6-byte entries holding
+    // the given 4-byte words.
+    fn dictionary_with(words: &[[u8; 4]], sorted: bool) -> (TestMemory, ZDictionary) {
+        let mut memory = TestMemory::new(0x200);
+        let count = if sorted {
+            words.len() as i16
+        } else {
+            -(words.len() as i16)
+        };
+        memory.bytes[0x10] = 0;
+        memory.bytes[0x11] = 6;
+        memory.bytes[0x12..0x14].copy_from_slice(&count.to_be_bytes());
+        for (idx, word) in words.iter().enumerate() {
+            let at = 0x14 + 6 * idx;
+            memory.bytes[at..at + 4].copy_from_slice(word);
+            memory.bytes[at + 4] = 0xee;
+        }
+        let dictionary = ZDictionary::new(&memory, ByteAddress::from_raw(0x10));
+        (memory, dictionary)
+    }
+
+    #[test]
+    fn test_lookup() {
+        let words = [
+            [0x10, 0x00, 0x80, 0x00],
+            [0x10, 0x00, 0x90, 0x00],
+            [0x2a, 0x01, 0x80, 0x00],
+            [0x2a, 0x02, 0x00, 0x00],
+            [0x7f, 0xff, 0xff, 0xff],
+        ];
+        let (memory, dictionary) = dictionary_with(&words, true);
+        for (idx, word) in words.iter().enumerate() {
+            assert_eq!(
+                Some(ByteAddress::from_raw(0x14 + 6 * idx as u16)),
+                dictionary.lookup(&memory, word)
+            );
+        }
+        assert_eq!(None, dictionary.lookup(&memory, &[0x2a, 0x01, 0x90, 0x00]));
+        assert_eq!(None, dictionary.lookup(&memory, &[0x00, 0x00, 0x00, 0x00]));
+        assert_eq!(None, dictionary.lookup(&memory, &[0xff, 0xff, 0xff, 0xff]));
+
+        let (memory, empty) = dictionary_with(&[], true);
+        assert_eq!(None, empty.lookup(&memory, &words[0]));
+    }
+
+    #[test]
+    fn test_report() {
+        let mut memory = TestMemory::new(0x200);
+        memory.bytes[0x10..0x15].copy_from_slice(&[2, b'.', b',', 7, 0]);
+        memory.bytes[0x15] = 2;
+        memory.bytes[0x16..0x1a].copy_from_slice(&encode_zstr("again", 6, ZVersion::V3));
+        memory.bytes[0x1a..0x1d].copy_from_slice(&[0x32, 0x00, 0xef]);
+        memory.bytes[0x1d..0x21].copy_from_slice(&encode_zstr("z", 6, ZVersion::V3));
+        let memory = new_handle(memory);
+        let mut header = TestHeader::new(ZVersion::V3);
+        header.dictionary = ByteAddress::from_raw(0x10);
+
+        let dictionary = ZDictionary::from_header(&header, &*memory.borrow());
+        assert_eq!(
+            "    **** Dictionary ****\n\n\
+             \x20 Word separators = \".\" \",\"\n\
+             \x20 Word count = 2, word size = 7\n\n\
+             \x20 [   1] 0016 again     32 00 ef\n\
+             \x20 [   2] 001d z         00 00 00\n",
+            dictionary.report(&header, &memory).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_misplaced_entries() {
+        let header = TestHeader::new(ZVersion::V3);
+        let words = [
+            [0x10, 0x00, 0x80, 0x00],
+            [0x2a, 0x01, 0x80, 0x00],
+            [0x2a, 0x01, 0x80, 0x00],
+            [0x10, 0x00, 0x90, 0x00],
+            [0x7f, 0xff, 0xff, 0xff],
+        ];
+        let (memory, dictionary) = dictionary_with(&words, true);
+        assert_eq!(
+            vec![
+                ByteAddress::from_raw(0x14 + 6 * 2),
+                ByteAddress::from_raw(0x14 + 6 * 3)
+            ],
+            dictionary.misplaced_entries(&header, &memory)
+        );
+
+        // Order doesn't matter in an unsorted dictionary.
+        let (memory, dictionary) = dictionary_with(&words, false);
+        assert!(dictionary.misplaced_entries(&header, &memory).is_empty());
+    }
+
+    #[test]
+    fn test_lookup_unsorted() {
+        let words = [
+            [0x7f, 0xff, 0xff, 0xff],
+            [0x10, 0x00, 0x80, 0x00],
+            [0x2a, 0x01, 0x80, 0x00],
+        ];
+        let (memory, dictionary) = dictionary_with(&words, false);
+        assert_eq!(
+            Some(ByteAddress::from_raw(0x14 + 6)),
+            dictionary.lookup(&memory, &words[1])
+        );
+        assert_eq!(
+            Some(ByteAddress::from_raw(0x14)),
+            dictionary.lookup(&memory, &words[0])
+        );
+        assert_eq!(None, dictionary.lookup(&memory, &[0x10, 0x00, 0x90, 0x00]));
+    }
+}
@@ -0,0 +1,620 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use super::addressing::{ByteAddress, ZOffset};
+use super::handle::Handle;
+use super::header::HOF_START_PC;
+use super::opcode::{
+    ZOperand, ZVariable, EXTENDED_OPCODE_SENTINEL, OPCODE_TYPE_MASK, SHORT_OPCODE_TYPE_MASK,
+    VAR_OPCODE_TYPE_MASK,
+};
+use super::processor::{opcode_info, OpcodeForm, OpcodeInfo};
+use super::result::{Result, ZErr};
+use super::traits::{Header, Memory};
+use super::version::ZVersion;
+use super::zscii::ZTextDecoder;
+
+// Where a branch goes: back from the routine with a value, or to an address.
+// (ZSpec 4.7)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ZBranchTarget {
+    ReturnFalse,
+    ReturnTrue,
+    Address(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ZBranch {
+    // Whether the branch is taken when the condition is true, or false.
+    pub on_true: bool,
+    pub target: ZBranchTarget,
+}
+
+impl fmt::Display for ZBranch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "?{}", if self.on_true { "" } else { "~" })?;
+        match self.target {
+            ZBranchTarget::ReturnFalse => write!(f, "rfalse"),
+            ZBranchTarget::ReturnTrue => write!(f, "rtrue"),
+            ZBranchTarget::Address(addr) => write!(f, "{:05x}", addr),
+        }
+    }
+}
+
+// One decoded instruction. Operands that the opcode treats as something
+// other than a number are shown as what they are: routine and string
+// addresses, variable names, and jump targets.
+#[derive(Debug)]
+pub struct ZInstruction {
+    pub address: usize,
+    pub info: &'static OpcodeInfo,
+    pub operands: Vec<ZOperand>,
+    pub store: Option<ZVariable>,
+    pub branch: Option<ZBranch>,
+    // The text of print and print_ret.
+    pub text: Option<String>,
+    // The address of the next instruction.
+    pub next: usize,
+
+    // For showing packed addresses.
+    version: ZVersion,
+    routines_offset: u16,
+    strings_offset: u16,
+}
+
+impl ZInstruction {
+    // The routine that a call with a constant address calls. Calling 0 does
+    // nothing, so it isn't a routine. (ZSpec 6.4.3)
+    pub fn called_routine(&self) -> Option<usize> {
+        if !self.info.name.starts_with("call") {
+            return None;
+        }
+        match self.operands.first() {
+            Some(ZOperand::LargeConstant(0)) | Some(ZOperand::SmallConstant(0)) => None,
+            Some(ZOperand::LargeConstant(packed)) => {
+                Some(self.unpack(*packed, self.routines_offset))
+            }
+            Some(ZOperand::SmallConstant(packed)) => {
+                Some(self.unpack(u16::from(*packed), self.routines_offset))
+            }
+            _ => None,
+        }
+    }
+
+    // The address that jump goes to, which is relative to the instruction
+    // after it, like a branch. (ZSpec 15 jump)
+    pub fn jump_target(&self) -> Option<usize> {
+        match (self.info.name, self.operands.first()) {
+            ("jump", Some(ZOperand::LargeConstant(offset))) => {
+                Some(branch_address(self.next, i32::from(*offset as i16)))
+            }
+            _ => None,
+        }
+    }
+
+    // Whether execution can carry on to the next instruction.
+    pub fn continues(&self) -> bool {
+        !matches!(
+            self.info.name,
+            "rtrue"
+                | "rfalse"
+                | "print_ret"
+                | "ret"
+                | "ret_popped"
+                | "jump"
+                | "quit"
+                | "restart"
+                | "throw"
+        )
+    }
+
+    fn unpack(&self, packed: u16, offset: u16) -> usize {
+        ZOffset::from(self.version.make_packed_address(packed, offset)).value()
+    }
+
+    // Some opcodes name a variable with a constant, rather than reading one.
+    // (ZSpec 4.2.3)
+    fn names_variable(&self) -> bool {
+        matches!(
+            self.info.name,
+            "inc" | "dec" | "inc_chk" | "dec_chk" | "load" | "store" | "pull"
+        )
+    }
+
+    fn format_operand(&self, idx: usize, operand: ZOperand) -> String {
+        match (idx, operand) {
+            (0, ZOperand::SmallConstant(var)) if self.names_variable() => {
+                format!("[{}]", ZVariable::from(var))
+            }
+            (0, ZOperand::LargeConstant(_)) if self.info.name == "jump" => {
+                format!("{:05x}", self.jump_target().unwrap_or(0))
+            }
+            (0, ZOperand::LargeConstant(packed)) if self.info.name == "print_paddr" => {
+                format!("s{:x}", self.unpack(packed, self.strings_offset))
+            }
+            (0, _) if self.called_routine().is_some() => {
+                format!("r{:x}", self.called_routine().unwrap_or(0))
+            }
+            _ => operand.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ZInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:05x}: {}", self.address, self.info.name)?;
+        for (idx, operand) in self.operands.iter().enumerate() {
+            write!(f, " {}", self.format_operand(idx, *operand))?;
+        }
+        if let Some(ref text) = self.text {
+            write!(f, " \"{}\"", text.replace('\n', "^"))?;
+        }
+        if let Some(store) = self.store {
+            write!(f, " -> {}", store)?;
+        }
+        if let Some(branch) = self.branch {
+            write!(f, " {}", branch)?;
+        }
+        Ok(())
+    }
+}
+
+// A routine's header and instructions, in address order. If the code can't
+// be decoded, the error is kept with its address, after the instructions
+// before it.
+pub struct ZRoutine {
+    pub address: usize,
+    // The initial values of the locals. They are always 0 from V5.
+    pub locals: Vec<u16>,
+    pub instructions: Vec<ZInstruction>,
+    pub error: Option<(usize, String)>,
+}
+
+impl fmt::Display for ZRoutine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Routine r{:x}, {} local{}",
+            self.address,
+            self.locals.len(),
+            if self.locals.len() == 1 { "" } else { "s" }
+        )?;
+        if self.locals.iter().any(|&local| local != 0) {
+            let locals = self
+                .locals
+                .iter()
+                .map(|local| format!("{:04x}", local))
+                .collect::<Vec<_>>();
+            write!(f, " ({})", locals.join(", "))?;
+        }
+        writeln!(f)?;
+        writeln!(f)?;
+        for instruction in &self.instructions {
+            writeln!(f, "{}", instruction)?;
+        }
+        if let Some((address, ref error)) = self.error {
+            writeln!(f, "{:05x}: Error: {}", address, error)?;
+        }
+        Ok(())
+    }
+}
+
+// Reads an instruction's bytes. Reads past the end of memory are errors,
+// since the code being decoded may not be code at all.
+struct Cursor<'a, M>
+where
+    M: Memory,
+{
+    memory: &'a M,
+    at: usize,
+}
+
+impl<'a, M> Cursor<'a, M>
+where
+    M: Memory,
+{
+    fn offset(&self) -> ZOffset {
+        ZOffset::from(ByteAddress::from_raw(0)).inc_by(self.at)
+    }
+
+    fn next_byte(&mut self) -> Result<u8> {
+        let byte = self.memory.try_read_byte(self.offset())?;
+        self.at += 1;
+        Ok(byte)
+    }
+
+    fn next_word(&mut self) -> Result<u16> {
+        let word = self.memory.try_read_word(self.offset())?;
+        self.at += 2;
+        Ok(word)
+    }
+
+    // Operand types are the ones used by ZOperandType: 0 large, 1 small,
+    // 2 variable, 3 omitted. (ZSpec 4.2)
+    fn operand(&mut self, optype: u8) -> Result<ZOperand> {
+        Ok(match optype & 0b11 {
+            0 => ZOperand::LargeConstant(self.next_word()?),
+            1 => ZOperand::SmallConstant(self.next_byte()?),
+            2 => ZOperand::Var(ZVariable::from(self.next_byte()?)),
+            _ => ZOperand::Omitted,
+        })
+    }
+
+    // Up to four operands for each types byte, stopping at the first that
+    // is omitted.
+    fn var_operands(&mut self, type_bytes: usize) -> Result<Vec<ZOperand>> {
+        let mut types = Vec::new();
+        for _ in 0..type_bytes {
+            let byte = self.next_byte()?;
+            types.extend((0..4).map(|idx| byte >> ((3 - idx) * 2)));
+        }
+        let mut operands = Vec::new();
+        for optype in types {
+            match self.operand(optype)? {
+                ZOperand::Omitted => break,
+                operand => operands.push(operand),
+            }
+        }
+        Ok(operands)
+    }
+}
+
+fn branch_address(next: usize, offset: i32) -> usize {
+    (next as i64 + i64::from(offset) - 2) as usize
+}
+
+// Decode the instruction at `address`. (ZSpec 4)
+pub fn decode_instruction<H, M>(
+    header: &H,
+    memory: &Handle<M>,
+    text: &mut ZTextDecoder<M>,
+    address: usize,
+) -> Result<ZInstruction>
+where
+    H: Header,
+    M: Memory,
+{
+    let version = header.version_number();
+    let mem = memory.borrow();
+    let mut cursor = Cursor {
+        memory: &*mem,
+        at: address,
+    };
+
+    let byte = cursor.next_byte()?;
+    let (form, number, operands) =
+        if byte == EXTENDED_OPCODE_SENTINEL && version.supports_extended_opcodes() {
+            let number = cursor.next_byte()?;
+            (OpcodeForm::Ext, number, cursor.var_operands(1)?)
+        } else {
+            match byte & OPCODE_TYPE_MASK {
+                SHORT_OPCODE_TYPE_MASK => {
+                    let optype = (byte >> 4) & 0b11;
+                    match cursor.operand(optype)? {
+                        ZOperand::Omitted => (OpcodeForm::ZeroOp, byte & 0b1111, vec![]),
+                        operand => (OpcodeForm::OneOp, byte & 0b1111, vec![operand]),
+                    }
+                }
+                VAR_OPCODE_TYPE_MASK => {
+                    let number = byte & 0b1_1111;
+                    if byte & 0b0010_0000 == 0 {
+                        (OpcodeForm::TwoOp, number, cursor.var_operands(1)?)
+                    } else {
+                        // call_vs2 and call_vn2 have a second types byte, for
+                        // up to eight operands. (ZSpec 4.4.3.1)
+                        let type_bytes = if number == 0x0c || number == 0x1a {
+                            2
+                        } else {
+                            1
+                        };
+                        (OpcodeForm::Var, number, cursor.var_operands(type_bytes)?)
+                    }
+                }
+                _ => {
+                    // Long form: bits 6 and 5 choose small constant or variable.
+                    let first = if byte & 0b0100_0000 == 0 { 1 } else { 2 };
+                    let second = if byte & 0b0010_0000 == 0 { 1 } else { 2 };
+                    let operands = vec![cursor.operand(first)?, cursor.operand(second)?];
+                    (OpcodeForm::TwoOp, byte & 0b1_1111, operands)
+                }
+            }
+        };
+
+    let info = opcode_info(form, number, version)
+        .ok_or_else(|| ZErr::UnknownOpcode(form.name(), number, version as u8, address))?;
+
+    let store = if info.stores() {
+        Some(ZVariable::from(cursor.next_byte()?))
+    } else {
+        None
+    };
+
+    let branch = if info.branches() {
+        let first = cursor.next_byte()?;
+        let offset = if first & 0b0100_0000 != 0 {
+            i32::from(first & 0b0011_1111)
+        } else {
+            // 14 bits, signed.
+            let second = cursor.next_byte()?;
+            let raw = (u16::from(first & 0b0011_1111) << 8) | u16::from(second);
+            i32::from(((raw << 2) as i16) >> 2)
+        };
+        let target = match offset {
+            0 => ZBranchTarget::ReturnFalse,
+            1 => ZBranchTarget::ReturnTrue,
+            _ => ZBranchTarget::Address(branch_address(cursor.at, offset)),
+        };
+        Some(ZBranch {
+            on_true: first & 0b1000_0000 != 0,
+            target,
+        })
+    } else {
+        None
+    };
+
+    // The string follows the opcode, and ends with the word whose top bit is
+    // set. (ZSpec 3.2)
+    let inline_text = if info.prints_text() {
+        let start = cursor.offset();
+        while cursor.next_word()? & 0x8000 == 0 {}
+        Some(text.decode_from_memory(start)?)
+    } else {
+        None
+    };
+
+    Ok(ZInstruction {
+        address,
+        info,
+        operands,
+        store,
+        branch,
+        text: inline_text,
+        next: cursor.at,
+        version,
+        routines_offset: header.routines_offset(),
+        strings_offset: header.strings_offset(),
+    })
+}
+
+// Decode the routine whose header is at `address`. Decoding carries on until
+// an instruction that doesn't continue to the next, once every branch and
+// jump target seen so far has been passed, as txd does.
+pub fn decode_routine<H, M>(
+    header: &H,
+    memory: &Handle<M>,
+    text: &mut ZTextDecoder<M>,
+    address: usize,
+) -> Result<ZRoutine>
+where
+    H: Header,
+    M: Memory,
+{
+    let version = header.version_number();
+    let mem = memory.borrow();
+    let mut cursor = Cursor {
+        memory: &*mem,
+        at: address,
+    };
+    let num_locals = cursor.next_byte()?;
+    if num_locals > 15 {
+        return Err(ZErr::GenericError("Routine has more than 15 locals"));
+    }
+    // Before V5, the initial values of the locals follow. (ZSpec 5.2)
+    let mut locals = Vec::new();
+    for _ in 0..num_locals {
+        locals.push(if version < ZVersion::V5 {
+            cursor.next_word()?
+        } else {
+            0
+        });
+    }
+
+    let mut routine = ZRoutine {
+        address,
+        locals,
+        instructions: Vec::new(),
+        error: None,
+    };
+    let mut at = cursor.at;
+    let mut furthest = at;
+    loop {
+        let instruction = match decode_instruction(header, memory, text, at) {
+            Ok(instruction) => instruction,
+            Err(err) => {
+                routine.error = Some((at, err.to_string()));
+                break;
+            }
+        };
+        let targets = instruction
+            .branch
+            .and_then(|branch| match branch.target {
+                ZBranchTarget::Address(addr) => Some(addr),
+                _ => None,
+            })
+            .into_iter()
+            .chain(instruction.jump_target());
+        for target in targets {
+            furthest = furthest.max(target);
+        }
+        at = instruction.next;
+        let done = !instruction.continues() && at > furthest;
+        routine.instructions.push(instruction);
+        if done {
+            break;
+        }
+    }
+    Ok(routine)
+}
+
+// Every routine that can be reached from the start of the story by calls
+// with constant addresses, in address order. Indirect calls can't be
+// followed, so some routines may be missing. A call to an address that
+// isn't a routine is left out.
+pub fn disassemble<H, M>(header: &H, memory: &Handle<M>) -> Result<Vec<ZRoutine>>
+where
+    H: Header,
+    M: Memory,
+{
+    let mut text = ZTextDecoder::new(header, memory);
+    // The main routine is packed in V6. Otherwise the start pc is its first
+    // instruction, after a header with no locals. (ZSpec 5.5)
+    let start = memory
+        .borrow()
+        .try_read_word(ByteAddress::from_raw(HOF_START_PC))?;
+    let main = if header.version_number() == ZVersion::V6 {
+        usize::from(header.routine_address(start))
+    } else {
+        usize::from(start).saturating_sub(1)
+    };
+
+    let mut routines = BTreeMap::new();
+    let mut pending = BTreeSet::new();
+    pending.insert(main);
+    while let Some(&address) = pending.iter().next() {
+        pending.remove(&address);
+        let routine = match decode_routine(header, memory, &mut text, address) {
+            Ok(routine) => routine,
+            Err(_) if address != main => continue,
+            Err(err) => return Err(err),
+        };
+        for called in routine
+            .instructions
+            .iter()
+            .filter_map(|instruction| instruction.called_routine())
+        {
+            if !routines.contains_key(&called) && called != address {
+                pending.insert(called);
+            }
+        }
+        routines.insert(address, routine);
+    }
+    Ok(routines.into_values().collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::fixtures::{TestHeader, TestMemory};
+    use super::super::handle::new_handle;
+    use super::super::zscii::encode_zstr;
+    use super::*;
+
+    fn memory_with(at: usize, code: &[u8]) -> Handle<TestMemory> {
+        let mut memory = TestMemory::new(0x200);
+        memory.bytes[at..at + code.len()].copy_from_slice(code);
+        new_handle(memory)
+    }
+
+    fn decode(version: ZVersion, code: &[u8]) -> ZInstruction {
+        let header = TestHeader::new(version);
+        let memory = memory_with(0x100, code);
+        let mut text = ZTextDecoder::new(&header, &memory);
+        decode_instruction(&header, &memory, &mut text, 0x100).unwrap()
+    }
+
+    #[test]
+    fn test_decode_forms() {
+        // Long: add l0 #05 -> sp
+        let add = decode(ZVersion::V3, &[0x54, 0x01, 0x05, 0x00]);
+        assert_eq!("00100: add l0 #05 -> sp", add.to_string());
+        assert_eq!(0x104, add.next);
+
+        // Short: jz g00 ?~rfalse
+        let jz = decode(ZVersion::V3, &[0xa0, 0x10, 0x40]);
+        assert_eq!("00100: jz g00 ?~rfalse", jz.to_string());
+
+        // Var: call_vs with a routine, a large constant and a variable.
+        let call = decode(
+            ZVersion::V5,
+            &[0xe0, 0x0b, 0x01, 0x00, 0x12, 0x34, 0x02, 0x03],
+        );
+        assert_eq!("00100: call_vs r400 #1234 l1 -> l2", call.to_string());
+        assert_eq!(Some(0x400), call.called_routine());
+
+        // Ext: log_shift #01 #02 -> sp
+        let shift = decode(ZVersion::V5, &[0xbe, 0x02, 0x5f, 0x01, 0x02, 0x00]);
+        assert_eq!("00100: log_shift #01 #02 -> sp", shift.to_string());
+
+        // call_vs2 has two types bytes.
+        let call = decode(
+            ZVersion::V5,
+            &[0xec, 0x15, 0x57, 0x00, 0x01, 1, 2, 3, 4, 5, 6, 0x00],
+        );
+        assert_eq!(
+            "00100: call_vs2 r4 #01 #02 #03 #04 #05 #06 -> sp",
+            call.to_string()
+        );
+    }
+
+    #[test]
+    fn test_decode_symbolic() {
+        // inc names its variable.
+        assert_eq!(
+            "00100: inc [g01]",
+            decode(ZVersion::V3, &[0x95, 0x11]).to_string()
+        );
+        // A 14-bit branch backwards.
+        let je = decode(ZVersion::V3, &[0x41, 0x01, 0x02, 0x3f, 0xf0]);
+        assert_eq!("00100: je l0 #02 ?~000f3", je.to_string());
+        // jump is relative to the next instruction.
+        let jump = decode(ZVersion::V3, &[0x8c, 0x00, 0x10]);
+        assert_eq!("00100: jump 00111", jump.to_string());
+        assert!(!jump.continues());
+        // print_paddr of a constant is a string.
+        assert_eq!(
+            "00100: print_paddr s200",
+            decode(ZVersion::V3, &[0x8d, 0x01, 0x00]).to_string()
+        );
+    }
+
+    #[test]
+    fn test_decode_text() {
+        let mut code = vec![0xb2];
+        code.extend(encode_zstr("hello", 6, ZVersion::V3));
+        let print = decode(ZVersion::V3, &code);
+        assert_eq!("00100: print \"hello\"", print.to_string());
+        assert_eq!(0x105, print.next);
+    }
+
+    #[test]
+    fn test_unknown_opcode() {
+        let header = TestHeader::new(ZVersion::V3);
+        let memory = memory_with(0x100, &[0xbe]);
+        let mut text = ZTextDecoder::new(&header, &memory);
+        match decode_instruction(&header, &memory, &mut text, 0x100) {
+            Err(ZErr::UnknownOpcode(..)) => (),
+            _ => panic!("Missing error"),
+        }
+    }
+
+    #[test]
+    fn test_disassemble() {
+        // V3 main routine at 0x40, with code from the start pc at 0x41:
+        //   call r80 -> sp; je sp #01 ?0004b; rtrue (skipped over); quit
+        // and a routine at 0x80 with one local, initially 5:
+        //   ret l0
+        let mut memory = TestMemory::new(0x100);
+        memory.bytes[0x06..0x08].copy_from_slice(&[0x00, 0x41]);
+        memory.bytes[0x40..0x4b].copy_from_slice(&[
+            0x00, 0xe0, 0x3f, 0x00, 0x40, 0x00, 0x41, 0x00, 0x01, 0xc3, 0xb0,
+        ]);
+        memory.bytes[0x4b] = 0xba;
+        memory.bytes[0x80..0x86].copy_from_slice(&[0x01, 0x00, 0x05, 0xab, 0x01, 0x00]);
+        let memory = new_handle(memory);
+        let header = TestHeader::new(ZVersion::V3);
+
+        let routines = disassemble(&header, &memory).unwrap();
+        assert_eq!(2, routines.len());
+        assert_eq!(
+            "Routine r40, 0 locals\n\n\
+             00041: call r80 -> sp\n\
+             00046: je sp #01 ?0004b\n\
+             0004a: rtrue\n\
+             0004b: quit\n",
+            routines[0].to_string()
+        );
+        assert_eq!(
+            "Routine r80, 1 local (0005)\n\n00083: ret l0\n",
+            routines[1].to_string()
+        );
+    }
+}
@@ -0,0 +1,188 @@
+// The line being typed at a read, with a cursor that can be moved to edit
+// it, and a history of earlier lines that can be recalled.
+//
+// The history lasts as long as the editor, so keep one for the whole session.
+// Recalling a line replaces whatever was typed; moving back past the newest
+// history entry restores it.
+#[derive(Default)]
+pub struct LineEditor {
+    chars: Vec<char>,
+    cursor: usize,
+
+    history: Vec<String>,
+    // Position in history while recalling lines. history.len() is the line
+    // being typed.
+    recall: usize,
+    typed: String,
+}
+
+impl LineEditor {
+    pub fn new() -> LineEditor {
+        LineEditor::default()
+    }
+
+    pub fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn insert(&mut self, ch: char) {
+        self.chars.insert(self.cursor, ch);
+        self.cursor += 1;
+    }
+
+    // Delete the character before the cursor.
+    pub fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    // Delete the character under the cursor.
+    pub fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    pub fn left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.chars.len());
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.chars.len();
+    }
+
+    // Recall the previous line from the history.
+    pub fn history_back(&mut self) {
+        if self.recall == 0 {
+            return;
+        }
+        if self.recall == self.history.len() {
+            self.typed = self.text();
+        }
+        self.recall -= 1;
+        let line = self.history[self.recall].clone();
+        self.replace(&line);
+    }
+
+    // Recall the next line from the history, or the line being typed.
+    pub fn history_forward(&mut self) {
+        if self.recall >= self.history.len() {
+            return;
+        }
+        self.recall += 1;
+        let line = if self.recall == self.history.len() {
+            self.typed.clone()
+        } else {
+            self.history[self.recall].clone()
+        };
+        self.replace(&line);
+    }
+
+    // Return the finished line, add it to the history, and start a new one.
+    pub fn finish(&mut self) -> String {
+        let line = self.take();
+        if !line.is_empty() && self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+            self.recall = self.history.len();
+        }
+        line
+    }
+
+    // Return the line without remembering it, as when input is interrupted,
+    // and start a new one.
+    pub fn take(&mut self) -> String {
+        let line = self.text();
+        self.chars.clear();
+        self.cursor = 0;
+        self.recall = self.history.len();
+        self.typed.clear();
+        line
+    }
+
+    fn replace(&mut self, line: &str) {
+        self.chars = line.chars().collect();
+        self.cursor = self.chars.len();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn type_text(editor: &mut LineEditor, text: &str) {
+        for ch in text.chars() {
+            editor.insert(ch);
+        }
+    }
+
+    #[test]
+    fn test_editing() {
+        let mut editor = LineEditor::new();
+        type_text(&mut editor, "opn door");
+        editor.home();
+        editor.right();
+        editor.right();
+        editor.insert('e');
+        assert_eq!("open door", editor.text());
+        assert_eq!(3, editor.cursor());
+
+        editor.end();
+        editor.backspace();
+        editor.left();
+        editor.delete();
+        assert_eq!("open do", editor.text());
+
+        // Nothing to delete at either end.
+        editor.end();
+        editor.delete();
+        editor.home();
+        editor.backspace();
+        assert_eq!("open do", editor.text());
+    }
+
+    #[test]
+    fn test_history() {
+        let mut editor = LineEditor::new();
+        type_text(&mut editor, "north");
+        assert_eq!("north", editor.finish());
+        type_text(&mut editor, "take lamp");
+        editor.finish();
+        // Repeats and empty lines aren't remembered.
+        type_text(&mut editor, "take lamp");
+        editor.finish();
+        editor.finish();
+
+        type_text(&mut editor, "ea");
+        editor.history_back();
+        assert_eq!("take lamp", editor.text());
+        editor.history_back();
+        assert_eq!("north", editor.text());
+        editor.history_back();
+        assert_eq!("north", editor.text());
+
+        editor.history_forward();
+        editor.history_forward();
+        assert_eq!("ea", editor.text());
+        assert_eq!(2, editor.cursor());
+        editor.history_forward();
+        assert_eq!("ea", editor.text());
+        // An interrupted line isn't remembered.
+        assert_eq!("ea", editor.take());
+        editor.history_back();
+        assert_eq!("take lamp", editor.text());
+    }
+}
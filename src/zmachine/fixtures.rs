@@ -1,9 +1,14 @@
 use std::collections::HashMap;
+use std::time::Duration;
 
-use super::addressing::ZOffset;
+use super::addressing::{ByteAddress, RoutinePackedAddress, ZOffset};
 use super::opcode::ZVariable;
 use super::result::{Result, ZErr};
-use super::traits::{Memory, Stack, Variables, PC};
+use super::screen::{Colour, TextStyle, WindowModel};
+use super::traits::{
+    bytes, Header, InputStreams, Memory, OutputStreams, Screen, SoundPlayer, Stack, Variables, PC,
+};
+use super::version::ZVersion;
 
 pub struct TestPC {
     pub pc: usize,
@@ -54,6 +59,16 @@ impl Variables for TestVariables {
         self.variables.insert(var, val);
         Ok(())
     }
+
+    // The test stack only holds one value, so there is no difference between
+    // accessing it in place and pushing/popping.
+    fn peek_variable(&mut self, var: ZVariable) -> Result<u16> {
+        self.read_variable(var)
+    }
+
+    fn poke_variable(&mut self, var: ZVariable, val: u16) -> Result<()> {
+        self.write_variable(var, val)
+    }
 }
 
 pub struct TestMemory {
@@ -79,6 +94,13 @@ impl Memory for TestMemory {
         self.bytes[at.into().value()]
     }
 
+    fn try_read_byte<T>(&self, at: T) -> Result<u8>
+    where
+        T: Into<ZOffset> + Copy,
+    {
+        bytes::checked_byte_from_slice(&self.bytes, at.into().value())
+    }
+
     fn write_byte<T>(&mut self, at: T, val: u8) -> Result<()>
     where
         T: Into<ZOffset> + Copy,
@@ -87,9 +109,20 @@ impl Memory for TestMemory {
         self.bytes[offset.value()] = val;
         Ok(())
     }
+
+    fn memory_size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    // Tests set up memory by hand, so there's no original to go back to.
+    fn original_dynamic(&self) -> &[u8] {
+        &[]
+    }
+
+    fn reset_dynamic(&mut self) {}
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct TestStack {
     pub arr: Vec<u8>, // a very small stack.
     pub map: HashMap<u8, u16>,
@@ -115,6 +148,20 @@ impl Stack for TestStack {
             .ok_or(ZErr::StackUnderflow("Underflow in TestStack"))
     }
 
+    fn peek_word(&self) -> Result<u16> {
+        match self.arr.len() {
+            len if len >= 2 => {
+                Ok((u16::from(self.arr[len - 2]) << 8) + u16::from(self.arr[len - 1]))
+            }
+            _ => Err(ZErr::StackUnderflow("Underflow in TestStack")),
+        }
+    }
+
+    fn poke_word(&mut self, word: u16) -> Result<()> {
+        self.pop_word()?;
+        self.push_word(word)
+    }
+
     fn read_local(&self, l: u8) -> Result<u16> {
         let value = if self.map.contains_key(&l) {
             self.map[&l]
@@ -133,8 +180,9 @@ impl Stack for TestStack {
         &mut self,
         _return_pc: usize,
         _num_locals: u8,
-        _return_var: ZVariable,
+        _return_var: Option<ZVariable>,
         _operands: &[u16],
+        _num_args: u8,
     ) -> Result<()> {
         panic!("unimplemented");
     }
@@ -145,7 +193,333 @@ impl Stack for TestStack {
     fn return_pc(&self) -> usize {
         panic!("unimplemented")
     }
-    fn return_variable(&self) -> ZVariable {
+    fn return_variable(&self) -> Option<ZVariable> {
+        panic!("unimplemented")
+    }
+    fn num_args(&self) -> u8 {
+        panic!("unimplemented")
+    }
+    fn frame_depth(&self) -> usize {
+        panic!("unimplemented")
+    }
+    fn quetzal_frames(&self) -> Vec<u8> {
+        panic!("unimplemented")
+    }
+    fn restore_quetzal_frames(&mut self, _data: &[u8]) -> Result<()> {
+        panic!("unimplemented")
+    }
+}
+
+// Only the values needed by the opcode tests are settable.
+pub struct TestHeader {
+    pub version: ZVersion,
+    pub abbrev: ByteAddress,
+    pub dictionary: ByteAddress,
+    pub flags1: u8,
+    pub otable: ByteAddress,
+    pub routines_offset: u16,
+    pub static_memory: ByteAddress,
+    pub strings_offset: u16,
+    pub terminating_chars: Option<ByteAddress>,
+}
+
+impl TestHeader {
+    pub fn new(version: ZVersion) -> TestHeader {
+        TestHeader {
+            version,
+            abbrev: ByteAddress::from_raw(0),
+            dictionary: ByteAddress::from_raw(0),
+            flags1: 0,
+            otable: ByteAddress::from_raw(0),
+            routines_offset: 0,
+            static_memory: ByteAddress::from_raw(0),
+            strings_offset: 0,
+            terminating_chars: None,
+        }
+    }
+}
+
+impl Header for TestHeader {
+    fn abbrev_location(&self) -> ByteAddress {
+        self.abbrev
+    }
+
+    fn dictionary_location(&self) -> ByteAddress {
+        self.dictionary
+    }
+
+    fn flags1(&self) -> u8 {
+        self.flags1
+    }
+
+    fn global_location(&self) -> ByteAddress {
+        panic!("unimplemented")
+    }
+
+    fn high_memory_base(&self) -> ByteAddress {
         panic!("unimplemented")
     }
+
+    fn static_memory_base(&self) -> ByteAddress {
+        self.static_memory
+    }
+
+    fn otable_location(&self) -> ByteAddress {
+        self.otable
+    }
+
+    fn routines_offset(&self) -> u16 {
+        self.routines_offset
+    }
+
+    fn strings_offset(&self) -> u16 {
+        self.strings_offset
+    }
+
+    fn terminating_chars_location(&self) -> Option<ByteAddress> {
+        self.terminating_chars
+    }
+
+    fn version_number(&self) -> ZVersion {
+        self.version
+    }
+}
+
+// Returns canned lines of input, each with its terminating character.
+#[derive(Default)]
+pub struct TestInputStreams {
+    pub lines: Vec<(String, u8)>,
+    pub chars: Vec<u8>,
+    pub selected: u8,
+    pub terminators: Vec<u8>,
+}
+
+impl TestInputStreams {
+    pub fn new(lines: &[(&str, u8)]) -> TestInputStreams {
+        TestInputStreams {
+            lines: lines.iter().map(|(l, t)| (l.to_string(), *t)).collect(),
+            ..TestInputStreams::default()
+        }
+    }
+}
+
+impl InputStreams for TestInputStreams {
+    fn select_input_stream(&mut self, stream: u8) -> Result<()> {
+        self.selected = stream;
+        Ok(())
+    }
+
+    fn current_stream(&self) -> u8 {
+        self.selected
+    }
+
+    fn read_line(
+        &mut self,
+        typed: &str,
+        terminators: &[u8],
+        _timeout: Option<Duration>,
+    ) -> Result<(String, u8)> {
+        self.terminators = terminators.to_vec();
+        let (line, terminator) = self.lines.remove(0);
+        Ok((format!("{}{}", typed, line), terminator))
+    }
+
+    fn read_char(&mut self, _timeout: Option<Duration>) -> Result<u8> {
+        Ok(self.chars.remove(0))
+    }
+
+    fn supports_timed_input(&self) -> bool {
+        true
+    }
+}
+
+// Collects all printed text, regardless of which streams are selected.
+#[derive(Default)]
+pub struct TestOutputStreams {
+    pub output: String,
+    pub recorded: Vec<String>,
+    pub selected: HashMap<u8, Option<ByteAddress>>,
+    pub style: TextStyle,
+}
+
+impl TestOutputStreams {
+    pub fn new() -> TestOutputStreams {
+        TestOutputStreams::default()
+    }
+}
+
+impl OutputStreams for TestOutputStreams {
+    fn select_stream(&mut self, stream: u8, table: Option<ByteAddress>) -> Result<()> {
+        self.selected.insert(stream, table);
+        Ok(())
+    }
+
+    fn deselect_stream(&mut self, stream: u8) -> Result<()> {
+        self.selected.remove(&stream);
+        Ok(())
+    }
+
+    fn set_text_style(&mut self, style: TextStyle) -> Result<()> {
+        self.style = self.style.apply(style);
+        Ok(())
+    }
+
+    fn print_str(&mut self, text: &str) -> Result<()> {
+        self.output.push_str(text);
+        Ok(())
+    }
+
+    fn record_line(&mut self, line: &str) -> Result<()> {
+        self.recorded.push(line.to_string());
+        Ok(())
+    }
+
+    fn record_key(&mut self, key: u8) -> Result<()> {
+        self.recorded.push(format!("key {}", key));
+        Ok(())
+    }
+}
+
+// Records each sound request as a string.
+#[derive(Default)]
+pub struct TestSoundPlayer {
+    pub events: Vec<String>,
+}
+
+impl TestSoundPlayer {
+    pub fn new() -> TestSoundPlayer {
+        TestSoundPlayer::default()
+    }
+}
+
+impl SoundPlayer for TestSoundPlayer {
+    fn bleep(&mut self, high: bool) -> Result<()> {
+        self.events
+            .push(format!("bleep {}", if high { "high" } else { "low" }));
+        Ok(())
+    }
+
+    fn prepare(&mut self, number: u16) -> Result<()> {
+        self.events.push(format!("prepare {}", number));
+        Ok(())
+    }
+
+    fn start(
+        &mut self,
+        number: u16,
+        volume: u8,
+        repeats: u8,
+        routine: Option<RoutinePackedAddress>,
+    ) -> Result<()> {
+        let routine = routine
+            .map(|r| r.to_string())
+            .unwrap_or_else(|| "none".to_string());
+        self.events.push(format!(
+            "start {} volume {} repeats {} routine {}",
+            number, volume, repeats, routine
+        ));
+        Ok(())
+    }
+
+    fn stop(&mut self, number: u16) -> Result<()> {
+        self.events.push(format!("stop {}", number));
+        Ok(())
+    }
+
+    fn finish_with(&mut self, number: u16) -> Result<()> {
+        self.events.push(format!("finish_with {}", number));
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct TestScreen {
+    pub output: String,
+    pub style: TextStyle,
+    pub colours: Option<(Colour, Colour)>,
+    pub windows: WindowModel,
+    pub erased: Vec<u16>,
+    pub erased_lines: u16,
+    pub unbuffered: bool,
+    pub status: Option<(String, String)>,
+}
+
+impl TestScreen {
+    pub fn new() -> TestScreen {
+        TestScreen::default()
+    }
+}
+
+impl Screen for TestScreen {
+    fn print(&mut self, text: &str) -> Result<()> {
+        self.windows.advance(text);
+        self.output.push_str(text);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_buffer_mode(&mut self, buffered: bool) -> Result<()> {
+        self.unbuffered = !buffered;
+        Ok(())
+    }
+
+    fn set_text_style(&mut self, style: TextStyle) -> Result<()> {
+        self.style = style;
+        Ok(())
+    }
+
+    fn set_colour(&mut self, foreground: Colour, background: Colour) -> Result<()> {
+        self.colours = Some((foreground, background));
+        Ok(())
+    }
+
+    fn supports_colour(&self) -> bool {
+        true
+    }
+
+    fn split_window(&mut self, lines: u16) -> Result<()> {
+        self.windows.split(lines);
+        Ok(())
+    }
+
+    fn set_window(&mut self, window: u16) -> Result<()> {
+        self.windows.select(window)
+    }
+
+    fn erase_window(&mut self, window: u16) -> Result<()> {
+        self.windows.erase(window);
+        self.erased.push(window);
+        Ok(())
+    }
+
+    fn erase_line(&mut self) -> Result<()> {
+        self.erased_lines += 1;
+        Ok(())
+    }
+
+    fn set_cursor(&mut self, line: u16, column: u16) -> Result<()> {
+        self.windows.set_cursor(line, column);
+        Ok(())
+    }
+
+    fn get_cursor(&self) -> (u16, u16) {
+        self.windows.cursor()
+    }
+
+    fn size(&self) -> (u16, u16) {
+        self.windows.size()
+    }
+
+    fn set_size(&mut self, lines: u16, columns: u16) -> Result<()> {
+        self.windows.set_size(lines, columns);
+        Ok(())
+    }
+
+    fn show_status(&mut self, location: &str, right: &str) -> Result<()> {
+        self.status = Some((location.to_string(), right.to_string()));
+        Ok(())
+    }
 }
@@ -1,19 +1,273 @@
-use super::addressing::ByteAddress;
+use super::addressing::{ByteAddress, RoutinePackedAddress};
 use super::handle::Handle;
 use super::memory::ZMemory;
+use super::options::ZOptions;
 use super::result::Result;
 use super::traits::{Header, Memory};
 use super::version::ZVersion;
 
+// The header is the first 64 bytes of memory. (ZSpec 11)
+pub const HEADER_SIZE: usize = 0x40;
+
 // Offsets for fields in the header. (ZSpec 11.1)
 pub const HOF_VERSION: u16 = 0x00;
+pub const HOF_FLAGS1: u16 = 0x01;
+pub const HOF_RELEASE: u16 = 0x02;
 pub const HOF_HIGH_MEMORY_BASE: u16 = 0x04;
 pub const HOF_START_PC: u16 = 0x06;
+pub const HOF_DICTIONARY_LOCATION: u16 = 0x08;
 pub const HOF_GLOBAL_LOCATION: u16 = 0x0c;
 pub const HOF_STATIC_MEMORY_BASE: u16 = 0x0e;
+pub const HOF_FLAGS2: u16 = 0x10;
+pub const HOF_SERIAL: u16 = 0x12;
 pub const HOF_FILE_LEN: u16 = 0x1a;
+pub const HOF_CHECKSUM: u16 = 0x1c;
+pub const HOF_INTERPRETER_NUMBER: u16 = 0x1e;
+pub const HOF_INTERPRETER_VERSION: u16 = 0x1f;
+pub const HOF_SCREEN_HEIGHT_LINES: u16 = 0x20;
+pub const HOF_SCREEN_WIDTH_CHARS: u16 = 0x21;
+pub const HOF_SCREEN_WIDTH_UNITS: u16 = 0x22;
+pub const HOF_SCREEN_HEIGHT_UNITS: u16 = 0x24;
+pub const HOF_FONT_WIDTH_UNITS: u16 = 0x26;
+pub const HOF_FONT_HEIGHT_UNITS: u16 = 0x27;
+pub const HOF_ROUTINES_OFFSET: u16 = 0x28;
+pub const HOF_STRINGS_OFFSET: u16 = 0x2a;
 pub const HOF_ABBREV_LOCATION: u16 = 0x18;
 pub const HOF_OTABLE_LOCATION: u16 = 0x0a;
+pub const HOF_DEFAULT_BACKGROUND: u16 = 0x2c;
+pub const HOF_DEFAULT_FOREGROUND: u16 = 0x2d;
+pub const HOF_TERMINATING_CHARS: u16 = 0x2e;
+pub const HOF_STANDARD_REVISION: u16 = 0x32;
+pub const HOF_ALPHABET_TABLE: u16 = 0x34;
+pub const HOF_HEADER_EXTENSION: u16 = 0x36;
+
+// Words in the header extension table. (ZSpec 11.1.7)
+pub const HEXT_UNICODE_TABLE: u16 = 3;
+
+// Bits in Flags 1 for V1-3 stories. (ZSpec 11.1.2)
+pub const FLAGS1_STATUS_TIME: u8 = 0b0000_0010;
+pub const FLAGS1_STATUS_UNAVAILABLE: u8 = 0b0001_0000;
+pub const FLAGS1_SPLIT_AVAILABLE: u8 = 0b0010_0000;
+pub const FLAGS1_VARIABLE_PITCH_DEFAULT: u8 = 0b0100_0000;
+
+// Bits in Flags 1 for V4+ stories. (ZSpec 11.1.2)
+pub const FLAGS1_COLOURS_AVAILABLE: u8 = 0b0000_0001;
+pub const FLAGS1_PICTURES_AVAILABLE: u8 = 0b0000_0010;
+pub const FLAGS1_BOLD_AVAILABLE: u8 = 0b0000_0100;
+pub const FLAGS1_ITALIC_AVAILABLE: u8 = 0b0000_1000;
+pub const FLAGS1_FIXED_AVAILABLE: u8 = 0b0001_0000;
+pub const FLAGS1_SOUND_AVAILABLE: u8 = 0b0010_0000;
+pub const FLAGS1_TIMED_INPUT_AVAILABLE: u8 = 0b1000_0000;
+
+// Bits in Flags 2 that the game and the interpreter share. (ZSpec 11.1.2)
+pub const FLAGS2_TRANSCRIPTING: u16 = 0b0000_0001;
+pub const FLAGS2_FORCE_FIXED_PITCH: u16 = 0b0000_0010;
+
+// The Flags 2 bits that must survive a restart or restore. (ZSpec 6.1.2.2)
+const FLAGS2_PRESERVED: u16 = FLAGS2_TRANSCRIPTING | FLAGS2_FORCE_FIXED_PITCH;
+
+// The only header bits that the game may change: transcripting, fixed pitch,
+// and (V6) the redraw request, all in the low byte of Flags 2. (ZSpec 11.1)
+const FLAGS2_GAME_WRITABLE: u8 = 0b0000_0111;
+
+// How the interpreter identifies itself, unless ZOptions says otherwise.
+// (ZSpec 11.1.3)
+pub const INTERPRETER_NUMBER: u8 = 6; // IBM PC
+pub const INTERPRETER_VERSION: u8 = b'A';
+
+// The version of the Standard that the interpreter follows. (ZSpec 11.1.5)
+pub const STANDARD_REVISION: (u8, u8) = (1, 1);
+
+// The colours that Colour::Default stands for, as palette numbers. Terminals
+// don't report their own, so assume white on black. (ZSpec 8.3.3)
+pub const DEFAULT_FOREGROUND: u8 = 9; // white
+pub const DEFAULT_BACKGROUND: u8 = 2; // black
+
+// The screen size, when the frontend can't tell. 255 lines means that the
+// screen never needs to page. (ZSpec 8.4.1)
+pub const SCREEN_HEIGHT_LINES: u8 = 255;
+pub const SCREEN_WIDTH_CHARS: u8 = 80;
+
+// Write the fields that the interpreter owns: the capability bits in Flags 1,
+// the interpreter number and version, the screen and font sizes, the default
+// colours, and the standard revision. The identifying fields come from the
+// options. The screen size is (lines, columns). This must happen at startup,
+// and again after restore or restart, since those overwrite the header.
+// (ZSpec 11.1)
+pub fn write_interpreter_fields<M>(
+    memory: &Handle<M>,
+    version: ZVersion,
+    colours: bool,
+    timed_input: bool,
+    screen_size: (u16, u16),
+    options: &ZOptions,
+) -> Result<()>
+where
+    M: Memory,
+{
+    write_screen_size(memory, version, screen_size)?;
+
+    let mut memory = memory.borrow_mut();
+    let flags1_offset = ByteAddress::from_raw(HOF_FLAGS1);
+    let flags1 = memory.read_byte(flags1_offset);
+    let flags1 = if version <= ZVersion::V3 {
+        let set = FLAGS1_SPLIT_AVAILABLE;
+        let clear = FLAGS1_STATUS_UNAVAILABLE | FLAGS1_VARIABLE_PITCH_DEFAULT;
+        (flags1 | set) & !clear
+    } else {
+        let mut set = FLAGS1_BOLD_AVAILABLE | FLAGS1_ITALIC_AVAILABLE | FLAGS1_FIXED_AVAILABLE;
+        if colours && version >= ZVersion::V5 {
+            set |= FLAGS1_COLOURS_AVAILABLE;
+        }
+        if timed_input {
+            set |= FLAGS1_TIMED_INPUT_AVAILABLE;
+        }
+        let clear = FLAGS1_COLOURS_AVAILABLE
+            | FLAGS1_PICTURES_AVAILABLE
+            | FLAGS1_SOUND_AVAILABLE
+            | FLAGS1_TIMED_INPUT_AVAILABLE;
+        (flags1 & !clear) | set
+    };
+    memory.write_interpreter_byte(flags1_offset, flags1)?;
+
+    if version >= ZVersion::V4 {
+        memory.write_interpreter_byte(
+            ByteAddress::from_raw(HOF_INTERPRETER_NUMBER),
+            options.interpreter_number,
+        )?;
+        memory.write_interpreter_byte(
+            ByteAddress::from_raw(HOF_INTERPRETER_VERSION),
+            options.interpreter_version,
+        )?;
+    }
+
+    if colours && version >= ZVersion::V5 {
+        memory.write_interpreter_byte(
+            ByteAddress::from_raw(HOF_DEFAULT_BACKGROUND),
+            DEFAULT_BACKGROUND,
+        )?;
+        memory.write_interpreter_byte(
+            ByteAddress::from_raw(HOF_DEFAULT_FOREGROUND),
+            DEFAULT_FOREGROUND,
+        )?;
+    }
+
+    memory.write_interpreter_byte(
+        ByteAddress::from_raw(HOF_STANDARD_REVISION),
+        options.standard_revision.0,
+    )?;
+    memory.write_interpreter_byte(
+        ByteAddress::from_raw(HOF_STANDARD_REVISION + 1),
+        options.standard_revision.1,
+    )
+}
+
+// Write the screen size, as (lines, columns), into the header. The game may
+// read it at any time, so this is needed again whenever the screen is
+// resized. The fields only exist in V4+. (ZSpec 8.4)
+pub fn write_screen_size<M>(
+    memory: &Handle<M>,
+    version: ZVersion,
+    screen_size: (u16, u16),
+) -> Result<()>
+where
+    M: Memory,
+{
+    let mut memory = memory.borrow_mut();
+
+    // The byte-sized fields can't describe a larger screen.
+    let (lines, columns) = screen_size;
+    let lines = lines.min(u16::from(SCREEN_HEIGHT_LINES));
+    let columns = columns.min(255);
+
+    if version >= ZVersion::V4 {
+        memory
+            .write_interpreter_byte(ByteAddress::from_raw(HOF_SCREEN_HEIGHT_LINES), lines as u8)?;
+        memory
+            .write_interpreter_byte(ByteAddress::from_raw(HOF_SCREEN_WIDTH_CHARS), columns as u8)?;
+    }
+
+    if version >= ZVersion::V5 {
+        // Characters are one unit in each direction. V6 swaps the order of
+        // the font width and height. (ZSpec 11.1.4)
+        memory.write_interpreter_word(ByteAddress::from_raw(HOF_SCREEN_WIDTH_UNITS), columns)?;
+        memory.write_interpreter_word(ByteAddress::from_raw(HOF_SCREEN_HEIGHT_UNITS), lines)?;
+        memory.write_interpreter_byte(ByteAddress::from_raw(HOF_FONT_WIDTH_UNITS), 1)?;
+        memory.write_interpreter_byte(ByteAddress::from_raw(HOF_FONT_HEIGHT_UNITS), 1)?;
+    }
+    Ok(())
+}
+
+// As ZHeader::header_extension_word, for code that only has the memory.
+pub fn header_extension_word<M>(memory: &M, index: u16) -> Option<u16>
+where
+    M: Memory,
+{
+    // The table only exists in V5+.
+    if memory.read_byte(ByteAddress::from_raw(HOF_VERSION)) < 5 || index == 0 {
+        return None;
+    }
+    match memory.read_word(ByteAddress::from_raw(HOF_HEADER_EXTENSION)) {
+        0 => None,
+        raw => {
+            let table = ByteAddress::from_raw(raw);
+            if index > memory.read_word(table) {
+                None
+            } else {
+                Some(memory.read_word(table.inc_by(index * 2)))
+            }
+        }
+    }
+}
+
+// Whether the game may change the byte at offset from old to new. Beyond the
+// header, anything goes. Within it, the game may rewrite a byte with the same
+// value, as copy_table or a whole-word storew might, but may only change the
+// bits it owns.
+pub fn is_legal_game_write(offset: usize, old: u8, new: u8) -> bool {
+    if offset >= HEADER_SIZE {
+        return true;
+    }
+    let writable = if offset == usize::from(HOF_FLAGS2) + 1 {
+        FLAGS2_GAME_WRITABLE
+    } else {
+        0
+    };
+    (old ^ new) & !writable == 0
+}
+
+pub fn read_flags2<M>(memory: &Handle<M>) -> u16
+where
+    M: Memory,
+{
+    memory.borrow().read_word(ByteAddress::from_raw(HOF_FLAGS2))
+}
+
+// Set or clear the bits in mask. The transcript stream uses this to keep the
+// transcripting bit in step with its selection. (ZSpec 7.3)
+pub fn set_flags2<M>(memory: &Handle<M>, mask: u16, on: bool) -> Result<()>
+where
+    M: Memory,
+{
+    let flags = read_flags2(memory);
+    let flags = if on { flags | mask } else { flags & !mask };
+    memory
+        .borrow_mut()
+        .write_interpreter_word(ByteAddress::from_raw(HOF_FLAGS2), flags)
+}
+
+// Put back the preserved bits from before a restart or restore, which
+// will have overwritten them with the saved values.
+pub fn restore_flags2<M>(memory: &Handle<M>, old_flags: u16) -> Result<()>
+where
+    M: Memory,
+{
+    let flags = read_flags2(memory);
+    let flags = (flags & !FLAGS2_PRESERVED) | (old_flags & FLAGS2_PRESERVED);
+    memory
+        .borrow_mut()
+        .write_interpreter_word(ByteAddress::from_raw(HOF_FLAGS2), flags)
+}
 
 // Read a Story's Header information.
 // See ZSpec 11.
@@ -48,6 +302,40 @@ impl ZHeader {
         ByteAddress::from_raw(raw_value)
     }
 
+    // In V6, the start pc field holds the packed address of the main
+    // routine instead. (ZSpec 5.5)
+    pub fn main_routine(&self) -> RoutinePackedAddress {
+        let raw_value = self
+            .memory
+            .borrow()
+            .read_word(ByteAddress::from_raw(HOF_START_PC));
+        self.routine_address(raw_value)
+    }
+
+    // Set or clear the bits in mask. Flags 1 is written by the interpreter
+    // to advertise its capabilities.
+    pub fn set_flags1(&self, mask: u8, on: bool) -> Result<()> {
+        let flags = self.flags1();
+        let flags = if on { flags | mask } else { flags & !mask };
+        self.memory
+            .borrow_mut()
+            .write_interpreter_byte(ByteAddress::from_raw(HOF_FLAGS1), flags)
+    }
+
+    // Read a word from the header extension table, if the story has one and
+    // it is long enough. Word 0 is the number of further words. (ZSpec 11.1.7)
+    pub fn header_extension_word(&self, index: u16) -> Option<u16> {
+        header_extension_word(&*self.memory.borrow(), index)
+    }
+
+    // The checksum that the story was built with, which verify compares with
+    // ZMemory::checksum. The earliest stories leave it 0. (ZSpec 11.1)
+    pub fn checksum(&self) -> u16 {
+        self.memory
+            .borrow()
+            .read_word(ByteAddress::from_raw(HOF_CHECKSUM))
+    }
+
     pub fn file_length(&self) -> usize {
         let raw_file_length = self
             .memory
@@ -55,9 +343,192 @@ impl ZHeader {
             .read_word(ByteAddress::from_raw(HOF_FILE_LEN));
         self.z_version.convert_file_length(raw_file_length)
     }
+
+    pub fn release(&self) -> u16 {
+        self.memory
+            .borrow()
+            .read_word(ByteAddress::from_raw(HOF_RELEASE))
+    }
+
+    // Usually the date the story was compiled, as YYMMDD.
+    pub fn serial(&self) -> String {
+        let memory = self.memory.borrow();
+        (0..6)
+            .map(|idx| char::from(memory.read_byte(ByteAddress::from_raw(HOF_SERIAL + idx))))
+            .collect()
+    }
+
+    // A description of the header, field by field, laid out like the header
+    // section of infodump's output. Addresses are in hex.
+    pub fn report(&self) -> String {
+        let version = self.z_version;
+        let memory = self.memory.borrow();
+        let word = |offset| memory.read_word(ByteAddress::from_raw(offset));
+
+        let mut lines = vec![
+            ("Z-code version", format!("{}", version as u8)),
+            ("Interpreter flags", flags1_names(version, self.flags1())),
+            ("Release number", format!("{}", self.release())),
+            (
+                "Size of resident memory",
+                format!("{:04x}", word(HOF_HIGH_MEMORY_BASE)),
+            ),
+        ];
+        if version == ZVersion::V6 {
+            lines.push(("Main routine", format!("{}", self.main_routine())));
+        } else {
+            lines.push(("Start PC", format!("{:04x}", word(HOF_START_PC))));
+        }
+        lines.extend(vec![
+            (
+                "Dictionary address",
+                format!("{:04x}", word(HOF_DICTIONARY_LOCATION)),
+            ),
+            (
+                "Object table address",
+                format!("{:04x}", word(HOF_OTABLE_LOCATION)),
+            ),
+            (
+                "Global variables address",
+                format!("{:04x}", word(HOF_GLOBAL_LOCATION)),
+            ),
+            (
+                "Size of dynamic memory",
+                format!("{:04x}", word(HOF_STATIC_MEMORY_BASE)),
+            ),
+            ("Game flags", flags2_names(word(HOF_FLAGS2))),
+            ("Serial number", self.serial()),
+            (
+                "Abbreviations address",
+                format!("{:04x}", word(HOF_ABBREV_LOCATION)),
+            ),
+            ("File size", format!("{:05x}", self.file_length())),
+        ]);
+        let checksum = self.checksum();
+        let computed = memory.checksum();
+        lines.push((
+            "Checksum",
+            if checksum == computed {
+                format!("{:04x}", checksum)
+            } else {
+                format!("{:04x} (but the story sums to {:04x})", checksum, computed)
+            },
+        ));
+        if version == ZVersion::V6 || version == ZVersion::V7 {
+            lines.push((
+                "Routines offset",
+                format!("{:04x}", word(HOF_ROUTINES_OFFSET)),
+            ));
+            lines.push((
+                "Strings offset",
+                format!("{:04x}", word(HOF_STRINGS_OFFSET)),
+            ));
+        }
+        if version >= ZVersion::V5 {
+            // Zero means that the story doesn't have the table.
+            let optional = |offset| match word(offset) {
+                0 => "None".to_string(),
+                addr => format!("{:04x}", addr),
+            };
+            lines.push(("Terminating keys address", optional(HOF_TERMINATING_CHARS)));
+            lines.push(("Alphabet table address", optional(HOF_ALPHABET_TABLE)));
+            lines.push(("Header extension address", optional(HOF_HEADER_EXTENSION)));
+        }
+
+        let mut report = String::from("    **** Story file header ****\n\n");
+        for (name, value) in lines {
+            report.push_str(&format!("{:<26}{}\n", format!("{}:", name), value));
+        }
+        report
+    }
+}
+
+// The names of the bits set in Flags 1, whose meaning changed in V4. The
+// story file only sets the V1-3 bits; the rest are set by the interpreter.
+// (ZSpec 11.1.2)
+fn flags1_names(version: ZVersion, flags: u8) -> String {
+    let flags = u16::from(flags);
+    if version < ZVersion::V4 {
+        let status = if flags & u16::from(FLAGS1_STATUS_TIME) != 0 {
+            "Display hours:minutes"
+        } else {
+            "Display score/moves"
+        };
+        let rest = flag_names(
+            flags,
+            &[
+                (0b0000_0100, "Story file split across two discs"),
+                (u16::from(FLAGS1_STATUS_UNAVAILABLE), "No status line"),
+                (
+                    u16::from(FLAGS1_SPLIT_AVAILABLE),
+                    "Screen splitting available",
+                ),
+                (
+                    u16::from(FLAGS1_VARIABLE_PITCH_DEFAULT),
+                    "Variable-pitch font by default",
+                ),
+            ],
+        );
+        match rest.as_str() {
+            "None" => status.to_string(),
+            _ => format!("{}, {}", status, rest),
+        }
+    } else {
+        flag_names(
+            flags,
+            &[
+                (u16::from(FLAGS1_COLOURS_AVAILABLE), "Colours"),
+                (u16::from(FLAGS1_PICTURES_AVAILABLE), "Pictures"),
+                (u16::from(FLAGS1_BOLD_AVAILABLE), "Bold"),
+                (u16::from(FLAGS1_ITALIC_AVAILABLE), "Italic"),
+                (u16::from(FLAGS1_FIXED_AVAILABLE), "Fixed-space font"),
+                (u16::from(FLAGS1_SOUND_AVAILABLE), "Sound effects"),
+                (u16::from(FLAGS1_TIMED_INPUT_AVAILABLE), "Timed input"),
+            ],
+        )
+    }
+}
+
+// The names of the bits set in Flags 2. Apart from the first three, these
+// are requests from the game for features it would like to use.
+// (ZSpec 11.1.2)
+fn flags2_names(flags: u16) -> String {
+    flag_names(
+        flags,
+        &[
+            (FLAGS2_TRANSCRIPTING, "Transcripting"),
+            (FLAGS2_FORCE_FIXED_PITCH, "Fixed-pitch font"),
+            (0b0000_0000_0000_0100, "Redraw requested"),
+            (0b0000_0000_0000_1000, "Pictures"),
+            (0b0000_0000_0001_0000, "UNDO"),
+            (0b0000_0000_0010_0000, "Mouse"),
+            (0b0000_0000_0100_0000, "Colours"),
+            (0b0000_0000_1000_0000, "Sound effects"),
+            (0b0000_0001_0000_0000, "Menus"),
+        ],
+    )
+}
+
+fn flag_names(flags: u16, names: &[(u16, &str)]) -> String {
+    let set = names
+        .iter()
+        .filter(|(mask, _)| flags & mask != 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>();
+    if set.is_empty() {
+        "None".to_string()
+    } else {
+        set.join(", ")
+    }
 }
 
 impl Header for ZHeader {
+    fn flags1(&self) -> u8 {
+        self.memory
+            .borrow()
+            .read_byte(ByteAddress::from_raw(HOF_FLAGS1))
+    }
+
     fn version_number(&self) -> ZVersion {
         self.z_version
     }
@@ -94,6 +565,41 @@ impl Header for ZHeader {
         )
     }
 
+    fn dictionary_location(&self) -> ByteAddress {
+        ByteAddress::from_raw(
+            self.memory
+                .borrow()
+                .read_word(ByteAddress::from_raw(HOF_DICTIONARY_LOCATION)),
+        )
+    }
+
+    fn routines_offset(&self) -> u16 {
+        self.memory
+            .borrow()
+            .read_word(ByteAddress::from_raw(HOF_ROUTINES_OFFSET))
+    }
+
+    fn strings_offset(&self) -> u16 {
+        self.memory
+            .borrow()
+            .read_word(ByteAddress::from_raw(HOF_STRINGS_OFFSET))
+    }
+
+    fn terminating_chars_location(&self) -> Option<ByteAddress> {
+        // Only V5+ has a terminating characters table, and even then, it is optional.
+        if self.z_version < ZVersion::V5 {
+            return None;
+        }
+        match self
+            .memory
+            .borrow()
+            .read_word(ByteAddress::from_raw(HOF_TERMINATING_CHARS))
+        {
+            0 => None,
+            raw => Some(ByteAddress::from_raw(raw)),
+        }
+    }
+
     fn otable_location(&self) -> ByteAddress {
         ByteAddress::from_raw(
             self.memory
@@ -117,7 +623,8 @@ mod test {
             0x00, 0x00, 0x00, // 0x01 - 0x03
             0x77, 0x22, // 0x04: high memory base (0x7722)
             0x34, 0x56, // 0x06: start pc (0x1122)
-            0x00, 0x00, 0x00, 0x00, // 0x08 - 0x0b
+            0x23, 0x45, // 0x08: dictionary location (0x2345)
+            0x00, 0x00, // 0x0a - 0x0b
             0x11, 0x22, // 0x0c: global location (0x1122)
             0x87, 0x64, // 0x0e - 0x0f
             0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 0x10 - 0x17
@@ -132,7 +639,15 @@ mod test {
         new_story_from_bytes(&basic_header()).unwrap()
     }
 
+    // The header's file length is scaled by the version, so pad the story out
+    // to whatever length that makes it.
     fn new_story_from_bytes(bytes: &[u8]) -> Result<(Handle<ZMemory>, ZHeader)> {
+        let mut bytes = bytes.to_vec();
+        let raw_length = u16::from_be_bytes([bytes[0x1a], bytes[0x1b]]);
+        let length = ZVersion::new(bytes[0])?.convert_file_length(raw_length);
+        if bytes.len() < length {
+            bytes.resize(length, 0);
+        }
         ZMemory::new(&mut Cursor::new(bytes))
     }
 
@@ -144,6 +659,8 @@ mod test {
         assert_eq!(ByteAddress::from_raw(0x1122), hdr.global_location());
         assert_eq!(ByteAddress::from_raw(0x8764), hdr.static_memory_base());
         assert_eq!(ByteAddress::from_raw(0x7722), hdr.high_memory_base());
+        assert_eq!(ByteAddress::from_raw(0x2345), hdr.dictionary_location());
+        assert_eq!(None, hdr.terminating_chars_location());
     }
 
     #[test]
@@ -151,13 +668,269 @@ mod test {
         let (_, hdr) = new_test_story();
         assert_eq!(0x24, hdr.file_length());
 
-        // TODO: test file length is below required mimimums.
-        // TODO: test that file loaded is the same length as the file length in the header.
         let mut v5_bytes = basic_header();
         v5_bytes[0] = 5;
         v5_bytes[0x1b] = 0x09;
         let (_, hdr) = new_story_from_bytes(&v5_bytes).unwrap();
         assert_eq!(0x24, hdr.file_length());
+
+        let mut v4_bytes = basic_header();
+        v4_bytes[0] = 4;
+        v4_bytes[0x1b] = 0x09;
+        let (_, hdr) = new_story_from_bytes(&v4_bytes).unwrap();
+        assert_eq!(ZVersion::V4, hdr.version_number());
+        assert_eq!(0x24, hdr.file_length());
+
+        let mut v8_bytes = basic_header();
+        v8_bytes[0] = 8;
+        v8_bytes[0x1b] = 0x04;
+        v8_bytes.extend_from_slice(&[0; 0x04]);
+        let (_, hdr) = new_story_from_bytes(&v8_bytes).unwrap();
+        assert_eq!(ZVersion::V8, hdr.version_number());
+        assert_eq!(0x20, hdr.file_length());
+    }
+
+    #[test]
+    fn test_checksum() {
+        let mut bytes = basic_header();
+        bytes[0x1c] = 0xab;
+        bytes[0x1d] = 0xcd;
+        let (_, hdr) = new_story_from_bytes(&bytes).unwrap();
+        assert_eq!(0xabcd, hdr.checksum());
+    }
+
+    #[test]
+    fn test_flags1() {
+        let (_, hdr) = new_test_story();
+        assert_eq!(0, hdr.flags1());
+
+        hdr.set_flags1(FLAGS1_COLOURS_AVAILABLE, true).unwrap();
+        assert_eq!(0b0000_0001, hdr.flags1());
+
+        hdr.set_flags1(FLAGS1_COLOURS_AVAILABLE, false).unwrap();
+        assert_eq!(0, hdr.flags1());
+    }
+
+    #[test]
+    fn test_packed_offsets() {
+        let mut v7_bytes = basic_header();
+        v7_bytes[0] = 7;
+        v7_bytes.extend_from_slice(&[0; 0x10]);
+        v7_bytes[0x29] = 0x10; // Routines offset.
+        v7_bytes[0x2b] = 0x20; // Strings offset.
+        let (_, hdr) = new_story_from_bytes(&v7_bytes).unwrap();
+        assert_eq!(ZVersion::V7, hdr.version_number());
+        assert_eq!(
+            0x100 * 4 + 0x10 * 8,
+            usize::from(hdr.routine_address(0x100))
+        );
+        assert_eq!(0x100 * 4 + 0x20 * 8, usize::from(hdr.string_address(0x100)));
+
+        let mut v6_bytes = v7_bytes.clone();
+        v6_bytes[0] = 6;
+        let (_, hdr) = new_story_from_bytes(&v6_bytes).unwrap();
+        assert_eq!(0x3456 * 4 + 0x10 * 8, usize::from(hdr.main_routine()));
+    }
+
+    #[test]
+    fn test_header_extension() {
+        let (_, hdr) = new_test_story();
+        assert_eq!(None, hdr.header_extension_word(1));
+
+        let mut v5_bytes = basic_header();
+        v5_bytes[0] = 5;
+        v5_bytes.extend_from_slice(&[0; 0x30]);
+        v5_bytes[0x37] = 0x40; // Extension table at 0x40.
+        v5_bytes[0x41] = 2; // with two words.
+        v5_bytes[0x43] = 0x11;
+        v5_bytes[0x45] = 0x22;
+        v5_bytes[0x47] = 0x33;
+        let (_, hdr) = new_story_from_bytes(&v5_bytes).unwrap();
+        assert_eq!(Some(0x11), hdr.header_extension_word(1));
+        assert_eq!(Some(0x22), hdr.header_extension_word(2));
+        assert_eq!(None, hdr.header_extension_word(3));
+    }
+
+    #[test]
+    fn test_flags2() {
+        let mut bytes = basic_header();
+        bytes[0x11] = 0b1000_0000;
+        let (memory, _) = new_story_from_bytes(&bytes).unwrap();
+
+        set_flags2(&memory, FLAGS2_TRANSCRIPTING, true).unwrap();
+        assert_eq!(0b1000_0001, read_flags2(&memory));
+        let old_flags = read_flags2(&memory);
+
+        // A restore replaces the whole word, but only the preserved bits come back.
+        memory
+            .borrow_mut()
+            .write_interpreter_word(ByteAddress::from_raw(HOF_FLAGS2), 0b0100_0010)
+            .unwrap();
+        restore_flags2(&memory, old_flags).unwrap();
+        assert_eq!(0b0100_0001, read_flags2(&memory));
+
+        set_flags2(&memory, FLAGS2_TRANSCRIPTING, false).unwrap();
+        assert_eq!(0b0100_0000, read_flags2(&memory));
+    }
+
+    #[test]
+    fn test_interpreter_fields_v3() {
+        let mut bytes = basic_header();
+        bytes[1] = FLAGS1_STATUS_TIME | FLAGS1_STATUS_UNAVAILABLE;
+        bytes.extend_from_slice(&[0; 0x20]);
+        let (memory, hdr) = new_story_from_bytes(&bytes).unwrap();
+
+        write_interpreter_fields(
+            &memory,
+            ZVersion::V3,
+            true,
+            true,
+            (25, 80),
+            &ZOptions::new(),
+        )
+        .unwrap();
+        assert_eq!(FLAGS1_STATUS_TIME | FLAGS1_SPLIT_AVAILABLE, hdr.flags1());
+        // Only the standard revision is written for V3.
+        assert_eq!(
+            0,
+            memory
+                .borrow()
+                .read_byte(ByteAddress::from_raw(HOF_INTERPRETER_NUMBER))
+        );
+        assert_eq!(
+            0x0101,
+            memory
+                .borrow()
+                .read_word(ByteAddress::from_raw(HOF_STANDARD_REVISION))
+        );
+    }
+
+    #[test]
+    fn test_interpreter_fields_v5() {
+        let mut bytes = basic_header();
+        bytes[0] = 5;
+        bytes[1] = FLAGS1_TIMED_INPUT_AVAILABLE;
+        bytes[0x1b] = 0x10;
+        bytes.extend_from_slice(&[0; 0x20]);
+        let (memory, hdr) = new_story_from_bytes(&bytes).unwrap();
+
+        write_interpreter_fields(
+            &memory,
+            ZVersion::V5,
+            true,
+            false,
+            (300, 100),
+            &ZOptions::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            FLAGS1_COLOURS_AVAILABLE
+                | FLAGS1_BOLD_AVAILABLE
+                | FLAGS1_ITALIC_AVAILABLE
+                | FLAGS1_FIXED_AVAILABLE,
+            hdr.flags1()
+        );
+        let memory = memory.borrow();
+        assert_eq!(
+            INTERPRETER_NUMBER,
+            memory.read_byte(ByteAddress::from_raw(HOF_INTERPRETER_NUMBER))
+        );
+        assert_eq!(
+            DEFAULT_BACKGROUND,
+            memory.read_byte(ByteAddress::from_raw(HOF_DEFAULT_BACKGROUND))
+        );
+        assert_eq!(
+            DEFAULT_FOREGROUND,
+            memory.read_byte(ByteAddress::from_raw(HOF_DEFAULT_FOREGROUND))
+        );
+        assert_eq!(
+            100,
+            memory.read_byte(ByteAddress::from_raw(HOF_SCREEN_WIDTH_CHARS))
+        );
+        // Taller screens are reported as 255 lines: infinite.
+        assert_eq!(
+            255,
+            memory.read_byte(ByteAddress::from_raw(HOF_SCREEN_HEIGHT_LINES))
+        );
+        assert_eq!(
+            100,
+            memory.read_word(ByteAddress::from_raw(HOF_SCREEN_WIDTH_UNITS))
+        );
+        assert_eq!(
+            1,
+            memory.read_byte(ByteAddress::from_raw(HOF_FONT_HEIGHT_UNITS))
+        );
+    }
+
+    #[test]
+    fn test_write_screen_size() {
+        let mut bytes = basic_header();
+        bytes[0] = 4;
+        bytes.extend_from_slice(&[0; 0x20]);
+        let (memory, _) = new_story_from_bytes(&bytes).unwrap();
+
+        write_screen_size(&memory, ZVersion::V4, (40, 132)).unwrap();
+        let memory = memory.borrow();
+        assert_eq!(
+            40,
+            memory.read_byte(ByteAddress::from_raw(HOF_SCREEN_HEIGHT_LINES))
+        );
+        assert_eq!(
+            132,
+            memory.read_byte(ByteAddress::from_raw(HOF_SCREEN_WIDTH_CHARS))
+        );
+        // The unit sizes are V5+.
+        assert_eq!(
+            0,
+            memory.read_word(ByteAddress::from_raw(HOF_SCREEN_WIDTH_UNITS))
+        );
+    }
+
+    #[test]
+    fn test_interpreter_fields_timed_input() {
+        let mut bytes = basic_header();
+        bytes[0] = 4;
+        bytes.extend_from_slice(&[0; 0x20]);
+        let (memory, hdr) = new_story_from_bytes(&bytes).unwrap();
+
+        write_interpreter_fields(
+            &memory,
+            ZVersion::V4,
+            false,
+            true,
+            (25, 80),
+            &ZOptions::new(),
+        )
+        .unwrap();
+        assert_ne!(0, hdr.flags1() & FLAGS1_TIMED_INPUT_AVAILABLE);
+    }
+
+    #[test]
+    fn test_interpreter_fields_options() {
+        let mut bytes = basic_header();
+        bytes[0] = 5;
+        bytes.extend_from_slice(&[0; 0x20]);
+        let (memory, _) = new_story_from_bytes(&bytes).unwrap();
+
+        let mut options = ZOptions::new();
+        options.interpreter_number = 2; // Apple IIe
+        options.interpreter_version = b'C';
+        options.standard_revision = (1, 0);
+        write_interpreter_fields(&memory, ZVersion::V5, true, false, (25, 80), &options).unwrap();
+
+        let memory = memory.borrow();
+        assert_eq!(
+            2,
+            memory.read_byte(ByteAddress::from_raw(HOF_INTERPRETER_NUMBER))
+        );
+        assert_eq!(
+            b'C',
+            memory.read_byte(ByteAddress::from_raw(HOF_INTERPRETER_VERSION))
+        );
+        assert_eq!(
+            0x0100,
+            memory.read_word(ByteAddress::from_raw(HOF_STANDARD_REVISION))
+        );
     }
 
     #[test]
@@ -171,4 +944,39 @@ mod test {
             _ => panic!("Something broke."),
         }
     }
+
+    #[test]
+    fn test_report() {
+        let mut bytes = basic_header();
+        bytes.resize(0x48, 0);
+        bytes[0x01] = FLAGS1_STATUS_TIME;
+        bytes[0x03] = 88; // Release.
+        bytes[0x0a] = 0x02; // Object table.
+        bytes[0x0b] = 0xb0;
+        bytes[0x11] = 0x11; // Transcripting and UNDO.
+        bytes[0x12..0x18].copy_from_slice(b"840726");
+        bytes[0x18] = 0x01; // Abbreviations.
+        bytes[0x19] = 0xf0;
+        bytes[0x1c] = 0x12; // Checksum.
+        bytes[0x1d] = 0x34;
+        let (_, hdr) = new_story_from_bytes(&bytes).unwrap();
+        assert_eq!(
+            "    **** Story file header ****\n\n\
+             Z-code version:           3\n\
+             Interpreter flags:        Display hours:minutes\n\
+             Release number:           88\n\
+             Size of resident memory:  7722\n\
+             Start PC:                 3456\n\
+             Dictionary address:       2345\n\
+             Object table address:     02b0\n\
+             Global variables address: 1122\n\
+             Size of dynamic memory:   8764\n\
+             Game flags:               Transcripting, UNDO\n\
+             Serial number:            840726\n\
+             Abbreviations address:    01f0\n\
+             File size:                00024\n\
+             Checksum:                 1234 (but the story sums to 0000)\n",
+            hdr.report()
+        );
+    }
 }
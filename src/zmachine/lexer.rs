@@ -0,0 +1,179 @@
+use super::addressing::{ByteAddress, ZOffset};
+use super::dictionary::ZDictionary;
+use super::handle::Handle;
+use super::result::Result;
+use super::traits::Memory;
+use super::version::ZVersion;
+use super::zscii::{encode_zstr_in, ZAlphabet};
+
+// A word found in the player's input.
+#[derive(Debug, PartialEq)]
+struct Token {
+    // Offset of the word's first character from the start of the text buffer.
+    position: u8,
+    text: String,
+}
+
+// Lexical analysis of the text buffer filled by read. (ZSpec 13.6)
+//
+// Words are separated by spaces, and by the dictionary's word separators,
+// which are also words in their own right. Each word is looked up in the
+// dictionary, and the results are written to the parse buffer. With
+// skip_unknown, the parse buffer's entries for words that aren't in the
+// dictionary are left as they were.
+pub fn tokenise<M>(
+    memory: &Handle<M>,
+    version: ZVersion,
+    text: ByteAddress,
+    parse: ByteAddress,
+    dictionary: &ZDictionary,
+    skip_unknown: bool,
+) -> Result<()>
+where
+    M: Memory,
+{
+    let tokens = {
+        let input = read_text_buffer(&*memory.borrow(), version, text);
+        split_words(&input, text_start(version), dictionary.separators())
+    };
+
+    let max_words = memory.borrow().read_byte(parse);
+    let num_words = tokens.len().min(usize::from(max_words));
+    for (idx, token) in tokens.iter().take(num_words).enumerate() {
+        let entry = lookup(&*memory.borrow(), version, dictionary, &token.text);
+        if entry.is_none() && skip_unknown {
+            continue;
+        }
+        let block = parse.inc_by(2 + 4 * idx as u16);
+
+        let mut mem = memory.borrow_mut();
+        mem.write_word(
+            block,
+            entry.map_or(0, |ba| ZOffset::from(ba).value() as u16),
+        )?;
+        mem.write_byte(block.inc_by(2), token.text.len() as u8)?;
+        mem.write_byte(block.inc_by(3), token.position)?;
+    }
+    memory
+        .borrow_mut()
+        .write_byte(parse.inc_by(1), num_words as u8)
+}
+
+// The text buffer's characters start after the max length byte, and in V5+,
+// also after the byte holding the number of characters. (ZSpec 15 read)
+fn text_start(version: ZVersion) -> u8 {
+    if version >= ZVersion::V5 {
+        2
+    } else {
+        1
+    }
+}
+
+fn read_text_buffer<M>(memory: &M, version: ZVersion, text: ByteAddress) -> Vec<u8>
+where
+    M: Memory,
+{
+    let start = text.inc_by(u16::from(text_start(version)));
+    let mut bytes = Vec::new();
+    if version >= ZVersion::V5 {
+        let len = memory.read_byte(text.inc_by(1));
+        for idx in 0..u16::from(len) {
+            bytes.push(memory.read_byte(start.inc_by(idx)));
+        }
+    } else {
+        // Zero-terminated.
+        let mut idx = 0;
+        loop {
+            let byte = memory.read_byte(start.inc_by(idx));
+            if byte == 0 {
+                break;
+            }
+            bytes.push(byte);
+            idx += 1;
+        }
+    }
+    bytes
+}
+
+fn split_words(input: &[u8], start: u8, separators: &[u8]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut current: Option<Token> = None;
+
+    for (idx, byte) in input.iter().enumerate() {
+        let position = start + idx as u8;
+        let ch = *byte as char;
+        if *byte == b' ' || separators.contains(byte) {
+            if let Some(token) = current.take() {
+                tokens.push(token);
+            }
+            if *byte != b' ' {
+                tokens.push(Token {
+                    position,
+                    text: ch.to_string(),
+                });
+            }
+        } else {
+            current
+                .get_or_insert(Token {
+                    position,
+                    text: String::new(),
+                })
+                .text
+                .push(ch);
+        }
+    }
+    if let Some(token) = current {
+        tokens.push(token);
+    }
+    tokens
+}
+
+// Dictionary entries start with the encoded word: 4 bytes (6 z-chars) in
+// V1-3, and 6 bytes (9 z-chars) in V4+. (ZSpec 13.3, 13.4)
+fn lookup<M>(
+    memory: &M,
+    version: ZVersion,
+    dictionary: &ZDictionary,
+    word: &str,
+) -> Option<ByteAddress>
+where
+    M: Memory,
+{
+    let alphabets = ZAlphabet::from_story(memory, version);
+    let encoded = encode_zstr_in(&alphabets, word, version.dictionary_word_length(), version);
+    dictionary.lookup(memory, &encoded)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn token(position: u8, text: &str) -> Token {
+        Token {
+            position,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(
+            vec![
+                token(1, "fred"),
+                token(5, ","),
+                token(7, "go"),
+                token(11, "north")
+            ],
+            split_words(b"fred, go  north", 1, b",")
+        );
+    }
+
+    #[test]
+    fn test_split_separators() {
+        assert_eq!(
+            vec![token(2, "."), token(3, "x"), token(4, "."), token(5, ".")],
+            split_words(b".x..", 2, b".")
+        );
+        assert!(split_words(b"   ", 2, b".").is_empty());
+    }
+}
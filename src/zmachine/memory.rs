@@ -1,8 +1,10 @@
 use std::io::Read;
 
 use super::addressing::{ByteAddress, ZOffset};
+use super::audit::ZAuditLog;
 use super::handle::{new_handle, Handle};
 use super::header::{self, ZHeader};
+use super::quetzal;
 use super::result::{Result, ZErr};
 use super::traits::{bytes, Header, Memory};
 
@@ -22,11 +24,28 @@ use super::traits::{bytes, Header, Memory};
 //   PackedAddress: used to reference high memory. The extent and interpretation
 //     of a PackedAddress changes depending on the ZMachine version in use.
 //
+// The three regions of core memory. (ZSpec 1.1)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ZRegion {
+    Dynamic,
+    Static,
+    High,
+}
+
 pub struct ZMemory {
     bytes: Box<[u8]>,
 
     static_mem: ZOffset, // Offset of the base of static memory.
     high_mem: ZOffset,   // Offset of the base of high memory.
+
+    // Dynamic memory as it was loaded, for restart and Quetzal's CMem.
+    original: Box<[u8]>,
+
+    // Computed at load time, before the game changes dynamic memory.
+    checksum: u16,
+
+    // When set, accesses to dynamic memory are recorded here.
+    audit: Option<Handle<ZAuditLog>>,
 }
 
 impl ZMemory {
@@ -40,19 +59,27 @@ impl ZMemory {
         let mut byte_vec = Vec::<u8>::new();
         rdr.read_to_end(&mut byte_vec)?;
 
-        // Have to bootstrap these.
+        // Have to bootstrap these. A file too short to hold them isn't a story.
         let static_base =
-            bytes::word_from_slice(&byte_vec, usize::from(header::HOF_STATIC_MEMORY_BASE));
+            bytes::checked_word_from_slice(&byte_vec, usize::from(header::HOF_STATIC_MEMORY_BASE))?;
         let high_base =
-            bytes::word_from_slice(&byte_vec, usize::from(header::HOF_HIGH_MEMORY_BASE));
+            bytes::checked_word_from_slice(&byte_vec, usize::from(header::HOF_HIGH_MEMORY_BASE))?;
 
+        // A static base past the end of the file doesn't leave any more to keep.
+        let original = byte_vec[..usize::from(static_base).min(byte_vec.len())].into();
         let zmem = new_handle(ZMemory {
             bytes: byte_vec.into(),
             static_mem: ByteAddress::from_raw(static_base).into(),
             high_mem: ByteAddress::from_raw(high_base).into(),
+            original,
+            checksum: 0,
+            audit: None,
         });
 
         let header = ZHeader::new(&zmem)?;
+        check_length(zmem.borrow().memory_size(), &header)?;
+        let checksum = checksum(&zmem.borrow().bytes, header.file_length());
+        zmem.borrow_mut().checksum = checksum;
 
         assert_eq!(zmem.borrow().static_mem, header.static_memory_base().into());
         assert_eq!(zmem.borrow().high_mem, header.high_memory_base().into());
@@ -60,9 +87,77 @@ impl ZMemory {
         Ok((zmem, header))
     }
 
-    // The total number of bytes in the ZMemory.
-    pub fn memory_size(&self) -> usize {
-        self.bytes.len()
+    // The story's checksum, as it was loaded. (ZSpec 15 verify)
+    pub fn checksum(&self) -> u16 {
+        self.checksum
+    }
+
+    // Each byte of dynamic memory XORed with its original value, so that
+    // unchanged bytes are zero. (Quetzal 3.1)
+    pub fn dynamic_diff(&self) -> Vec<u8> {
+        self.original
+            .iter()
+            .zip(self.bytes.iter())
+            .map(|(orig, cur)| orig ^ cur)
+            .collect()
+    }
+
+    // Record every read and write of dynamic memory in the log, or stop
+    // recording with None. The owner of the log keeps its pc up to date.
+    pub fn set_audit(&mut self, audit: Option<Handle<ZAuditLog>>) {
+        self.audit = audit;
+    }
+
+    fn audit(&self, offset: ZOffset, value: u8, write: bool) {
+        if let Some(ref audit) = self.audit {
+            if offset < self.static_mem {
+                audit.borrow_mut().record(offset.value(), value, write);
+            }
+        }
+    }
+
+    // The changes to dynamic memory, run-length encoded as for Quetzal's
+    // CMem chunk.
+    pub fn compressed_diff(&self) -> Vec<u8> {
+        quetzal::compress_memory(&self.original, &self.bytes[..self.original.len()])
+    }
+
+    // Replace dynamic memory with the original plus a compressed diff.
+    pub fn apply_compressed_diff(&mut self, diff: &[u8]) -> Result<()> {
+        let dynamic = quetzal::decompress_memory(&self.original, diff)?;
+        self.bytes[..dynamic.len()].copy_from_slice(&dynamic);
+        Ok(())
+    }
+
+    // High memory may overlap static memory, but never dynamic memory.
+    // (ZSpec 1.1.3)
+    pub fn region<T>(&self, at: T) -> ZRegion
+    where
+        T: Into<ZOffset>,
+    {
+        let offset = at.into();
+        if offset < self.static_mem {
+            ZRegion::Dynamic
+        } else if offset < self.high_mem {
+            ZRegion::Static
+        } else {
+            ZRegion::High
+        }
+    }
+
+    // The game may write to dynamic memory. Flags 2 is also writable wherever
+    // static memory begins, since games set its bits to make requests of the
+    // interpreter. (ZSpec 1.1.1.1, 11.1)
+    pub fn is_writable<T>(&self, at: T) -> bool
+    where
+        T: Into<ZOffset>,
+    {
+        let offset = at.into();
+        if offset.value() >= self.bytes.len() {
+            return false;
+        }
+        let flags2 = ZOffset::from(ByteAddress::from_raw(header::HOF_FLAGS2));
+        self.region(offset) == ZRegion::Dynamic || offset == flags2 || offset == flags2.inc_by(1)
     }
 }
 
@@ -71,7 +166,20 @@ impl Memory for ZMemory {
     where
         T: Into<ZOffset> + Copy,
     {
-        self.bytes[at.into().value()]
+        let offset = at.into();
+        let value = self.bytes[offset.value()];
+        self.audit(offset, value, false);
+        value
+    }
+
+    fn try_read_byte<T>(&self, at: T) -> Result<u8>
+    where
+        T: Into<ZOffset> + Copy,
+    {
+        let offset = at.into();
+        let value = bytes::checked_byte_from_slice(&self.bytes, offset.value())?;
+        self.audit(offset, value, false);
+        Ok(value)
     }
 
     fn write_byte<T>(&mut self, at: T, val: u8) -> Result<()>
@@ -79,21 +187,83 @@ impl Memory for ZMemory {
         T: Into<ZOffset> + Copy,
     {
         let offset = at.into();
-        if offset < self.static_mem {
+        if self.is_writable(offset)
+            && header::is_legal_game_write(offset.value(), self.bytes[offset.value()], val)
+        {
+            self.bytes[offset.value()] = val;
+            self.audit(offset, val, true);
+            Ok(())
+        } else {
+            Err(ZErr::WriteViolation(offset.value()))
+        }
+    }
+
+    fn memory_size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn original_dynamic(&self) -> &[u8] {
+        &self.original
+    }
+
+    fn write_interpreter_byte<T>(&mut self, at: T, val: u8) -> Result<()>
+    where
+        T: Into<ZOffset> + Copy,
+    {
+        let offset = at.into();
+        if self.is_writable(offset) {
             self.bytes[offset.value()] = val;
+            self.audit(offset, val, true);
             Ok(())
         } else {
             Err(ZErr::WriteViolation(offset.value()))
         }
     }
+
+    fn reset_dynamic(&mut self) {
+        let len = self.original.len();
+        self.bytes[..len].copy_from_slice(&self.original);
+    }
+}
+
+// A story may be padded beyond the length in its header, but not cut short.
+// The earliest stories leave the length 0, so only the version's limit can be
+// checked.
+fn check_length(length: usize, header: &ZHeader) -> Result<()> {
+    let max = header.version_number().max_story_length();
+    if length > max {
+        return Err(ZErr::StoryTooLarge(length, max));
+    }
+    let expected = header.file_length();
+    if length < expected {
+        return Err(ZErr::StoryTruncated(length, expected));
+    }
+    Ok(())
+}
+
+// The sum of the bytes after the header, up to the file length in the
+// header, modulo 0x10000. Files are sometimes padded past that length, and
+// the earliest stories leave it 0, in which case the whole file is used.
+fn checksum(bytes: &[u8], file_length: usize) -> u16 {
+    let end = if file_length == 0 {
+        bytes.len()
+    } else {
+        file_length.min(bytes.len())
+    };
+    bytes
+        .get(header::HEADER_SIZE..end)
+        .unwrap_or(&[])
+        .iter()
+        .fold(0u16, |sum, &byte| sum.wrapping_add(u16::from(byte)))
 }
 
 #[cfg(test)]
 mod test {
     use std::io::Cursor;
 
-    use super::super::addressing::{ByteAddress, WordAddress};
+    use super::super::addressing::{ByteAddress, WordAddress, ZPC};
     use super::super::handle::Handle;
+    use super::super::traits::PC;
     use super::super::version::ZVersion;
     use super::*;
 
@@ -158,13 +328,17 @@ mod test {
 
         let wa = WordAddress::from_raw(0x02);
         assert_eq!(0x00a0, zmem.borrow().read_word(wa));
-        zmem.borrow_mut().write_word(wa, 0x1234).unwrap();
+        zmem.borrow_mut()
+            .write_interpreter_word(wa, 0x1234)
+            .unwrap();
         assert_eq!(0x1234, zmem.borrow().read_word(wa));
 
         // Read/write from/to a non-word-aligned location.
         let wa = WordAddress::from_raw(0x03);
         assert_eq!(0x0000, zmem.borrow().read_word(wa));
-        zmem.borrow_mut().write_word(wa, 0x6789).unwrap();
+        zmem.borrow_mut()
+            .write_interpreter_word(wa, 0x6789)
+            .unwrap();
         assert_eq!(0x6789, zmem.borrow().read_word(wa));
     }
 
@@ -182,4 +356,308 @@ mod test {
             _ => false,
         })
     }
+
+    #[test]
+    fn test_regions() {
+        let zmem = make_test_mem(ZVersion::V3);
+        let zmem = zmem.borrow();
+
+        assert_eq!(ZRegion::Dynamic, zmem.region(ByteAddress::from_raw(0)));
+        assert_eq!(ZRegion::Dynamic, zmem.region(ByteAddress::from_raw(0x7f)));
+        assert_eq!(ZRegion::Static, zmem.region(ByteAddress::from_raw(0x80)));
+        assert_eq!(ZRegion::Static, zmem.region(ByteAddress::from_raw(0x9f)));
+        assert_eq!(ZRegion::High, zmem.region(ByteAddress::from_raw(0xa0)));
+        assert_eq!(ZRegion::High, zmem.region(ByteAddress::from_raw(0xff)));
+
+        assert!(zmem.is_writable(ByteAddress::from_raw(0x7f)));
+        assert!(!zmem.is_writable(ByteAddress::from_raw(0x80)));
+        assert!(!zmem.is_writable(ByteAddress::from_raw(0xa0)));
+        assert!(!zmem.is_writable(ByteAddress::from_raw(0x100)));
+    }
+
+    #[test]
+    fn test_flags2_always_writable() {
+        // A (broken) story whose static memory starts inside the header.
+        let mut bytes = sample_bytes();
+        bytes[0x0e] = 0x00;
+        bytes[0x0f] = 0x08;
+        let zmem = ZMemory::new(&mut Cursor::new(bytes)).unwrap().0;
+
+        let flags2 = ByteAddress::from_raw(header::HOF_FLAGS2);
+        assert_eq!(ZRegion::Static, zmem.borrow().region(flags2));
+        zmem.borrow_mut().write_word(flags2, 0x0005).unwrap();
+        assert_eq!(0x0005, zmem.borrow().read_word(flags2));
+
+        assert!(match zmem.borrow_mut().write_byte(flags2.inc_by(2), 0x03) {
+            Err(ZErr::WriteViolation(0x12)) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn test_header_writes() {
+        let zmem = make_test_mem(ZVersion::V5);
+        let flags1 = ByteAddress::from_raw(header::HOF_FLAGS1);
+        let flags2 = ByteAddress::from_raw(header::HOF_FLAGS2);
+
+        // The game may only change the bits of Flags 2 that it owns...
+        zmem.borrow_mut().write_word(flags2, 0x0007).unwrap();
+        zmem.borrow_mut().write_word(flags2, 0x0002).unwrap();
+        assert_eq!(0x0002, zmem.borrow().read_word(flags2));
+        assert!(match zmem.borrow_mut().write_word(flags2, 0x0012) {
+            Err(ZErr::WriteViolation(0x11)) => true,
+            _ => false,
+        });
+        assert!(match zmem.borrow_mut().write_word(flags2, 0x0102) {
+            Err(ZErr::WriteViolation(0x10)) => true,
+            _ => false,
+        });
+
+        // ...and may write the rest of the header without changing it.
+        zmem.borrow_mut().write_byte(flags1, 0).unwrap();
+        assert!(match zmem.borrow_mut().write_byte(flags1, 0x80) {
+            Err(ZErr::WriteViolation(0x01)) => true,
+            _ => false,
+        });
+        // Past the header, dynamic memory is the game's.
+        zmem.borrow_mut()
+            .write_byte(ByteAddress::from_raw(header::HEADER_SIZE as u16), 0x80)
+            .unwrap();
+
+        // The interpreter may write anything in the header.
+        zmem.borrow_mut()
+            .write_interpreter_byte(flags1, 0x80)
+            .unwrap();
+        assert_eq!(0x80, zmem.borrow().read_byte(flags1));
+        zmem.borrow_mut()
+            .write_interpreter_word(flags2, 0x0412)
+            .unwrap();
+        assert_eq!(0x0412, zmem.borrow().read_word(flags2));
+    }
+
+    #[test]
+    fn test_reset_dynamic() {
+        let zmem = make_test_mem(ZVersion::V3);
+        assert_eq!(vec![0; 0x80], zmem.borrow().dynamic_diff());
+
+        zmem.borrow_mut()
+            .write_interpreter_byte(ByteAddress::from_raw(0x0c), 0x13)
+            .unwrap();
+        zmem.borrow_mut()
+            .write_byte(ByteAddress::from_raw(0x50), 0xaa)
+            .unwrap();
+        let diff = zmem.borrow().dynamic_diff();
+        assert_eq!(0x80, diff.len());
+        assert_eq!(0x01, diff[0x0c]);
+        assert_eq!(0xaa, diff[0x50]);
+        assert_eq!(2, diff.iter().filter(|b| **b != 0).count());
+        assert_eq!(0x12, zmem.borrow().original_dynamic()[0x0c]);
+
+        zmem.borrow_mut().reset_dynamic();
+        assert_eq!(0x12, zmem.borrow().read_byte(ByteAddress::from_raw(0x0c)));
+        assert_eq!(0x00, zmem.borrow().read_byte(ByteAddress::from_raw(0x50)));
+        assert_eq!(vec![0; 0x80], zmem.borrow().dynamic_diff());
+    }
+
+    #[test]
+    fn test_compressed_diff() {
+        let zmem = make_test_mem(ZVersion::V3);
+        assert!(zmem.borrow().compressed_diff().is_empty());
+
+        zmem.borrow_mut()
+            .write_interpreter_byte(ByteAddress::from_raw(0x0c), 0x13)
+            .unwrap();
+        zmem.borrow_mut()
+            .write_byte(ByteAddress::from_raw(0x50), 0xaa)
+            .unwrap();
+        let diff = zmem.borrow().compressed_diff();
+        // 12 zeros, a change, 67 zeros, a change, and no trailing zeros.
+        assert_eq!(vec![0, 11, 0x01, 0, 66, 0xaa], diff);
+
+        zmem.borrow_mut().reset_dynamic();
+        zmem.borrow_mut().apply_compressed_diff(&diff).unwrap();
+        assert_eq!(0x13, zmem.borrow().read_byte(ByteAddress::from_raw(0x0c)));
+        assert_eq!(0xaa, zmem.borrow().read_byte(ByteAddress::from_raw(0x50)));
+
+        assert!(
+            match zmem.borrow_mut().apply_compressed_diff(&[0, 255, 1]) {
+                Err(ZErr::BadSaveFile(_)) => true,
+                _ => false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_audit() {
+        let zmem = make_test_mem(ZVersion::V3);
+        let audit = new_handle(ZAuditLog::new(10));
+        zmem.borrow_mut().set_audit(Some(audit.clone()));
+
+        audit.borrow_mut().set_pc(0x1234);
+        zmem.borrow_mut()
+            .write_word(ByteAddress::from_raw(0x50), 0xabcd)
+            .unwrap();
+        audit.borrow_mut().set_pc(0x1238);
+        assert_eq!(0xab, zmem.borrow().read_byte(ByteAddress::from_raw(0x50)));
+        // Static and high memory aren't recorded.
+        zmem.borrow().read_byte(ByteAddress::from_raw(0x80));
+        zmem.borrow().read_byte(ByteAddress::from_raw(0xa0));
+
+        let log = audit
+            .borrow()
+            .accesses()
+            .map(|access| access.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "01234: write 00050 ab",
+                "01234: write 00051 cd",
+                "01238: read  00050 ab",
+            ],
+            log
+        );
+
+        zmem.borrow_mut().set_audit(None);
+        zmem.borrow().read_byte(ByteAddress::from_raw(0x50));
+        assert_eq!(3, audit.borrow().accesses().count());
+    }
+
+    #[test]
+    fn test_read_violation() {
+        let zmem = make_test_mem(ZVersion::V3);
+
+        assert_eq!(
+            0x12,
+            zmem.borrow()
+                .try_read_byte(ByteAddress::from_raw(0x0c))
+                .unwrap()
+        );
+        assert_eq!(
+            0,
+            zmem.borrow()
+                .try_read_byte(ByteAddress::from_raw(0xff))
+                .unwrap()
+        );
+        assert!(
+            match zmem.borrow().try_read_byte(ByteAddress::from_raw(0x100)) {
+                Err(ZErr::ReadViolation(0x100)) => true,
+                _ => false,
+            }
+        );
+        // A word straddling the end fails on its second byte.
+        assert!(
+            match zmem.borrow().try_read_word(ByteAddress::from_raw(0xff)) {
+                Err(ZErr::ReadViolation(0x100)) => true,
+                _ => false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_short_story() {
+        let bytes = sample_bytes()[..0x0f].to_vec();
+        assert!(match ZMemory::new(&mut Cursor::new(bytes)) {
+            Err(ZErr::ReadViolation(0x0f)) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn test_story_length() {
+        // The header says 0x80 words, which is 0x100 bytes in V3.
+        let mut bytes = sample_bytes();
+        bytes[0x1a] = 0x00;
+        bytes[0x1b] = 0x80;
+        assert!(ZMemory::new(&mut Cursor::new(bytes.clone())).is_ok());
+
+        // Padding is fine, but a short file isn't.
+        let mut padded = bytes.clone();
+        padded.resize(0x200, 0);
+        assert!(ZMemory::new(&mut Cursor::new(padded)).is_ok());
+        assert!(
+            match ZMemory::new(&mut Cursor::new(bytes[..0xff].to_vec())) {
+                Err(ZErr::StoryTruncated(0xff, 0x100)) => true,
+                _ => false,
+            }
+        );
+
+        // In V5, the same header length is 0x200 bytes.
+        bytes[0] = 5;
+        assert!(match ZMemory::new(&mut Cursor::new(bytes.clone())) {
+            Err(ZErr::StoryTruncated(0x100, 0x200)) => true,
+            _ => false,
+        });
+
+        let mut huge = sample_bytes();
+        huge.resize(128 * 1024 + 1, 0);
+        assert!(match ZMemory::new(&mut Cursor::new(huge.clone())) {
+            Err(ZErr::StoryTooLarge(0x20001, 0x20000)) => true,
+            _ => false,
+        });
+        huge[0] = 4;
+        assert!(ZMemory::new(&mut Cursor::new(huge)).is_ok());
+    }
+
+    #[test]
+    fn test_large_story() {
+        let mut bytes = sample_bytes();
+        bytes[0] = 8;
+        bytes[0x1a] = 0x60; // 0x6000 * 8 = 0x30000 bytes.
+        bytes.resize(0x30000, 0);
+        bytes[0xffff] = 0x11;
+        bytes[0x10000] = 0x22;
+        bytes[0x2fff0] = 0xab;
+        bytes[0x2fff1] = 0xcd;
+        let (zmem, header) = ZMemory::new(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(0x30000, header.file_length());
+        assert_eq!(0x11 + 0x22 + 0xab + 0xcd, zmem.borrow().checksum());
+
+        let string = header.string_address(0x5ffe);
+        assert_eq!(0x2fff0, usize::from(string));
+        assert_eq!(0xabcd, zmem.borrow().read_word(string));
+        assert_eq!(ZRegion::High, zmem.borrow().region(string));
+
+        let mut pc = ZPC::new(&zmem, ByteAddress::from_raw(0xffff));
+        assert_eq!(0x1122, pc.next_word());
+        assert_eq!(0x10001, pc.current_pc());
+
+        let end = ZOffset::from(string).inc_by(0x10);
+        assert!(match zmem.borrow().try_read_byte(end) {
+            Err(ZErr::ReadViolation(0x30000)) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn test_checksum() {
+        let mut bytes = sample_bytes();
+        bytes[0x20] = 0x77; // In the header, so not counted.
+        bytes[0x40] = 0xff;
+        bytes[0x41] = 0x02;
+        bytes[0xd0] = 0x10;
+        let zmem = ZMemory::new(&mut Cursor::new(bytes.clone())).unwrap().0;
+        assert_eq!(0x111, zmem.borrow().checksum());
+
+        // The game changing memory doesn't change the checksum.
+        zmem.borrow_mut()
+            .write_byte(ByteAddress::from_raw(0x40), 0)
+            .unwrap();
+        assert_eq!(0x111, zmem.borrow().checksum());
+
+        // Only bytes up to the file length count: 0x60 words in V3.
+        bytes[0x1b] = 0x60;
+        let zmem = ZMemory::new(&mut Cursor::new(bytes.clone())).unwrap().0;
+        assert_eq!(0x101, zmem.borrow().checksum());
+
+        // The sum wraps.
+        for byte in bytes[0x40..0x80].iter_mut() {
+            *byte = 0xff;
+        }
+        bytes[0x1b] = 0;
+        let zmem = ZMemory::new(&mut Cursor::new(bytes)).unwrap().0;
+        assert_eq!(
+            ((0x40 * 0xff + 0x10) % 0x10000) as u16,
+            zmem.borrow().checksum()
+        );
+    }
 }
@@ -1,22 +1,64 @@
 mod addressing;
+mod audit;
+mod check;
 mod constants;
+mod debugger;
+mod dictionary;
+mod disasm;
+#[cfg(any(feature = "terminal", test))]
+mod editor;
 mod handle;
 mod header;
+mod lexer;
 mod memory;
 mod objects;
 mod opcode;
+mod options;
+mod pager;
 mod processor;
+mod quetzal;
+mod random;
 mod result;
+mod saves;
+mod screen;
+mod session;
+mod snapshot;
+mod sound;
 mod stack;
+mod status;
 mod story;
+mod streams;
+#[cfg(feature = "terminal")]
+mod terminal;
 mod traits;
+mod undo;
 mod variables;
 mod version;
+mod violations;
+mod wrap;
 mod zscii;
 
 #[cfg(test)]
 mod fixtures;
 
-pub use self::processor::ZProcessor;
+pub use self::audit::{ZAccess, ZAuditLog};
+pub use self::check::ZProblem;
+pub use self::dictionary::ZDictionary;
+pub use self::options::ZOptions;
+pub use self::processor::{ZComponents, ZProcessor};
+pub use self::quetzal::{
+    compress_memory, decompress_memory, memory_chunk, read_quetzal, restore_memory, Chunk,
+    MemoryFormat, QuetzalSave, SaveHeader,
+};
 pub use self::result::Result;
-pub use self::story::new_story_processor;
+pub use self::saves::{FileSaveHandler, SaveEntry, SaveInfo};
+pub use self::snapshot::ZSnapshot;
+#[cfg(feature = "terminal")]
+pub use self::story::new_terminal_story_processor;
+pub use self::story::{
+    new_dumb_story_processor, new_story_processor, story_dictionary, story_disassembly,
+    story_header, story_objects, story_problems, story_strings,
+};
+pub use self::traits::SaveHandler;
+pub use self::violations::ErrorPolicy;
+pub use self::zscii::ZKey;
@@ -1,8 +1,12 @@
+use std::collections::HashSet;
+use std::fmt;
+
 use super::addressing::{ByteAddress, ZOffset};
 use super::handle::Handle;
 use super::result::{Result, ZErr};
 use super::traits::{Header, Memory};
 use super::version::ZVersion;
+use super::zscii::ZTextDecoder;
 
 // jin a b           - jump if a in b (if parent of a is b)
 // test_attr o a     - jump if object has attr
@@ -20,14 +24,26 @@ use super::version::ZVersion;
 // print_obj o       - print the short name of object
 // put_prop o p v    - write value to property in object
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct ObjectNumber(u16);
 
+impl ObjectNumber {
+    pub fn value(self) -> u16 {
+        self.0
+    }
+}
+
+impl From<u16> for ObjectNumber {
+    fn from(num: u16) -> ObjectNumber {
+        ObjectNumber(num)
+    }
+}
+
 pub trait Object {}
 
 pub trait ObjectTable {
     type O: Object;
 
-    // TODO: range check.
     fn get_object(&self, num: ObjectNumber) -> Result<Self::O>;
 
     fn get_object_child(&self, o: Self::O) -> Result<ObjectNumber>;
@@ -44,12 +60,205 @@ pub trait ObjectTable {
     fn get_object_property(&self, o: Self::O, p: u8) -> Result<u16>; // Is this right? Are all properties u16?
     fn set_object_property(&self, o: Self::O, p: u8, v: u16) -> Result<()>;
 
-    fn get_default_property(&self, p: u8) -> Result<u16>; // Is this right? Are all properties u16?
+    fn get_default_property(&self, p: u8) -> Result<u16>;
+
+    fn get_object_short_name(&self, o: Self::O) -> Result<String>;
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct ZObject(ByteAddress);
 
+// Something wrong with the links in the object tree, found by validate.
+// Objects are given by number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TreeProblem {
+    // A parent, sibling, or child link names an object past the last one.
+    OutOfRange {
+        object: u16,
+        link: &'static str,
+        target: u16,
+    },
+    // Following the sibling links from the object's first child comes back
+    // to an object already seen.
+    Cycle {
+        object: u16,
+    },
+    // The object is among the parent's children, but gives another parent.
+    WrongParent {
+        object: u16,
+        parent: u16,
+        found: u16,
+    },
+    // The object gives a parent, but isn't among that parent's children.
+    Orphan {
+        object: u16,
+        parent: u16,
+    },
+}
+
+impl TreeProblem {
+    // The object whose entry has the problem.
+    pub fn object(&self) -> u16 {
+        use self::TreeProblem::*;
+        match *self {
+            OutOfRange { object, .. }
+            | Cycle { object }
+            | WrongParent { object, .. }
+            | Orphan { object, .. } => object,
+        }
+    }
+}
+
+impl fmt::Display for TreeProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::TreeProblem::*;
+        match *self {
+            OutOfRange {
+                object,
+                link,
+                target,
+            } => write!(
+                f,
+                "Object {} has {} {}, which is out of range",
+                object, link, target
+            ),
+            Cycle { object } => write!(f, "The children of object {} form a cycle", object),
+            WrongParent {
+                object,
+                parent,
+                found,
+            } => write!(
+                f,
+                "Object {} is a child of {}, but has parent {}",
+                object, parent, found
+            ),
+            Orphan { object, parent } => write!(
+                f,
+                "Object {} has parent {}, but is not among its children",
+                object, parent
+            ),
+        }
+    }
+}
+
+// The iterator returned by ZObjectTable::children. It stops at a link past
+// the last object, and after object_count steps, so a damaged tree can't
+// keep it going forever.
+pub struct ZChildren<'a, M>
+where
+    M: Memory,
+{
+    table: &'a ZObjectTable<M>,
+    next: u16,
+    remaining: u16,
+}
+
+impl<'a, M> Iterator for ZChildren<'a, M>
+where
+    M: Memory,
+{
+    type Item = (ObjectNumber, ZObject);
+
+    fn next(&mut self) -> Option<(ObjectNumber, ZObject)> {
+        if self.next == 0 || self.next > self.table.count || self.remaining == 0 {
+            return None;
+        }
+        let num = ObjectNumber(self.next);
+        let object = self.table.entry(self.next);
+        self.next = self.table.link(object, SIBLING);
+        self.remaining -= 1;
+        Some((num, object))
+    }
+}
+
+// A property from an object's property table. (ZSpec 12.4)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ZProperty {
+    pub number: u8,
+    // The length of the data, in bytes.
+    pub len: u16,
+    pub data: ByteAddress,
+}
+
+// The properties of an object, in the order they are stored, which is
+// descending by number. (ZSpec 12.4)
+pub struct ZPropertyIter<M>
+where
+    M: Memory,
+{
+    memory: Handle<M>,
+    version: ZVersion,
+    // The size byte of the next property, or None once the list has ended.
+    next: Option<ByteAddress>,
+}
+
+impl<M> ZPropertyIter<M>
+where
+    M: Memory,
+{
+    fn read_property(&self, at: ByteAddress) -> Option<ZProperty> {
+        let memory = self.memory.borrow();
+        let size = memory.read_byte(at);
+        // The list ends with a size byte of zero.
+        if size == 0 {
+            return None;
+        }
+        let number_mask = (1 << self.version.property_number_bits()) - 1;
+        let number = size & number_mask;
+
+        // In V1-3, a property starts with a single size byte, holding
+        // 32 * (length - 1) + number. (ZSpec 12.4.1)
+        if self.version <= ZVersion::V3 {
+            return Some(ZProperty {
+                number,
+                len: u16::from(size >> self.version.property_number_bits()) + 1,
+                data: at.inc_by(1),
+            });
+        }
+
+        // In V4+, the number has 6 bits. If the top bit is set, the length is
+        // in the low 6 bits of a second size byte, where 0 means 64.
+        // Otherwise bit 6 gives a length of 2 rather than 1. (ZSpec 12.4.2)
+        if size & 0b1000_0000 != 0 {
+            let len = match memory.read_byte(at.inc_by(1)) & 0b0011_1111 {
+                0 => 64,
+                len => u16::from(len),
+            };
+            Some(ZProperty {
+                number,
+                len,
+                data: at.inc_by(2),
+            })
+        } else {
+            Some(ZProperty {
+                number,
+                len: if size & 0b0100_0000 != 0 { 2 } else { 1 },
+                data: at.inc_by(1),
+            })
+        }
+    }
+}
+
+impl<M> Iterator for ZPropertyIter<M>
+where
+    M: Memory,
+{
+    type Item = ZProperty;
+
+    fn next(&mut self) -> Option<ZProperty> {
+        let property = self.read_property(self.next?);
+        self.next = property.map(|p| p.data.inc_by(p.len));
+        property
+    }
+}
+
+impl ZObject {
+    // The address of the object's entry in the table.
+    pub fn address(self) -> ByteAddress {
+        self.0
+    }
+}
+
 impl Object for ZObject {}
 
 pub struct ZObjectTable<M>
@@ -59,31 +268,348 @@ where
     memory: Handle<M>,
 
     version: ZVersion,
+    text: Handle<ZTextDecoder<M>>,
     defaults_offset: ByteAddress,
     tree_offset: ByteAddress,
+    // Inferred when the table is created, since the story doesn't say.
+    count: u16,
 }
 
 impl<M> ZObjectTable<M>
 where
     M: Memory,
 {
-    fn new<H>(header: &H, memory: &Handle<M>) -> ZObjectTable<M>
+    pub fn new<H>(header: &H, memory: &Handle<M>, text: &Handle<ZTextDecoder<M>>) -> ZObjectTable<M>
     where
         H: Header,
     {
+        let version = header.version_number();
         let base = header.otable_location();
-        // This depends on version number!!!!  VNUM_DEPEND
-        let tree = base.inc_by(31 * 2); // 31 words in V1-3 only. Fix for V4+.
-        ZObjectTable {
+        let tree = base.inc_by(version.property_defaults_count() * 2);
+        let mut table = ZObjectTable {
             memory: memory.clone(),
-            version: header.version_number(),
+            version,
 
+            text: text.clone(),
             defaults_offset: base,
             tree_offset: tree,
+            count: 0,
+        };
+        table.count = table.infer_object_count();
+        table
+    }
+
+    pub fn object_count(&self) -> u16 {
+        self.count
+    }
+
+    // Every object, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (ObjectNumber, ZObject)> + '_ {
+        (1..=self.count).map(move |num| (ObjectNumber(num), self.entry(num)))
+    }
+
+    // The children of an object, following the sibling links from its
+    // first child.
+    pub fn children(&self, o: ZObject) -> ZChildren<'_, M> {
+        ZChildren {
+            table: self,
+            next: self.link(o, CHILD),
+            remaining: self.count,
+        }
+    }
+
+    // In V1-3, object entries are 9 bytes: 4 bytes of attributes, then parent,
+    // sibling, and child as bytes, then the property table address.
+    // In V4+, they are 14 bytes: 6 bytes of attributes, then parent, sibling,
+    // and child as words, then the property table address. (ZSpec 12.3)
+    fn is_wide(&self) -> bool {
+        self.version.max_objects() > 255
+    }
+
+    // Offset of parent, sibling, or child within an entry.
+    fn relative_offset(&self, relative: u16) -> u16 {
+        if self.is_wide() {
+            6 + relative * 2
+        } else {
+            4 + relative
+        }
+    }
+
+    fn ptable_offset(&self) -> u16 {
+        if self.is_wide() {
+            12
+        } else {
+            7
+        }
+    }
+
+    fn read_relative(&self, o: ZObject, relative: u16) -> Result<ObjectNumber> {
+        let at = o.0.inc_by(self.relative_offset(relative));
+        let raw_number = if self.is_wide() {
+            self.memory.borrow().read_word(at)
+        } else {
+            u16::from(self.memory.borrow().read_byte(at))
+        };
+        Ok(ObjectNumber(raw_number))
+    }
+
+    fn write_relative(&self, o: ZObject, relative: u16, num: ObjectNumber) -> Result<()> {
+        let at = o.0.inc_by(self.relative_offset(relative));
+        if self.is_wide() {
+            self.memory.borrow_mut().write_word(at, num.0)
+        } else {
+            self.memory.borrow_mut().write_byte(at, num.0 as u8)
+        }
+    }
+
+    fn property_table(&self, o: ZObject) -> ByteAddress {
+        ByteAddress::from_raw(
+            self.memory
+                .borrow()
+                .read_word(o.0.inc_by(self.ptable_offset())),
+        )
+    }
+
+    // The properties follow the short name, whose length in words is the
+    // first byte of the property table. (ZSpec 12.4)
+    pub fn properties(&self, o: ZObject) -> ZPropertyIter<M> {
+        let ptable = self.property_table(o);
+        let name_words = self.memory.borrow().read_byte(ptable);
+        ZPropertyIter {
+            memory: self.memory.clone(),
+            version: self.version,
+            next: Some(ptable.inc_by(1 + 2 * u16::from(name_words))),
+        }
+    }
+
+    // The short name of an object, by number. Object 0 has no name.
+    pub fn short_name(&self, num: ObjectNumber) -> Result<String> {
+        let object = self.get_object(num)?;
+        self.get_object_short_name(object)
+    }
+
+    pub fn find_property(&self, o: ZObject, p: u8) -> Option<ZProperty> {
+        self.properties(o).find(|prop| prop.number == p)
+    }
+
+    // The number of the property after p, or of the first property when p
+    // is 0. It is 0 after the last property. (ZSpec 15, get_next_prop)
+    pub fn next_property(&self, o: ZObject, p: u8) -> Result<u8> {
+        let mut properties = self.properties(o);
+        if p != 0 && properties.by_ref().find(|prop| prop.number == p).is_none() {
+            return Err(ZErr::MissingProperty(p));
+        }
+        Ok(properties.next().map_or(0, |prop| prop.number))
+    }
+
+    // The length of a property, given the address of its data, from the
+    // size byte before it. In V4+, that may be the second of two size bytes,
+    // which has its top bit set. Address 0 has length 0, so that
+    // get_prop_len of a missing property's address is 0. (ZSpec 15, get_prop_len)
+    pub fn property_length(&self, data: u16) -> u16 {
+        if data == 0 {
+            return 0;
+        }
+        let size = self
+            .memory
+            .borrow()
+            .read_byte(ByteAddress::from_raw(data - 1));
+        if self.version <= ZVersion::V3 {
+            u16::from(size >> self.version.property_number_bits()) + 1
+        } else if size & 0b1000_0000 != 0 {
+            match size & 0b0011_1111 {
+                0 => 64,
+                len => u16::from(len),
+            }
+        } else if size & 0b0100_0000 != 0 {
+            2
+        } else {
+            1
+        }
+    }
+
+    // Detach an object from its parent, taking its children with it.
+    // (ZSpec 15, remove_obj)
+    pub fn remove_object(&self, num: ObjectNumber) -> Result<()> {
+        let object = self.get_object(num)?;
+        let parent = self.get_object_parent(object)?;
+        if parent.0 == 0 {
+            return Ok(());
         }
+        let parent_object = self.get_object(parent)?;
+        let sibling = self.get_object_sibling(object)?;
+        if self.get_object_child(parent_object)? == num {
+            self.set_object_child(parent_object, sibling)?;
+        } else if let Some((_, previous)) = self
+            .children(parent_object)
+            .find(|&(_, child)| self.link(child, SIBLING) == num.0)
+        {
+            self.set_object_sibling(previous, sibling)?;
+        }
+        self.set_object_parent(object, ObjectNumber(0))?;
+        self.set_object_sibling(object, ObjectNumber(0))
+    }
+
+    // Make an object the first child of the destination. (ZSpec 15, insert_obj)
+    pub fn insert_object(&self, num: ObjectNumber, destination: ObjectNumber) -> Result<()> {
+        self.remove_object(num)?;
+        let object = self.get_object(num)?;
+        let dest = self.get_object(destination)?;
+        self.set_object_sibling(object, self.get_object_child(dest)?)?;
+        self.set_object_child(dest, num)?;
+        self.set_object_parent(object, destination)
+    }
+
+    // Objects are numbered from 1.
+    fn entry(&self, num: u16) -> ZObject {
+        ZObject(
+            self.tree_offset
+                .inc_by((num - 1) * self.version.object_entry_size()),
+        )
+    }
+
+    fn link(&self, o: ZObject, relative: u16) -> u16 {
+        self.read_relative(o, relative).map_or(0, |num| num.0)
+    }
+
+    // The table doesn't say how many objects there are, but the property
+    // tables come after the object entries, so the entries end at the lowest
+    // property table address. (ZSpec 12.3)
+    fn infer_object_count(&self) -> u16 {
+        let entry_size = usize::from(self.version.object_entry_size());
+        let tree = ZOffset::from(self.tree_offset).value();
+        let mut end = usize::from(u16::MAX);
+        let mut count = 0;
+        while count < self.version.max_objects() {
+            if tree + (usize::from(count) + 1) * entry_size > end {
+                break;
+            }
+            count += 1;
+            let ptable = self.property_table(self.entry(count));
+            end = end.min(ZOffset::from(ptable).value());
+        }
+        count
+    }
+
+    // Check the links between objects, returning any problems found. An
+    // empty list means that the tree is sound.
+    pub fn validate(&self) -> Vec<TreeProblem> {
+        let count = self.count;
+        let mut problems = Vec::new();
+        // (parent, child) for each object found among a parent's children.
+        let mut children = HashSet::new();
+
+        for (num, object) in self.iter() {
+            let num = num.value();
+            for &(relative, link) in &[(PARENT, "parent"), (SIBLING, "sibling"), (CHILD, "child")] {
+                let target = self.link(object, relative);
+                if target > count {
+                    problems.push(TreeProblem::OutOfRange {
+                        object: num,
+                        link,
+                        target,
+                    });
+                }
+            }
+
+            let mut seen = HashSet::new();
+            let mut child = self.link(object, CHILD);
+            while child != 0 && child <= count {
+                if !seen.insert(child) {
+                    problems.push(TreeProblem::Cycle { object: num });
+                    break;
+                }
+                children.insert((num, child));
+                let found = self.link(self.entry(child), PARENT);
+                if found != num {
+                    problems.push(TreeProblem::WrongParent {
+                        object: child,
+                        parent: num,
+                        found,
+                    });
+                }
+                child = self.link(self.entry(child), SIBLING);
+            }
+        }
+
+        for (num, object) in self.iter() {
+            let num = num.value();
+            let parent = self.link(object, PARENT);
+            if parent != 0 && parent <= count && !children.contains(&(parent, num)) {
+                problems.push(TreeProblem::Orphan {
+                    object: num,
+                    parent,
+                });
+            }
+        }
+        problems
+    }
+
+    // A listing of every object, with its name, attributes, links, and
+    // properties, laid out like the object section of infodump's output.
+    pub fn report(&self) -> Result<String> {
+        let mut report = format!("    **** Objects ****\n\n  Object count = {}\n", self.count);
+        for (num, object) in self.iter() {
+            report.push_str(&self.describe(num, object)?);
+        }
+        Ok(report)
+    }
+
+    fn describe(&self, num: ObjectNumber, object: ZObject) -> Result<String> {
+        let mut attributes = Vec::new();
+        for a in 0..self.version.attribute_count() {
+            if self.get_object_attribute(object, a)? != 0 {
+                attributes.push(a.to_string());
+            }
+        }
+        let attributes = if attributes.is_empty() {
+            "None".to_string()
+        } else {
+            attributes.join(", ")
+        };
+
+        let mut text = format!("\n{:4}. Attributes: {}\n", num.value(), attributes);
+        text.push_str(&format!(
+            "      Parent object: {:3}  Sibling object: {:3}  Child object: {:3}\n",
+            self.link(object, PARENT),
+            self.link(object, SIBLING),
+            self.link(object, CHILD),
+        ));
+        text.push_str(&format!(
+            "      Property address: {:04x}\n",
+            ZOffset::from(self.property_table(object)).value()
+        ));
+        text.push_str(&format!(
+            "          Description: \"{}\"\n           Properties:\n",
+            self.get_object_short_name(object)?
+        ));
+        let memory = self.memory.borrow();
+        for prop in self.properties(object) {
+            let data = (0..prop.len)
+                .map(|idx| format!("{:02x}", memory.read_byte(prop.data.inc_by(idx))))
+                .collect::<Vec<_>>();
+            text.push_str(&format!(
+                "               [{:2}] {}\n",
+                prop.number,
+                data.join(" ")
+            ));
+        }
+        Ok(text)
+    }
+
+    // Attribute 0 is the top bit of the first byte. (ZSpec 12.3.1)
+    fn attribute_location(&self, o: ZObject, a: u8) -> Result<(ByteAddress, u8)> {
+        if a >= self.version.attribute_count() {
+            return Err(ZErr::BadAttribute(a));
+        }
+        Ok((o.0.inc_by(u16::from(a / 8)), 0b1000_0000 >> (a % 8)))
     }
 }
 
+const PARENT: u16 = 0;
+const SIBLING: u16 = 1;
+const CHILD: u16 = 2;
+
 impl<M> ObjectTable for ZObjectTable<M>
 where
     M: Memory,
@@ -91,89 +617,526 @@ where
     type O = ZObject;
 
     fn get_object(&self, num: ObjectNumber) -> Result<ZObject> {
-        // TODO: range check
-        // VNUM DEPEND
         // Objects are 1-indexed. (Zero is the null object.)
         if num.0 == 0 {
             Err(ZErr::NullObject)
+        } else if num.0 > self.count {
+            Err(ZErr::BadObject(num.0))
         } else {
-            Ok(ZObject(self.tree_offset.inc_by((num.0 - 1) * 9)))
+            Ok(self.entry(num.0))
         }
     }
 
     // Consider returning Option here instead of an ObjectNumber(0).
     fn get_object_child(&self, o: ZObject) -> Result<ObjectNumber> {
-        // VNUM DEPEND
-        let raw_number = self.memory.borrow().read_byte(ZOffset::from(o.0.inc_by(6)));
-        Ok(ObjectNumber(u16::from(raw_number)))
+        self.read_relative(o, CHILD)
     }
 
     fn get_object_sibling(&self, o: ZObject) -> Result<ObjectNumber> {
-        // VNUM DEPEND
-        let raw_number = self.memory.borrow().read_byte(ZOffset::from(o.0.inc_by(5)));
-        Ok(ObjectNumber(u16::from(raw_number)))
+        self.read_relative(o, SIBLING)
     }
     fn get_object_parent(&self, o: ZObject) -> Result<ObjectNumber> {
-        // VNUM DEPEND
-        let raw_number = self.memory.borrow().read_byte(ZOffset::from(o.0.inc_by(4)));
-        Ok(ObjectNumber(u16::from(raw_number)))
+        self.read_relative(o, PARENT)
     }
 
     fn set_object_child(&self, o: ZObject, c: ObjectNumber) -> Result<()> {
-        // VNUM DEPEND
-        self.memory
-            .borrow_mut()
-            .write_byte(o.0.inc_by(6), c.0 as u8)
+        self.write_relative(o, CHILD, c)
     }
     fn set_object_sibling(&self, o: ZObject, s: ObjectNumber) -> Result<()> {
-        // VNUM DEPEND
-        self.memory
-            .borrow_mut()
-            .write_byte(o.0.inc_by(5), s.0 as u8)
+        self.write_relative(o, SIBLING, s)
     }
     fn set_object_parent(&self, o: ZObject, p: ObjectNumber) -> Result<()> {
-        // VNUM DEPEND
-        self.memory
-            .borrow_mut()
-            .write_byte(o.0.inc_by(4), p.0 as u8)
+        self.write_relative(o, PARENT, p)
     }
 
     fn get_object_attribute(&self, o: ZObject, a: u8) -> Result<u8> {
-        // VNUM DEPEND
-        // range check.
-        let ba = o.0.inc_by(if a > 15 { 1 } else { 0 });
-        let bitnum = a % 16;
-        let word = self.memory.borrow().read_word(ba);
-        Ok(((word >> (15 - bitnum)) & 0b1) as u8)
+        let (ba, mask) = self.attribute_location(o, a)?;
+        let byte = self.memory.borrow().read_byte(ba);
+        Ok(if byte & mask == 0 { 0 } else { 1 })
     }
 
     fn set_object_attribute(&self, o: ZObject, a: u8, v: u8) -> Result<()> {
-        // VNUM DEPEND
-        // range check
-        let ba = o.0.inc_by(if a > 15 { 1 } else { 0 });
-        let word = self.memory.borrow().read_word(ba);
-        let bitnum = a % 16;
-        let the_bit = 1 << (15 - bitnum);
-        let new_word = if v == 0 {
-            word & !the_bit
-        } else {
-            word | the_bit
-        };
+        let (ba, mask) = self.attribute_location(o, a)?;
+        let byte = self.memory.borrow().read_byte(ba);
+        let new_byte = if v == 0 { byte & !mask } else { byte | mask };
 
-        self.memory.borrow_mut().write_word(ba, new_word)
+        self.memory.borrow_mut().write_byte(ba, new_byte)
     }
 
+    // A property of length 1 is a byte. Longer ones are read as their first
+    // word. (ZSpec 15, get_prop)
     fn get_object_property(&self, o: ZObject, p: u8) -> Result<u16> // Is this right? Are all properties u16?
     {
-        panic!("Unimplemented")
+        match self.find_property(o, p) {
+            None => self.get_default_property(p),
+            Some(prop) if prop.len == 1 => Ok(u16::from(self.memory.borrow().read_byte(prop.data))),
+            Some(prop) => Ok(self.memory.borrow().read_word(prop.data)),
+        }
     }
 
+    // The property must exist. (ZSpec 15, put_prop)
     fn set_object_property(&self, o: ZObject, p: u8, v: u16) -> Result<()> {
-        panic!("Unimplemented")
+        match self.find_property(o, p) {
+            None => Err(ZErr::MissingProperty(p)),
+            Some(prop) if prop.len == 1 => self.memory.borrow_mut().write_byte(prop.data, v as u8),
+            Some(prop) => self.memory.borrow_mut().write_word(prop.data, v),
+        }
     }
 
-    fn get_default_property(&self, p: u8) -> Result<u16> // Is this right? Are all properties u16?
-    {
-        panic!("Unimplemented")
+    // The object table starts with a word for each property number, from 1,
+    // giving the value of properties an object doesn't have. (ZSpec 12.2)
+    fn get_default_property(&self, p: u8) -> Result<u16> {
+        if p == 0 || u16::from(p) > self.version.property_defaults_count() {
+            return Err(ZErr::BadProperty(p));
+        }
+        Ok(self
+            .memory
+            .borrow()
+            .read_word(self.defaults_offset.inc_by(2 * (u16::from(p) - 1))))
+    }
+
+    // The short name is at the start of the property table, preceded by its
+    // length in words. (ZSpec 12.4)
+    fn get_object_short_name(&self, o: ZObject) -> Result<String> {
+        let ptable = self.property_table(o);
+        if self.memory.borrow().read_byte(ptable) == 0 {
+            return Ok(String::new());
+        }
+        self.text.borrow_mut().decode_from_memory(ptable.inc_by(1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::fixtures::{TestHeader, TestMemory};
+    use super::super::handle::new_handle;
+    use super::*;
+
+    // A table at 0x40, with the objects' property tables at `ptable`.
+    fn table_with_ptable(
+        version: ZVersion,
+        ptable: u16,
+    ) -> (Handle<TestMemory>, ZObjectTable<TestMemory>) {
+        let mut memory = TestMemory::new(0x200);
+        let size = version.object_entry_size();
+        let tree = 0x40 + 2 * version.property_defaults_count();
+        let mut entry = tree;
+        while entry + size <= ptable {
+            memory
+                .write_word(ByteAddress::from_raw(entry + size - 2), ptable)
+                .unwrap();
+            entry += size;
+        }
+        let memory = new_handle(memory);
+        let mut header = TestHeader::new(version);
+        header.otable = ByteAddress::from_raw(0x40);
+        let table = ZObjectTable::new(
+            &header,
+            &memory,
+            &new_handle(ZTextDecoder::new(&header, &memory)),
+        );
+        (memory, table)
+    }
+
+    fn new_table(version: ZVersion) -> (Handle<TestMemory>, ZObjectTable<TestMemory>) {
+        table_with_ptable(version, 0x180)
+    }
+
+    #[test]
+    fn test_v3_layout() {
+        let (memory, table) = new_table(ZVersion::V3);
+        // 31 default words, then 9-byte entries.
+        let obj = table.get_object(ObjectNumber(2)).unwrap();
+        assert_eq!(ZObject(ByteAddress::from_raw(0x40 + 62 + 9)), obj);
+
+        table.set_object_parent(obj, ObjectNumber(3)).unwrap();
+        table.set_object_sibling(obj, ObjectNumber(4)).unwrap();
+        table.set_object_child(obj, ObjectNumber(5)).unwrap();
+        assert_eq!(&[3, 4, 5], &memory.borrow().bytes[0x8b..0x8e]);
+        assert_eq!(4, table.get_object_sibling(obj).unwrap().0);
+    }
+
+    #[test]
+    fn test_v4_layout() {
+        let (memory, table) = new_table(ZVersion::V4);
+        // 63 default words, then 14-byte entries.
+        let obj = table.get_object(ObjectNumber(2)).unwrap();
+        assert_eq!(ZObject(ByteAddress::from_raw(0x40 + 126 + 14)), obj);
+
+        table.set_object_parent(obj, ObjectNumber(0x123)).unwrap();
+        table.set_object_sibling(obj, ObjectNumber(4)).unwrap();
+        table.set_object_child(obj, ObjectNumber(5)).unwrap();
+        assert_eq!(
+            &[0x01, 0x23, 0x00, 0x04, 0x00, 0x05],
+            &memory.borrow().bytes[0xd2..0xd8]
+        );
+        assert_eq!(0x123, table.get_object_parent(obj).unwrap().0);
+        assert_eq!(5, table.get_object_child(obj).unwrap().0);
+    }
+
+    #[test]
+    fn test_attributes() {
+        let (memory, table) = new_table(ZVersion::V4);
+        let obj = table.get_object(ObjectNumber(1)).unwrap();
+
+        table.set_object_attribute(obj, 0, 1).unwrap();
+        table.set_object_attribute(obj, 17, 1).unwrap();
+        table.set_object_attribute(obj, 47, 1).unwrap();
+        assert_eq!(
+            &[0x80, 0x00, 0x40, 0x00, 0x00, 0x01],
+            &memory.borrow().bytes[0xbe..0xc4]
+        );
+        assert_eq!(1, table.get_object_attribute(obj, 17).unwrap());
+        assert_eq!(0, table.get_object_attribute(obj, 16).unwrap());
+
+        table.set_object_attribute(obj, 17, 0).unwrap();
+        assert_eq!(0, table.get_object_attribute(obj, 17).unwrap());
+
+        // Only 32 attributes in V1-3.
+        let (_, table) = new_table(ZVersion::V3);
+        let obj = table.get_object(ObjectNumber(1)).unwrap();
+        assert!(table.get_object_attribute(obj, 32).is_err());
+    }
+
+    #[test]
+    fn test_attribute_range() {
+        for &(version, count) in &[(ZVersion::V3, 32), (ZVersion::V5, 48)] {
+            let (memory, table) = new_table(version);
+            let obj = table.get_object(ObjectNumber(1)).unwrap();
+            let before = memory.borrow().bytes.clone();
+
+            table.set_object_attribute(obj, count - 1, 1).unwrap();
+            assert_eq!(1, table.get_object_attribute(obj, count - 1).unwrap());
+            table.set_object_attribute(obj, count - 1, 0).unwrap();
+
+            for &a in &[count, count + 8, 255] {
+                match table.set_object_attribute(obj, a, 1) {
+                    Err(ZErr::BadAttribute(bad)) => assert_eq!(a, bad),
+                    _ => panic!("Missing error for {} in {:?}", a, version),
+                }
+                assert!(table.get_object_attribute(obj, a).is_err());
+            }
+            // The parent, sibling and child after the attributes are untouched.
+            assert_eq!(before, memory.borrow().bytes);
+        }
+    }
+
+    // Object 1's property table at 0x100: a one word name, then properties
+    // 18 (two bytes), 7 (one byte) and 3 (eight bytes).
+    fn add_v3_properties(memory: &Handle<TestMemory>, table: &ZObjectTable<TestMemory>) {
+        let obj = table.get_object(ObjectNumber(1)).unwrap();
+        let mut memory = memory.borrow_mut();
+        memory.write_word(obj.0.inc_by(7), 0x100).unwrap();
+        let ptable: &[u8] = &[
+            1, 0x80, 0x00, // Name.
+            0x32, 0x12, 0x34, // 32 * 1 + 18
+            0x07, 0x56, // 32 * 0 + 7
+            0xe3, 1, 2, 3, 4, 5, 6, 7, 8, // 32 * 7 + 3
+            0,
+        ];
+        memory.bytes[0x100..0x100 + ptable.len()].copy_from_slice(ptable);
+    }
+
+    #[test]
+    fn test_v3_properties() {
+        let (memory, table) = new_table(ZVersion::V3);
+        add_v3_properties(&memory, &table);
+        let obj = table.get_object(ObjectNumber(1)).unwrap();
+
+        let properties: Vec<ZProperty> = table.properties(obj).collect();
+        assert_eq!(
+            vec![
+                ZProperty {
+                    number: 18,
+                    len: 2,
+                    data: ByteAddress::from_raw(0x104)
+                },
+                ZProperty {
+                    number: 7,
+                    len: 1,
+                    data: ByteAddress::from_raw(0x107)
+                },
+                ZProperty {
+                    number: 3,
+                    len: 8,
+                    data: ByteAddress::from_raw(0x109)
+                },
+            ],
+            properties
+        );
+        assert_eq!(None, table.find_property(obj, 4));
+
+        assert_eq!(0x1234, table.get_object_property(obj, 18).unwrap());
+        assert_eq!(0x56, table.get_object_property(obj, 7).unwrap());
+        assert_eq!(0x0102, table.get_object_property(obj, 3).unwrap());
+
+        table.set_object_property(obj, 7, 0x1ff).unwrap();
+        table.set_object_property(obj, 18, 0xabcd).unwrap();
+        assert_eq!(0xff, table.get_object_property(obj, 7).unwrap());
+        assert_eq!(0xabcd, table.get_object_property(obj, 18).unwrap());
+        match table.set_object_property(obj, 4, 0) {
+            Err(ZErr::MissingProperty(4)) => (),
+            _ => panic!("Missing error"),
+        }
+    }
+
+    #[test]
+    fn test_v4_properties() {
+        let (memory, table) = new_table(ZVersion::V5);
+        let obj = table.get_object(ObjectNumber(1)).unwrap();
+        memory
+            .borrow_mut()
+            .write_word(obj.0.inc_by(12), 0x100)
+            .unwrap();
+        let mut ptable = vec![
+            0,    // No name.
+            0x72, // Two bytes, property 50.
+            0x12, 0x34, 0x21, // One byte, property 33.
+            0x56, 0x8a, // Property 10, with a second size byte: 3 bytes.
+            0x83, 1, 2, 3, 0x82, // Property 2: length 0 means 64.
+            0x80,
+        ];
+        ptable.extend_from_slice(&[9; 64]);
+        ptable.push(0);
+        memory.borrow_mut().bytes[0x100..0x100 + ptable.len()].copy_from_slice(&ptable);
+
+        let properties: Vec<(u8, u16, ByteAddress)> = table
+            .properties(obj)
+            .map(|p| (p.number, p.len, p.data))
+            .collect();
+        assert_eq!(
+            vec![
+                (50, 2, ByteAddress::from_raw(0x102)),
+                (33, 1, ByteAddress::from_raw(0x105)),
+                (10, 3, ByteAddress::from_raw(0x108)),
+                (2, 64, ByteAddress::from_raw(0x10d)),
+            ],
+            properties
+        );
+        assert_eq!(0x1234, table.get_object_property(obj, 50).unwrap());
+        assert_eq!(0x56, table.get_object_property(obj, 33).unwrap());
+        assert_eq!(0x0102, table.get_object_property(obj, 10).unwrap());
+    }
+
+    #[test]
+    fn test_default_properties() {
+        let (memory, table) = new_table(ZVersion::V3);
+        add_v3_properties(&memory, &table);
+        memory
+            .borrow_mut()
+            .write_word(ByteAddress::from_raw(0x40), 0x1111)
+            .unwrap();
+        memory
+            .borrow_mut()
+            .write_word(ByteAddress::from_raw(0x40 + 2 * 3), 0x4444)
+            .unwrap();
+        memory
+            .borrow_mut()
+            .write_word(ByteAddress::from_raw(0x40 + 2 * 30), 0x3131)
+            .unwrap();
+
+        assert_eq!(0x1111, table.get_default_property(1).unwrap());
+        assert_eq!(0x3131, table.get_default_property(31).unwrap());
+        // Properties the object lacks come from the defaults.
+        let obj = table.get_object(ObjectNumber(1)).unwrap();
+        assert_eq!(0x4444, table.get_object_property(obj, 4).unwrap());
+        assert_eq!(0x0102, table.get_object_property(obj, 3).unwrap());
+
+        for &p in &[0, 32] {
+            match table.get_default_property(p) {
+                Err(ZErr::BadProperty(bad)) => assert_eq!(p, bad),
+                _ => panic!("Missing error for {}", p),
+            }
+        }
+
+        // V4+ has 63 defaults.
+        let (memory, table) = new_table(ZVersion::V5);
+        memory
+            .borrow_mut()
+            .write_word(ByteAddress::from_raw(0x40 + 2 * 62), 0x6363)
+            .unwrap();
+        assert_eq!(0x6363, table.get_default_property(63).unwrap());
+        assert!(table.get_default_property(64).is_err());
+    }
+
+    #[test]
+    fn test_short_name() {
+        let (memory, table) = new_table(ZVersion::V3);
+        add_v3_properties(&memory, &table);
+        // The name is the single word 0x8000: three spaces ("   "), with the
+        // end bit set.
+        assert_eq!("   ", table.short_name(ObjectNumber(1)).unwrap());
+        match table.short_name(ObjectNumber(0)) {
+            Err(ZErr::NullObject) => (),
+            _ => panic!("Missing error"),
+        }
+
+        // Object 2 has an empty name.
+        let obj = table.get_object(ObjectNumber(2)).unwrap();
+        memory
+            .borrow_mut()
+            .write_word(obj.0.inc_by(7), 0x180)
+            .unwrap();
+        assert_eq!("", table.short_name(ObjectNumber(2)).unwrap());
+    }
+
+    #[test]
+    fn test_describe() {
+        let (memory, table) = new_table(ZVersion::V3);
+        add_v3_properties(&memory, &table);
+        link(&table, 1, &[2, 3]);
+        let obj = table.get_object(ObjectNumber(1)).unwrap();
+        table.set_object_attribute(obj, 3, 1).unwrap();
+        table.set_object_attribute(obj, 31, 1).unwrap();
+
+        assert_eq!(
+            "\n   1. Attributes: 3, 31\n\
+             \x20     Parent object:   0  Sibling object:   0  Child object:   2\n\
+             \x20     Property address: 0100\n\
+             \x20         Description: \"   \"\n\
+             \x20          Properties:\n\
+             \x20              [18] 12 34\n\
+             \x20              [ 7] 56\n\
+             \x20              [ 3] 01 02 03 04 05 06 07 08\n",
+            table.describe(ObjectNumber(1), obj).unwrap()
+        );
+        let obj = table.get_object(ObjectNumber(3)).unwrap();
+        assert_eq!(
+            "\n   3. Attributes: None\n\
+             \x20     Parent object:   1  Sibling object:   0  Child object:   0\n\
+             \x20     Property address: 0180\n\
+             \x20         Description: \"\"\n\
+             \x20          Properties:\n",
+            table.describe(ObjectNumber(3), obj).unwrap()
+        );
+        // Object 1's property table overlaps the later entries, so list a
+        // table without it.
+        let (_, table) = new_table(ZVersion::V3);
+        assert!(table
+            .report()
+            .unwrap()
+            .starts_with("    **** Objects ****\n\n  Object count = 28\n\n   1. "));
+    }
+
+    // The tree starts at 0x7e, after the V3 defaults at 0x40, and the
+    // property tables at 0xea leave room for 12 objects.
+    fn new_tree() -> (Handle<TestMemory>, ZObjectTable<TestMemory>) {
+        table_with_ptable(ZVersion::V3, 0xea)
+    }
+
+    fn link(table: &ZObjectTable<TestMemory>, parent: u16, children: &[u16]) {
+        let parent_obj = table.get_object(ObjectNumber(parent)).unwrap();
+        table
+            .set_object_child(parent_obj, ObjectNumber(children[0]))
+            .unwrap();
+        for (idx, &child) in children.iter().enumerate() {
+            let obj = table.get_object(ObjectNumber(child)).unwrap();
+            table.set_object_parent(obj, ObjectNumber(parent)).unwrap();
+            let sibling = children.get(idx + 1).cloned().unwrap_or(0);
+            table
+                .set_object_sibling(obj, ObjectNumber(sibling))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let (_, table) = new_tree();
+        assert_eq!(12, table.object_count());
+        link(&table, 1, &[2, 3, 4]);
+        link(&table, 3, &[5]);
+        assert_eq!(Vec::<TreeProblem>::new(), table.validate());
+
+        // Object 6 claims 1 as its parent, 4 claims 7 as its sibling, though
+        // 7 has no parent, and 5 has a child past the last object.
+        let obj = table.get_object(ObjectNumber(6)).unwrap();
+        table.set_object_parent(obj, ObjectNumber(1)).unwrap();
+        let obj = table.get_object(ObjectNumber(4)).unwrap();
+        table.set_object_sibling(obj, ObjectNumber(7)).unwrap();
+        let obj = table.get_object(ObjectNumber(5)).unwrap();
+        table.set_object_child(obj, ObjectNumber(13)).unwrap();
+        assert_eq!(
+            vec![
+                TreeProblem::WrongParent {
+                    object: 7,
+                    parent: 1,
+                    found: 0
+                },
+                TreeProblem::OutOfRange {
+                    object: 5,
+                    link: "child",
+                    target: 13
+                },
+                TreeProblem::Orphan {
+                    object: 6,
+                    parent: 1
+                },
+            ],
+            table.validate()
+        );
+    }
+
+    #[test]
+    fn test_validate_cycle() {
+        let (_, table) = new_tree();
+        link(&table, 1, &[2, 3]);
+        let obj = table.get_object(ObjectNumber(3)).unwrap();
+        table.set_object_sibling(obj, ObjectNumber(2)).unwrap();
+        assert_eq!(vec![TreeProblem::Cycle { object: 1 }], table.validate());
+    }
+
+    #[test]
+    fn test_object_count() {
+        // 0x180 - 0x7e leaves room for 28 V3 entries, and 0x180 - 0xbe for
+        // 13 V4 ones.
+        let (_, table) = new_table(ZVersion::V3);
+        assert_eq!(28, table.object_count());
+        assert!(table.get_object(ObjectNumber(28)).is_ok());
+        match table.get_object(ObjectNumber(29)) {
+            Err(ZErr::BadObject(29)) => (),
+            _ => panic!("Missing error"),
+        }
+        let (_, table) = new_table(ZVersion::V4);
+        assert_eq!(13, table.object_count());
+
+        // A lower property table, for a later object, ends the entries after
+        // that object.
+        let (memory, _) = new_table(ZVersion::V3);
+        memory
+            .borrow_mut()
+            .write_word(ByteAddress::from_raw(0x7e + 9 * 4 + 7), 0xa0)
+            .unwrap();
+        let mut header = TestHeader::new(ZVersion::V3);
+        header.otable = ByteAddress::from_raw(0x40);
+        assert_eq!(
+            5,
+            ZObjectTable::new(
+                &header,
+                &memory,
+                &new_handle(ZTextDecoder::new(&header, &memory))
+            )
+            .object_count()
+        );
+    }
+
+    #[test]
+    fn test_iteration() {
+        let (_, table) = new_tree();
+        let numbers: Vec<u16> = table.iter().map(|(num, _)| num.value()).collect();
+        assert_eq!((1..=12).collect::<Vec<_>>(), numbers);
+        let (num, obj) = table.iter().nth(3).unwrap();
+        assert_eq!(table.get_object(num).unwrap(), obj);
+
+        link(&table, 1, &[4, 2, 7]);
+        let parent = table.get_object(ObjectNumber(1)).unwrap();
+        let children: Vec<u16> = table.children(parent).map(|(num, _)| num.value()).collect();
+        assert_eq!(vec![4, 2, 7], children);
+        let leaf = table.get_object(ObjectNumber(4)).unwrap();
+        assert_eq!(0, table.children(leaf).count());
+
+        // A looping sibling chain still ends.
+        let obj = table.get_object(ObjectNumber(7)).unwrap();
+        table.set_object_sibling(obj, ObjectNumber(4)).unwrap();
+        assert_eq!(12, table.children(parent).count());
     }
 }
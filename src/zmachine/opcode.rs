@@ -1,15 +1,33 @@
 // UNREVIEWED
 
 use std::fmt;
+use std::time::Duration;
 
 use log::{debug, warn};
 
-use super::addressing::ByteAddress;
+use super::addressing::{ByteAddress, ZOffset};
+use super::dictionary::ZDictionary;
 use super::handle::Handle;
+use super::header::FLAGS1_STATUS_TIME;
+use super::lexer;
+use super::objects::{ObjectNumber, ObjectTable, ZObject, ZObjectTable};
+use super::random::ZRandom;
 use super::result::{Result, ZErr};
-use super::traits::{Memory, Stack, Variables, PC};
+use super::screen::{self, Colour, TextStyle};
+use super::snapshot::ZSnapshot;
+use super::sound;
+use super::status;
+use super::streams::COMMAND_FILE_STREAM;
+use super::traits::{
+    Header, InputStreams, Memory, OutputStreams, Screen, SoundPlayer, Stack, Variables, PC,
+};
+use super::undo::ZUndoRing;
 use super::version::ZVersion;
-use super::zscii::read_zstr_from_pc;
+use super::violations::{Violation, ZViolations};
+use super::zscii::{
+    char_to_zscii, encode_zstr_in, key_to_zscii, translate_key, zscii_for_output, zscii_to_char,
+    ZAlphabet, ZTextDecoder,
+};
 
 // Each (non-extended) opcode indicates its type (Short, Long, Var) with the top two bits.
 pub const OPCODE_TYPE_MASK: u8 = 0b1100_0000;
@@ -181,7 +199,9 @@ where
     let return_pc = stack.borrow().return_pc();
     let return_variable = stack.borrow().return_variable();
     stack.borrow_mut().pop_frame()?;
-    variables.write_variable(return_variable, value)?;
+    if let Some(return_variable) = return_variable {
+        variables.write_variable(return_variable, value)?;
+    }
     pc.set_current_pc(return_pc);
     Ok(())
 }
@@ -214,537 +234,5131 @@ pub mod zero_op {
     }
 
     // ZSpec: 0OP:178 0x02 print (literal-string)
-    // UNTESTED
-    pub fn o_178_print<M, P>(
-        memory: &Handle<M>,
+    pub fn o_178_print<M, O, P>(
+        text: &Handle<ZTextDecoder<M>>,
         pc: &mut P,
-        abbrev_offset: ByteAddress,
+        streams: &mut O,
     ) -> Result<()>
     where
         M: Memory,
+        O: OutputStreams,
         P: PC,
     {
-        // TODO: This is not acceptible in a world with multiple output streams.
         debug!("print");
-        let zstr = read_zstr_from_pc(&memory, abbrev_offset, pc)?;
-        print!("{}", zstr);
-        Ok(())
+        text.borrow_mut().print_from_pc(pc, streams)
     }
 
-    // ZSpec: 0OP:187 0x0B new_line
-    // UNTESTED
-    pub fn o_187_new_line() {
-        // TODO: This is not acceptible in a world with multiple output streams.
-        println!("\n");
-        debug!("new_line");
+    // ZSpec: 0OP:179 0x03 print_ret (literal-string)
+    pub fn o_179_print_ret<M, O, P, S, V>(
+        text: &Handle<ZTextDecoder<M>>,
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        streams: &mut O,
+    ) -> Result<()>
+    where
+        M: Memory,
+        O: OutputStreams,
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        debug!("print_ret");
+        text.borrow_mut().print_from_pc(pc, streams)?;
+        streams.print_str("\n")?;
+        return_value(1, pc, stack, variables)
     }
-}
 
-pub mod one_op {
-    use super::*;
+    // ZSpec: 0OP:180 0x04 nop
+    pub fn o_180_nop() -> Result<()> {
+        debug!("nop");
+        Ok(())
+    }
 
-    // ZSpec: 1OP:128 0x00 jz a ?(label)
-    // UNTESTED
-    pub fn o_128_jz<P, V>(pc: &mut P, variables: &mut V, operand: ZOperand) -> Result<()>
+    // ZSpec: 0OP:184 0x08 ret_popped
+    pub fn o_184_ret_popped<P, S, V>(pc: &mut P, stack: &Handle<S>, variables: &mut V) -> Result<()>
     where
         P: PC,
+        S: Stack,
         V: Variables,
     {
-        let first_offset_byte = pc.next_byte();
-        branch(first_offset_byte, pc, |offset, branch_on_truth| {
-            debug!(
-                "jz         {} ?{}(x{:x})",
-                operand,
-                if branch_on_truth { "" } else { "~" },
-                offset
-            );
-
-            // TODO: what if this is Omitted?
-            Ok(operand.value(variables)? == 0)
-        })
+        debug!("ret_popped");
+        let value = stack.borrow_mut().pop_word()?;
+        return_value(value, pc, stack, variables)
     }
 
-    // ZSpec: 1OP:139 0x0b ret value
-    // UNTESTED
-    pub fn o_139_ret<P, S, V>(
+    // ZSpec: 0OP:181 0x05 V1 save ?(label)
+    // ZSpec: 0OP:181 0x05 V4 save -> (result)
+    //
+    // The processor has already tried to save, as the save resumes at the
+    // branch or store byte.
+    pub fn o_181_save<P, S, V>(
         pc: &mut P,
         stack: &Handle<S>,
         variables: &mut V,
-        operand: ZOperand,
+        version: ZVersion,
+        saved: bool,
     ) -> Result<()>
     where
         P: PC,
         S: Stack,
         V: Variables,
     {
-        let result = operand.value(variables)?;
-        debug!("ret         {}", operand);
-        return_value(result, pc, stack, variables)
+        if version >= ZVersion::V4 {
+            let variable = ZVariable::from(pc.next_byte());
+            debug!("save       -> {}", variable);
+            return variables.write_variable(variable, u16::from(saved));
+        }
+
+        let first_offset_byte = pc.next_byte();
+        branch(
+            first_offset_byte,
+            pc,
+            stack,
+            variables,
+            |offset, branch_on_truth, _| {
+                debug!(
+                    "save       ?{}(x{:x})",
+                    if branch_on_truth { "" } else { "~" },
+                    offset
+                );
+                Ok(saved)
+            },
+        )
     }
 
-    // ZSpec: 1OP:140 0x0c jump ?(label)
-    // UNTESTED
-    pub fn o_140_jump<P, V>(pc: &mut P, variables: &mut V, operand: ZOperand) -> Result<()>
+    // ZSpec: 0OP:182 0x06 V1 restore ?(label)
+    // ZSpec: 0OP:182 0x06 V4 restore -> (result)
+    //
+    // After the processor restores, the pc is at the branch or store byte of
+    // the save that was restored, which then succeeds, storing 2. A failed
+    // restore carries on from here. (ZSpec 15 restore)
+    pub fn o_182_restore<P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        version: ZVersion,
+        restored: bool,
+    ) -> Result<()>
     where
         P: PC,
+        S: Stack,
         V: Variables,
     {
-        debug!("jump       {}", operand);
+        if version >= ZVersion::V4 {
+            let variable = ZVariable::from(pc.next_byte());
+            debug!("restore    -> {}", variable);
+            return variables.write_variable(variable, if restored { 2 } else { 0 });
+        }
 
-        let offset = isize::from(operand.value(variables)? as i16) - 2;
-        pc.offset_pc(offset);
-        Ok(())
+        let first_offset_byte = pc.next_byte();
+        branch(
+            first_offset_byte,
+            pc,
+            stack,
+            variables,
+            |offset, branch_on_truth, _| {
+                debug!(
+                    "restore    ?{}(x{:x})",
+                    if branch_on_truth { "" } else { "~" },
+                    offset
+                );
+                Ok(restored)
+            },
+        )
     }
-}
-
-fn interpret_offset_byte<P>(byte: u8, pc: &mut P) -> i16
-where
-    P: PC,
-{
-    // TODO: move all of the pc manipulation here so that it can be called from all branches.
-    if byte & 0b0100_0000 != 0 {
-        // One byte only.
-        i16::from(byte & 0b0011_1111)
-    } else {
-        let second_byte = pc.next_byte();
-        let mut offset: u16 = ((byte as u16 & 0b0011_1111) << 8) + second_byte as u16;
-        // Check for a negative 14-bit value, and sign extend to 16-bit if necessary.
-        if offset & 0b0010_0000_0000_0000 != 0 {
-            offset |= 0b1100_0000_0000_0000;
-        }
 
-        offset as i16
+    // ZSpec: 0OP:185 0x09 V1 pop
+    pub fn o_185_pop<S>(stack: &Handle<S>) -> Result<()>
+    where
+        S: Stack,
+    {
+        debug!("pop");
+        stack.borrow_mut().pop_word().map(|_| ())
     }
-}
 
-fn branch<P, F>(byte: u8, pc: &mut P, tst: F) -> Result<()>
-where
-    F: FnOnce(i16, bool) -> Result<bool>,
-    P: PC,
-{
-    // TODO: do all offset handling (and reading from PC in interpret_offset_byte.
-    let branch_on_truth = !((byte & 0b1000_0000) == 0);
-    let offset = interpret_offset_byte(byte, pc);
+    // ZSpec: 0OP:185 0x09 V5 catch -> (result)
+    //
+    // The result only has to identify the current routine to throw, so it is
+    // the depth of the call stack.
+    pub fn o_185_catch<P, S, V>(pc: &mut P, stack: &Handle<S>, variables: &mut V) -> Result<()>
+    where
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        let variable = ZVariable::from(pc.next_byte());
+        debug!("catch      -> {}", variable);
 
-    let truth = tst(offset, branch_on_truth)?;
+        let depth = stack.borrow().frame_depth();
+        variables.write_variable(variable, depth as u16)
+    }
 
-    if branch_on_truth == truth {
-        // Branch!
-        match offset {
-            0 => unimplemented!("ret false"),
-            1 => unimplemented!("ret true"),
-            o => {
-                pc.offset_pc((o - 2) as isize);
-            }
-        }
+    // ZSpec: 0OP:186 0x0A quit
+    //
+    // The processor stops after this.
+    pub fn o_186_quit() {
+        debug!("quit");
     }
-    Ok(())
-}
 
-pub mod two_op {
-    use super::*;
+    // ZSpec: 0OP:187 0x0B new_line
+    pub fn o_187_new_line<O>(streams: &mut O) -> Result<()>
+    where
+        O: OutputStreams,
+    {
+        debug!("new_line");
+        streams.print_str("\n")
+    }
 
-    // ZSpec: 2OP:1 0x01 je a b ?(label)
-    // UNTESTED
-    pub fn o_1_je<P, V>(pc: &mut P, variables: &mut V, operands: [ZOperand; 2]) -> Result<()>
+    // ZSpec: 0OP:188 0x0C V3 show_status
+    pub fn o_188_show_status<H, M, Scr, V>(
+        objects: &ZObjectTable<M>,
+        variables: &mut V,
+        header: &H,
+        screen: &Handle<Scr>,
+        twenty_four_hour_clock: bool,
+    ) -> Result<()>
     where
-        P: PC,
+        H: Header,
+        M: Memory,
+        Scr: Screen,
         V: Variables,
     {
-        let first_offset_byte = pc.next_byte();
-        branch(first_offset_byte, pc, |offset, branch_on_truth| {
-            debug!(
-                "je          {} {} ?{}(x{:x})",
-                operands[0],
-                operands[1],
-                if branch_on_truth { "" } else { "~" },
-                offset
-            );
-            let first_val = operands[0].value(variables);
-            let second_val = operands[1].value(variables);
+        debug!("show_status");
 
-            // TODO: this needs to deal with the case when there are > 2 arguments. It's a real thing.
+        // The status line only exists in V1-3. (ZSpec 8.2.1)
+        if header.version_number() > ZVersion::V3 {
+            return Ok(());
+        }
 
-            if first_val.is_ok() && second_val.is_ok() {
-                Ok(first_val.unwrap() == second_val.unwrap())
-            } else {
-                Ok(false)
-            }
-        })
+        let location = match variables.read_variable(ZVariable::Global(0))? {
+            0 => String::new(),
+            num => objects.short_name(ObjectNumber::from(num))?,
+        };
+
+        let first = variables.read_variable(ZVariable::Global(1))?;
+        let second = variables.read_variable(ZVariable::Global(2))?;
+        let right = if header.flags1() & FLAGS1_STATUS_TIME != 0 {
+            status::time_text(first, second, twenty_four_hour_clock)
+        } else {
+            status::score_text(first as i16, second)
+        };
+
+        screen.borrow_mut().show_status(&location, &right)
     }
 
-    // ZSpec: 2OP:5 0x05 inc_chk (variable) value ?(label)
-    // UNTESTED
-    pub fn o_5_inc_chk<P, V>(pc: &mut P, variables: &mut V, operands: [ZOperand; 2]) -> Result<()>
+    // ZSpec: 0OP:189 0x0D V3 verify ?(label)
+    pub fn o_189_verify<P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        verified: bool,
+    ) -> Result<()>
     where
         P: PC,
+        S: Stack,
         V: Variables,
     {
-        let variable = ZVariable::from(operands[0].value(variables)? as u8);
         let first_offset_byte = pc.next_byte();
-        branch(first_offset_byte, pc, |offset, branch_on_truth| {
-            debug!(
-                "inc_chk    {} {} ?{}({:x})",
-                variable,
-                operands[1],
-                if branch_on_truth { "" } else { "~" },
-                offset
-            );
+        branch(
+            first_offset_byte,
+            pc,
+            stack,
+            variables,
+            |offset, branch_on_truth, _| {
+                debug!(
+                    "verify     ?{}(x{:x})",
+                    if branch_on_truth { "" } else { "~" },
+                    offset
+                );
 
-            let old_value = variables.read_variable(variable)?;
-            let (result, overflow) = old_value.overflowing_add(1);
-            if overflow {
-                warn!("inc_chk    {} causes overflow.", variable);
-            }
-            variables.write_variable(variable, result)?;
-
-            let test_value = operands[1].value(variables)?;
-            Ok(result > test_value)
-        })
+                // Branch if the story's checksum is right. (ZSpec 15 verify)
+                Ok(verified)
+            },
+        )
     }
 
-    // ZSpec: 2OP:9 0x09 and a b -> (result)
-    // UNTESTED
-    pub fn o_9_and<P, V>(pc: &mut P, variables: &mut V, operands: [ZOperand; 2]) -> Result<()>
+    // ZSpec: 0OP:191 0x0F V5 piracy ?(label)
+    pub fn o_191_piracy<P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        pirated: bool,
+    ) -> Result<()>
     where
         P: PC,
+        S: Stack,
         V: Variables,
     {
-        let store = ZVariable::from(pc.next_byte());
-
-        let lhs = operands[0].value(variables)?;
-        let rhs = operands[1].value(variables)?;
-
-        debug!("and        {} {} -> {}", operands[0], operands[1], store);
+        let first_offset_byte = pc.next_byte();
+        branch(
+            first_offset_byte,
+            pc,
+            stack,
+            variables,
+            |offset, branch_on_truth, _| {
+                debug!(
+                    "piracy     ?{}(x{:x})",
+                    if branch_on_truth { "" } else { "~" },
+                    offset
+                );
 
-        variables.write_variable(store, lhs & rhs)
+                // Branch if the game is genuine. (ZSpec 15 piracy)
+                Ok(!pirated)
+            },
+        )
     }
+}
 
-    // ZSpec: 2OP:10 0x0A test_attr object attribute ?(label)
+pub mod one_op {
+    use super::*;
+
+    // ZSpec: 1OP:128 0x00 jz a ?(label)
     // UNTESTED
-    pub fn o_10_test_attr<P>(pc: &mut P, operands: [ZOperand; 2])
+    pub fn o_128_jz<P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        operand: ZOperand,
+    ) -> Result<()>
     where
         P: PC,
+        S: Stack,
+        V: Variables,
     {
-        let branch = pc.next_byte();
-        debug!(
-            "test_attr   {} {} ?{:b} XXX",
-            operands[0], operands[1], branch
-        );
-        unimplemented!("test_attr")
+        let first_offset_byte = pc.next_byte();
+        branch(
+            first_offset_byte,
+            pc,
+            stack,
+            variables,
+            |offset, branch_on_truth, variables| {
+                debug!(
+                    "jz         {} ?{}(x{:x})",
+                    operand,
+                    if branch_on_truth { "" } else { "~" },
+                    offset
+                );
+
+                // TODO: what if this is Omitted?
+                Ok(operand.value(variables)? == 0)
+            },
+        )
     }
 
-    // ZSpec: 2OP:13 0x0D store (variable) value
-    pub fn o_13_store<V>(variables: &mut V, operands: [ZOperand; 2]) -> Result<()>
+    // ZSpec: 1OP:129 0x01 get_sibling object -> (result) ?(label)
+    pub fn o_129_get_sibling<M, P, S, V>(
+        objects: &ZObjectTable<M>,
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        violations: &mut ZViolations,
+        operand: ZOperand,
+    ) -> Result<()>
     where
+        M: Memory,
+        P: PC,
+        S: Stack,
         V: Variables,
     {
-        let variable = ZVariable::from(operands[0]);
-        debug!("store       {} {}", variable, operands[1]);
-
-        let value = operands[1].value(variables)?;
-        variables.write_variable(variable, value)
+        let sibling = match operand_object(objects, variables, violations, "get_sibling", operand)?
+        {
+            Some(object) => objects.get_object_sibling(object)?,
+            None => ObjectNumber::from(0),
+        };
+        store_and_branch("get_sibling", sibling, pc, stack, variables, operand)
     }
 
-    // ZSpec: 2OP:15 0x0f loadw array word-index -> (result)
-    // UNTESTED
-    pub fn o_15_loadw<M, P, V>(
-        memory: &Handle<M>,
+    // ZSpec: 1OP:130 0x02 get_child object -> (result) ?(label)
+    pub fn o_130_get_child<M, P, S, V>(
+        objects: &ZObjectTable<M>,
         pc: &mut P,
+        stack: &Handle<S>,
         variables: &mut V,
-        operands: [ZOperand; 2],
+        violations: &mut ZViolations,
+        operand: ZOperand,
     ) -> Result<()>
     where
         M: Memory,
         P: PC,
+        S: Stack,
         V: Variables,
     {
-        let store = ZVariable::from(pc.next_byte());
-        debug!("loadw      {} {} -> {}", operands[0], operands[1], store);
+        let child = match operand_object(objects, variables, violations, "get_child", operand)? {
+            Some(object) => objects.get_object_child(object)?,
+            None => ObjectNumber::from(0),
+        };
+        store_and_branch("get_child", child, pc, stack, variables, operand)
+    }
 
-        let array = operands[0].value(variables)?;
-        let word_index = operands[1].value(variables)?;
+    // get_sibling and get_child store the object found, and branch if there
+    // was one.
+    fn store_and_branch<P, S, V>(
+        name: &str,
+        found: ObjectNumber,
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        operand: ZOperand,
+    ) -> Result<()>
+    where
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        let variable = ZVariable::from(pc.next_byte());
+        variables.write_variable(variable, found.value())?;
 
-        let byte_address = ByteAddress::from_raw(array).inc_by(2 * word_index);
-        let value = memory.borrow().read_word(byte_address);
-        variables.write_variable(store, value)
+        let first_offset_byte = pc.next_byte();
+        branch(
+            first_offset_byte,
+            pc,
+            stack,
+            variables,
+            |offset, branch_on_truth, _| {
+                debug!(
+                    "{:<11}{} -> {} ?{}(x{:x})",
+                    name,
+                    operand,
+                    variable,
+                    if branch_on_truth { "" } else { "~" },
+                    offset
+                );
+                Ok(found.value() != 0)
+            },
+        )
     }
 
-    // ZSpec: 2OP:16 0x10 loadb array byte-index -> (result)
-    // UNTESTED
-    pub fn o_16_loadb<M, P, V>(
-        memory: &Handle<M>,
+    // ZSpec: 1OP:131 0x03 get_parent object -> (result)
+    pub fn o_131_get_parent<M, P, V>(
+        objects: &ZObjectTable<M>,
         pc: &mut P,
         variables: &mut V,
-        operands: [ZOperand; 2],
+        violations: &mut ZViolations,
+        operand: ZOperand,
     ) -> Result<()>
     where
         M: Memory,
         P: PC,
         V: Variables,
     {
-        let store = ZVariable::from(pc.next_byte());
-        debug!("loadb      {} {} -> {}", operands[0], operands[1], store);
-
-        let array = operands[0].value(variables)?;
-        let byte_index = operands[1].value(variables)?;
+        let variable = ZVariable::from(pc.next_byte());
+        debug!("get_parent {} -> {}", operand, variable);
 
-        let byte_address = ByteAddress::from_raw(array).inc_by(byte_index);
-        let value = memory.borrow().read_byte(byte_address);
-        variables.write_variable(store, u16::from(value))
+        let parent = match operand_object(objects, variables, violations, "get_parent", operand)? {
+            Some(object) => objects.get_object_parent(object)?.value(),
+            None => 0,
+        };
+        variables.write_variable(variable, parent)
     }
 
-    // ZSpec: 2OP:20 0x14 add a b -> (result)
-    pub fn o_20_add<P, V>(pc: &mut P, variables: &mut V, operands: [ZOperand; 2]) -> Result<()>
+    // ZSpec: 1OP:132 0x04 get_prop_len property-address -> (result)
+    pub fn o_132_get_prop_len<M, P, V>(
+        objects: &ZObjectTable<M>,
+        pc: &mut P,
+        variables: &mut V,
+        operand: ZOperand,
+    ) -> Result<()>
     where
+        M: Memory,
         P: PC,
         V: Variables,
     {
-        let store = pc.next_byte();
-        let variable = ZVariable::from(store);
-        debug!(
-            "add         {} {} -> {}",
-            operands[0], operands[1], variable
-        );
+        let variable = ZVariable::from(pc.next_byte());
+        debug!("get_prop_len {} -> {}", operand, variable);
 
-        let lhs = operands[0].value(variables)?;
-        let rhs = operands[1].value(variables)?;
+        let len = objects.property_length(operand.value(variables)?);
+        variables.write_variable(variable, len)
+    }
 
-        let (result, overflow) = lhs.overflowing_add(rhs);
-        if overflow {
-            warn!("add {:x} + {:x} causes overflow.", lhs, rhs);
-        }
+    // ZSpec: 1OP:133 0x05 inc (variable)
+    pub fn o_133_inc<V>(variables: &mut V, operand: ZOperand) -> Result<()>
+    where
+        V: Variables,
+    {
+        let variable = variable_operand(operand, variables)?;
+        debug!("inc        {}", variable);
 
-        variables.write_variable(variable, result)
+        let value = variables.peek_variable(variable)?;
+        variables.poke_variable(variable, value.wrapping_add(1))
     }
 
-    // ZSpec: TODO
-    // UNTESTED
-    pub fn o_21_sub<P, V>(pc: &mut P, variables: &mut V, operands: [ZOperand; 2]) -> Result<()>
+    // ZSpec: 1OP:134 0x06 dec (variable)
+    pub fn o_134_dec<V>(variables: &mut V, operand: ZOperand) -> Result<()>
     where
-        P: PC,
         V: Variables,
     {
-        let store = pc.next_byte();
-        let variable = ZVariable::from(store);
-        debug!(
-            "sub         {} {} -> {}",
-            operands[0], operands[1], variable
-        );
+        let variable = variable_operand(operand, variables)?;
+        debug!("dec        {}", variable);
 
-        let lhs = operands[0].value(variables)? as i16;
-        let rhs = operands[1].value(variables)? as i16;
+        let value = variables.peek_variable(variable)?;
+        variables.poke_variable(variable, value.wrapping_sub(1))
+    }
 
-        let (result, overflow) = lhs.overflowing_sub(rhs);
-        if overflow {
+    // ZSpec: 1OP:135 0x07 print_addr byte-address-of-string
+    pub fn o_135_print_addr<M, O, V>(
+        text: &Handle<ZTextDecoder<M>>,
+        variables: &mut V,
+        streams: &mut O,
+        operand: ZOperand,
+    ) -> Result<()>
+    where
+        M: Memory,
+        O: OutputStreams,
+        V: Variables,
+    {
+        let address = operand.value(variables)?;
+        debug!("print_addr {}", address);
+        text.borrow_mut()
+            .print_from_memory(ByteAddress::from_raw(address), streams)
+    }
+
+    // ZSpec: 1OP:136 0x08 V4 call_1s routine -> (result)
+    pub fn o_136_call_1s<H, P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        header: &H,
+        operand: ZOperand,
+    ) -> Result<()>
+    where
+        H: Header,
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        let store = ZVariable::from(pc.next_byte());
+        debug!("call_1s    {} -> {}", operand, store);
+        call_operands(pc, stack, variables, header, &[operand], Some(store))
+    }
+
+    // ZSpec: 1OP:137 0x09 remove_obj object
+    pub fn o_137_remove_obj<M, V>(
+        objects: &ZObjectTable<M>,
+        variables: &mut V,
+        violations: &mut ZViolations,
+        operand: ZOperand,
+    ) -> Result<()>
+    where
+        M: Memory,
+        V: Variables,
+    {
+        debug!("remove_obj {}", operand);
+
+        let num = operand.value(variables)?;
+        if num == 0 {
+            return violations.report(Violation::NullObject("remove_obj"));
+        }
+        objects.remove_object(ObjectNumber::from(num))
+    }
+
+    // ZSpec: 1OP:138 0x0a print_obj object
+    pub fn o_138_print_obj<M, O, V>(
+        objects: &ZObjectTable<M>,
+        variables: &mut V,
+        streams: &mut O,
+        violations: &mut ZViolations,
+        operand: ZOperand,
+    ) -> Result<()>
+    where
+        M: Memory,
+        O: OutputStreams,
+        V: Variables,
+    {
+        let object = operand.value(variables)?;
+        debug!("print_obj  {}", object);
+        if object == 0 {
+            return violations.report(Violation::NullObject("print_obj"));
+        }
+        let name = objects.short_name(ObjectNumber::from(object))?;
+        streams.print_str(&name)
+    }
+
+    // ZSpec: 1OP:139 0x0b ret value
+    // UNTESTED
+    pub fn o_139_ret<P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        operand: ZOperand,
+    ) -> Result<()>
+    where
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        let result = operand.value(variables)?;
+        debug!("ret         {}", operand);
+        return_value(result, pc, stack, variables)
+    }
+
+    // ZSpec: 1OP:140 0x0c jump ?(label)
+    // UNTESTED
+    pub fn o_140_jump<P, V>(pc: &mut P, variables: &mut V, operand: ZOperand) -> Result<()>
+    where
+        P: PC,
+        V: Variables,
+    {
+        debug!("jump       {}", operand);
+
+        let offset = isize::from(operand.value(variables)? as i16) - 2;
+        pc.offset_pc(offset);
+        Ok(())
+    }
+
+    // ZSpec: 1OP:141 0x0d print_paddr packed-address-of-string
+    pub fn o_141_print_paddr<H, M, O, V>(
+        text: &Handle<ZTextDecoder<M>>,
+        header: &H,
+        variables: &mut V,
+        streams: &mut O,
+        operand: ZOperand,
+    ) -> Result<()>
+    where
+        H: Header,
+        M: Memory,
+        O: OutputStreams,
+        V: Variables,
+    {
+        let packed = operand.value(variables)?;
+        debug!("print_paddr {}", packed);
+        text.borrow_mut()
+            .print_from_memory(header.string_address(packed), streams)
+    }
+
+    // ZSpec: 1OP:142 0x0e load (variable) -> (result)
+    pub fn o_142_load<P, V>(pc: &mut P, variables: &mut V, operand: ZOperand) -> Result<()>
+    where
+        P: PC,
+        V: Variables,
+    {
+        let store = ZVariable::from(pc.next_byte());
+        let variable = variable_operand(operand, variables)?;
+        debug!("load       {} -> {}", variable, store);
+
+        let value = variables.peek_variable(variable)?;
+        variables.write_variable(store, value)
+    }
+
+    // ZSpec: 1OP:143 0x0F V1 not value -> (result)
+    pub fn o_143_not<P, V>(pc: &mut P, variables: &mut V, operand: ZOperand) -> Result<()>
+    where
+        P: PC,
+        V: Variables,
+    {
+        let store = ZVariable::from(pc.next_byte());
+        debug!("not        {} -> {}", operand, store);
+
+        let value = operand.value(variables)?;
+        variables.write_variable(store, !value)
+    }
+
+    // ZSpec: 1OP:143 0x0F V5 call_1n routine
+    pub fn o_143_call_1n<H, P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        header: &H,
+        operand: ZOperand,
+    ) -> Result<()>
+    where
+        H: Header,
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        debug!("call_1n    {}", operand);
+        call_operands(pc, stack, variables, header, &[operand], None)
+    }
+}
+
+// Call the routine at the packed address with the given arguments, arranging
+// for its result to be stored in the store variable, or thrown away if there
+// is none. (ZSpec 6.4)
+//
+// Calling address 0 does nothing, and returns false. (ZSpec 6.4.3)
+pub fn call_routine<H, P, S, V>(
+    pc: &mut P,
+    stack: &Handle<S>,
+    variables: &mut V,
+    header: &H,
+    routine: u16,
+    args: &[u16],
+    store: Option<ZVariable>,
+) -> Result<()>
+where
+    H: Header,
+    P: PC,
+    S: Stack,
+    V: Variables,
+{
+    if routine == 0 {
+        return match store {
+            Some(store) => variables.write_variable(store, 0),
+            None => Ok(()),
+        };
+    }
+
+    let return_pc = pc.current_pc();
+    let packed = header.routine_address(routine);
+    debug!("calling     {} {:?}", packed, args);
+    pc.set_current_pc(packed.into());
+
+    // Read function header.
+    let num_locals = pc.next_byte();
+
+    let mut local_values = [0u16; 15];
+    if header.version_number() < ZVersion::V5 {
+        // On <V5, the function header also contains the starting values for the locals.
+        for i in 0..num_locals {
+            local_values[usize::from(i)] = pc.next_word();
+        }
+    }
+
+    // Arguments replace the first locals' starting values. Arguments beyond
+    // the number of locals are discarded. (ZSpec 6.4.4)
+    let num_args = args.len().min(usize::from(num_locals));
+    local_values[..num_args].copy_from_slice(&args[..num_args]);
+
+    stack
+        .borrow_mut()
+        .push_frame(return_pc, num_locals, store, &local_values, num_args as u8)
+}
+
+// The call opcodes take the routine as their first operand, and the arguments
+// as the rest, up to the first omitted one.
+fn call_operands<H, P, S, V>(
+    pc: &mut P,
+    stack: &Handle<S>,
+    variables: &mut V,
+    header: &H,
+    operands: &[ZOperand],
+    store: Option<ZVariable>,
+) -> Result<()>
+where
+    H: Header,
+    P: PC,
+    S: Stack,
+    V: Variables,
+{
+    let routine = operands[0].value(variables)?;
+    let mut args = Vec::new();
+    for operand in operands[1..].iter() {
+        match operand {
+            ZOperand::Omitted => break,
+            operand => args.push(operand.value(variables)?),
+        }
+    }
+    call_routine(pc, stack, variables, header, routine, &args, store)
+}
+
+// Opcodes that take a variable by number (inc, dec, inc_chk, dec_chk, load,
+// store, pull) read the number from their first operand. (ZSpec 4.2.3)
+fn variable_operand<V>(operand: ZOperand, variables: &mut V) -> Result<ZVariable>
+where
+    V: Variables,
+{
+    Ok(ZVariable::from(operand.value(variables)? as u8))
+}
+
+// The object that an operand names, or None for object 0. Games shouldn't
+// use object 0, but some do, so that is a violation rather than an error. The
+// opcode should then act as if object 0 had no relatives, attributes or
+// properties.
+fn operand_object<M, V>(
+    objects: &ZObjectTable<M>,
+    variables: &mut V,
+    violations: &mut ZViolations,
+    opcode: &'static str,
+    operand: ZOperand,
+) -> Result<Option<ZObject>>
+where
+    M: Memory,
+    V: Variables,
+{
+    let num = operand.value(variables)?;
+    if num == 0 {
+        violations.report(Violation::NullObject(opcode))?;
+        return Ok(None);
+    }
+    objects.get_object(ObjectNumber::from(num)).map(Some)
+}
+
+fn interpret_offset_byte<P>(byte: u8, pc: &mut P) -> i16
+where
+    P: PC,
+{
+    // TODO: move all of the pc manipulation here so that it can be called from all branches.
+    if byte & 0b0100_0000 != 0 {
+        // One byte only.
+        i16::from(byte & 0b0011_1111)
+    } else {
+        let second_byte = pc.next_byte();
+        let mut offset: u16 = ((byte as u16 & 0b0011_1111) << 8) + second_byte as u16;
+        // Check for a negative 14-bit value, and sign extend to 16-bit if necessary.
+        if offset & 0b0010_0000_0000_0000 != 0 {
+            offset |= 0b1100_0000_0000_0000;
+        }
+
+        offset as i16
+    }
+}
+
+fn branch<F, P, S, V>(
+    byte: u8,
+    pc: &mut P,
+    stack: &Handle<S>,
+    variables: &mut V,
+    tst: F,
+) -> Result<()>
+where
+    F: FnOnce(i16, bool, &mut V) -> Result<bool>,
+    P: PC,
+    S: Stack,
+    V: Variables,
+{
+    // TODO: do all offset handling (and reading from PC in interpret_offset_byte.
+    let branch_on_truth = !((byte & 0b1000_0000) == 0);
+    let offset = interpret_offset_byte(byte, pc);
+
+    let truth = tst(offset, branch_on_truth, variables)?;
+
+    if branch_on_truth == truth {
+        // Branch!
+        // Offsets 0 and 1 return false or true from the current routine
+        // instead of branching. (ZSpec 4.7.1)
+        match offset {
+            0 => return_value(0, pc, stack, variables)?,
+            1 => return_value(1, pc, stack, variables)?,
+            o => {
+                pc.offset_pc((o - 2) as isize);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub mod two_op {
+    use super::*;
+
+    // ZSpec: 2OP:1 0x01 je a b ?(label)
+    // UNTESTED
+    pub fn o_1_je<P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        let first_offset_byte = pc.next_byte();
+        branch(
+            first_offset_byte,
+            pc,
+            stack,
+            variables,
+            |offset, branch_on_truth, variables| {
+                debug!(
+                    "je          {} {} {} {} ?{}(x{:x})",
+                    operands[0],
+                    operands[1],
+                    operands[2],
+                    operands[3],
+                    if branch_on_truth { "" } else { "~" },
+                    offset
+                );
+
+                // Branch if the first operand equals any of the others. Every
+                // operand is read, so that each one taken from the stack is
+                // popped.
+                let first = operands[0].value(variables)?;
+                let mut matched = false;
+                for operand in &operands[1..] {
+                    if let ZOperand::Omitted = operand {
+                        break;
+                    }
+                    matched |= operand.value(variables)? == first;
+                }
+                Ok(matched)
+            },
+        )
+    }
+
+    // ZSpec: 2OP:2 0x02 jl a b ?(label)
+    pub fn o_2_jl<P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        operands: [ZOperand; 2],
+    ) -> Result<()>
+    where
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        let first_offset_byte = pc.next_byte();
+        branch(
+            first_offset_byte,
+            pc,
+            stack,
+            variables,
+            |offset, branch_on_truth, variables| {
+                debug!(
+                    "jl         {} {} ?{}(x{:x})",
+                    operands[0],
+                    operands[1],
+                    if branch_on_truth { "" } else { "~" },
+                    offset
+                );
+
+                // The comparison is signed.
+                let a = operands[0].value(variables)? as i16;
+                let b = operands[1].value(variables)? as i16;
+                Ok(a < b)
+            },
+        )
+    }
+
+    // ZSpec: 2OP:3 0x03 jg a b ?(label)
+    pub fn o_3_jg<P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        operands: [ZOperand; 2],
+    ) -> Result<()>
+    where
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        let first_offset_byte = pc.next_byte();
+        branch(
+            first_offset_byte,
+            pc,
+            stack,
+            variables,
+            |offset, branch_on_truth, variables| {
+                debug!(
+                    "jg         {} {} ?{}(x{:x})",
+                    operands[0],
+                    operands[1],
+                    if branch_on_truth { "" } else { "~" },
+                    offset
+                );
+
+                // The comparison is signed.
+                let a = operands[0].value(variables)? as i16;
+                let b = operands[1].value(variables)? as i16;
+                Ok(a > b)
+            },
+        )
+    }
+
+    // ZSpec: 2OP:4 0x04 dec_chk (variable) value ?(label)
+    pub fn o_4_dec_chk<P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        operands: [ZOperand; 2],
+    ) -> Result<()>
+    where
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        let variable = variable_operand(operands[0], variables)?;
+        let first_offset_byte = pc.next_byte();
+        branch(
+            first_offset_byte,
+            pc,
+            stack,
+            variables,
+            |offset, branch_on_truth, variables| {
+                debug!(
+                    "dec_chk    {} {} ?{}({:x})",
+                    variable,
+                    operands[1],
+                    if branch_on_truth { "" } else { "~" },
+                    offset
+                );
+
+                // The value is read first, so that it is the variable's old
+                // value when it names the variable being changed.
+                let test_value = operands[1].value(variables)?;
+                let result = variables.peek_variable(variable)?.wrapping_sub(1);
+                variables.poke_variable(variable, result)?;
+
+                // The comparison is signed.
+                Ok((result as i16) < (test_value as i16))
+            },
+        )
+    }
+
+    // ZSpec: 2OP:5 0x05 inc_chk (variable) value ?(label)
+    pub fn o_5_inc_chk<P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        operands: [ZOperand; 2],
+    ) -> Result<()>
+    where
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        let variable = variable_operand(operands[0], variables)?;
+        let first_offset_byte = pc.next_byte();
+        branch(
+            first_offset_byte,
+            pc,
+            stack,
+            variables,
+            |offset, branch_on_truth, variables| {
+                debug!(
+                    "inc_chk    {} {} ?{}({:x})",
+                    variable,
+                    operands[1],
+                    if branch_on_truth { "" } else { "~" },
+                    offset
+                );
+
+                // The value is read first, so that it is the variable's old
+                // value when it names the variable being changed.
+                let test_value = operands[1].value(variables)?;
+                let result = variables.peek_variable(variable)?.wrapping_add(1);
+                variables.poke_variable(variable, result)?;
+
+                // The comparison is signed.
+                Ok((result as i16) > (test_value as i16))
+            },
+        )
+    }
+
+    // ZSpec: 2OP:6 0x06 jin obj1 obj2 ?(label)
+    pub fn o_6_jin<M, P, S, V>(
+        objects: &ZObjectTable<M>,
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        violations: &mut ZViolations,
+        operands: [ZOperand; 2],
+    ) -> Result<()>
+    where
+        M: Memory,
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        let first_offset_byte = pc.next_byte();
+        branch(
+            first_offset_byte,
+            pc,
+            stack,
+            variables,
+            |offset, branch_on_truth, variables| {
+                debug!(
+                    "jin        {} {} ?{}(x{:x})",
+                    operands[0],
+                    operands[1],
+                    if branch_on_truth { "" } else { "~" },
+                    offset
+                );
+
+                // Branch if obj2 is the parent of obj1.
+                let parent =
+                    match operand_object(objects, variables, violations, "jin", operands[0])? {
+                        Some(object) => objects.get_object_parent(object)?.value(),
+                        None => return Ok(false),
+                    };
+                Ok(parent == operands[1].value(variables)?)
+            },
+        )
+    }
+
+    // ZSpec: 2OP:7 0x07 test bitmap flags ?(label)
+    pub fn o_7_test<P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        operands: [ZOperand; 2],
+    ) -> Result<()>
+    where
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        let first_offset_byte = pc.next_byte();
+        branch(
+            first_offset_byte,
+            pc,
+            stack,
+            variables,
+            |offset, branch_on_truth, variables| {
+                debug!(
+                    "test       {} {} ?{}(x{:x})",
+                    operands[0],
+                    operands[1],
+                    if branch_on_truth { "" } else { "~" },
+                    offset
+                );
+
+                // Branch if all of the flags are set in the bitmap.
+                let bitmap = operands[0].value(variables)?;
+                let flags = operands[1].value(variables)?;
+                Ok(bitmap & flags == flags)
+            },
+        )
+    }
+
+    // ZSpec: 2OP:8 0x08 or a b -> (result)
+    pub fn o_8_or<P, V>(pc: &mut P, variables: &mut V, operands: [ZOperand; 2]) -> Result<()>
+    where
+        P: PC,
+        V: Variables,
+    {
+        let store = ZVariable::from(pc.next_byte());
+
+        let lhs = operands[0].value(variables)?;
+        let rhs = operands[1].value(variables)?;
+
+        debug!("or         {} {} -> {}", operands[0], operands[1], store);
+
+        variables.write_variable(store, lhs | rhs)
+    }
+
+    // ZSpec: 2OP:9 0x09 and a b -> (result)
+    // UNTESTED
+    pub fn o_9_and<P, V>(pc: &mut P, variables: &mut V, operands: [ZOperand; 2]) -> Result<()>
+    where
+        P: PC,
+        V: Variables,
+    {
+        let store = ZVariable::from(pc.next_byte());
+
+        let lhs = operands[0].value(variables)?;
+        let rhs = operands[1].value(variables)?;
+
+        debug!("and        {} {} -> {}", operands[0], operands[1], store);
+
+        variables.write_variable(store, lhs & rhs)
+    }
+
+    // ZSpec: 2OP:10 0x0A test_attr object attribute ?(label)
+    pub fn o_10_test_attr<M, P, S, V>(
+        objects: &ZObjectTable<M>,
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        violations: &mut ZViolations,
+        operands: [ZOperand; 2],
+    ) -> Result<()>
+    where
+        M: Memory,
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        let first_offset_byte = pc.next_byte();
+        branch(
+            first_offset_byte,
+            pc,
+            stack,
+            variables,
+            |offset, branch_on_truth, variables| {
+                debug!(
+                    "test_attr  {} {} ?{}(x{:x})",
+                    operands[0],
+                    operands[1],
+                    if branch_on_truth { "" } else { "~" },
+                    offset
+                );
+
+                let object =
+                    match operand_object(objects, variables, violations, "test_attr", operands[0])?
+                    {
+                        Some(object) => object,
+                        None => return Ok(false),
+                    };
+                let attribute = operands[1].value(variables)? as u8;
+                Ok(objects.get_object_attribute(object, attribute)? != 0)
+            },
+        )
+    }
+
+    // ZSpec: 2OP:11 0x0B set_attr object attribute
+    pub fn o_11_set_attr<M, V>(
+        objects: &ZObjectTable<M>,
+        variables: &mut V,
+        violations: &mut ZViolations,
+        operands: [ZOperand; 2],
+    ) -> Result<()>
+    where
+        M: Memory,
+        V: Variables,
+    {
+        debug!("set_attr   {} {}", operands[0], operands[1]);
+
+        if let Some(object) =
+            operand_object(objects, variables, violations, "set_attr", operands[0])?
+        {
+            objects.set_object_attribute(object, operands[1].value(variables)? as u8, 1)?;
+        }
+        Ok(())
+    }
+
+    // ZSpec: 2OP:12 0x0C clear_attr object attribute
+    pub fn o_12_clear_attr<M, V>(
+        objects: &ZObjectTable<M>,
+        variables: &mut V,
+        violations: &mut ZViolations,
+        operands: [ZOperand; 2],
+    ) -> Result<()>
+    where
+        M: Memory,
+        V: Variables,
+    {
+        debug!("clear_attr {} {}", operands[0], operands[1]);
+
+        if let Some(object) =
+            operand_object(objects, variables, violations, "clear_attr", operands[0])?
+        {
+            objects.set_object_attribute(object, operands[1].value(variables)? as u8, 0)?;
+        }
+        Ok(())
+    }
+
+    // ZSpec: 2OP:13 0x0D store (variable) value
+    pub fn o_13_store<V>(variables: &mut V, operands: [ZOperand; 2]) -> Result<()>
+    where
+        V: Variables,
+    {
+        let variable = variable_operand(operands[0], variables)?;
+        debug!("store       {} {}", variable, operands[1]);
+
+        let value = operands[1].value(variables)?;
+        variables.poke_variable(variable, value)
+    }
+
+    // ZSpec: 2OP:14 0x0E insert_obj object destination
+    pub fn o_14_insert_obj<M, V>(
+        objects: &ZObjectTable<M>,
+        variables: &mut V,
+        violations: &mut ZViolations,
+        operands: [ZOperand; 2],
+    ) -> Result<()>
+    where
+        M: Memory,
+        V: Variables,
+    {
+        debug!("insert_obj {} {}", operands[0], operands[1]);
+
+        let object = operands[0].value(variables)?;
+        let destination = operands[1].value(variables)?;
+        if object == 0 || destination == 0 {
+            return violations.report(Violation::NullObject("insert_obj"));
+        }
+        objects.insert_object(ObjectNumber::from(object), ObjectNumber::from(destination))
+    }
+
+    // ZSpec: 2OP:15 0x0f loadw array word-index -> (result)
+    // UNTESTED
+    pub fn o_15_loadw<M, P, V>(
+        memory: &Handle<M>,
+        pc: &mut P,
+        variables: &mut V,
+        operands: [ZOperand; 2],
+    ) -> Result<()>
+    where
+        M: Memory,
+        P: PC,
+        V: Variables,
+    {
+        let store = ZVariable::from(pc.next_byte());
+        debug!("loadw      {} {} -> {}", operands[0], operands[1], store);
+
+        let array = operands[0].value(variables)?;
+        let word_index = operands[1].value(variables)?;
+
+        let byte_address = ByteAddress::from_raw(array).inc_by(word_index.wrapping_mul(2));
+        let value = memory.borrow().read_word(byte_address);
+        variables.write_variable(store, value)
+    }
+
+    // ZSpec: 2OP:16 0x10 loadb array byte-index -> (result)
+    // UNTESTED
+    pub fn o_16_loadb<M, P, V>(
+        memory: &Handle<M>,
+        pc: &mut P,
+        variables: &mut V,
+        operands: [ZOperand; 2],
+    ) -> Result<()>
+    where
+        M: Memory,
+        P: PC,
+        V: Variables,
+    {
+        let store = ZVariable::from(pc.next_byte());
+        debug!("loadb      {} {} -> {}", operands[0], operands[1], store);
+
+        let array = operands[0].value(variables)?;
+        let byte_index = operands[1].value(variables)?;
+
+        let byte_address = ByteAddress::from_raw(array).inc_by(byte_index);
+        let value = memory.borrow().read_byte(byte_address);
+        variables.write_variable(store, u16::from(value))
+    }
+
+    // ZSpec: 2OP:17 0x11 get_prop object property -> (result)
+    pub fn o_17_get_prop<M, P, V>(
+        objects: &ZObjectTable<M>,
+        pc: &mut P,
+        variables: &mut V,
+        violations: &mut ZViolations,
+        operands: [ZOperand; 2],
+    ) -> Result<()>
+    where
+        M: Memory,
+        P: PC,
+        V: Variables,
+    {
+        let store = ZVariable::from(pc.next_byte());
+        debug!("get_prop   {} {} -> {}", operands[0], operands[1], store);
+
+        let value = match operand_object(objects, variables, violations, "get_prop", operands[0])? {
+            Some(object) => {
+                objects.get_object_property(object, operands[1].value(variables)? as u8)?
+            }
+            None => 0,
+        };
+        variables.write_variable(store, value)
+    }
+
+    // ZSpec: 2OP:18 0x12 get_prop_addr object property -> (result)
+    pub fn o_18_get_prop_addr<M, P, V>(
+        objects: &ZObjectTable<M>,
+        pc: &mut P,
+        variables: &mut V,
+        violations: &mut ZViolations,
+        operands: [ZOperand; 2],
+    ) -> Result<()>
+    where
+        M: Memory,
+        P: PC,
+        V: Variables,
+    {
+        let store = ZVariable::from(pc.next_byte());
+        debug!("get_prop_addr {} {} -> {}", operands[0], operands[1], store);
+
+        // The address of the property's data, or 0 if the object lacks it.
+        let address =
+            match operand_object(objects, variables, violations, "get_prop_addr", operands[0])? {
+                Some(object) => objects
+                    .find_property(object, operands[1].value(variables)? as u8)
+                    .map_or(0, |prop| ZOffset::from(prop.data).value() as u16),
+                None => 0,
+            };
+        variables.write_variable(store, address)
+    }
+
+    // ZSpec: 2OP:19 0x13 get_next_prop object property -> (result)
+    pub fn o_19_get_next_prop<M, P, V>(
+        objects: &ZObjectTable<M>,
+        pc: &mut P,
+        variables: &mut V,
+        violations: &mut ZViolations,
+        operands: [ZOperand; 2],
+    ) -> Result<()>
+    where
+        M: Memory,
+        P: PC,
+        V: Variables,
+    {
+        let store = ZVariable::from(pc.next_byte());
+        debug!("get_next_prop {} {} -> {}", operands[0], operands[1], store);
+
+        let next =
+            match operand_object(objects, variables, violations, "get_next_prop", operands[0])? {
+                Some(object) => {
+                    objects.next_property(object, operands[1].value(variables)? as u8)?
+                }
+                None => 0,
+            };
+        variables.write_variable(store, u16::from(next))
+    }
+
+    // ZSpec: 2OP:20 0x14 add a b -> (result)
+    pub fn o_20_add<P, V>(pc: &mut P, variables: &mut V, operands: [ZOperand; 2]) -> Result<()>
+    where
+        P: PC,
+        V: Variables,
+    {
+        let store = pc.next_byte();
+        let variable = ZVariable::from(store);
+        debug!(
+            "add         {} {} -> {}",
+            operands[0], operands[1], variable
+        );
+
+        let lhs = operands[0].value(variables)?;
+        let rhs = operands[1].value(variables)?;
+
+        let (result, overflow) = lhs.overflowing_add(rhs);
+        if overflow {
+            warn!("add {:x} + {:x} causes overflow.", lhs, rhs);
+        }
+
+        variables.write_variable(variable, result)
+    }
+
+    // ZSpec: TODO
+    // UNTESTED
+    pub fn o_21_sub<P, V>(pc: &mut P, variables: &mut V, operands: [ZOperand; 2]) -> Result<()>
+    where
+        P: PC,
+        V: Variables,
+    {
+        let store = pc.next_byte();
+        let variable = ZVariable::from(store);
+        debug!(
+            "sub         {} {} -> {}",
+            operands[0], operands[1], variable
+        );
+
+        let lhs = operands[0].value(variables)? as i16;
+        let rhs = operands[1].value(variables)? as i16;
+
+        let (result, overflow) = lhs.overflowing_sub(rhs);
+        if overflow {
             warn!("sub {:x} - {:x} causes overflow.", lhs, rhs);
         }
 
-        variables.write_variable(variable, result as u16)
+        variables.write_variable(variable, result as u16)
+    }
+
+    // ZSpec: 2OP:22 0x16 mul a b -> (result)
+    pub fn o_22_mul<P, V>(pc: &mut P, variables: &mut V, operands: [ZOperand; 2]) -> Result<()>
+    where
+        P: PC,
+        V: Variables,
+    {
+        let store = ZVariable::from(pc.next_byte());
+        debug!("mul        {} {} -> {}", operands[0], operands[1], store);
+
+        let lhs = operands[0].value(variables)? as i16;
+        let rhs = operands[1].value(variables)? as i16;
+        variables.write_variable(store, lhs.wrapping_mul(rhs) as u16)
+    }
+
+    // ZSpec: 2OP:23 0x17 div a b -> (result)
+    pub fn o_23_div<P, V>(pc: &mut P, variables: &mut V, operands: [ZOperand; 2]) -> Result<()>
+    where
+        P: PC,
+        V: Variables,
+    {
+        let store = ZVariable::from(pc.next_byte());
+        debug!("div        {} {} -> {}", operands[0], operands[1], store);
+
+        // Division is signed, and rounds towards zero. (ZSpec 2.4.3)
+        let lhs = operands[0].value(variables)? as i16;
+        let rhs = operands[1].value(variables)? as i16;
+        if rhs == 0 {
+            return Err(ZErr::DivisionByZero);
+        }
+        variables.write_variable(store, lhs.wrapping_div(rhs) as u16)
+    }
+
+    // ZSpec: 2OP:24 0x18 mod a b -> (result)
+    pub fn o_24_mod<P, V>(pc: &mut P, variables: &mut V, operands: [ZOperand; 2]) -> Result<()>
+    where
+        P: PC,
+        V: Variables,
+    {
+        let store = ZVariable::from(pc.next_byte());
+        debug!("mod        {} {} -> {}", operands[0], operands[1], store);
+
+        // The result has the sign of the dividend. (ZSpec 2.4.3)
+        let lhs = operands[0].value(variables)? as i16;
+        let rhs = operands[1].value(variables)? as i16;
+        if rhs == 0 {
+            return Err(ZErr::DivisionByZero);
+        }
+        variables.write_variable(store, lhs.wrapping_rem(rhs) as u16)
+    }
+
+    // ZSpec: 2OP:25 0x19 V4 call_2s routine arg1 -> (result)
+    pub fn o_25_call_2s<H, P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        header: &H,
+        operands: [ZOperand; 2],
+    ) -> Result<()>
+    where
+        H: Header,
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        let store = ZVariable::from(pc.next_byte());
+        debug!("call_2s    {} {} -> {}", operands[0], operands[1], store);
+        call_operands(pc, stack, variables, header, &operands, Some(store))
+    }
+
+    // ZSpec: 2OP:26 0x1A V5 call_2n routine arg1
+    pub fn o_26_call_2n<H, P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        header: &H,
+        operands: [ZOperand; 2],
+    ) -> Result<()>
+    where
+        H: Header,
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        debug!("call_2n    {} {}", operands[0], operands[1]);
+        call_operands(pc, stack, variables, header, &operands, None)
+    }
+
+    // ZSpec: 2OP:27 0x1B V5 set_colour foreground background
+    //                    V6 set_colour foreground background window
+    pub fn o_27_set_colour<Scr, V>(
+        variables: &mut V,
+        screen: &Handle<Scr>,
+        violations: &mut ZViolations,
+        operands: [ZOperand; 2],
+    ) -> Result<()>
+    where
+        Scr: Screen,
+        V: Variables,
+    {
+        debug!("set_colour  {} {}", operands[0], operands[1]);
+
+        let foreground = operands[0].value(variables)?;
+        let background = operands[1].value(variables)?;
+        match (
+            Colour::from_palette(foreground),
+            Colour::from_palette(background),
+        ) {
+            (Some(fg), Some(bg)) => screen.borrow_mut().set_colour(fg, bg),
+            _ => violations.report(Violation::IllegalColour(foreground, background)),
+        }
+    }
+
+    // ZSpec: 2OP:28 0x1C V5 throw value stack-frame
+    //
+    // Return the value from the routine that called catch, discarding the
+    // routines that it called.
+    pub fn o_28_throw<P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        operands: [ZOperand; 2],
+    ) -> Result<()>
+    where
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        debug!("throw      {} {}", operands[0], operands[1]);
+
+        let value = operands[0].value(variables)?;
+        let frame = usize::from(operands[1].value(variables)?);
+        if frame > stack.borrow().frame_depth() {
+            return Err(ZErr::GenericError("throw to a routine that has returned"));
+        }
+        while stack.borrow().frame_depth() > frame {
+            stack.borrow_mut().pop_frame()?;
+        }
+        return_value(value, pc, stack, variables)
+    }
+}
+
+// The time and routine operands of read and read_char. While the player is
+// typing, the routine is called every time tenths of a second, and input
+// stops if it returns true. (ZSpec 10.4.2)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputTimer {
+    pub tenths: u16,
+    pub routine: u16,
+}
+
+impl InputTimer {
+    // Input is only timed if both operands are given, and non-zero.
+    fn from_operands<V>(
+        time: ZOperand,
+        routine: ZOperand,
+        variables: &mut V,
+    ) -> Result<Option<InputTimer>>
+    where
+        V: Variables,
+    {
+        let tenths = match time {
+            ZOperand::Omitted => 0,
+            operand => operand.value(variables)?,
+        };
+        let routine = match routine {
+            ZOperand::Omitted => 0,
+            operand => operand.value(variables)?,
+        };
+        if tenths == 0 || routine == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(InputTimer { tenths, routine }))
+        }
+    }
+
+    pub fn duration(self) -> Duration {
+        Duration::from_millis(u64::from(self.tenths) * 100)
+    }
+}
+
+pub mod var_op {
+    use super::*;
+
+    // ZSpec: VAR:224 0x00 V1 call routine ...up to 3 args... -> (result)
+    pub fn o_224_call<H, P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        header: &H,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        H: Header,
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        let store = ZVariable::from(pc.next_byte());
+        debug!(
+            "call        {} {} {} {} -> {}",
+            operands[0], operands[1], operands[2], operands[3], store
+        );
+
+        call_operands(pc, stack, variables, header, &operands, Some(store))
+    }
+
+    // ZSpec: VAR:225 0x01 storew array word-index value
+    pub fn o_225_storew<M, V>(
+        mem_h: &Handle<M>,
+        variables: &mut V,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        M: Memory,
+        V: Variables,
+    {
+        debug!(
+            "storew      {} {} {} {}",
+            operands[0], operands[1], operands[2], operands[3]
+        );
+
+        let array = operands[0].value(variables)?;
+        let word_index = operands[1].value(variables)?;
+        let value = operands[2].value(variables)?;
+
+        let ba = ByteAddress::from_raw(array).inc_by(word_index.wrapping_mul(2));
+        mem_h.borrow_mut().write_word(ba, value)
+    }
+
+    // ZSpec: VAR:226 0x02 storeb array byte-index value
+    pub fn o_226_storeb<M, V>(
+        memory: &Handle<M>,
+        variables: &mut V,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        M: Memory,
+        V: Variables,
+    {
+        debug!("storeb     {} {} {}", operands[0], operands[1], operands[2]);
+
+        let array = operands[0].value(variables)?;
+        let byte_index = operands[1].value(variables)?;
+        let value = operands[2].value(variables)?;
+
+        let byte_address = ByteAddress::from_raw(array).inc_by(byte_index);
+        memory.borrow_mut().write_byte(byte_address, value as u8)
+    }
+
+    // ZSpec: VAR:227 0x03 put_prop object property value
+    pub fn o_227_put_prop<M, V>(
+        objects: &ZObjectTable<M>,
+        variables: &mut V,
+        violations: &mut ZViolations,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        M: Memory,
+        V: Variables,
+    {
+        debug!("put_prop   {} {} {}", operands[0], operands[1], operands[2]);
+
+        let object = match operand_object(objects, variables, violations, "put_prop", operands[0])?
+        {
+            Some(object) => object,
+            None => return Ok(()),
+        };
+        let property = operands[1].value(variables)? as u8;
+        objects.set_object_property(object, property, operands[2].value(variables)?)
+    }
+
+    // A read that is waiting for the player. The processor collects the
+    // input, calling the timer routine if there is one, and then hands it to
+    // finish_read.
+    pub struct ReadRequest {
+        text: ByteAddress,
+        parse: u16,
+        store: Option<u8>,
+        pub terminators: Vec<u8>,
+        pub timer: Option<InputTimer>,
+    }
+
+    // ZSpec: VAR:228 0x04 V1 sread text parse
+    //                     V4 sread text parse time routine
+    //                     V5 aread text parse time routine -> (result)
+    pub fn o_228_read<H, M, P, Scr, V>(
+        memory: &Handle<M>,
+        pc: &mut P,
+        variables: &mut V,
+        header: &H,
+        screen: &Handle<Scr>,
+        operands: [ZOperand; 4],
+    ) -> Result<ReadRequest>
+    where
+        H: Header,
+        M: Memory,
+        P: PC,
+        Scr: Screen,
+        V: Variables,
+    {
+        let version = header.version_number();
+        let store = if version >= ZVersion::V5 {
+            Some(pc.next_byte())
+        } else {
+            None
+        };
+        debug!(
+            "read       {} {} {} {}",
+            operands[0], operands[1], operands[2], operands[3]
+        );
+
+        let text = ByteAddress::from_raw(operands[0].value(variables)?);
+        let parse = match operands[1] {
+            ZOperand::Omitted => 0,
+            operand => operand.value(variables)?,
+        };
+        let timer = if version >= ZVersion::V4 {
+            InputTimer::from_operands(operands[2], operands[3], variables)?
+        } else {
+            None
+        };
+
+        // Anything still buffered must be visible before the player types.
+        screen.borrow_mut().flush()?;
+
+        let terminators = header
+            .terminating_chars_location()
+            .map_or_else(Vec::new, |table| read_terminators(&*memory.borrow(), table));
+        Ok(ReadRequest {
+            text,
+            parse,
+            store,
+            terminators,
+            timer,
+        })
+    }
+
+    // The keyboard echoes what the player types, but commands played back
+    // from a file must be shown explicitly.
+    pub fn echo_command<I, Scr>(input: &I, screen: &Handle<Scr>, line: &str) -> Result<()>
+    where
+        I: InputStreams,
+        Scr: Screen,
+    {
+        if input.current_stream() == COMMAND_FILE_STREAM {
+            let mut screen = screen.borrow_mut();
+            screen.print(line)?;
+            screen.print("\n")?;
+        }
+        Ok(())
+    }
+
+    // Store the player's input for a read. A terminator of 0 means that the
+    // timer routine stopped the input, and line holds whatever had been typed.
+    pub fn finish_read<H, M, O, V>(
+        memory: &Handle<M>,
+        variables: &mut V,
+        header: &H,
+        streams: &mut O,
+        request: ReadRequest,
+        line: &str,
+        terminator: u8,
+    ) -> Result<()>
+    where
+        H: Header,
+        M: Memory,
+        O: OutputStreams,
+        V: Variables,
+    {
+        let version = header.version_number();
+        let ReadRequest {
+            text, parse, store, ..
+        } = request;
+
+        // An interrupted command isn't worth playing back.
+        if terminator != 0 {
+            streams.record_line(line)?;
+        }
+
+        // Input is stored in lower case. (ZSpec 15 read)
+        // Characters that the story can't represent become '?'.
+        let typed: Vec<u8> = {
+            let mem = memory.borrow();
+            line.chars()
+                .flat_map(char::to_lowercase)
+                .map(|ch| char_to_zscii(&*mem, ch).unwrap_or(b'?'))
+                .collect()
+        };
+
+        {
+            let mut mem = memory.borrow_mut();
+            let max = mem.read_byte(text);
+            if version >= ZVersion::V5 {
+                // Byte 1 holds the number of characters already in the buffer.
+                // New input is appended to them.
+                let existing = mem.read_byte(text.inc_by(1));
+                let count = typed.len().min(usize::from(max.saturating_sub(existing)));
+                let start = text.inc_by(2 + u16::from(existing));
+                for (idx, byte) in typed.iter().take(count).enumerate() {
+                    mem.write_byte(start.inc_by(idx as u16), *byte)?;
+                }
+                mem.write_byte(text.inc_by(1), existing + count as u8)?;
+            } else {
+                // The text is zero terminated, so one byte of the buffer is
+                // unavailable to the player.
+                let count = typed.len().min(usize::from(max.saturating_sub(1)));
+                for (idx, byte) in typed.iter().take(count).enumerate() {
+                    mem.write_byte(text.inc_by(1 + idx as u16), *byte)?;
+                }
+                mem.write_byte(text.inc_by(1 + count as u16), 0)?;
+            }
+        }
+
+        // In V5, a parse buffer of 0 means that the text is not tokenised.
+        if parse != 0 {
+            let dictionary = ZDictionary::from_header(header, &*memory.borrow());
+            lexer::tokenise(
+                memory,
+                version,
+                text,
+                ByteAddress::from_raw(parse),
+                &dictionary,
+                false,
+            )?;
+        }
+
+        if let Some(store) = store {
+            variables.write_variable(store.into(), u16::from(terminator))?;
+        }
+        Ok(())
+    }
+
+    // The terminating characters table is a zero-terminated list of
+    // function key codes. (ZSpec 11.1.8)
+    fn read_terminators<M>(memory: &M, table: ByteAddress) -> Vec<u8>
+    where
+        M: Memory,
+    {
+        let mut terminators = Vec::new();
+        let mut idx = 0;
+        loop {
+            let byte = memory.read_byte(table.inc_by(idx));
+            if byte == 0 {
+                break;
+            }
+            terminators.push(byte);
+            idx += 1;
+        }
+        terminators
+    }
+
+    // ZSpec: VAR:229 0x05 print_char output_character_code
+    pub fn o_229_print_char<M, O, V>(
+        memory: &Handle<M>,
+        variables: &mut V,
+        streams: &mut O,
+        violations: &mut ZViolations,
+        version: ZVersion,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        M: Memory,
+        O: OutputStreams,
+        V: Variables,
+    {
+        debug!("print_char {}", operands[0]);
+        // TODO: deal with the case where extra argements are passed.
+        //       stuff will break if an extra SP arg is passed, but never popped.
+        let zscii = operands[0].value(variables)?;
+        let mut buf = [0; 4];
+        match zscii_for_output(&*memory.borrow(), version, zscii, violations, &mut buf)? {
+            Some(text) => streams.print_str(text),
+            None => Ok(()),
+        }
+    }
+
+    // ZSpec: VAR:230 0x06 print_num value
+    pub fn o_230_print_num<O, V>(
+        variables: &mut V,
+        streams: &mut O,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        O: OutputStreams,
+        V: Variables,
+    {
+        debug!(
+            "print_num  {} {} {} {}",
+            operands[0], operands[1], operands[2], operands[3]
+        );
+
+        let num = operands[0].value(variables)?;
+        streams.print_str(&(num as i16).to_string())
+    }
+
+    // ZSpec: VAR:231 0x07 random range -> (result)
+    pub fn o_231_random<P, V>(
+        pc: &mut P,
+        variables: &mut V,
+        random: &mut ZRandom,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        P: PC,
+        V: Variables,
+    {
+        let range = operands[0].value(variables)? as i16;
+        let variable = ZVariable::from(pc.next_byte());
+        debug!("random     {} -> {}", range, variable);
+
+        // A negative range seeds the generator, and zero reseeds it
+        // unpredictably. Both return 0.
+        let result = match range {
+            0 => {
+                random.reseed();
+                0
+            }
+            range if range < 0 => {
+                random.seed(range.wrapping_neg() as u16);
+                0
+            }
+            range => random.next(range as u16),
+        };
+        variables.write_variable(variable, result)
+    }
+
+    // ZSpec: VAR:232 0x08 push value
+    pub fn o_232_push<V>(variables: &mut V, operands: [ZOperand; 4]) -> Result<()>
+    where
+        V: Variables,
+    {
+        debug!("push       {}", operands[0]);
+
+        let value = operands[0].value(variables)?;
+        variables.write_variable(ZVariable::Stack, value)
+    }
+
+    // ZSpec: VAR:233 0x09 V1 pull (variable)
+    //                     V6 pull stack -> (result)
+    pub fn o_233_pull<M, P, S, V>(
+        memory: &Handle<M>,
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        version: ZVersion,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        M: Memory,
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        if version == ZVersion::V6 {
+            let variable = ZVariable::from(pc.next_byte());
+            debug!("pull       {} -> {}", operands[0], variable);
+
+            // Without an operand, the value comes from the game's stack.
+            let value = match operands[0] {
+                ZOperand::Omitted => stack.borrow_mut().pop_word()?,
+                operand => {
+                    pop_user_stack(memory, ByteAddress::from_raw(operand.value(variables)?))?
+                }
+            };
+            return variables.write_variable(variable, value);
+        }
+
+        let variable = variable_operand(operands[0], variables)?;
+        debug!("pull       {}", variable);
+
+        let value = stack.borrow_mut().pop_word()?;
+        variables.poke_variable(variable, value)
+    }
+
+    // A V6 user stack is a table whose first word counts the free slots
+    // that follow it. The values are stored after the free slots, so the top
+    // value is in the slot after the last free one. (ZSpec 15 push_stack)
+    fn pop_user_stack<M>(memory: &Handle<M>, table: ByteAddress) -> Result<u16>
+    where
+        M: Memory,
+    {
+        let mut memory = memory.borrow_mut();
+        let free = memory.read_word(table).wrapping_add(1);
+        memory.write_word(table, free)?;
+        Ok(memory.read_word(table.inc_by(free.wrapping_mul(2))))
+    }
+
+    // ZSpec: VAR:234 0x0A V3 split_window lines
+    pub fn o_234_split_window<Scr, V>(
+        variables: &mut V,
+        screen: &Handle<Scr>,
+        version: ZVersion,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        Scr: Screen,
+        V: Variables,
+    {
+        debug!("split_window {}", operands[0]);
+
+        let lines = operands[0].value(variables)?;
+        let mut screen = screen.borrow_mut();
+        screen.split_window(lines)?;
+
+        // In V3 only, the upper window is cleared after a split. (ZSpec 8.6.1.1.2)
+        if version == ZVersion::V3 {
+            screen.erase_window(screen::UPPER_WINDOW)?;
+        }
+        Ok(())
+    }
+
+    // ZSpec: VAR:235 0x0B V3 set_window window
+    pub fn o_235_set_window<Scr, V>(
+        variables: &mut V,
+        screen: &Handle<Scr>,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        Scr: Screen,
+        V: Variables,
+    {
+        debug!("set_window {}", operands[0]);
+
+        let window = operands[0].value(variables)?;
+        screen.borrow_mut().set_window(window)
+    }
+
+    // ZSpec: VAR:236 0x0C V4 call_vs2 routine ...up to 7 args... -> (result)
+    pub fn o_236_call_vs2<H, P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        header: &H,
+        operands: [ZOperand; 8],
+    ) -> Result<()>
+    where
+        H: Header,
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        let store = ZVariable::from(pc.next_byte());
+        debug!("call_vs2   {} ... -> {}", operands[0], store);
+        call_operands(pc, stack, variables, header, &operands, Some(store))
+    }
+
+    // ZSpec: VAR:237 0x0D V4 erase_window window
+    pub fn o_237_erase_window<Scr, V>(
+        variables: &mut V,
+        screen: &Handle<Scr>,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        Scr: Screen,
+        V: Variables,
+    {
+        debug!("erase_window {}", operands[0]);
+
+        let window = operands[0].value(variables)? as i16;
+        let mut screen = screen.borrow_mut();
+        match window {
+            // Unsplit the screen, select the lower window and clear it all. (ZSpec 8.7.3.3)
+            -1 => {
+                screen.split_window(0)?;
+                screen.set_window(screen::LOWER_WINDOW)?;
+                screen.erase_window(screen::LOWER_WINDOW)
+            }
+            // Clear the whole screen, but leave the windows alone.
+            -2 => {
+                screen.erase_window(screen::UPPER_WINDOW)?;
+                screen.erase_window(screen::LOWER_WINDOW)
+            }
+            w => screen.erase_window(w as u16),
+        }
+    }
+
+    // ZSpec: VAR:238 0x0E V4 erase_line value
+    pub fn o_238_erase_line<Scr, V>(
+        variables: &mut V,
+        screen: &Handle<Scr>,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        Scr: Screen,
+        V: Variables,
+    {
+        debug!("erase_line {}", operands[0]);
+
+        // Only a value of 1 does anything outside of V6. (ZSpec 15 erase_line)
+        if operands[0].value(variables)? == 1 {
+            screen.borrow_mut().erase_line()
+        } else {
+            Ok(())
+        }
+    }
+
+    // ZSpec: VAR:239 0x0F V4 set_cursor line column
+    pub fn o_239_set_cursor<Scr, V>(
+        variables: &mut V,
+        screen: &Handle<Scr>,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        Scr: Screen,
+        V: Variables,
+    {
+        debug!("set_cursor {} {}", operands[0], operands[1]);
+
+        let line = operands[0].value(variables)?;
+        let column = operands[1].value(variables)?;
+        screen.borrow_mut().set_cursor(line, column)
+    }
+
+    // ZSpec: VAR:240 0x10 V4 get_cursor array
+    pub fn o_240_get_cursor<M, Scr, V>(
+        memory: &Handle<M>,
+        variables: &mut V,
+        screen: &Handle<Scr>,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        M: Memory,
+        Scr: Screen,
+        V: Variables,
+    {
+        debug!("get_cursor {}", operands[0]);
+
+        let array = ByteAddress::from_raw(operands[0].value(variables)?);
+        let (line, column) = screen.borrow().get_cursor();
+
+        let mut memory = memory.borrow_mut();
+        memory.write_word(array, line)?;
+        memory.write_word(array.inc_by(2), column)
+    }
+
+    // ZSpec: VAR:241 0x11 V4 set_text_style style
+    pub fn o_241_set_text_style<O, V>(
+        variables: &mut V,
+        streams: &mut O,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        O: OutputStreams,
+        V: Variables,
+    {
+        debug!("set_text_style {}", operands[0]);
+
+        let style = operands[0].value(variables)?;
+        streams.set_text_style(TextStyle::from_raw(style as u8))
+    }
+
+    // ZSpec: VAR:242 0x12 V4 buffer_mode flag
+    pub fn o_242_buffer_mode<Scr, V>(
+        variables: &mut V,
+        screen: &Handle<Scr>,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        Scr: Screen,
+        V: Variables,
+    {
+        debug!("buffer_mode {}", operands[0]);
+
+        let flag = operands[0].value(variables)?;
+        screen.borrow_mut().set_buffer_mode(flag != 0)
+    }
+
+    // ZSpec: VAR:243 0x13 V3 output_stream number
+    //                     V5 output_stream number table
+    //                     V6 output_stream number table width
+    pub fn o_243_output_stream<O, V>(
+        variables: &mut V,
+        streams: &mut O,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        O: OutputStreams,
+        V: Variables,
+    {
+        debug!(
+            "output_stream {} {} {}",
+            operands[0], operands[1], operands[2]
+        );
+
+        // A positive number selects a stream, a negative number deselects it,
+        // and zero does nothing. (ZSpec 7.1.1)
+        let number = operands[0].value(variables)? as i16;
+        if number > 0 {
+            let table = match operands[1] {
+                ZOperand::Omitted => None,
+                operand => Some(ByteAddress::from_raw(operand.value(variables)?)),
+            };
+            // TODO: the V6 width operand is ignored.
+            streams.select_stream(number as u8, table)
+        } else if number < 0 {
+            streams.deselect_stream(-number as u8)
+        } else {
+            Ok(())
+        }
+    }
+
+    // ZSpec: VAR:244 0x14 V3 input_stream number
+    pub fn o_244_input_stream<I, V>(
+        variables: &mut V,
+        input: &mut I,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        I: InputStreams,
+        V: Variables,
+    {
+        debug!("input_stream {}", operands[0]);
+
+        let number = operands[0].value(variables)?;
+        input.select_input_stream(number as u8)
+    }
+
+    // ZSpec: VAR:245 0x15 V5/3 sound_effect number effect volume routine
+    pub fn o_245_sound_effect<A, H, V>(
+        variables: &mut V,
+        sound: &mut A,
+        header: &H,
+        violations: &mut ZViolations,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        A: SoundPlayer,
+        H: Header,
+        V: Variables,
+    {
+        let version = header.version_number();
+        debug!(
+            "sound_effect {} {} {} {}",
+            operands[0], operands[1], operands[2], operands[3]
+        );
+
+        // With no operands, just beep. (ZSpec 9.2.1)
+        let number = match operands[0] {
+            ZOperand::Omitted => sound::HIGH_BLEEP,
+            operand => operand.value(variables)?,
+        };
+
+        match number {
+            sound::HIGH_BLEEP => sound.bleep(true),
+            sound::LOW_BLEEP => sound.bleep(false),
+            _ => {
+                let effect = operands[1].value(variables)?;
+                match effect {
+                    sound::EFFECT_PREPARE => sound.prepare(number),
+                    sound::EFFECT_START => {
+                        let (volume, repeats) = match operands[2] {
+                            ZOperand::Omitted => (0xff, 0),
+                            operand => sound::unpack_volume(operand.value(variables)?),
+                        };
+                        // Repeats and the finish routine were added in V5. (ZSpec 9.2.3.1)
+                        let repeats = if version < ZVersion::V5 { 0 } else { repeats };
+                        let routine = match operands[3] {
+                            _ if version < ZVersion::V5 => None,
+                            ZOperand::Omitted => None,
+                            operand => match operand.value(variables)? {
+                                0 => None,
+                                val => Some(header.routine_address(val)),
+                            },
+                        };
+                        sound.start(number, volume, repeats, routine)
+                    }
+                    sound::EFFECT_STOP => sound.stop(number),
+                    sound::EFFECT_FINISH_WITH => sound.finish_with(number),
+                    _ => violations.report(Violation::UnknownSoundEffect(effect)),
+                }
+            }
+        }
+    }
+
+    // A read_char that is waiting for a key, as for ReadRequest.
+    pub struct ReadCharRequest {
+        store: ZVariable,
+        pub timer: Option<InputTimer>,
+    }
+
+    // ZSpec: VAR:246 0x16 V4 read_char 1 time routine -> (result)
+    pub fn o_246_read_char<P, Scr, V>(
+        pc: &mut P,
+        variables: &mut V,
+        screen: &Handle<Scr>,
+        operands: [ZOperand; 4],
+    ) -> Result<ReadCharRequest>
+    where
+        P: PC,
+        Scr: Screen,
+        V: Variables,
+    {
+        let store = ZVariable::from(pc.next_byte());
+        debug!(
+            "read_char  {} {} {} -> {}",
+            operands[0], operands[1], operands[2], store
+        );
+
+        // The first operand is always 1, the keyboard.
+        let timer = InputTimer::from_operands(operands[1], operands[2], variables)?;
+        screen.borrow_mut().flush()?;
+        Ok(ReadCharRequest { store, timer })
+    }
+
+    // Store the key for a read_char. 0 means that the timer routine stopped
+    // the input.
+    pub fn finish_read_char<M, O, V>(
+        memory: &Handle<M>,
+        variables: &mut V,
+        streams: &mut O,
+        request: ReadCharRequest,
+        key: u8,
+    ) -> Result<()>
+    where
+        M: Memory,
+        O: OutputStreams,
+        V: Variables,
+    {
+        let key = translate_key(&*memory.borrow(), key);
+        if key != 0 {
+            streams.record_key(key)?;
+        }
+        variables.write_variable(request.store, u16::from(key))
+    }
+
+    // ZSpec: VAR:247 0x17 V4 scan_table x table len form -> (result) ?(label)
+    pub fn o_247_scan_table<M, P, S, V>(
+        memory: &Handle<M>,
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        M: Memory,
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        let variable = ZVariable::from(pc.next_byte());
+
+        let x = operands[0].value(variables)?;
+        let table = operands[1].value(variables)?;
+        let len = operands[2].value(variables)?;
+        // The top bit of form chooses words or bytes, and the rest is the
+        // length of each field. By default, the fields are words.
+        let form = match operands[3] {
+            ZOperand::Omitted => 0x82,
+            operand => operand.value(variables)?,
+        };
+        let field_len = form & 0x7f;
+
+        let mut found = 0;
+        {
+            let memory = memory.borrow();
+            let mut address = table;
+            for _ in 0..len {
+                let value = if form & 0x80 != 0 {
+                    memory.read_word(ByteAddress::from_raw(address))
+                } else {
+                    u16::from(memory.read_byte(ByteAddress::from_raw(address)))
+                };
+                if value == x {
+                    found = address;
+                    break;
+                }
+                address = address.wrapping_add(field_len);
+            }
+        }
+        variables.write_variable(variable, found)?;
+
+        let first_offset_byte = pc.next_byte();
+        branch(
+            first_offset_byte,
+            pc,
+            stack,
+            variables,
+            |offset, branch_on_truth, _| {
+                debug!(
+                    "scan_table {} {} {} {} -> {} ?{}(x{:x})",
+                    operands[0],
+                    operands[1],
+                    operands[2],
+                    operands[3],
+                    variable,
+                    if branch_on_truth { "" } else { "~" },
+                    offset
+                );
+                Ok(found != 0)
+            },
+        )
+    }
+
+    // ZSpec: VAR:248 0x18 V5 not value -> (result)
+    pub fn o_248_not<P, V>(pc: &mut P, variables: &mut V, operands: [ZOperand; 4]) -> Result<()>
+    where
+        P: PC,
+        V: Variables,
+    {
+        one_op::o_143_not(pc, variables, operands[0])
+    }
+
+    // ZSpec: VAR:249 0x19 V5 call_vn routine ...up to 3 args...
+    pub fn o_249_call_vn<H, P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        header: &H,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        H: Header,
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        debug!(
+            "call_vn    {} {} {} {}",
+            operands[0], operands[1], operands[2], operands[3]
+        );
+        call_operands(pc, stack, variables, header, &operands, None)
+    }
+
+    // ZSpec: VAR:250 0x1A V5 call_vn2 routine ...up to 7 args...
+    pub fn o_250_call_vn2<H, P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        header: &H,
+        operands: [ZOperand; 8],
+    ) -> Result<()>
+    where
+        H: Header,
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        debug!("call_vn2   {} ...", operands[0]);
+        call_operands(pc, stack, variables, header, &operands, None)
+    }
+
+    // ZSpec: VAR:251 0x1b V5 tokenise text parse dictionary flag
+    pub fn o_251_tokenise<H, M, V>(
+        memory: &Handle<M>,
+        variables: &mut V,
+        header: &H,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        H: Header,
+        M: Memory,
+        V: Variables,
+    {
+        debug!(
+            "tokenise   {} {} {} {}",
+            operands[0], operands[1], operands[2], operands[3]
+        );
+        let text = ByteAddress::from_raw(operands[0].value(variables)?);
+        let parse = ByteAddress::from_raw(operands[1].value(variables)?);
+
+        // The game may give its own dictionary, which may be unsorted. With
+        // the flag set, words that aren't in it are left alone in the parse
+        // buffer. (ZSpec 15 tokenise)
+        let dictionary = match operands[2] {
+            ZOperand::Omitted => 0,
+            operand => operand.value(variables)?,
+        };
+        let dictionary = match dictionary {
+            0 => ZDictionary::from_header(header, &*memory.borrow()),
+            address => ZDictionary::new(&*memory.borrow(), ByteAddress::from_raw(address)),
+        };
+        let skip_unknown = match operands[3] {
+            ZOperand::Omitted => false,
+            operand => operand.value(variables)? != 0,
+        };
+
+        lexer::tokenise(
+            memory,
+            header.version_number(),
+            text,
+            parse,
+            &dictionary,
+            skip_unknown,
+        )
+    }
+
+    // ZSpec: VAR:252 0x1c V5 encode_text zscii-text length from coded-text
+    pub fn o_252_encode_text<M, V>(
+        memory: &Handle<M>,
+        variables: &mut V,
+        version: ZVersion,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        M: Memory,
+        V: Variables,
+    {
+        debug!(
+            "encode_text {} {} {} {}",
+            operands[0], operands[1], operands[2], operands[3]
+        );
+        let zscii_text = operands[0].value(variables)?;
+        let length = operands[1].value(variables)?;
+        let from = operands[2].value(variables)?;
+        let coded_text = ByteAddress::from_raw(operands[3].value(variables)?);
+
+        // The word is encoded as it would be for the dictionary.
+        let start = ByteAddress::from_raw(zscii_text.wrapping_add(from));
+        let word: String = {
+            let mem = memory.borrow();
+            (0..length)
+                .map(|idx| zscii_to_char(&*mem, u16::from(mem.read_byte(start.inc_by(idx)))))
+                .collect()
+        };
+        let alphabets = ZAlphabet::from_story(&*memory.borrow(), version);
+        let encoded = encode_zstr_in(&alphabets, &word, version.dictionary_word_length(), version);
+
+        let mut mem = memory.borrow_mut();
+        for (idx, byte) in encoded.iter().enumerate() {
+            mem.write_byte(coded_text.inc_by(idx as u16), *byte)?;
+        }
+        Ok(())
+    }
+
+    // ZSpec: VAR:253 0x1D V5 copy_table first second size
+    pub fn o_253_copy_table<M, V>(
+        memory: &Handle<M>,
+        variables: &mut V,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        M: Memory,
+        V: Variables,
+    {
+        debug!("copy_table {} {} {}", operands[0], operands[1], operands[2]);
+
+        let first = ByteAddress::from_raw(operands[0].value(variables)?);
+        let second = operands[1].value(variables)?;
+        let size = operands[2].value(variables)? as i16;
+        let len = size.unsigned_abs();
+
+        let mut memory = memory.borrow_mut();
+        // With no second table, the first is zeroed.
+        if second == 0 {
+            for idx in 0..len {
+                memory.write_byte(first.inc_by(idx), 0)?;
+            }
+            return Ok(());
+        }
+
+        // The copy must not be spoiled by the tables overlapping, unless the
+        // size is negative, which asks for a forward copy regardless.
+        // (ZSpec 15 copy_table)
+        let second = ByteAddress::from_raw(second);
+        if size < 0 || ZOffset::from(second).value() < ZOffset::from(first).value() {
+            for idx in 0..len {
+                let byte = memory.read_byte(first.inc_by(idx));
+                memory.write_byte(second.inc_by(idx), byte)?;
+            }
+        } else {
+            for idx in (0..len).rev() {
+                let byte = memory.read_byte(first.inc_by(idx));
+                memory.write_byte(second.inc_by(idx), byte)?;
+            }
+        }
+        Ok(())
+    }
+
+    // ZSpec: VAR:254 0x1E V5 print_table zscii-text width height skip
+    //
+    // Each row starts below the last, at the column where the first began.
+    pub fn o_254_print_table<M, O, Scr, V>(
+        memory: &Handle<M>,
+        variables: &mut V,
+        streams: &mut O,
+        screen: &Handle<Scr>,
+        violations: &mut ZViolations,
+        version: ZVersion,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        M: Memory,
+        O: OutputStreams,
+        Scr: Screen,
+        V: Variables,
+    {
+        debug!(
+            "print_table {} {} {} {}",
+            operands[0], operands[1], operands[2], operands[3]
+        );
+
+        let mut address = ByteAddress::from_raw(operands[0].value(variables)?);
+        let width = operands[1].value(variables)?;
+        let height = match operands[2] {
+            ZOperand::Omitted => 1,
+            operand => operand.value(variables)?,
+        };
+        let skip = match operands[3] {
+            ZOperand::Omitted => 0,
+            operand => operand.value(variables)?,
+        };
+
+        let (line, column) = screen.borrow().get_cursor();
+        for row in 0..height {
+            if row > 0 {
+                streams.print_str("\n")?;
+                screen.borrow_mut().set_cursor(line + row, column)?;
+            }
+
+            // Printing may write to memory, if stream 3 is selected.
+            let text = {
+                let memory = memory.borrow();
+                let mut text = String::new();
+                let mut buf = [0; 4];
+                for idx in 0..width {
+                    let zscii = u16::from(memory.read_byte(address.inc_by(idx)));
+                    if let Some(ch) =
+                        zscii_for_output(&*memory, version, zscii, violations, &mut buf)?
+                    {
+                        text.push_str(ch);
+                    }
+                }
+                text
+            };
+            streams.print_str(&text)?;
+            address = address.inc_by(width.wrapping_add(skip));
+        }
+        Ok(())
+    }
+
+    // ZSpec: VAR:255 0x1F V5 check_arg_count argument-number ?(label)
+    pub fn o_255_check_arg_count<P, S, V>(
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        P: PC,
+        S: Stack,
+        V: Variables,
+    {
+        let first_offset_byte = pc.next_byte();
+        let num_args = stack.borrow().num_args();
+        branch(
+            first_offset_byte,
+            pc,
+            stack,
+            variables,
+            |offset, branch_on_truth, variables| {
+                debug!(
+                    "check_arg_count {} ?{}(x{:x})",
+                    operands[0],
+                    if branch_on_truth { "" } else { "~" },
+                    offset
+                );
+
+                // Branch if the argument was passed to the current routine.
+                Ok(operands[0].value(variables)? <= u16::from(num_args))
+            },
+        )
+    }
+}
+
+pub mod ext_op {
+    use super::*;
+
+    // ZSpec: EXT:0 0x00 V5 save table bytes name prompt -> (result)
+    //
+    // As for 0OP:181, the processor has already tried to save.
+    pub fn o_0_save<P, V>(
+        pc: &mut P,
+        variables: &mut V,
+        operands: [ZOperand; 4],
+        saved: bool,
+    ) -> Result<()>
+    where
+        P: PC,
+        V: Variables,
+    {
+        let variable = ZVariable::from(pc.next_byte());
+        debug!(
+            "save       {} {} {} -> {}",
+            operands[0], operands[1], operands[2], variable
+        );
+        variables.write_variable(variable, u16::from(saved))
+    }
+
+    // ZSpec: EXT:1 0x01 V5 restore table bytes name prompt -> (result)
+    //
+    // As for 0OP:182, a successful restore stores 2 from the save's store
+    // byte.
+    pub fn o_1_restore<P, V>(
+        pc: &mut P,
+        variables: &mut V,
+        operands: [ZOperand; 4],
+        restored: bool,
+    ) -> Result<()>
+    where
+        P: PC,
+        V: Variables,
+    {
+        let variable = ZVariable::from(pc.next_byte());
+        debug!(
+            "restore    {} {} {} -> {}",
+            operands[0], operands[1], operands[2], variable
+        );
+        variables.write_variable(variable, if restored { 2 } else { 0 })
+    }
+
+    // ZSpec: EXT:2 0x02 V5 log_shift number places -> (result)
+    pub fn o_2_log_shift<P, V>(pc: &mut P, variables: &mut V, operands: [ZOperand; 4]) -> Result<()>
+    where
+        P: PC,
+        V: Variables,
+    {
+        let variable = ZVariable::from(pc.next_byte());
+        debug!(
+            "log_shift   {} {} -> {}",
+            operands[0], operands[1], variable
+        );
+
+        let number = operands[0].value(variables)?;
+        let places = operands[1].value(variables)? as i16;
+
+        // Positive places shift left, negative shift right. (ZSpec 15: log_shift)
+        let result = match places {
+            0..=15 => number << places,
+            -15..=-1 => number >> -places,
+            _ => 0,
+        };
+        variables.write_variable(variable, result)
+    }
+
+    // ZSpec: EXT:3 0x03 V5 art_shift number places -> (result)
+    pub fn o_3_art_shift<P, V>(pc: &mut P, variables: &mut V, operands: [ZOperand; 4]) -> Result<()>
+    where
+        P: PC,
+        V: Variables,
+    {
+        let variable = ZVariable::from(pc.next_byte());
+        debug!(
+            "art_shift   {} {} -> {}",
+            operands[0], operands[1], variable
+        );
+
+        let number = operands[0].value(variables)? as i16;
+        let places = operands[1].value(variables)? as i16;
+
+        // Right shifts preserve the sign. (ZSpec 15: art_shift)
+        let result = match places {
+            0..=15 => number << places,
+            -15..=-1 => number >> -places,
+            _ if number < 0 => -1,
+            _ => 0,
+        };
+        variables.write_variable(variable, result as u16)
+    }
+
+    // ZSpec: EXT:4 0x04 V5 set_font font -> (result)
+    //
+    // Only the normal font (1) and the fixed-pitch font (4) are available.
+    // The result is the previous font, or 0 if the font is unavailable. Font
+    // 0 asks for the current font without changing it. (ZSpec 8.1.3)
+    pub fn o_4_set_font<P, V>(
+        pc: &mut P,
+        variables: &mut V,
+        font: &mut u16,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        P: PC,
+        V: Variables,
+    {
+        let variable = ZVariable::from(pc.next_byte());
+        debug!("set_font    {} -> {}", operands[0], variable);
+
+        let result = match operands[0].value(variables)? {
+            0 => *font,
+            requested @ 1 | requested @ 4 => std::mem::replace(font, requested),
+            _ => 0,
+        };
+        variables.write_variable(variable, result)
+    }
+
+    // ZSpec: EXT:9 0x09 V5 save_undo -> (result)
+    //
+    // Up to `levels` states are kept. With no levels, undo is unavailable,
+    // and save_undo returns -1.
+    pub fn o_9_save_undo<H, M, P, S, V>(
+        memory: &Handle<M>,
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        header: &H,
+        undo: &mut ZUndoRing<S>,
+        levels: usize,
+    ) -> Result<()>
+    where
+        H: Header,
+        M: Memory,
+        P: PC,
+        S: Stack + Clone,
+        V: Variables,
+    {
+        // Capture the state before reading the store byte, so that
+        // restore_undo can read it again.
+        let state = ZSnapshot::capture(memory, header.static_memory_base(), stack, pc);
+        let variable = ZVariable::from(pc.next_byte());
+        debug!("save_undo   -> {}", variable);
+
+        if levels == 0 {
+            return variables.write_variable(variable, -1i16 as u16);
+        }
+        undo.push(state, levels);
+        variables.write_variable(variable, 1)
+    }
+
+    // ZSpec: EXT:10 0x0A V5 restore_undo -> (result)
+    pub fn o_10_restore_undo<M, P, S, V>(
+        memory: &Handle<M>,
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        undo: &mut ZUndoRing<S>,
+    ) -> Result<()>
+    where
+        M: Memory,
+        P: PC,
+        S: Stack + Clone,
+        V: Variables,
+    {
+        let variable = ZVariable::from(pc.next_byte());
+        debug!("restore_undo -> {}", variable);
+
+        match undo.pop() {
+            None => variables.write_variable(variable, 0),
+            Some(state) => resume_undo(memory, pc, stack, variables, &state),
+        }
+    }
+
+    // Execution continues from the save_undo that captured the state, which
+    // now stores 2.
+    pub fn resume_undo<M, P, S, V>(
+        memory: &Handle<M>,
+        pc: &mut P,
+        stack: &Handle<S>,
+        variables: &mut V,
+        state: &ZSnapshot<S>,
+    ) -> Result<()>
+    where
+        M: Memory,
+        P: PC,
+        S: Stack + Clone,
+        V: Variables,
+    {
+        state.restore(memory, stack, pc)?;
+        let variable = ZVariable::from(pc.next_byte());
+        variables.write_variable(variable, 2)
+    }
+
+    // ZSpec: EXT:11 0x0B V5 print_unicode char-number
+    pub fn o_11_print_unicode<O, V>(
+        variables: &mut V,
+        streams: &mut O,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        O: OutputStreams,
+        V: Variables,
+    {
+        debug!("print_unicode {}", operands[0]);
+
+        let ch = std::char::from_u32(u32::from(operands[0].value(variables)?)).unwrap_or('?');
+        streams.print_str(&ch.to_string())
+    }
+
+    // ZSpec: EXT:12 0x0C V5 check_unicode char-number -> (result)
+    pub fn o_12_check_unicode<M, P, V>(
+        memory: &Handle<M>,
+        pc: &mut P,
+        variables: &mut V,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        M: Memory,
+        P: PC,
+        V: Variables,
+    {
+        let variable = ZVariable::from(pc.next_byte());
+        debug!("check_unicode {} -> {}", operands[0], variable);
+
+        // Bit 0 means the character can be printed, bit 1 that it can be
+        // typed. Anything printable goes straight to the terminal, but typed
+        // characters must be in the story's Unicode table as well as the
+        // keyboard's. (ZSpec 3.8.5.4)
+        let ch = std::char::from_u32(u32::from(operands[0].value(variables)?));
+        let result = match ch {
+            Some(ch) if !ch.is_control() => {
+                let typeable =
+                    key_to_zscii(ch).is_some() && char_to_zscii(&*memory.borrow(), ch).is_some();
+                if typeable {
+                    0b11
+                } else {
+                    0b01
+                }
+            }
+            _ => 0,
+        };
+        variables.write_variable(variable, result)
+    }
+
+    // ZSpec: EXT:13 0x0D V5 set_true_colour foreground background
+    //                    V6 set_true_colour foreground background window
+    pub fn o_13_set_true_colour<Scr, V>(
+        variables: &mut V,
+        screen: &Handle<Scr>,
+        violations: &mut ZViolations,
+        operands: [ZOperand; 4],
+    ) -> Result<()>
+    where
+        Scr: Screen,
+        V: Variables,
+    {
+        debug!("set_true_colour {} {}", operands[0], operands[1]);
+
+        let foreground = operands[0].value(variables)?;
+        let background = operands[1].value(variables)?;
+        match (Colour::from_true(foreground), Colour::from_true(background)) {
+            (Some(fg), Some(bg)) => screen.borrow_mut().set_colour(fg, bg),
+            _ => violations.report(Violation::IllegalColour(foreground, background)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::fixtures::*;
+    use super::super::handle::new_handle;
+    use super::super::stack::ZStack;
+    use super::super::streams::KEYBOARD_STREAM;
+    use super::super::variables::ZVariables;
+    use super::super::violations::ErrorPolicy;
+    use super::super::zscii::encode_zstr;
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        let mut pc = TestPC::new(
+            8,
+            vec![
+                0, // Stack
+            ],
+        );
+        let mut variables = TestVariables::new();
+        let operands: [ZOperand; 2] = [ZOperand::SmallConstant(3), ZOperand::LargeConstant(98)];
+
+        two_op::o_20_add(&mut pc, &mut variables, operands).unwrap();
+
+        // Ensure that the pc advanced one byte.
+        assert_eq!(9, pc.current_pc());
+        assert_eq!(101, variables.variables[&ZVariable::Stack]);
+    }
+
+    #[test]
+    fn test_add_overflow() {
+        let mut pc = TestPC::new(
+            8,
+            vec![
+                0, // Stack
+            ],
+        );
+        let mut variables = TestVariables::new();
+        let operands: [ZOperand; 2] = [ZOperand::LargeConstant(65530), ZOperand::SmallConstant(98)];
+
+        two_op::o_20_add(&mut pc, &mut variables, operands).unwrap();
+
+        // Ensure that the pc advanced one byte.
+        assert_eq!(9, pc.current_pc());
+        assert_eq!(92, variables.variables[&ZVariable::Stack]);
+    }
+
+    #[test]
+    fn test_store() {
+        let mut variables = TestVariables::new();
+        let operands: [ZOperand; 2] = [
+            ZOperand::SmallConstant(0), // Stack
+            ZOperand::LargeConstant(45),
+        ];
+        two_op::o_13_store(&mut variables, operands).unwrap();
+
+        assert_eq!(45, variables.variables[&ZVariable::Stack]);
+    }
+
+    #[test]
+    fn test_inc_dec_load() {
+        let mut pc = TestPC::new(8, vec![0x10]);
+        let mut variables = TestVariables::new();
+        variables.write_variable(ZVariable::Local(2), 0).unwrap();
+
+        one_op::o_133_inc(&mut variables, ZOperand::SmallConstant(3)).unwrap();
+        one_op::o_133_inc(&mut variables, ZOperand::SmallConstant(3)).unwrap();
+        assert_eq!(2, variables.variables[&ZVariable::Local(2)]);
+
+        one_op::o_134_dec(&mut variables, ZOperand::SmallConstant(3)).unwrap();
+        one_op::o_134_dec(&mut variables, ZOperand::SmallConstant(3)).unwrap();
+        one_op::o_134_dec(&mut variables, ZOperand::SmallConstant(3)).unwrap();
+        assert_eq!(0xffff, variables.variables[&ZVariable::Local(2)]);
+
+        one_op::o_142_load(&mut pc, &mut variables, ZOperand::SmallConstant(3)).unwrap();
+        assert_eq!(0xffff, variables.variables[&ZVariable::Global(0)]);
+    }
+
+    #[test]
+    fn test_dec_chk() {
+        let stack = new_handle(TestStack::new(0));
+        let mut variables = TestVariables::new();
+        variables.write_variable(ZVariable::Local(0), 1).unwrap();
+
+        // Branch if the new value (0) is less than -1. It isn't.
+        let mut pc = TestPC::new(8, vec![0xc5]);
+        let operands = [ZOperand::SmallConstant(1), ZOperand::LargeConstant(0xffff)];
+        two_op::o_4_dec_chk(&mut pc, &stack, &mut variables, operands).unwrap();
+        assert_eq!(9, pc.current_pc());
+        assert_eq!(0, variables.variables[&ZVariable::Local(0)]);
+
+        // Branch if the new value (-1) is less than 0. It is.
+        let mut pc = TestPC::new(8, vec![0xc5]);
+        let operands = [ZOperand::SmallConstant(1), ZOperand::SmallConstant(0)];
+        two_op::o_4_dec_chk(&mut pc, &stack, &mut variables, operands).unwrap();
+        assert_eq!(12, pc.current_pc());
+        assert_eq!(0xffff, variables.variables[&ZVariable::Local(0)]);
+
+        // Compared with itself, the new value (4) is less than the old (5).
+        variables.write_variable(ZVariable::Local(0), 5).unwrap();
+        let mut pc = TestPC::new(8, vec![0xc5]);
+        let operands = [
+            ZOperand::SmallConstant(1),
+            ZOperand::Var(ZVariable::Local(0)),
+        ];
+        two_op::o_4_dec_chk(&mut pc, &stack, &mut variables, operands).unwrap();
+        assert_eq!(12, pc.current_pc());
+        assert_eq!(4, variables.variables[&ZVariable::Local(0)]);
+    }
+
+    #[test]
+    fn test_inc_chk() {
+        let stack = new_handle(TestStack::new(0));
+        let mut variables = TestVariables::new();
+        variables
+            .write_variable(ZVariable::Local(0), 0x7ffe)
+            .unwrap();
+
+        // Branch if the new value (32767) is greater than 0. It is.
+        let mut pc = TestPC::new(8, vec![0xc5]);
+        let operands = [ZOperand::SmallConstant(1), ZOperand::SmallConstant(0)];
+        two_op::o_5_inc_chk(&mut pc, &stack, &mut variables, operands).unwrap();
+        assert_eq!(12, pc.current_pc());
+        assert_eq!(0x7fff, variables.variables[&ZVariable::Local(0)]);
+
+        // Branch if the new value (-32768) is greater than 0. It isn't.
+        let mut pc = TestPC::new(8, vec![0xc5]);
+        two_op::o_5_inc_chk(&mut pc, &stack, &mut variables, operands).unwrap();
+        assert_eq!(9, pc.current_pc());
+        assert_eq!(0x8000, variables.variables[&ZVariable::Local(0)]);
+
+        // Branch if the new value (0) is greater than -1. It is.
+        variables
+            .write_variable(ZVariable::Local(0), 0xffff)
+            .unwrap();
+        let mut pc = TestPC::new(8, vec![0xc5]);
+        let operands = [ZOperand::SmallConstant(1), ZOperand::LargeConstant(0xffff)];
+        two_op::o_5_inc_chk(&mut pc, &stack, &mut variables, operands).unwrap();
+        assert_eq!(12, pc.current_pc());
+        assert_eq!(0, variables.variables[&ZVariable::Local(0)]);
+
+        // Compared with itself, the new value (6) is greater than the old (5).
+        variables.write_variable(ZVariable::Local(0), 5).unwrap();
+        let mut pc = TestPC::new(8, vec![0xc5]);
+        let operands = [
+            ZOperand::SmallConstant(1),
+            ZOperand::Var(ZVariable::Local(0)),
+        ];
+        two_op::o_5_inc_chk(&mut pc, &stack, &mut variables, operands).unwrap();
+        assert_eq!(12, pc.current_pc());
+        assert_eq!(6, variables.variables[&ZVariable::Local(0)]);
+    }
+
+    #[test]
+    fn test_indirect_stack_access() {
+        let stack = new_handle(ZStack::new());
+        let memory = new_handle(TestMemory::new(0x100));
+        let mut variables =
+            ZVariables::new(ByteAddress::from_raw(0x10), memory.clone(), stack.clone());
+        variables.write_variable(ZVariable::Stack, 5).unwrap();
+        variables.write_variable(ZVariable::Stack, 7).unwrap();
+
+        // Each of these works on the top of the stack, without pushing or popping.
+        let sp = ZOperand::SmallConstant(0);
+        one_op::o_133_inc(&mut variables, sp).unwrap();
+        assert_eq!(8, stack.borrow().peek_word().unwrap());
+        one_op::o_134_dec(&mut variables, sp).unwrap();
+        one_op::o_134_dec(&mut variables, sp).unwrap();
+        assert_eq!(6, stack.borrow().peek_word().unwrap());
+        two_op::o_13_store(&mut variables, [sp, ZOperand::SmallConstant(42)]).unwrap();
+        assert_eq!(42, stack.borrow().peek_word().unwrap());
+
+        // Pull replaces the value below the one that was popped.
+        var_op::o_233_pull(
+            &memory,
+            &mut TestPC::new(8, vec![]),
+            &stack,
+            &mut variables,
+            ZVersion::V5,
+            one_operand(sp),
+        )
+        .unwrap();
+        assert_eq!(42, stack.borrow_mut().pop_word().unwrap());
+        assert!(stack.borrow_mut().pop_word().is_err());
+    }
+
+    #[test]
+    fn test_pull_v6() {
+        let stack = new_handle(ZStack::new());
+        let mut memory = TestMemory::new(0x100);
+        // A user stack at 0x80 with room for three values, holding 0x1234
+        // and 0x5678, which is on top.
+        memory.bytes[0x80..0x88].copy_from_slice(&[0x00, 0x01, 0, 0, 0x56, 0x78, 0x12, 0x34]);
+        let memory = new_handle(memory);
+        let mut variables =
+            ZVariables::new(ByteAddress::from_raw(0x10), memory.clone(), stack.clone());
+
+        let mut pc = TestPC::new(8, vec![0x10]);
+        let operands = one_operand(ZOperand::SmallConstant(0x80));
+        var_op::o_233_pull(
+            &memory,
+            &mut pc,
+            &stack,
+            &mut variables,
+            ZVersion::V6,
+            operands,
+        )
+        .unwrap();
+        assert_eq!(
+            0x5678,
+            variables.read_variable(ZVariable::Global(0)).unwrap()
+        );
+        assert_eq!(2, memory.borrow().read_word(ByteAddress::from_raw(0x80)));
+
+        // Without a user stack, the game's stack is used.
+        variables.write_variable(ZVariable::Stack, 9).unwrap();
+        let mut pc = TestPC::new(8, vec![0x11]);
+        var_op::o_233_pull(
+            &memory,
+            &mut pc,
+            &stack,
+            &mut variables,
+            ZVersion::V6,
+            one_operand(ZOperand::Omitted),
+        )
+        .unwrap();
+        assert_eq!(9, variables.read_variable(ZVariable::Global(1)).unwrap());
+        assert!(stack.borrow_mut().pop_word().is_err());
+    }
+
+    #[test]
+    fn test_call_v3_arguments() {
+        // Store to the stack; 3 locals with starting values.
+        let mut pc = TestPC::new(8, vec![0x00, 3, 0x11, 0x11, 0x22, 0x22, 0x33, 0x33]);
+        let stack = new_handle(ZStack::new());
+        let mut variables = TestVariables::new();
+
+        let operands: [ZOperand; 4] = [
+            ZOperand::LargeConstant(0x100),
+            ZOperand::SmallConstant(7),
+            ZOperand::Omitted,
+            ZOperand::Omitted,
+        ];
+        var_op::o_224_call(
+            &mut pc,
+            &stack,
+            &mut variables,
+            &TestHeader::new(ZVersion::V3),
+            operands,
+        )
+        .unwrap();
+
+        assert_eq!(0x200 + 7, pc.current_pc());
+        let stack = stack.borrow();
+        assert_eq!(9, stack.return_pc());
+        assert_eq!(1, stack.num_args());
+        assert_eq!(7, stack.read_local(0).unwrap());
+        assert_eq!(0x2222, stack.read_local(1).unwrap());
+        assert_eq!(0x3333, stack.read_local(2).unwrap());
+    }
+
+    #[test]
+    fn test_call_v7_routine_offset() {
+        let mut pc = TestPC::new(8, vec![0x00, 0]);
+        let stack = new_handle(ZStack::new());
+        let mut variables = TestVariables::new();
+        let mut header = TestHeader::new(ZVersion::V7);
+        header.routines_offset = 0x10;
+        header.strings_offset = 0x20;
+
+        let operands = one_operand(ZOperand::LargeConstant(0x100));
+        var_op::o_224_call(&mut pc, &stack, &mut variables, &header, operands).unwrap();
+
+        // 4 * 0x100 + 8 * 0x10, plus the locals byte.
+        assert_eq!(0x481, pc.current_pc());
+    }
+
+    #[test]
+    fn test_call_v5_surplus_arguments() {
+        // Store to the stack; 2 locals, which start at zero.
+        let mut pc = TestPC::new(8, vec![0x00, 2]);
+        let stack = new_handle(ZStack::new());
+        let mut variables = TestVariables::new();
+
+        let operands: [ZOperand; 4] = [
+            ZOperand::LargeConstant(0x100),
+            ZOperand::SmallConstant(7),
+            ZOperand::SmallConstant(8),
+            ZOperand::SmallConstant(9),
+        ];
+        var_op::o_224_call(
+            &mut pc,
+            &stack,
+            &mut variables,
+            &TestHeader::new(ZVersion::V5),
+            operands,
+        )
+        .unwrap();
+
+        let stack = stack.borrow();
+        assert_eq!(2, stack.num_args());
+        assert_eq!(7, stack.read_local(0).unwrap());
+        assert_eq!(8, stack.read_local(1).unwrap());
+        assert!(stack.read_local(2).is_err());
+    }
+
+    #[test]
+    fn test_call_address_zero() {
+        let mut pc = TestPC::new(8, vec![0x10]);
+        let stack = new_handle(TestStack::new(0));
+        let mut variables = TestVariables::new();
+        variables.write_variable(ZVariable::Global(0), 99).unwrap();
+
+        let operands: [ZOperand; 4] = [
+            ZOperand::LargeConstant(0),
+            ZOperand::SmallConstant(1),
+            ZOperand::Omitted,
+            ZOperand::Omitted,
+        ];
+        var_op::o_224_call(
+            &mut pc,
+            &stack,
+            &mut variables,
+            &TestHeader::new(ZVersion::V3),
+            operands,
+        )
+        .unwrap();
+
+        // Nothing is called, and false is stored.
+        assert_eq!(9, pc.current_pc());
+        assert_eq!(0, variables.variables[&ZVariable::Global(0)]);
+    }
+
+    #[test]
+    fn test_storew() {
+        let mut variables = TestVariables::new();
+        let mem_h = new_handle(TestMemory::new(1000));
+        let operands: [ZOperand; 4] = [
+            ZOperand::SmallConstant(234),
+            ZOperand::SmallConstant(5),
+            ZOperand::LargeConstant(0xabcd),
+            ZOperand::Omitted,
+        ];
+
+        var_op::o_225_storew(&mem_h, &mut variables, operands).unwrap();
+
+        assert_eq!(0xab, mem_h.borrow().bytes[244]);
+        assert_eq!(0xcd, mem_h.borrow().bytes[245]);
+    }
+
+    // Run a whole read, as the processor does when input isn't timed.
+    fn run_read(
+        memory: &Handle<TestMemory>,
+        pc: &mut TestPC,
+        variables: &mut TestVariables,
+        header: &TestHeader,
+        input: &mut TestInputStreams,
+        streams: &mut TestOutputStreams,
+        operands: [ZOperand; 4],
+    ) {
+        let screen = new_handle(TestScreen::new());
+        let request = var_op::o_228_read(memory, pc, variables, header, &screen, operands).unwrap();
+        let (line, terminator) = input.read_line("", &request.terminators, None).unwrap();
+        var_op::finish_read(
+            memory, variables, header, streams, request, &line, terminator,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_read() {
+        let mut memory = TestMemory::new(0x200);
+        memory.bytes[0x40] = 20; // text buffer
+        memory.bytes[0x80] = 4; // parse buffer
+
+        // A dictionary with one separator and two words.
+        memory.bytes[0x100..0x105].copy_from_slice(&[1, b',', 7, 0, 2]);
+        memory.bytes[0x105..0x109].copy_from_slice(&encode_zstr("go", 6, ZVersion::V3));
+        memory.bytes[0x10c..0x110].copy_from_slice(&encode_zstr("north", 6, ZVersion::V3));
+        let memory = new_handle(memory);
+
+        let mut pc = TestPC::new(8, vec![]);
+        let mut variables = TestVariables::new();
+        let mut header = TestHeader::new(ZVersion::V3);
+        header.dictionary = ByteAddress::from_raw(0x100);
+        let mut input = TestInputStreams::new(&[("Go North,X", 13)]);
+        let mut streams = TestOutputStreams::new();
+
+        let operands: [ZOperand; 4] = [
+            ZOperand::LargeConstant(0x40),
+            ZOperand::LargeConstant(0x80),
+            ZOperand::Omitted,
+            ZOperand::Omitted,
+        ];
+        run_read(
+            &memory,
+            &mut pc,
+            &mut variables,
+            &header,
+            &mut input,
+            &mut streams,
+            operands,
+        );
+
+        // No store byte before V5.
+        assert_eq!(8, pc.current_pc());
+        // The command is recorded as typed.
+        assert_eq!(vec!["Go North,X"], streams.recorded);
+
+        let bytes = &memory.borrow().bytes;
+        assert_eq!(b"go north,x\0", &bytes[0x41..0x4c]);
+        assert_eq!(
+            &[
+                4, 4, // max words, word count
+                0x01, 0x05, 2, 1, // go
+                0x01, 0x0c, 5, 4, // north
+                0x00, 0x00, 1, 9, // ,
+                0x00, 0x00, 1, 10, // x
+            ],
+            &bytes[0x80..0x92]
+        );
+    }
+
+    #[test]
+    fn test_tokenise() {
+        let mut memory = TestMemory::new(0x200);
+        // A V5 text buffer, already filled in.
+        memory.bytes[0x40..0x4b]
+            .copy_from_slice(&[20, 9, b'x', b'y', b'z', b'z', b'y', b' ', b'g', b'o', b' ']);
+        memory.bytes[0x80] = 4; // parse buffer
+
+        // The standard dictionary has "go", and a user dictionary, unsorted,
+        // has "xyzzy".
+        memory.bytes[0x100..0x105].copy_from_slice(&[0, 9, 0, 1, 0]);
+        memory.bytes[0x104..0x10a].copy_from_slice(&encode_zstr("go", 9, ZVersion::V5));
+        memory.bytes[0x140..0x144].copy_from_slice(&[0, 9, 0xff, 0xfe]);
+        memory.bytes[0x144..0x14a].copy_from_slice(&encode_zstr("plugh", 9, ZVersion::V5));
+        memory.bytes[0x14d..0x153].copy_from_slice(&encode_zstr("xyzzy", 9, ZVersion::V5));
+        let memory = new_handle(memory);
+
+        let mut variables = TestVariables::new();
+        let mut header = TestHeader::new(ZVersion::V5);
+        header.dictionary = ByteAddress::from_raw(0x100);
+
+        let mut operands: [ZOperand; 4] = [
+            ZOperand::LargeConstant(0x40),
+            ZOperand::LargeConstant(0x80),
+            ZOperand::Omitted,
+            ZOperand::Omitted,
+        ];
+        var_op::o_251_tokenise(&memory, &mut variables, &header, operands).unwrap();
+        assert_eq!(
+            &[4, 2, 0x00, 0x00, 5, 2, 0x01, 0x04, 2, 8],
+            &memory.borrow().bytes[0x80..0x8a]
+        );
+
+        // With the user dictionary, and the flag set, "go" is left alone.
+        memory.borrow_mut().bytes[0x86..0x8a].copy_from_slice(&[0xaa; 4]);
+        operands[2] = ZOperand::LargeConstant(0x140);
+        operands[3] = ZOperand::SmallConstant(1);
+        var_op::o_251_tokenise(&memory, &mut variables, &header, operands).unwrap();
+        assert_eq!(
+            &[4, 2, 0x01, 0x4d, 5, 2, 0xaa, 0xaa, 0xaa, 0xaa],
+            &memory.borrow().bytes[0x80..0x8a]
+        );
+    }
+
+    #[test]
+    fn test_encode_text() {
+        let mut memory = TestMemory::new(0x100);
+        memory.bytes[0x40..0x4a].copy_from_slice(b"xx mailbox");
+        let memory = new_handle(memory);
+        let mut variables = TestVariables::new();
+
+        let operands: [ZOperand; 4] = [
+            ZOperand::LargeConstant(0x40),
+            ZOperand::SmallConstant(4),
+            ZOperand::SmallConstant(3),
+            ZOperand::LargeConstant(0x80),
+        ];
+        var_op::o_252_encode_text(&memory, &mut variables, ZVersion::V5, operands).unwrap();
+        assert_eq!(
+            &encode_zstr("mail", 9, ZVersion::V5)[..],
+            &memory.borrow().bytes[0x80..0x86]
+        );
+    }
+
+    #[test]
+    fn test_read_v5() {
+        let mut memory = TestMemory::new(0x100);
+        // A text buffer with room for 10 characters, preloaded with 3.
+        memory.bytes[0x40..0x45].copy_from_slice(&[10, 3, b'g', b'o', b' ']);
+        memory.bytes[0x60] = 0xaa; // not a parse buffer
+        memory.bytes[0x90..0x93].copy_from_slice(&[129, 130, 0]);
+        let memory = new_handle(memory);
+
+        let mut pc = TestPC::new(8, vec![0x10]);
+        let mut variables = TestVariables::new();
+        let mut header = TestHeader::new(ZVersion::V5);
+        header.terminating_chars = Some(ByteAddress::from_raw(0x90));
+        let mut input = TestInputStreams::new(&[("ÉAST now", 129)]);
+        let mut streams = TestOutputStreams::new();
+
+        let operands: [ZOperand; 4] = [
+            ZOperand::LargeConstant(0x40),
+            ZOperand::SmallConstant(0),
+            ZOperand::Omitted,
+            ZOperand::Omitted,
+        ];
+        run_read(
+            &memory,
+            &mut pc,
+            &mut variables,
+            &header,
+            &mut input,
+            &mut streams,
+            operands,
+        );
+
+        assert_eq!(vec![129, 130], input.terminators);
+        assert_eq!(129, variables.variables[&ZVariable::Global(0)]);
+
+        let bytes = &memory.borrow().bytes;
+        // The new input is appended to the preloaded text, and truncated.
+        // É is lowered to é, which is ZSCII 170.
+        assert_eq!(10, bytes[0x41]);
+        assert_eq!(b"go \xaaast no", &bytes[0x42..0x4c]);
+        assert_eq!(0xaa, bytes[0x60]);
+    }
+
+    #[test]
+    fn test_echo_command() {
+        let mut input = TestInputStreams::new(&[]);
+        let screen = new_handle(TestScreen::new());
+
+        for (stream, line) in &[(KEYBOARD_STREAM, "look"), (COMMAND_FILE_STREAM, "wait")] {
+            input.select_input_stream(*stream).unwrap();
+            var_op::echo_command(&input, &screen, line).unwrap();
+        }
+
+        // Only the command from the file is echoed.
+        assert_eq!("wait\n", screen.borrow().output);
+    }
+
+    #[test]
+    fn test_read_timer() {
+        let mut memory = TestMemory::new(0x100);
+        memory.bytes[0x40] = 20;
+        let memory = new_handle(memory);
+
+        let mut pc = TestPC::new(8, vec![]);
+        let mut variables = TestVariables::new();
+        let mut streams = TestOutputStreams::new();
+        let screen = new_handle(TestScreen::new());
+
+        let operands: [ZOperand; 4] = [
+            ZOperand::LargeConstant(0x40),
+            ZOperand::SmallConstant(0),
+            ZOperand::SmallConstant(15),
+            ZOperand::LargeConstant(0x1234),
+        ];
+
+        // Timed input is ignored before V4.
+        let header = TestHeader::new(ZVersion::V3);
+        let request =
+            var_op::o_228_read(&memory, &mut pc, &mut variables, &header, &screen, operands)
+                .unwrap();
+        assert_eq!(None, request.timer);
+
+        let header = TestHeader::new(ZVersion::V4);
+        let request =
+            var_op::o_228_read(&memory, &mut pc, &mut variables, &header, &screen, operands)
+                .unwrap();
+        let timer = request.timer.unwrap();
+        assert_eq!(0x1234, timer.routine);
+        assert_eq!(Duration::from_millis(1500), timer.duration());
+
+        // The routine stopped the input. What was typed is kept, but isn't
+        // recorded.
+        var_op::finish_read(
+            &memory,
+            &mut variables,
+            &header,
+            &mut streams,
+            request,
+            "op",
+            0,
+        )
+        .unwrap();
+        assert_eq!(b"op\0", &memory.borrow().bytes[0x41..0x44]);
+        assert!(streams.recorded.is_empty());
+    }
+
+    #[test]
+    fn test_print() {
+        let memory = new_handle(TestMemory::new(0x10));
+        let mut variables = TestVariables::new();
+        let mut violations = ZViolations::new(ErrorPolicy::WarnOnce);
+        let mut streams = TestOutputStreams::new();
+
+        let mut pc = TestPC::new(8, encode_zstr("go", 6, ZVersion::V3));
+        let text = new_handle(ZTextDecoder::new(&TestHeader::new(ZVersion::V3), &memory));
+        zero_op::o_178_print(&text, &mut pc, &mut streams).unwrap();
+        assert_eq!(12, pc.current_pc());
+
+        zero_op::o_187_new_line(&mut streams).unwrap();
+        // ZSCII 13 is a newline.
+        var_op::o_229_print_char(
+            &memory,
+            &mut variables,
+            &mut streams,
+            &mut violations,
+            ZVersion::V3,
+            one_operand(ZOperand::SmallConstant(13)),
+        )
+        .unwrap();
+        var_op::o_229_print_char(
+            &memory,
+            &mut variables,
+            &mut streams,
+            &mut violations,
+            ZVersion::V3,
+            one_operand(ZOperand::SmallConstant(b'x')),
+        )
+        .unwrap();
+
+        assert_eq!("go\n\nx", streams.output);
+
+        // Codes that aren't defined for output are dropped.
+        for &zscii in &[0, 9, 27, 260] {
+            var_op::o_229_print_char(
+                &memory,
+                &mut variables,
+                &mut streams,
+                &mut violations,
+                ZVersion::V3,
+                one_operand(ZOperand::LargeConstant(zscii)),
+            )
+            .unwrap();
+        }
+        assert_eq!("go\n\nx", streams.output);
+
+        // They are violations, apart from 0, which is defined to print nothing.
+        let mut violations = ZViolations::new(ErrorPolicy::Fatal);
+        let mut print_char = |zscii| {
+            var_op::o_229_print_char(
+                &memory,
+                &mut variables,
+                &mut streams,
+                &mut violations,
+                ZVersion::V3,
+                one_operand(ZOperand::LargeConstant(zscii)),
+            )
+        };
+        print_char(0).unwrap();
+        match print_char(260) {
+            Err(ZErr::Violation(Violation::UndefinedZsciiOutput(260))) => (),
+            _ => panic!("Missing violation"),
+        }
+    }
+
+    #[test]
+    fn test_print_ret() {
+        let memory = new_handle(TestMemory::new(0x100));
+        let stack = new_handle(ZStack::new());
+        let mut variables =
+            ZVariables::new(ByteAddress::from_raw(0x10), memory.clone(), stack.clone());
+        let mut streams = TestOutputStreams::new();
+        let text = new_handle(ZTextDecoder::new(&TestHeader::new(ZVersion::V3), &memory));
+
+        stack
+            .borrow_mut()
+            .push_frame(0x1234, 0, Some(ZVariable::Stack), &[], 0)
+            .unwrap();
+        let mut pc = TestPC::new(8, encode_zstr("go", 6, ZVersion::V3));
+        zero_op::o_179_print_ret(&text, &mut pc, &stack, &mut variables, &mut streams).unwrap();
+        assert_eq!("go\n", streams.output);
+        assert_eq!(0x1234, pc.current_pc());
+        assert_eq!(1, variables.read_variable(ZVariable::Stack).unwrap());
+    }
+
+    #[test]
+    fn test_print_addr() {
+        let mut memory = TestMemory::new(0x100);
+        let encoded = encode_zstr("south", 6, ZVersion::V3);
+        memory.bytes[0x81..0x81 + encoded.len()].copy_from_slice(&encoded);
+        let memory = new_handle(memory);
+        let mut variables = TestVariables::new();
+        let mut streams = TestOutputStreams::new();
+        let text = new_handle(ZTextDecoder::new(&TestHeader::new(ZVersion::V3), &memory));
+
+        // Byte addresses needn't be aligned.
+        one_op::o_135_print_addr(
+            &text,
+            &mut variables,
+            &mut streams,
+            ZOperand::LargeConstant(0x81),
+        )
+        .unwrap();
+        assert_eq!("south", streams.output);
+    }
+
+    #[test]
+    fn test_print_paddr() {
+        let mut memory = TestMemory::new(0x100);
+        let encoded = encode_zstr("north", 6, ZVersion::V5);
+        memory.bytes[0x80..0x80 + encoded.len()].copy_from_slice(&encoded);
+        let memory = new_handle(memory);
+        let mut variables = TestVariables::new();
+        let mut streams = TestOutputStreams::new();
+        let header = TestHeader::new(ZVersion::V5);
+        let text = new_handle(ZTextDecoder::new(&header, &memory));
+
+        // Strings are packed by 4 in V5.
+        one_op::o_141_print_paddr(
+            &text,
+            &header,
+            &mut variables,
+            &mut streams,
+            ZOperand::SmallConstant(0x20),
+        )
+        .unwrap();
+        assert_eq!("north", streams.output);
+    }
+
+    #[test]
+    fn test_read_char() {
+        let memory = new_handle(TestMemory::new(0x40));
+        let mut pc = TestPC::new(8, vec![0]);
+        let mut variables = TestVariables::new();
+        let mut input = TestInputStreams::new(&[]);
+        input.chars.push(b'q');
+        let mut streams = TestOutputStreams::new();
+        let screen = new_handle(TestScreen::new());
+
+        let request = var_op::o_246_read_char(
+            &mut pc,
+            &mut variables,
+            &screen,
+            one_operand(ZOperand::SmallConstant(1)),
+        )
+        .unwrap();
+        assert_eq!(None, request.timer);
+        let key = input.read_char(None).unwrap();
+        var_op::finish_read_char(&memory, &mut variables, &mut streams, request, key).unwrap();
+        assert_eq!(u16::from(b'q'), variables.variables[&ZVariable::Stack]);
+        assert_eq!(vec!["key 113"], streams.recorded);
+    }
+
+    #[test]
+    fn test_read_char_timer() {
+        let memory = new_handle(TestMemory::new(0x40));
+        let mut pc = TestPC::new(8, vec![0x10]);
+        let mut variables = TestVariables::new();
+        let mut streams = TestOutputStreams::new();
+        let screen = new_handle(TestScreen::new());
+
+        let operands: [ZOperand; 4] = [
+            ZOperand::SmallConstant(1),
+            ZOperand::SmallConstant(5),
+            ZOperand::LargeConstant(0x1234),
+            ZOperand::Omitted,
+        ];
+        let request = var_op::o_246_read_char(&mut pc, &mut variables, &screen, operands).unwrap();
+        assert_eq!(
+            Some(InputTimer {
+                tenths: 5,
+                routine: 0x1234
+            }),
+            request.timer
+        );
+
+        var_op::finish_read_char(&memory, &mut variables, &mut streams, request, 0).unwrap();
+        assert_eq!(0, variables.variables[&ZVariable::Global(0)]);
+        assert!(streams.recorded.is_empty());
+    }
+
+    #[test]
+    fn test_print_num() {
+        let mut variables = TestVariables::new();
+        let mut streams = TestOutputStreams::new();
+        let operands: [ZOperand; 4] = [
+            ZOperand::LargeConstant(0xfffe),
+            ZOperand::Omitted,
+            ZOperand::Omitted,
+            ZOperand::Omitted,
+        ];
+
+        var_op::o_230_print_num(&mut variables, &mut streams, operands).unwrap();
+
+        assert_eq!("-2", streams.output);
+    }
+
+    #[test]
+    fn test_random() {
+        let mut variables = TestVariables::new();
+        let mut random = ZRandom::new(0);
+        let operands = |range: u16| {
+            [
+                ZOperand::LargeConstant(range),
+                ZOperand::Omitted,
+                ZOperand::Omitted,
+                ZOperand::Omitted,
+            ]
+        };
+
+        let mut pc = TestPC::new(0, vec![0x10]);
+        var_op::o_231_random(&mut pc, &mut variables, &mut random, operands(10)).unwrap();
+        let value = variables.variables[&ZVariable::Global(0)];
+        assert!(value >= 1 && value <= 10);
+
+        // -2 asks for the predictable sequence 1, 2, 1, ...
+        let mut pc = TestPC::new(0, vec![0x10, 0x10, 0x10]);
+        var_op::o_231_random(&mut pc, &mut variables, &mut random, operands(0xfffe)).unwrap();
+        assert_eq!(0, variables.variables[&ZVariable::Global(0)]);
+        var_op::o_231_random(&mut pc, &mut variables, &mut random, operands(100)).unwrap();
+        var_op::o_231_random(&mut pc, &mut variables, &mut random, operands(100)).unwrap();
+        assert_eq!(2, variables.variables[&ZVariable::Global(0)]);
+    }
+
+    #[test]
+    fn test_output_stream() {
+        let mut variables = TestVariables::new();
+        let mut streams = TestOutputStreams::new();
+
+        let operands: [ZOperand; 4] = [
+            ZOperand::SmallConstant(3),
+            ZOperand::LargeConstant(0x1234),
+            ZOperand::Omitted,
+            ZOperand::Omitted,
+        ];
+        var_op::o_243_output_stream(&mut variables, &mut streams, operands).unwrap();
+        assert_eq!(Some(ByteAddress::from_raw(0x1234)), streams.selected[&3]);
+
+        let operands: [ZOperand; 4] = [
+            ZOperand::LargeConstant(0xfffd), // -3
+            ZOperand::Omitted,
+            ZOperand::Omitted,
+            ZOperand::Omitted,
+        ];
+        var_op::o_243_output_stream(&mut variables, &mut streams, operands).unwrap();
+        assert!(!streams.selected.contains_key(&3));
+    }
+
+    #[test]
+    fn test_shifts() {
+        let mut variables = TestVariables::new();
+        let shift = |number: u16, places: i16| {
+            [
+                ZOperand::LargeConstant(number),
+                ZOperand::LargeConstant(places as u16),
+                ZOperand::Omitted,
+                ZOperand::Omitted,
+            ]
+        };
+
+        let mut pc = TestPC::new(8, vec![0, 0, 0, 0]);
+        let operands = shift(0x8001, 1);
+        ext_op::o_2_log_shift(&mut pc, &mut variables, operands).unwrap();
+        assert_eq!(0x0002, variables.variables[&ZVariable::Stack]);
+
+        let operands = shift(0x8000, -2);
+        ext_op::o_2_log_shift(&mut pc, &mut variables, operands).unwrap();
+        assert_eq!(0x2000, variables.variables[&ZVariable::Stack]);
+
+        let operands = shift(0x8000, -2);
+        ext_op::o_3_art_shift(&mut pc, &mut variables, operands).unwrap();
+        assert_eq!(0xe000, variables.variables[&ZVariable::Stack]);
+
+        let operands = shift(0x0003, 2);
+        ext_op::o_3_art_shift(&mut pc, &mut variables, operands).unwrap();
+        assert_eq!(0x000c, variables.variables[&ZVariable::Stack]);
+    }
+
+    #[test]
+    fn test_undo() {
+        let memory = new_handle(TestMemory::new(0x100));
+        let stack = new_handle(ZStack::new());
+        let mut variables =
+            ZVariables::new(ByteAddress::from_raw(0x10), memory.clone(), stack.clone());
+        let mut header = TestHeader::new(ZVersion::V5);
+        header.static_memory = ByteAddress::from_raw(0x80);
+        let mut undo = ZUndoRing::new();
+
+        // With nothing saved, restore_undo fails.
+        let mut pc = TestPC::new(10, vec![0x10]);
+        ext_op::o_10_restore_undo(&memory, &mut pc, &stack, &mut variables, &mut undo).unwrap();
+        assert_eq!(0, variables.read_variable(ZVariable::Global(0)).unwrap());
+
+        memory.borrow_mut().bytes[0x40] = 1;
+        stack.borrow_mut().push_word(5).unwrap();
+
+        // Both opcodes store to G00, and save_undo's store byte is read twice.
+        let mut pc = TestPC::new(20, vec![0x10, 0x10, 0x10]);
+        ext_op::o_9_save_undo(
+            &memory,
+            &mut pc,
+            &stack,
+            &mut variables,
+            &header,
+            &mut undo,
+            1,
+        )
+        .unwrap();
+        assert_eq!(1, variables.read_variable(ZVariable::Global(0)).unwrap());
+
+        memory.borrow_mut().bytes[0x40] = 9;
+        stack.borrow_mut().push_word(6).unwrap();
+        pc.set_current_pc(30);
+
+        ext_op::o_10_restore_undo(&memory, &mut pc, &stack, &mut variables, &mut undo).unwrap();
+        assert_eq!(21, pc.current_pc());
+        assert_eq!(2, variables.read_variable(ZVariable::Global(0)).unwrap());
+        assert_eq!(1, memory.borrow().bytes[0x40]);
+        assert_eq!(5, stack.borrow_mut().pop_word().unwrap());
+    }
+
+    #[test]
+    fn test_undo_levels() {
+        let memory = new_handle(TestMemory::new(0x100));
+        let stack = new_handle(ZStack::new());
+        let mut variables =
+            ZVariables::new(ByteAddress::from_raw(0x10), memory.clone(), stack.clone());
+        let mut header = TestHeader::new(ZVersion::V5);
+        header.static_memory = ByteAddress::from_raw(0x80);
+        let mut undo = ZUndoRing::new();
+
+        // Save three turns, keeping only two.
+        for turn in 1..=3 {
+            memory.borrow_mut().bytes[0x40] = turn;
+            let mut pc = TestPC::new(usize::from(turn) * 10, vec![0x10]);
+            ext_op::o_9_save_undo(
+                &memory,
+                &mut pc,
+                &stack,
+                &mut variables,
+                &header,
+                &mut undo,
+                2,
+            )
+            .unwrap();
+        }
+
+        // Each restore steps back one more turn.
+        for turn in (2..=3).rev() {
+            let mut pc = TestPC::new(100, vec![0x10, 0x10]);
+            ext_op::o_10_restore_undo(&memory, &mut pc, &stack, &mut variables, &mut undo).unwrap();
+            assert_eq!(turn, memory.borrow().bytes[0x40]);
+            assert_eq!(2, variables.read_variable(ZVariable::Global(0)).unwrap());
+        }
+        let mut pc = TestPC::new(100, vec![0x10]);
+        ext_op::o_10_restore_undo(&memory, &mut pc, &stack, &mut variables, &mut undo).unwrap();
+        assert_eq!(0, variables.read_variable(ZVariable::Global(0)).unwrap());
+
+        // With no levels, undo isn't available.
+        let mut pc = TestPC::new(100, vec![0x10]);
+        ext_op::o_9_save_undo(
+            &memory,
+            &mut pc,
+            &stack,
+            &mut variables,
+            &header,
+            &mut undo,
+            0,
+        )
+        .unwrap();
+        assert_eq!(
+            0xffff,
+            variables.read_variable(ZVariable::Global(0)).unwrap()
+        );
+        let mut pc = TestPC::new(100, vec![0x10]);
+        ext_op::o_10_restore_undo(&memory, &mut pc, &stack, &mut variables, &mut undo).unwrap();
+        assert_eq!(0, variables.read_variable(ZVariable::Global(0)).unwrap());
+    }
+
+    #[test]
+    fn test_unicode() {
+        let mut variables = TestVariables::new();
+        let mut streams = TestOutputStreams::new();
+        ext_op::o_11_print_unicode(
+            &mut variables,
+            &mut streams,
+            one_operand(ZOperand::LargeConstant(0xe9)),
+        )
+        .unwrap();
+        assert_eq!("\u{e9}", streams.output);
+
+        let memory = new_handle(TestMemory::new(0x40));
+        let mut pc = TestPC::new(8, vec![0, 0, 0]);
+        ext_op::o_12_check_unicode(
+            &memory,
+            &mut pc,
+            &mut variables,
+            one_operand(ZOperand::LargeConstant(0xe9)),
+        )
+        .unwrap();
+        assert_eq!(3, variables.variables[&ZVariable::Stack]);
+        ext_op::o_12_check_unicode(
+            &memory,
+            &mut pc,
+            &mut variables,
+            one_operand(ZOperand::SmallConstant(7)),
+        )
+        .unwrap();
+        assert_eq!(0, variables.variables[&ZVariable::Stack]);
+        // ł isn't in the default Unicode table.
+        ext_op::o_12_check_unicode(
+            &memory,
+            &mut pc,
+            &mut variables,
+            one_operand(ZOperand::LargeConstant(0x142)),
+        )
+        .unwrap();
+        assert_eq!(1, variables.variables[&ZVariable::Stack]);
+    }
+
+    #[test]
+    fn test_check_unicode_story_table() {
+        let mut memory = TestMemory::new(0x80);
+        memory.bytes[0] = 5;
+        memory.bytes[0x37] = 0x40; // Header extension at 0x40,
+        memory.bytes[0x41] = 3; // with three words.
+        memory.bytes[0x47] = 0x50; // Unicode table at 0x50,
+        memory.bytes[0x50] = 1; // with only å.
+        memory.bytes[0x51..0x53].copy_from_slice(&[0x00, 0xe5]);
+        let memory = new_handle(memory);
+        let mut variables = TestVariables::new();
+
+        let mut pc = TestPC::new(8, vec![0, 0]);
+        ext_op::o_12_check_unicode(
+            &memory,
+            &mut pc,
+            &mut variables,
+            one_operand(ZOperand::LargeConstant(0xe5)),
+        )
+        .unwrap();
+        assert_eq!(3, variables.variables[&ZVariable::Stack]);
+        // é is in the default table, but not the story's.
+        ext_op::o_12_check_unicode(
+            &memory,
+            &mut pc,
+            &mut variables,
+            one_operand(ZOperand::LargeConstant(0xe9)),
+        )
+        .unwrap();
+        assert_eq!(1, variables.variables[&ZVariable::Stack]);
+    }
+
+    fn one_operand(operand: ZOperand) -> [ZOperand; 4] {
+        [
+            operand,
+            ZOperand::Omitted,
+            ZOperand::Omitted,
+            ZOperand::Omitted,
+        ]
+    }
+
+    #[test]
+    fn test_split_window() {
+        let mut variables = TestVariables::new();
+        let screen = new_handle(TestScreen::new());
+
+        let operands = one_operand(ZOperand::SmallConstant(2));
+        var_op::o_234_split_window(&mut variables, &screen, ZVersion::V3, operands).unwrap();
+        assert_eq!(2, screen.borrow().windows.upper_lines());
+        assert_eq!(vec![screen::UPPER_WINDOW], screen.borrow().erased);
+
+        // No clearing in V4+.
+        let operands = one_operand(ZOperand::SmallConstant(5));
+        var_op::o_234_split_window(&mut variables, &screen, ZVersion::V5, operands).unwrap();
+        assert_eq!(5, screen.borrow().windows.upper_lines());
+        assert_eq!(1, screen.borrow().erased.len());
+    }
+
+    #[test]
+    fn test_set_window() {
+        let mut variables = TestVariables::new();
+        let screen = new_handle(TestScreen::new());
+
+        var_op::o_235_set_window(
+            &mut variables,
+            &screen,
+            one_operand(ZOperand::SmallConstant(1)),
+        )
+        .unwrap();
+        assert_eq!(
+            screen::UPPER_WINDOW,
+            screen.borrow().windows.current_window()
+        );
+
+        assert!(var_op::o_235_set_window(
+            &mut variables,
+            &screen,
+            one_operand(ZOperand::SmallConstant(7))
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_erase_window() {
+        let mut variables = TestVariables::new();
+        let screen = new_handle(TestScreen::new());
+        screen.borrow_mut().split_window(4).unwrap();
+        screen
+            .borrow_mut()
+            .set_window(screen::UPPER_WINDOW)
+            .unwrap();
+
+        var_op::o_237_erase_window(
+            &mut variables,
+            &screen,
+            one_operand(ZOperand::SmallConstant(1)),
+        )
+        .unwrap();
+        assert_eq!(vec![screen::UPPER_WINDOW], screen.borrow().erased);
+        assert_eq!(4, screen.borrow().windows.upper_lines());
+
+        screen.borrow_mut().erased.clear();
+        var_op::o_237_erase_window(
+            &mut variables,
+            &screen,
+            one_operand(ZOperand::LargeConstant(0xfffe)), // -2
+        )
+        .unwrap();
+        assert_eq!(2, screen.borrow().erased.len());
+        assert_eq!(4, screen.borrow().windows.upper_lines());
+        assert_eq!(
+            screen::UPPER_WINDOW,
+            screen.borrow().windows.current_window()
+        );
+
+        screen.borrow_mut().erased.clear();
+        var_op::o_237_erase_window(
+            &mut variables,
+            &screen,
+            one_operand(ZOperand::LargeConstant(0xffff)), // -1
+        )
+        .unwrap();
+        assert_eq!(vec![screen::LOWER_WINDOW], screen.borrow().erased);
+        assert_eq!(0, screen.borrow().windows.upper_lines());
+        assert_eq!(
+            screen::LOWER_WINDOW,
+            screen.borrow().windows.current_window()
+        );
+    }
+
+    #[test]
+    fn test_erase_line() {
+        let mut variables = TestVariables::new();
+        let screen = new_handle(TestScreen::new());
+
+        var_op::o_238_erase_line(
+            &mut variables,
+            &screen,
+            one_operand(ZOperand::SmallConstant(1)),
+        )
+        .unwrap();
+        var_op::o_238_erase_line(
+            &mut variables,
+            &screen,
+            one_operand(ZOperand::SmallConstant(7)),
+        )
+        .unwrap();
+        assert_eq!(1, screen.borrow().erased_lines);
+    }
+
+    #[test]
+    fn test_set_and_get_cursor() {
+        let mut variables = TestVariables::new();
+        let memory = new_handle(TestMemory::new(0x100));
+        let screen = new_handle(TestScreen::new());
+        screen.borrow_mut().split_window(3).unwrap();
+        screen
+            .borrow_mut()
+            .set_window(screen::UPPER_WINDOW)
+            .unwrap();
+
+        let operands: [ZOperand; 4] = [
+            ZOperand::SmallConstant(2),
+            ZOperand::SmallConstant(17),
+            ZOperand::Omitted,
+            ZOperand::Omitted,
+        ];
+        var_op::o_239_set_cursor(&mut variables, &screen, operands).unwrap();
+        screen.borrow_mut().print("abc").unwrap();
+
+        let operands = one_operand(ZOperand::SmallConstant(0x40));
+        var_op::o_240_get_cursor(&memory, &mut variables, &screen, operands).unwrap();
+        assert_eq!(2, memory.borrow().read_word(ByteAddress::from_raw(0x40)));
+        assert_eq!(20, memory.borrow().read_word(ByteAddress::from_raw(0x42)));
+    }
+
+    #[test]
+    fn test_set_text_style() {
+        let mut variables = TestVariables::new();
+        let mut streams = TestOutputStreams::new();
+        let operands: [ZOperand; 4] = [
+            ZOperand::SmallConstant(2),
+            ZOperand::Omitted,
+            ZOperand::Omitted,
+            ZOperand::Omitted,
+        ];
+
+        var_op::o_241_set_text_style(&mut variables, &mut streams, operands).unwrap();
+
+        assert_eq!(TextStyle::BOLD, streams.style);
+    }
+
+    #[test]
+    fn test_set_colour() {
+        let mut variables = TestVariables::new();
+        let screen = new_handle(TestScreen::new());
+        let mut violations = ZViolations::new(ErrorPolicy::WarnOnce);
+
+        two_op::o_27_set_colour(
+            &mut variables,
+            &screen,
+            &mut violations,
+            [ZOperand::SmallConstant(3), ZOperand::SmallConstant(0)],
+        )
+        .unwrap();
+        assert_eq!(
+            Some((Colour::Red, Colour::Current)),
+            screen.borrow().colours
+        );
+
+        // Illegal colours are ignored.
+        two_op::o_27_set_colour(
+            &mut variables,
+            &screen,
+            &mut violations,
+            [ZOperand::SmallConstant(2), ZOperand::SmallConstant(99)],
+        )
+        .unwrap();
+        assert_eq!(
+            Some((Colour::Red, Colour::Current)),
+            screen.borrow().colours
+        );
+
+        // Unless the policy makes them fatal.
+        let mut violations = ZViolations::new(ErrorPolicy::Fatal);
+        assert!(two_op::o_27_set_colour(
+            &mut variables,
+            &screen,
+            &mut violations,
+            [ZOperand::SmallConstant(2), ZOperand::SmallConstant(99)],
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_set_true_colour() {
+        let mut variables = TestVariables::new();
+        let screen = new_handle(TestScreen::new());
+        let operands: [ZOperand; 4] = [
+            ZOperand::LargeConstant(0x03e0),
+            ZOperand::LargeConstant(0xffff),
+            ZOperand::Omitted,
+            ZOperand::Omitted,
+        ];
+
+        let mut violations = ZViolations::new(ErrorPolicy::WarnOnce);
+        ext_op::o_13_set_true_colour(&mut variables, &screen, &mut violations, operands).unwrap();
+        assert_eq!(
+            Some((Colour::True(0x03e0), Colour::Default)),
+            screen.borrow().colours
+        );
+
+        // The colour under the cursor and transparency aren't supported, so
+        // they are ignored.
+        for &colour in &[0xfffd, 0xfffc] {
+            let operands: [ZOperand; 4] = [
+                ZOperand::LargeConstant(colour),
+                ZOperand::LargeConstant(0x001f),
+                ZOperand::Omitted,
+                ZOperand::Omitted,
+            ];
+            ext_op::o_13_set_true_colour(&mut variables, &screen, &mut violations, operands)
+                .unwrap();
+            assert_eq!(
+                Some((Colour::True(0x03e0), Colour::Default)),
+                screen.borrow().colours
+            );
+
+            let mut fatal = ZViolations::new(ErrorPolicy::Fatal);
+            assert!(
+                ext_op::o_13_set_true_colour(&mut variables, &screen, &mut fatal, operands)
+                    .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_buffer_mode() {
+        let mut variables = TestVariables::new();
+        let screen = new_handle(TestScreen::new());
+
+        var_op::o_242_buffer_mode(
+            &mut variables,
+            &screen,
+            one_operand(ZOperand::SmallConstant(0)),
+        )
+        .unwrap();
+        assert!(screen.borrow().unbuffered);
+
+        var_op::o_242_buffer_mode(
+            &mut variables,
+            &screen,
+            one_operand(ZOperand::SmallConstant(1)),
+        )
+        .unwrap();
+        assert!(!screen.borrow().unbuffered);
+    }
+
+    #[test]
+    fn test_show_status() {
+        // An object table at 0x40, with object 1 named "hall".
+        let mut memory = TestMemory::new(0x100);
+        memory.bytes[0x85..0x87].copy_from_slice(&[0x00, 0xc0]);
+        memory.bytes[0xc0] = 2;
+        memory.bytes[0xc1..0xc5].copy_from_slice(&encode_zstr("hall", 6, ZVersion::V3));
+        let memory = new_handle(memory);
+
+        let mut variables = TestVariables::new();
+        variables.write_variable(ZVariable::Global(0), 1).unwrap();
+        variables
+            .write_variable(ZVariable::Global(1), 0xfffb)
+            .unwrap();
+        variables.write_variable(ZVariable::Global(2), 7).unwrap();
+        let mut header = TestHeader::new(ZVersion::V3);
+        header.otable = ByteAddress::from_raw(0x40);
+        let objects = ZObjectTable::new(
+            &header,
+            &memory,
+            &new_handle(ZTextDecoder::new(&header, &memory)),
+        );
+        let screen = new_handle(TestScreen::new());
+
+        zero_op::o_188_show_status(&objects, &mut variables, &header, &screen, false).unwrap();
+        assert_eq!(
+            Some(("hall".to_string(), "Score: -5  Moves: 7".to_string())),
+            screen.borrow().status
+        );
+
+        // A time game.
+        header.flags1 = FLAGS1_STATUS_TIME;
+        variables.write_variable(ZVariable::Global(1), 14).unwrap();
+        zero_op::o_188_show_status(&objects, &mut variables, &header, &screen, false).unwrap();
+        assert_eq!(
+            Some(("hall".to_string(), "Time: 2:07 pm".to_string())),
+            screen.borrow().status
+        );
+
+        zero_op::o_188_show_status(&objects, &mut variables, &header, &screen, true).unwrap();
+        assert_eq!(
+            Some(("hall".to_string(), "Time: 14:07".to_string())),
+            screen.borrow().status
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        let stack = new_handle(TestStack::new(0));
+        let mut variables = TestVariables::new();
+
+        let mut pc = TestPC::new(20, vec![0b1100_1010]);
+        zero_op::o_189_verify(&mut pc, &stack, &mut variables, true).unwrap();
+        assert_eq!(29, pc.current_pc());
+
+        let mut pc = TestPC::new(20, vec![0b1100_1010]);
+        zero_op::o_189_verify(&mut pc, &stack, &mut variables, false).unwrap();
+        assert_eq!(21, pc.current_pc());
+    }
+
+    #[test]
+    fn test_piracy() {
+        let stack = new_handle(TestStack::new(0));
+        let mut variables = TestVariables::new();
+
+        // Branch on true, with a one-byte offset of 10.
+        let mut pc = TestPC::new(20, vec![0b1100_1010]);
+        zero_op::o_191_piracy(&mut pc, &stack, &mut variables, false).unwrap();
+        assert_eq!(29, pc.current_pc());
+
+        let mut pc = TestPC::new(20, vec![0b1100_1010]);
+        zero_op::o_191_piracy(&mut pc, &stack, &mut variables, true).unwrap();
+        assert_eq!(21, pc.current_pc());
+    }
+
+    #[test]
+    fn test_je() {
+        let stack = new_handle(ZStack::new());
+        let mut variables = ZVariables::new(
+            ByteAddress::from_raw(0x10),
+            new_handle(TestMemory::new(0x200)),
+            stack.clone(),
+        );
+        stack
+            .borrow_mut()
+            .push_frame(0x1234, 0, None, &[], 0)
+            .unwrap();
+
+        // Three operands, matching the last.
+        let operands = [
+            ZOperand::SmallConstant(3),
+            ZOperand::SmallConstant(1),
+            ZOperand::SmallConstant(3),
+            ZOperand::Omitted,
+        ];
+        let mut pc = TestPC::new(20, vec![0b1100_1010]);
+        two_op::o_1_je(&mut pc, &stack, &mut variables, operands).unwrap();
+        assert_eq!(29, pc.current_pc());
+
+        // Four operands, with a match before a stack operand, which is still
+        // popped.
+        variables.write_variable(ZVariable::Stack, 9).unwrap();
+        variables.write_variable(ZVariable::Stack, 4).unwrap();
+        let operands = [
+            ZOperand::SmallConstant(2),
+            ZOperand::SmallConstant(2),
+            ZOperand::SmallConstant(3),
+            ZOperand::Var(ZVariable::Stack),
+        ];
+        let mut pc = TestPC::new(20, vec![0b1100_1010]);
+        two_op::o_1_je(&mut pc, &stack, &mut variables, operands).unwrap();
+        assert_eq!(29, pc.current_pc());
+        assert_eq!(9, variables.read_variable(ZVariable::Stack).unwrap());
+
+        // Four operands, with no match.
+        let operands = [
+            ZOperand::SmallConstant(5),
+            ZOperand::SmallConstant(1),
+            ZOperand::SmallConstant(2),
+            ZOperand::SmallConstant(3),
+        ];
+        let mut pc = TestPC::new(20, vec![0b1100_1010]);
+        two_op::o_1_je(&mut pc, &stack, &mut variables, operands).unwrap();
+        assert_eq!(21, pc.current_pc());
+    }
+
+    #[test]
+    fn test_save_restore() {
+        let stack = new_handle(TestStack::new(0));
+        let mut variables = TestVariables::new();
+        let operands = [ZOperand::Omitted; 4];
+
+        // In V1-3 they branch on success.
+        let mut pc = TestPC::new(20, vec![0b1100_1010]);
+        zero_op::o_181_save(&mut pc, &stack, &mut variables, ZVersion::V3, true).unwrap();
+        assert_eq!(29, pc.current_pc());
+
+        let mut pc = TestPC::new(20, vec![0b1100_1010]);
+        zero_op::o_182_restore(&mut pc, &stack, &mut variables, ZVersion::V3, false).unwrap();
+        assert_eq!(21, pc.current_pc());
+
+        // Later, save stores 1 and a restored save stores 2.
+        let mut pc = TestPC::new(20, vec![0x10]);
+        zero_op::o_181_save(&mut pc, &stack, &mut variables, ZVersion::V4, true).unwrap();
+        assert_eq!(1, variables.read_variable(ZVariable::Global(0)).unwrap());
+
+        let mut pc = TestPC::new(20, vec![0x10]);
+        zero_op::o_182_restore(&mut pc, &stack, &mut variables, ZVersion::V4, true).unwrap();
+        assert_eq!(2, variables.read_variable(ZVariable::Global(0)).unwrap());
+
+        // Both store 0 on failure.
+        let mut pc = TestPC::new(20, vec![0x10]);
+        ext_op::o_0_save(&mut pc, &mut variables, operands, false).unwrap();
+        assert_eq!(0, variables.read_variable(ZVariable::Global(0)).unwrap());
+
+        let mut pc = TestPC::new(20, vec![0x11]);
+        ext_op::o_1_restore(&mut pc, &mut variables, operands, false).unwrap();
+        assert_eq!(0, variables.read_variable(ZVariable::Global(1)).unwrap());
+        assert_eq!(21, pc.current_pc());
     }
-}
 
-pub mod var_op {
-    use super::*;
+    #[test]
+    fn test_sound_effect() {
+        let mut variables = TestVariables::new();
+        let mut sound = TestSoundPlayer::new();
+        let mut violations = ZViolations::new(ErrorPolicy::WarnOnce);
 
-    // ZSpec: VAR:224 0x00 V1 call routine ...up to 3 args... -> (result)
-    // UNTESTED
-    pub fn o_224_call<P, S, V>(
-        pc: &mut P,
-        stack: &Handle<S>,
-        variables: &mut V,
-        version: ZVersion,
-        operands: [ZOperand; 4],
-    ) -> Result<()>
-    where
-        P: PC,
-        S: Stack,
-        V: Variables,
-    {
-        let store = pc.next_byte();
+        let operands: [ZOperand; 4] = [
+            ZOperand::SmallConstant(2),
+            ZOperand::Omitted,
+            ZOperand::Omitted,
+            ZOperand::Omitted,
+        ];
+        var_op::o_245_sound_effect(
+            &mut variables,
+            &mut sound,
+            &TestHeader::new(ZVersion::V5),
+            &mut violations,
+            operands,
+        )
+        .unwrap();
+
+        let operands: [ZOperand; 4] = [
+            ZOperand::SmallConstant(3),
+            ZOperand::SmallConstant(2),
+            ZOperand::LargeConstant(0x0205),
+            ZOperand::LargeConstant(0x0100),
+        ];
+        var_op::o_245_sound_effect(
+            &mut variables,
+            &mut sound,
+            &TestHeader::new(ZVersion::V5),
+            &mut violations,
+            operands,
+        )
+        .unwrap();
 
-        let return_pc = pc.current_pc();
+        // Before V5, there are no repeats or finish routine.
+        var_op::o_245_sound_effect(
+            &mut variables,
+            &mut sound,
+            &TestHeader::new(ZVersion::V4),
+            &mut violations,
+            operands,
+        )
+        .unwrap();
 
-        let packed = version.make_packed_address(operands[0].value(variables)?);
-        pc.set_current_pc(packed.into());
+        assert_eq!(
+            vec![
+                "bleep low".to_string(),
+                "start 3 volume 5 repeats 2 routine r400".to_string(),
+                "start 3 volume 5 repeats 0 routine none".to_string(),
+            ],
+            sound.events
+        );
+    }
 
-        // Read function header.
-        let num_locals = pc.next_byte();
+    use super::super::fixtures::TestPC;
+    #[test]
+    fn test_branch_offset_returns() {
+        let stack = new_handle(ZStack::new());
+        let mut variables = ZVariables::new(
+            ByteAddress::from_raw(0x10),
+            new_handle(TestMemory::new(0x100)),
+            stack.clone(),
+        );
 
-        let mut local_values = [0u16; 15];
-        if version < ZVersion::V5 {
-            // On <V5, the function header also contains the starting values for the locals.
-            for i in 0..num_locals {
-                local_values[usize::from(i)] = pc.next_word();
-            }
-        }
+        // Offset 1 returns true.
+        stack
+            .borrow_mut()
+            .push_frame(0x1234, 0, Some(ZVariable::Stack), &[], 0)
+            .unwrap();
+        let mut pc = TestPC::new(20, vec![0b1100_0001]);
+        one_op::o_128_jz(&mut pc, &stack, &mut variables, ZOperand::SmallConstant(0)).unwrap();
+        assert_eq!(0x1234, pc.current_pc());
+        assert_eq!(1, variables.read_variable(ZVariable::Stack).unwrap());
 
+        // Offset 0 returns false.
         stack
             .borrow_mut()
-            .push_frame(return_pc, num_locals, store.into(), &local_values)?;
+            .push_frame(0x5678, 0, Some(ZVariable::Stack), &[], 0)
+            .unwrap();
+        let mut pc = TestPC::new(20, vec![0b1100_0000]);
+        one_op::o_128_jz(&mut pc, &stack, &mut variables, ZOperand::SmallConstant(0)).unwrap();
+        assert_eq!(0x5678, pc.current_pc());
+        assert_eq!(0, variables.read_variable(ZVariable::Stack).unwrap());
+    }
 
-        // TODO: do you ever push the arguments? I think you're not.
-        // TODO: something is not right about the interaction between the routine header
-        //       and the parameters. Write some test cases for this.
+    #[test]
+    fn test_interpret_offset_byte() {
+        let mut pc = TestPC::new(10, vec![0; 0]);
+        assert_eq!(0b10_1010, interpret_offset_byte(0b0110_1010, &mut pc));
 
-        // TODO: print operand[0] as a PackedAddress.
-        debug!(
-            "call        {} {} {} {} -> {}",
-            packed, operands[1], operands[2], operands[3], store
+        let mut pc = TestPC::new(10, vec![0xab]);
+        assert_eq!(0x0aab, interpret_offset_byte(0b0000_1010, &mut pc));
+
+        let mut pc = TestPC::new(10, vec![0xab]);
+        assert_eq!(
+            0b1110_1010_1010_1011u32 as i16,
+            interpret_offset_byte(0b0010_1010, &mut pc)
         );
-        Ok(())
     }
 
-    // ZSpec: VAR:225 0x01 storew array word-index value
-    pub fn o_225_storew<M, V>(
-        mem_h: &Handle<M>,
-        variables: &mut V,
-        operands: [ZOperand; 4],
-    ) -> Result<()>
-    where
-        M: Memory,
-        V: Variables,
-    {
-        debug!(
-            "storew      {} {} {} {}",
-            operands[0], operands[1], operands[2], operands[3]
-        );
+    #[test]
+    fn test_jl_jg() {
+        let stack = new_handle(TestStack::new(0));
+        let mut variables = TestVariables::new();
 
-        let array = operands[0].value(variables)?;
-        let word_index = operands[1].value(variables)?;
-        let value = operands[2].value(variables)?;
+        // The comparisons are signed, so -1 is less than 1.
+        let operands = [ZOperand::LargeConstant(0xffff), ZOperand::SmallConstant(1)];
+        let mut pc = TestPC::new(8, vec![0xc5]);
+        two_op::o_2_jl(&mut pc, &stack, &mut variables, operands).unwrap();
+        assert_eq!(12, pc.current_pc());
 
-        let ba = ByteAddress::from_raw(array).inc_by(2 * word_index);
-        mem_h.borrow_mut().write_word(ba, value)
+        let mut pc = TestPC::new(8, vec![0xc5]);
+        two_op::o_3_jg(&mut pc, &stack, &mut variables, operands).unwrap();
+        assert_eq!(9, pc.current_pc());
     }
 
-    // ZSpec: VAR:227 0x03 put_prop object property value
-    // UNTESTED
-    pub fn o_227_put_prop(operands: [ZOperand; 4]) {
-        debug!(
-            "put_prop   {} {} {} {}             XXX",
-            operands[0], operands[1], operands[2], operands[3]
-        );
-        unimplemented!("put_prop")
-    }
+    #[test]
+    fn test_test_or() {
+        let stack = new_handle(TestStack::new(0));
+        let mut variables = TestVariables::new();
 
-    // ZSpec: VAR:229 0x05 print_char output_character_code
-    // UNTESTED
-    pub fn o_229_print_char<V>(variables: &mut V, operands: [ZOperand; 4]) -> Result<()>
-    where
-        V: Variables,
-    {
-        debug!("print_char {}", operands[0]);
-        // TODO: deal with the case where extra argements are passed.
-        //       stuff will break if an extra SP arg is passed, but never popped.
-        let ch = operands[0].value(variables)? as u8 as char;
-        print!("{}", ch);
-        Ok(())
+        // Branch if all of the flags are set.
+        let operands = [
+            ZOperand::SmallConstant(0b1011),
+            ZOperand::SmallConstant(0b0011),
+        ];
+        let mut pc = TestPC::new(8, vec![0xc5]);
+        two_op::o_7_test(&mut pc, &stack, &mut variables, operands).unwrap();
+        assert_eq!(12, pc.current_pc());
+
+        let operands = [
+            ZOperand::SmallConstant(0b1011),
+            ZOperand::SmallConstant(0b0111),
+        ];
+        let mut pc = TestPC::new(8, vec![0xc5]);
+        two_op::o_7_test(&mut pc, &stack, &mut variables, operands).unwrap();
+        assert_eq!(9, pc.current_pc());
+
+        let mut pc = TestPC::new(8, vec![0x00]);
+        two_op::o_8_or(&mut pc, &mut variables, operands).unwrap();
+        assert_eq!(0b1111, variables.variables[&ZVariable::Stack]);
     }
 
-    // ZSpec: VAR:230 0x06 print_num value
-    // UNTESTED
-    pub fn o_230_print_num<V>(variables: &mut V, operands: [ZOperand; 4]) -> Result<()>
-    where
-        V: Variables,
-    {
-        debug!(
-            "print_num  {} {} {} {}",
-            operands[0], operands[1], operands[2], operands[3]
-        );
+    #[test]
+    fn test_mul_div_mod() {
+        let mut variables = TestVariables::new();
+        let operands = [
+            ZOperand::LargeConstant(-13i16 as u16),
+            ZOperand::SmallConstant(5),
+        ];
 
-        let num = operands[0].value(variables)?;
-        print!("{}", (num as i16));
+        two_op::o_22_mul(&mut TestPC::new(8, vec![0x00]), &mut variables, operands).unwrap();
+        assert_eq!(-65i16 as u16, variables.variables[&ZVariable::Stack]);
 
-        Ok(())
+        // Division rounds towards zero, and the remainder has the sign of the
+        // dividend.
+        two_op::o_23_div(&mut TestPC::new(8, vec![0x00]), &mut variables, operands).unwrap();
+        assert_eq!(-2i16 as u16, variables.variables[&ZVariable::Stack]);
+        two_op::o_24_mod(&mut TestPC::new(8, vec![0x00]), &mut variables, operands).unwrap();
+        assert_eq!(-3i16 as u16, variables.variables[&ZVariable::Stack]);
+
+        let operands = [ZOperand::SmallConstant(1), ZOperand::SmallConstant(0)];
+        match two_op::o_23_div(&mut TestPC::new(8, vec![0x00]), &mut variables, operands) {
+            Err(ZErr::DivisionByZero) => (),
+            _ => panic!("Division by zero should fail"),
+        }
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::super::fixtures::*;
-    use super::super::handle::new_handle;
-    use super::*;
+    #[test]
+    fn test_storeb_push() {
+        let stack = new_handle(ZStack::new());
+        let memory = new_handle(TestMemory::new(0x100));
+        let mut variables =
+            ZVariables::new(ByteAddress::from_raw(0x10), memory.clone(), stack.clone());
+
+        let operands = [
+            ZOperand::SmallConstant(0x80),
+            ZOperand::SmallConstant(3),
+            ZOperand::LargeConstant(0x1234),
+            ZOperand::Omitted,
+        ];
+        var_op::o_226_storeb(&memory, &mut variables, operands).unwrap();
+        assert_eq!(0x34, memory.borrow().bytes[0x83]);
+        assert_eq!(0, memory.borrow().bytes[0x82]);
+
+        var_op::o_232_push(&mut variables, one_operand(ZOperand::SmallConstant(9))).unwrap();
+        assert_eq!(9, stack.borrow_mut().pop_word().unwrap());
+    }
 
     #[test]
-    fn test_add() {
-        let mut pc = TestPC::new(
-            8,
-            vec![
-                0, // Stack
-            ],
+    fn test_call_vn_discards_result() {
+        // A V5 routine with no locals.
+        let mut pc = TestPC::new(8, vec![0]);
+        let stack = new_handle(ZStack::new());
+        let mut variables = ZVariables::new(
+            ByteAddress::from_raw(0x10),
+            new_handle(TestMemory::new(0x200)),
+            stack.clone(),
         );
-        let mut variables = TestVariables::new();
-        let operands: [ZOperand; 2] = [ZOperand::SmallConstant(3), ZOperand::LargeConstant(98)];
+        variables.write_variable(ZVariable::Stack, 42).unwrap();
 
-        two_op::o_20_add(&mut pc, &mut variables, operands).unwrap();
+        let operands = one_operand(ZOperand::LargeConstant(0x40));
+        var_op::o_249_call_vn(
+            &mut pc,
+            &stack,
+            &mut variables,
+            &TestHeader::new(ZVersion::V5),
+            operands,
+        )
+        .unwrap();
+        assert_eq!(0x101, pc.current_pc());
 
-        // Ensure that the pc advanced one byte.
-        assert_eq!(9, pc.current_pc());
-        assert_eq!(101, variables.variables[&ZVariable::Stack]);
+        // There is no store byte to skip, and the result goes nowhere.
+        one_op::o_139_ret(&mut pc, &stack, &mut variables, ZOperand::SmallConstant(7)).unwrap();
+        assert_eq!(8, pc.current_pc());
+        assert_eq!(42, variables.read_variable(ZVariable::Stack).unwrap());
+        assert!(variables.read_variable(ZVariable::Stack).is_err());
     }
 
     #[test]
-    fn test_add_overflow() {
-        let mut pc = TestPC::new(
-            8,
-            vec![
-                0, // Stack
-            ],
+    fn test_catch_throw() {
+        let stack = new_handle(ZStack::new());
+        let mut variables = ZVariables::new(
+            ByteAddress::from_raw(0x10),
+            new_handle(TestMemory::new(0x200)),
+            stack.clone(),
         );
+
+        // The catching routine's result goes on the stack.
+        stack
+            .borrow_mut()
+            .push_frame(0x1234, 0, Some(ZVariable::Stack), &[], 0)
+            .unwrap();
+        let mut pc = TestPC::new(20, vec![0x10]);
+        zero_op::o_185_catch(&mut pc, &stack, &mut variables).unwrap();
+        let frame = variables.read_variable(ZVariable::Global(0)).unwrap();
+        assert_eq!(1, frame);
+
+        // Two calls deeper, throw returns from the catching routine.
+        stack
+            .borrow_mut()
+            .push_frame(0x2000, 0, Some(ZVariable::Stack), &[], 0)
+            .unwrap();
+        stack
+            .borrow_mut()
+            .push_frame(0x3000, 0, None, &[], 0)
+            .unwrap();
+        let operands = [ZOperand::SmallConstant(9), ZOperand::LargeConstant(frame)];
+        two_op::o_28_throw(&mut pc, &stack, &mut variables, operands).unwrap();
+        assert_eq!(0x1234, pc.current_pc());
+        assert_eq!(0, stack.borrow().frame_depth());
+        assert_eq!(9, variables.read_variable(ZVariable::Stack).unwrap());
+
+        // That routine has returned, so it can't be thrown to again.
+        assert!(two_op::o_28_throw(&mut pc, &stack, &mut variables, operands).is_err());
+    }
+
+    #[test]
+    fn test_check_arg_count() {
+        let stack = new_handle(ZStack::new());
         let mut variables = TestVariables::new();
-        let operands: [ZOperand; 2] = [ZOperand::LargeConstant(65530), ZOperand::SmallConstant(98)];
+        stack
+            .borrow_mut()
+            .push_frame(0x1234, 3, Some(ZVariable::Stack), &[5, 6], 2)
+            .unwrap();
 
-        two_op::o_20_add(&mut pc, &mut variables, operands).unwrap();
+        let mut pc = TestPC::new(8, vec![0xc5]);
+        let operands = one_operand(ZOperand::SmallConstant(2));
+        var_op::o_255_check_arg_count(&mut pc, &stack, &mut variables, operands).unwrap();
+        assert_eq!(12, pc.current_pc());
 
-        // Ensure that the pc advanced one byte.
+        let mut pc = TestPC::new(8, vec![0xc5]);
+        let operands = one_operand(ZOperand::SmallConstant(3));
+        var_op::o_255_check_arg_count(&mut pc, &stack, &mut variables, operands).unwrap();
         assert_eq!(9, pc.current_pc());
-        assert_eq!(92, variables.variables[&ZVariable::Stack]);
     }
 
     #[test]
-    fn test_store() {
+    fn test_scan_table() {
+        let stack = new_handle(TestStack::new(0));
         let mut variables = TestVariables::new();
-        let operands: [ZOperand; 2] = [
-            ZOperand::SmallConstant(0), // Stack
-            ZOperand::LargeConstant(45),
+        let mut memory = TestMemory::new(0x100);
+        memory.bytes[0x40..0x46].copy_from_slice(&[0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc]);
+        let memory = new_handle(memory);
+
+        // The fields are words by default.
+        let operands = [
+            ZOperand::LargeConstant(0x5678),
+            ZOperand::SmallConstant(0x40),
+            ZOperand::SmallConstant(3),
+            ZOperand::Omitted,
         ];
-        two_op::o_13_store(&mut variables, operands).unwrap();
+        let mut pc = TestPC::new(8, vec![0x00, 0xc5]);
+        var_op::o_247_scan_table(&memory, &mut pc, &stack, &mut variables, operands).unwrap();
+        assert_eq!(13, pc.current_pc());
+        assert_eq!(0x42, variables.variables[&ZVariable::Stack]);
 
-        assert_eq!(45, variables.variables[&ZVariable::Stack]);
+        // Three-byte fields, compared by their first byte.
+        let operands = [
+            ZOperand::SmallConstant(0x78),
+            ZOperand::SmallConstant(0x40),
+            ZOperand::SmallConstant(2),
+            ZOperand::SmallConstant(0x03),
+        ];
+        let mut pc = TestPC::new(8, vec![0x00, 0xc5]);
+        var_op::o_247_scan_table(&memory, &mut pc, &stack, &mut variables, operands).unwrap();
+        assert_eq!(13, pc.current_pc());
+        assert_eq!(0x43, variables.variables[&ZVariable::Stack]);
+
+        // 0x9a doesn't start a field.
+        let operands = [
+            ZOperand::SmallConstant(0x9a),
+            ZOperand::SmallConstant(0x40),
+            ZOperand::SmallConstant(2),
+            ZOperand::SmallConstant(0x03),
+        ];
+        let mut pc = TestPC::new(8, vec![0x00, 0xc5]);
+        var_op::o_247_scan_table(&memory, &mut pc, &stack, &mut variables, operands).unwrap();
+        assert_eq!(10, pc.current_pc());
+        assert_eq!(0, variables.variables[&ZVariable::Stack]);
     }
 
     #[test]
-    fn test_storew() {
+    fn test_copy_table() {
         let mut variables = TestVariables::new();
-        let mem_h = new_handle(TestMemory::new(1000));
-        let operands: [ZOperand; 4] = [
-            ZOperand::SmallConstant(234),
-            ZOperand::SmallConstant(5),
-            ZOperand::LargeConstant(0xabcd),
-            ZOperand::Omitted,
+        let memory = new_handle(TestMemory::new_from_vec(vec![1, 2, 3, 4, 5, 6, 0, 0]));
+        let copy = |first, second, size: i16| {
+            [
+                ZOperand::SmallConstant(first),
+                ZOperand::SmallConstant(second),
+                ZOperand::LargeConstant(size as u16),
+                ZOperand::Omitted,
+            ]
+        };
+
+        // Copying onto an overlapping table keeps the original bytes.
+        var_op::o_253_copy_table(&memory, &mut variables, copy(0, 2, 4)).unwrap();
+        assert_eq!(vec![1, 2, 1, 2, 3, 4, 0, 0], memory.borrow().bytes);
+
+        // A negative size copies forwards regardless.
+        var_op::o_253_copy_table(&memory, &mut variables, copy(0, 2, -4)).unwrap();
+        assert_eq!(vec![1, 2, 1, 2, 1, 2, 0, 0], memory.borrow().bytes);
+
+        // With no second table, the first is zeroed.
+        var_op::o_253_copy_table(&memory, &mut variables, copy(1, 0, 3)).unwrap();
+        assert_eq!(vec![1, 0, 0, 0, 1, 2, 0, 0], memory.borrow().bytes);
+    }
+
+    #[test]
+    fn test_print_table() {
+        let mut memory = TestMemory::new(0x100);
+        memory.bytes[0x40..0x48].copy_from_slice(b"abcXdefX");
+        let memory = new_handle(memory);
+        let mut variables = TestVariables::new();
+        let mut violations = ZViolations::new(ErrorPolicy::WarnOnce);
+        let mut streams = TestOutputStreams::new();
+        let screen = new_handle(TestScreen::new());
+
+        // Two rows of three, skipping a byte after each.
+        let operands = [
+            ZOperand::SmallConstant(0x40),
+            ZOperand::SmallConstant(3),
+            ZOperand::SmallConstant(2),
+            ZOperand::SmallConstant(1),
         ];
+        var_op::o_254_print_table(
+            &memory,
+            &mut variables,
+            &mut streams,
+            &screen,
+            &mut violations,
+            ZVersion::V5,
+            operands,
+        )
+        .unwrap();
+        assert_eq!("abc\ndef", streams.output);
+    }
 
-        var_op::o_225_storew(&mem_h, &mut variables, operands).unwrap();
+    #[test]
+    fn test_set_font() {
+        fn set_font(font: &mut u16, requested: u8) -> u16 {
+            let mut variables = TestVariables::new();
+            let operands = one_operand(ZOperand::SmallConstant(requested));
+            ext_op::o_4_set_font(
+                &mut TestPC::new(8, vec![0x00]),
+                &mut variables,
+                font,
+                operands,
+            )
+            .unwrap();
+            variables.variables[&ZVariable::Stack]
+        }
 
-        assert_eq!(0xab, mem_h.borrow().bytes[244]);
-        assert_eq!(0xcd, mem_h.borrow().bytes[245]);
+        let mut font = 1;
+        assert_eq!(1, set_font(&mut font, 4));
+        assert_eq!(4, set_font(&mut font, 0));
+        // The character graphics font is unavailable.
+        assert_eq!(0, set_font(&mut font, 3));
+        assert_eq!(4, set_font(&mut font, 1));
+        assert_eq!(1, font);
+    }
+
+    // A V3 object table at 0x40. Object 1 holds objects 2 and 3, and they
+    // share a property table at 0xc0, with no name and properties 18 (two
+    // bytes) and 7 (one byte).
+    fn v3_objects() -> (Handle<TestMemory>, ZObjectTable<TestMemory>) {
+        let mut memory = TestMemory::new(0x100);
+        memory.bytes[0x82..0x87].copy_from_slice(&[0, 0, 2, 0x00, 0xc0]);
+        memory.bytes[0x8b..0x90].copy_from_slice(&[1, 3, 0, 0x00, 0xc0]);
+        memory.bytes[0x94..0x99].copy_from_slice(&[1, 0, 0, 0x00, 0xc0]);
+        memory.bytes[0xc0..0xc7].copy_from_slice(&[0, 0x32, 0x12, 0x34, 0x07, 0x56, 0]);
+        let memory = new_handle(memory);
+        let mut header = TestHeader::new(ZVersion::V3);
+        header.otable = ByteAddress::from_raw(0x40);
+        let objects = ZObjectTable::new(
+            &header,
+            &memory,
+            &new_handle(ZTextDecoder::new(&header, &memory)),
+        );
+        (memory, objects)
     }
 
-    use super::super::fixtures::TestPC;
     #[test]
-    fn test_interpret_offset_byte() {
-        let mut pc = TestPC::new(10, vec![0; 0]);
-        assert_eq!(0b10_1010, interpret_offset_byte(0b0110_1010, &mut pc));
+    fn test_object_relatives() {
+        let (_, objects) = v3_objects();
+        let stack = new_handle(TestStack::new(0));
+        let mut variables = TestVariables::new();
+        let mut violations = ZViolations::new(ErrorPolicy::WarnOnce);
 
-        let mut pc = TestPC::new(10, vec![0xab]);
-        assert_eq!(0x0aab, interpret_offset_byte(0b0000_1010, &mut pc));
+        // Object 2 has a sibling, so get_sibling stores it and branches.
+        let mut pc = TestPC::new(8, vec![0x00, 0xc5]);
+        let operand = ZOperand::SmallConstant(2);
+        one_op::o_129_get_sibling(
+            &objects,
+            &mut pc,
+            &stack,
+            &mut variables,
+            &mut violations,
+            operand,
+        )
+        .unwrap();
+        assert_eq!(13, pc.current_pc());
+        assert_eq!(3, variables.variables[&ZVariable::Stack]);
 
-        let mut pc = TestPC::new(10, vec![0xab]);
+        // It has no child.
+        let mut pc = TestPC::new(8, vec![0x00, 0xc5]);
+        one_op::o_130_get_child(
+            &objects,
+            &mut pc,
+            &stack,
+            &mut variables,
+            &mut violations,
+            operand,
+        )
+        .unwrap();
+        assert_eq!(10, pc.current_pc());
+        assert_eq!(0, variables.variables[&ZVariable::Stack]);
+
+        let mut pc = TestPC::new(8, vec![0x00, 0xc5]);
+        let operand = ZOperand::SmallConstant(1);
+        one_op::o_130_get_child(
+            &objects,
+            &mut pc,
+            &stack,
+            &mut variables,
+            &mut violations,
+            operand,
+        )
+        .unwrap();
+        assert_eq!(13, pc.current_pc());
+        assert_eq!(2, variables.variables[&ZVariable::Stack]);
+
+        let mut pc = TestPC::new(8, vec![0x00]);
+        let operand = ZOperand::SmallConstant(3);
+        one_op::o_131_get_parent(&objects, &mut pc, &mut variables, &mut violations, operand)
+            .unwrap();
+        assert_eq!(1, variables.variables[&ZVariable::Stack]);
+    }
+
+    #[test]
+    fn test_object_tree() {
+        let (memory, objects) = v3_objects();
+        let stack = new_handle(TestStack::new(0));
+        let mut variables = TestVariables::new();
+        let mut violations = ZViolations::new(ErrorPolicy::WarnOnce);
+
+        // Object 3 is in object 1, but not in object 2.
+        let mut pc = TestPC::new(8, vec![0xc5]);
+        let operands = [ZOperand::SmallConstant(3), ZOperand::SmallConstant(1)];
+        two_op::o_6_jin(
+            &objects,
+            &mut pc,
+            &stack,
+            &mut variables,
+            &mut violations,
+            operands,
+        )
+        .unwrap();
+        assert_eq!(12, pc.current_pc());
+        let mut pc = TestPC::new(8, vec![0xc5]);
+        let operands = [ZOperand::SmallConstant(3), ZOperand::SmallConstant(2)];
+        two_op::o_6_jin(
+            &objects,
+            &mut pc,
+            &stack,
+            &mut variables,
+            &mut violations,
+            operands,
+        )
+        .unwrap();
+        assert_eq!(9, pc.current_pc());
+
+        // Removing the last child unlinks it from its older sibling.
+        one_op::o_137_remove_obj(
+            &objects,
+            &mut variables,
+            &mut violations,
+            ZOperand::SmallConstant(3),
+        )
+        .unwrap();
+        assert_eq!(&[0, 0, 2], &memory.borrow().bytes[0x82..0x85]);
+        assert_eq!(&[1, 0, 0], &memory.borrow().bytes[0x8b..0x8e]);
+        assert_eq!(&[0, 0, 0], &memory.borrow().bytes[0x94..0x97]);
+
+        // Inserting makes it the first child.
+        let operands = [ZOperand::SmallConstant(3), ZOperand::SmallConstant(1)];
+        two_op::o_14_insert_obj(&objects, &mut variables, &mut violations, operands).unwrap();
+        assert_eq!(&[0, 0, 3], &memory.borrow().bytes[0x82..0x85]);
+        assert_eq!(&[1, 2, 0], &memory.borrow().bytes[0x94..0x97]);
+
+        // Removing the first child passes the parent on to its sibling.
+        one_op::o_137_remove_obj(
+            &objects,
+            &mut variables,
+            &mut violations,
+            ZOperand::SmallConstant(3),
+        )
+        .unwrap();
+        assert_eq!(&[0, 0, 2], &memory.borrow().bytes[0x82..0x85]);
+        assert_eq!(&[0, 0, 0], &memory.borrow().bytes[0x94..0x97]);
+
+        // Moving object 1 into object 3 keeps its children.
+        let operands = [ZOperand::SmallConstant(1), ZOperand::SmallConstant(3)];
+        two_op::o_14_insert_obj(&objects, &mut variables, &mut violations, operands).unwrap();
+        assert_eq!(&[3, 0, 2], &memory.borrow().bytes[0x82..0x85]);
+        assert_eq!(&[0, 0, 1], &memory.borrow().bytes[0x94..0x97]);
+
+        // Object 0 isn't in the tree, so moving it is a violation, and does
+        // nothing.
+        let before = memory.borrow().bytes.clone();
+        let operand = ZOperand::SmallConstant(0);
+        one_op::o_137_remove_obj(&objects, &mut variables, &mut violations, operand).unwrap();
+        let operands = [ZOperand::SmallConstant(2), ZOperand::SmallConstant(0)];
+        two_op::o_14_insert_obj(&objects, &mut variables, &mut violations, operands).unwrap();
+        assert_eq!(before, memory.borrow().bytes);
+
+        let mut violations = ZViolations::new(ErrorPolicy::Fatal);
+        match one_op::o_137_remove_obj(&objects, &mut variables, &mut violations, operand) {
+            Err(ZErr::Violation(Violation::NullObject("remove_obj"))) => (),
+            _ => panic!("Missing violation"),
+        }
+    }
+
+    #[test]
+    fn test_object_attributes() {
+        let (memory, objects) = v3_objects();
+        let stack = new_handle(TestStack::new(0));
+        let mut variables = TestVariables::new();
+        let mut violations = ZViolations::new(ErrorPolicy::WarnOnce);
+
+        let operands = [ZOperand::SmallConstant(2), ZOperand::SmallConstant(9)];
+        two_op::o_11_set_attr(&objects, &mut variables, &mut violations, operands).unwrap();
         assert_eq!(
-            0b1110_1010_1010_1011u32 as i16,
-            interpret_offset_byte(0b0010_1010, &mut pc)
+            &[0x00, 0x40, 0x00, 0x00],
+            &memory.borrow().bytes[0x87..0x8b]
+        );
+
+        let mut pc = TestPC::new(8, vec![0xc5]);
+        two_op::o_10_test_attr(
+            &objects,
+            &mut pc,
+            &stack,
+            &mut variables,
+            &mut violations,
+            operands,
+        )
+        .unwrap();
+        assert_eq!(12, pc.current_pc());
+
+        two_op::o_12_clear_attr(&objects, &mut variables, &mut violations, operands).unwrap();
+        assert_eq!(
+            &[0x00, 0x00, 0x00, 0x00],
+            &memory.borrow().bytes[0x87..0x8b]
+        );
+
+        let mut pc = TestPC::new(8, vec![0xc5]);
+        two_op::o_10_test_attr(
+            &objects,
+            &mut pc,
+            &stack,
+            &mut variables,
+            &mut violations,
+            operands,
+        )
+        .unwrap();
+        assert_eq!(9, pc.current_pc());
+
+        // There are only 32 attributes in V3.
+        let operands = [ZOperand::SmallConstant(2), ZOperand::SmallConstant(32)];
+        assert!(
+            two_op::o_11_set_attr(&objects, &mut variables, &mut violations, operands).is_err()
         );
     }
 
+    #[test]
+    fn test_object_properties() {
+        let (memory, objects) = v3_objects();
+        memory
+            .borrow_mut()
+            .write_word(ByteAddress::from_raw(0x46), 0x4444)
+            .unwrap();
+        let mut variables = TestVariables::new();
+        let mut violations = ZViolations::new(ErrorPolicy::WarnOnce);
+        let object = ZOperand::SmallConstant(1);
+
+        let mut pc = TestPC::new(8, vec![0x00]);
+        let operands = [object, ZOperand::SmallConstant(18)];
+        two_op::o_17_get_prop(&objects, &mut pc, &mut variables, &mut violations, operands)
+            .unwrap();
+        assert_eq!(0x1234, variables.variables[&ZVariable::Stack]);
+
+        // Missing properties come from the defaults.
+        let mut pc = TestPC::new(8, vec![0x00]);
+        let operands = [object, ZOperand::SmallConstant(4)];
+        two_op::o_17_get_prop(&objects, &mut pc, &mut variables, &mut violations, operands)
+            .unwrap();
+        assert_eq!(0x4444, variables.variables[&ZVariable::Stack]);
+
+        // Object 0 has no properties, and using it is a violation.
+        let mut pc = TestPC::new(8, vec![0x00]);
+        let operands = [ZOperand::SmallConstant(0), ZOperand::SmallConstant(18)];
+        two_op::o_17_get_prop(&objects, &mut pc, &mut variables, &mut violations, operands)
+            .unwrap();
+        assert_eq!(0, variables.variables[&ZVariable::Stack]);
+        let mut fatal = ZViolations::new(ErrorPolicy::Fatal);
+        let mut pc = TestPC::new(8, vec![0x00]);
+        match two_op::o_17_get_prop(&objects, &mut pc, &mut variables, &mut fatal, operands) {
+            Err(ZErr::Violation(Violation::NullObject("get_prop"))) => (),
+            _ => panic!("Missing violation"),
+        }
+
+        let operands = [
+            object,
+            ZOperand::SmallConstant(7),
+            ZOperand::LargeConstant(0x1ff),
+            ZOperand::Omitted,
+        ];
+        var_op::o_227_put_prop(&objects, &mut variables, &mut violations, operands).unwrap();
+        assert_eq!(0xff, memory.borrow().bytes[0xc5]);
+        let operands = [
+            object,
+            ZOperand::SmallConstant(4),
+            ZOperand::SmallConstant(1),
+            ZOperand::Omitted,
+        ];
+        assert!(
+            var_op::o_227_put_prop(&objects, &mut variables, &mut violations, operands).is_err()
+        );
+
+        let mut pc = TestPC::new(8, vec![0x00]);
+        let operands = [object, ZOperand::SmallConstant(7)];
+        two_op::o_18_get_prop_addr(&objects, &mut pc, &mut variables, &mut violations, operands)
+            .unwrap();
+        assert_eq!(0xc5, variables.variables[&ZVariable::Stack]);
+        let mut pc = TestPC::new(8, vec![0x00]);
+        let operands = [object, ZOperand::SmallConstant(4)];
+        two_op::o_18_get_prop_addr(&objects, &mut pc, &mut variables, &mut violations, operands)
+            .unwrap();
+        assert_eq!(0, variables.variables[&ZVariable::Stack]);
+
+        for &(address, len) in &[(0xc2, 2), (0xc5, 1), (0, 0)] {
+            let mut pc = TestPC::new(8, vec![0x00]);
+            let operand = ZOperand::LargeConstant(address);
+            one_op::o_132_get_prop_len(&objects, &mut pc, &mut variables, operand).unwrap();
+            assert_eq!(len, variables.variables[&ZVariable::Stack]);
+        }
+
+        for &(property, next) in &[(0, 18), (18, 7), (7, 0)] {
+            let mut pc = TestPC::new(8, vec![0x00]);
+            let operands = [object, ZOperand::SmallConstant(property)];
+            two_op::o_19_get_next_prop(
+                &objects,
+                &mut pc,
+                &mut variables,
+                &mut violations,
+                operands,
+            )
+            .unwrap();
+            assert_eq!(next, variables.variables[&ZVariable::Stack]);
+        }
+        let mut pc = TestPC::new(8, vec![0x00]);
+        let operands = [object, ZOperand::SmallConstant(4)];
+        assert!(two_op::o_19_get_next_prop(
+            &objects,
+            &mut pc,
+            &mut variables,
+            &mut violations,
+            operands
+        )
+        .is_err());
+    }
 }
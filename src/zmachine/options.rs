@@ -0,0 +1,95 @@
+use std::path::PathBuf;
+
+use super::header::{INTERPRETER_NUMBER, INTERPRETER_VERSION, STANDARD_REVISION};
+use super::quetzal::MemoryFormat;
+use super::violations::ErrorPolicy;
+
+pub const DEFAULT_UNDO_LEVELS: usize = 10;
+
+// Interpreter settings that are not part of the story file.
+#[derive(Clone, Debug)]
+pub struct ZOptions {
+    // When set, the piracy opcode reports the story as a pirated copy.
+    // Useful for testing a game's copy-protection paths.
+    pub pirated: bool,
+
+    // Show the time in time games on a 24-hour clock, rather than 12-hour.
+    pub twenty_four_hour_clock: bool,
+
+    // Use this screen size, as (lines, columns), instead of the terminal's.
+    // It is what the game is told, and what text is wrapped and paged to.
+    pub screen_size: Option<(u16, u16)>,
+
+    // What the header says about the interpreter. A few games act on the
+    // interpreter number, so pretending to be another machine (2 is an
+    // Apple IIe, for instance) can change how they behave. (ZSpec 11.1.3)
+    pub interpreter_number: u8,
+    pub interpreter_version: u8,
+    // As (major, minor).
+    pub standard_revision: (u8, u8),
+
+    // How many save_undo states to keep. Zero turns undo off.
+    pub undo_levels: usize,
+
+    // How saves store dynamic memory. Uncompressed saves are larger, but
+    // simpler for other tools to read.
+    pub memory_format: MemoryFormat,
+
+    // Seed the random number generator with this, rather than the time, so
+    // that a run can be repeated.
+    pub random_seed: Option<u64>,
+
+    // Start with the transcript (stream 2) open, writing to this file, rather
+    // than waiting for the game to open it.
+    pub transcript: Option<PathBuf>,
+
+    // Start with the command script (stream 4) open, writing every command
+    // to this file.
+    pub command_script: Option<PathBuf>,
+    // Start reading commands from this file (input stream 1), and switch to
+    // the keyboard when it runs out.
+    pub command_file: Option<PathBuf>,
+
+    // Write the seed, and all keyboard input, to this session file.
+    pub record_session: Option<PathBuf>,
+    // Play back a session file, using its seed, and then carry on from the
+    // keyboard.
+    pub replay_session: Option<PathBuf>,
+
+    // Keep this many of the latest reads and writes of dynamic memory, and
+    // log them if the game stops with an error. Zero turns this off.
+    pub memory_audit: usize,
+
+    // What to do when the game breaks a rule that the interpreter can work
+    // around, like printing object 0.
+    pub strict: ErrorPolicy,
+}
+
+impl Default for ZOptions {
+    fn default() -> ZOptions {
+        ZOptions {
+            pirated: false,
+            twenty_four_hour_clock: false,
+            screen_size: None,
+            interpreter_number: INTERPRETER_NUMBER,
+            interpreter_version: INTERPRETER_VERSION,
+            standard_revision: STANDARD_REVISION,
+            undo_levels: DEFAULT_UNDO_LEVELS,
+            memory_format: MemoryFormat::default(),
+            random_seed: None,
+            transcript: None,
+            command_script: None,
+            command_file: None,
+            record_session: None,
+            replay_session: None,
+            memory_audit: 0,
+            strict: ErrorPolicy::default(),
+        }
+    }
+}
+
+impl ZOptions {
+    pub fn new() -> ZOptions {
+        ZOptions::default()
+    }
+}
@@ -0,0 +1,90 @@
+// Counts the lines printed since the player last typed something, so that
+// a screenful of text can't scroll away unread. (ZSpec 8.4.1)
+//
+// Text is passed through as it is displayed, and comes back split into
+// pages. The screen should show a [MORE] prompt, and wait for a key, between
+// each page. Counting restarts whenever the player is asked for input.
+//
+// A pager with no page size never splits text. Use that for non-interactive
+// runs, where there is nobody to press a key.
+pub struct Pager {
+    page_lines: Option<u16>,
+    lines: u16,
+}
+
+impl Pager {
+    pub fn new(page_lines: Option<u16>) -> Pager {
+        Pager {
+            page_lines,
+            lines: 0,
+        }
+    }
+
+    pub fn page_lines(&self) -> Option<u16> {
+        self.page_lines
+    }
+
+    // Change the page size, for instance because the upper window grew.
+    pub fn set_page_lines(&mut self, page_lines: Option<u16>) {
+        self.page_lines = page_lines;
+    }
+
+    // Call whenever the player is asked for input.
+    pub fn reset(&mut self) {
+        self.lines = 0;
+    }
+
+    // Split displayed text into pages. Each break follows a newline.
+    pub fn page(&mut self, text: &str) -> Vec<String> {
+        let page_lines = match self.page_lines {
+            Some(lines) => lines.max(1),
+            None => return vec![text.to_string()],
+        };
+
+        let mut pages = vec![String::new()];
+        for ch in text.chars() {
+            if self.lines >= page_lines {
+                pages.push(String::new());
+                self.lines = 0;
+            }
+            pages.last_mut().unwrap().push(ch);
+            if ch == '\n' {
+                self.lines += 1;
+            }
+        }
+        pages
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_page_breaks() {
+        let mut pager = Pager::new(Some(2));
+        assert_eq!(vec!["one\n"], pager.page("one\n"));
+        assert_eq!(
+            vec!["two\n", "three\nfour\n"],
+            pager.page("two\nthree\nfour\n")
+        );
+
+        // The break is only needed once more text arrives.
+        assert_eq!(vec!["", "five"], pager.page("five"));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut pager = Pager::new(Some(2));
+        pager.page("one\ntwo\n");
+        pager.reset();
+        assert_eq!(vec!["three\n"], pager.page("three\n"));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut pager = Pager::new(None);
+        let text = "a\n".repeat(100);
+        assert_eq!(vec![text.clone()], pager.page(&text));
+    }
+}
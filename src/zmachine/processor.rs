@@ -1,19 +1,272 @@
+use log::{error, warn};
+
+use super::audit::ZAuditLog;
 use super::handle::Handle;
-use super::opcode::{one_op, two_op, var_op, zero_op};
-use super::opcode::{ZOperand, ZOperandType};
+use super::header::{self, FLAGS1_STATUS_TIME};
+use super::objects::{ObjectNumber, ZObjectTable};
+use super::opcode::{self, ext_op, one_op, two_op, var_op, zero_op};
+use super::opcode::{InputTimer, ZOperand, ZOperandType, ZVariable};
 use super::opcode::{
     EXTENDED_OPCODE_SENTINEL, OPCODE_TYPE_MASK, SHORT_OPCODE_TYPE_MASK, VAR_OPCODE_TYPE_MASK,
 };
+use super::options::ZOptions;
+use super::quetzal::{self, QuetzalSave};
+use super::random::{self, ZRandom};
 use super::result::{Result, ToTrue, ZErr};
-use super::traits::{Header, Memory, Stack, Variables, PC};
+use super::saves::SaveInfo;
+use super::snapshot::{self, ZSnapshot};
+use super::traits::{
+    Header, InputStreams, Memory, OutputStreams, SaveHandler, Screen, SoundPlayer, Stack,
+    Variables, PC,
+};
+use super::undo::ZUndoRing;
 use super::version::ZVersion;
+use super::violations::{ErrorPolicy, ZViolations};
+use super::zscii::ZTextDecoder;
+
+// The form of an instruction, which determines how its operands are encoded,
+// and which numbering its opcode uses. (ZSpec 4.3)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpcodeForm {
+    ZeroOp,
+    OneOp,
+    TwoOp,
+    Var,
+    Ext,
+}
+
+impl OpcodeForm {
+    pub fn name(self) -> &'static str {
+        use self::OpcodeForm::*;
+        match self {
+            ZeroOp => "0OP",
+            OneOp => "1OP",
+            TwoOp => "2OP",
+            Var => "VAR",
+            Ext => "EXT",
+        }
+    }
+}
+
+// The opcode bytes of call_vs2 and call_vn2, which have a second byte of
+// operand types.
+const CALL_VS2_OPCODE: u8 = 0xec;
+const CALL_VN2_OPCODE: u8 = 0xfa;
+
+// Flags describing what follows an instruction's operands. (ZSpec 4.6-4.8)
+pub const STORES: u8 = 0b001;
+pub const BRANCHES: u8 = 0b010;
+pub const PRINTS_TEXT: u8 = 0b100;
+
+// One entry in the opcode map. Some opcode numbers mean different things in
+// different versions, so each entry is only legal for a range of versions.
+#[derive(Debug)]
+pub struct OpcodeInfo {
+    pub form: OpcodeForm,
+    pub number: u8,
+    pub name: &'static str,
+    pub first_version: u8,
+    pub last_version: u8,
+    flags: u8,
+}
+
+impl OpcodeInfo {
+    const fn new(
+        form: OpcodeForm,
+        number: u8,
+        name: &'static str,
+        first_version: u8,
+        last_version: u8,
+        flags: u8,
+    ) -> OpcodeInfo {
+        OpcodeInfo {
+            form,
+            number,
+            name,
+            first_version,
+            last_version,
+            flags,
+        }
+    }
+
+    pub fn stores(&self) -> bool {
+        self.flags & STORES != 0
+    }
+
+    pub fn branches(&self) -> bool {
+        self.flags & BRANCHES != 0
+    }
+
+    pub fn prints_text(&self) -> bool {
+        self.flags & PRINTS_TEXT != 0
+    }
+
+    fn is_legal_in(&self, version: ZVersion) -> bool {
+        let version = version as u8;
+        self.first_version <= version && version <= self.last_version
+    }
+}
+
+// Find the opcode with this number in the given version, if it is legal.
+pub fn opcode_info(form: OpcodeForm, number: u8, version: ZVersion) -> Option<&'static OpcodeInfo> {
+    OPCODES
+        .iter()
+        .find(|info| info.form == form && info.number == number && info.is_legal_in(version))
+}
+
+// The complete opcode map. (ZSpec 14)
+//
+// V7 and V8 have the V5 opcodes, without the V6 additions.
+const OPCODES: &[OpcodeInfo] = {
+    use self::OpcodeForm::*;
+    const NONE: u8 = 0;
+    &[
+        OpcodeInfo::new(ZeroOp, 0, "rtrue", 1, 8, NONE),
+        OpcodeInfo::new(ZeroOp, 1, "rfalse", 1, 8, NONE),
+        OpcodeInfo::new(ZeroOp, 2, "print", 1, 8, PRINTS_TEXT),
+        OpcodeInfo::new(ZeroOp, 3, "print_ret", 1, 8, PRINTS_TEXT),
+        OpcodeInfo::new(ZeroOp, 4, "nop", 1, 8, NONE),
+        OpcodeInfo::new(ZeroOp, 5, "save", 1, 3, BRANCHES),
+        OpcodeInfo::new(ZeroOp, 5, "save", 4, 4, STORES),
+        OpcodeInfo::new(ZeroOp, 6, "restore", 1, 3, BRANCHES),
+        OpcodeInfo::new(ZeroOp, 6, "restore", 4, 4, STORES),
+        OpcodeInfo::new(ZeroOp, 7, "restart", 1, 8, NONE),
+        OpcodeInfo::new(ZeroOp, 8, "ret_popped", 1, 8, NONE),
+        OpcodeInfo::new(ZeroOp, 9, "pop", 1, 4, NONE),
+        OpcodeInfo::new(ZeroOp, 9, "catch", 5, 8, STORES),
+        OpcodeInfo::new(ZeroOp, 10, "quit", 1, 8, NONE),
+        OpcodeInfo::new(ZeroOp, 11, "new_line", 1, 8, NONE),
+        OpcodeInfo::new(ZeroOp, 12, "show_status", 3, 3, NONE),
+        OpcodeInfo::new(ZeroOp, 13, "verify", 3, 8, BRANCHES),
+        OpcodeInfo::new(ZeroOp, 15, "piracy", 5, 8, BRANCHES),
+        //
+        OpcodeInfo::new(OneOp, 0, "jz", 1, 8, BRANCHES),
+        OpcodeInfo::new(OneOp, 1, "get_sibling", 1, 8, STORES | BRANCHES),
+        OpcodeInfo::new(OneOp, 2, "get_child", 1, 8, STORES | BRANCHES),
+        OpcodeInfo::new(OneOp, 3, "get_parent", 1, 8, STORES),
+        OpcodeInfo::new(OneOp, 4, "get_prop_len", 1, 8, STORES),
+        OpcodeInfo::new(OneOp, 5, "inc", 1, 8, NONE),
+        OpcodeInfo::new(OneOp, 6, "dec", 1, 8, NONE),
+        OpcodeInfo::new(OneOp, 7, "print_addr", 1, 8, NONE),
+        OpcodeInfo::new(OneOp, 8, "call_1s", 4, 8, STORES),
+        OpcodeInfo::new(OneOp, 9, "remove_obj", 1, 8, NONE),
+        OpcodeInfo::new(OneOp, 10, "print_obj", 1, 8, NONE),
+        OpcodeInfo::new(OneOp, 11, "ret", 1, 8, NONE),
+        OpcodeInfo::new(OneOp, 12, "jump", 1, 8, NONE),
+        OpcodeInfo::new(OneOp, 13, "print_paddr", 1, 8, NONE),
+        OpcodeInfo::new(OneOp, 14, "load", 1, 8, STORES),
+        OpcodeInfo::new(OneOp, 15, "not", 1, 4, STORES),
+        OpcodeInfo::new(OneOp, 15, "call_1n", 5, 8, NONE),
+        //
+        OpcodeInfo::new(TwoOp, 1, "je", 1, 8, BRANCHES),
+        OpcodeInfo::new(TwoOp, 2, "jl", 1, 8, BRANCHES),
+        OpcodeInfo::new(TwoOp, 3, "jg", 1, 8, BRANCHES),
+        OpcodeInfo::new(TwoOp, 4, "dec_chk", 1, 8, BRANCHES),
+        OpcodeInfo::new(TwoOp, 5, "inc_chk", 1, 8, BRANCHES),
+        OpcodeInfo::new(TwoOp, 6, "jin", 1, 8, BRANCHES),
+        OpcodeInfo::new(TwoOp, 7, "test", 1, 8, BRANCHES),
+        OpcodeInfo::new(TwoOp, 8, "or", 1, 8, STORES),
+        OpcodeInfo::new(TwoOp, 9, "and", 1, 8, STORES),
+        OpcodeInfo::new(TwoOp, 10, "test_attr", 1, 8, BRANCHES),
+        OpcodeInfo::new(TwoOp, 11, "set_attr", 1, 8, NONE),
+        OpcodeInfo::new(TwoOp, 12, "clear_attr", 1, 8, NONE),
+        OpcodeInfo::new(TwoOp, 13, "store", 1, 8, NONE),
+        OpcodeInfo::new(TwoOp, 14, "insert_obj", 1, 8, NONE),
+        OpcodeInfo::new(TwoOp, 15, "loadw", 1, 8, STORES),
+        OpcodeInfo::new(TwoOp, 16, "loadb", 1, 8, STORES),
+        OpcodeInfo::new(TwoOp, 17, "get_prop", 1, 8, STORES),
+        OpcodeInfo::new(TwoOp, 18, "get_prop_addr", 1, 8, STORES),
+        OpcodeInfo::new(TwoOp, 19, "get_next_prop", 1, 8, STORES),
+        OpcodeInfo::new(TwoOp, 20, "add", 1, 8, STORES),
+        OpcodeInfo::new(TwoOp, 21, "sub", 1, 8, STORES),
+        OpcodeInfo::new(TwoOp, 22, "mul", 1, 8, STORES),
+        OpcodeInfo::new(TwoOp, 23, "div", 1, 8, STORES),
+        OpcodeInfo::new(TwoOp, 24, "mod", 1, 8, STORES),
+        OpcodeInfo::new(TwoOp, 25, "call_2s", 4, 8, STORES),
+        OpcodeInfo::new(TwoOp, 26, "call_2n", 5, 8, NONE),
+        OpcodeInfo::new(TwoOp, 27, "set_colour", 5, 8, NONE),
+        OpcodeInfo::new(TwoOp, 28, "throw", 5, 8, NONE),
+        //
+        OpcodeInfo::new(Var, 0, "call", 1, 3, STORES),
+        OpcodeInfo::new(Var, 0, "call_vs", 4, 8, STORES),
+        OpcodeInfo::new(Var, 1, "storew", 1, 8, NONE),
+        OpcodeInfo::new(Var, 2, "storeb", 1, 8, NONE),
+        OpcodeInfo::new(Var, 3, "put_prop", 1, 8, NONE),
+        OpcodeInfo::new(Var, 4, "sread", 1, 4, NONE),
+        OpcodeInfo::new(Var, 4, "aread", 5, 8, STORES),
+        OpcodeInfo::new(Var, 5, "print_char", 1, 8, NONE),
+        OpcodeInfo::new(Var, 6, "print_num", 1, 8, NONE),
+        OpcodeInfo::new(Var, 7, "random", 1, 8, STORES),
+        OpcodeInfo::new(Var, 8, "push", 1, 8, NONE),
+        OpcodeInfo::new(Var, 9, "pull", 1, 5, NONE),
+        OpcodeInfo::new(Var, 9, "pull", 6, 6, STORES),
+        OpcodeInfo::new(Var, 9, "pull", 7, 8, NONE),
+        OpcodeInfo::new(Var, 10, "split_window", 3, 8, NONE),
+        OpcodeInfo::new(Var, 11, "set_window", 3, 8, NONE),
+        OpcodeInfo::new(Var, 12, "call_vs2", 4, 8, STORES),
+        OpcodeInfo::new(Var, 13, "erase_window", 4, 8, NONE),
+        OpcodeInfo::new(Var, 14, "erase_line", 4, 8, NONE),
+        OpcodeInfo::new(Var, 15, "set_cursor", 4, 8, NONE),
+        OpcodeInfo::new(Var, 16, "get_cursor", 4, 8, NONE),
+        OpcodeInfo::new(Var, 17, "set_text_style", 4, 8, NONE),
+        OpcodeInfo::new(Var, 18, "buffer_mode", 4, 8, NONE),
+        OpcodeInfo::new(Var, 19, "output_stream", 3, 8, NONE),
+        OpcodeInfo::new(Var, 20, "input_stream", 3, 8, NONE),
+        OpcodeInfo::new(Var, 21, "sound_effect", 3, 8, NONE),
+        OpcodeInfo::new(Var, 22, "read_char", 4, 8, STORES),
+        OpcodeInfo::new(Var, 23, "scan_table", 4, 8, STORES | BRANCHES),
+        OpcodeInfo::new(Var, 24, "not", 5, 8, STORES),
+        OpcodeInfo::new(Var, 25, "call_vn", 5, 8, NONE),
+        OpcodeInfo::new(Var, 26, "call_vn2", 5, 8, NONE),
+        OpcodeInfo::new(Var, 27, "tokenise", 5, 8, NONE),
+        OpcodeInfo::new(Var, 28, "encode_text", 5, 8, NONE),
+        OpcodeInfo::new(Var, 29, "copy_table", 5, 8, NONE),
+        OpcodeInfo::new(Var, 30, "print_table", 5, 8, NONE),
+        OpcodeInfo::new(Var, 31, "check_arg_count", 5, 8, BRANCHES),
+        //
+        OpcodeInfo::new(Ext, 0, "save", 5, 8, STORES),
+        OpcodeInfo::new(Ext, 1, "restore", 5, 8, STORES),
+        OpcodeInfo::new(Ext, 2, "log_shift", 5, 8, STORES),
+        OpcodeInfo::new(Ext, 3, "art_shift", 5, 8, STORES),
+        OpcodeInfo::new(Ext, 4, "set_font", 5, 8, STORES),
+        OpcodeInfo::new(Ext, 5, "draw_picture", 6, 6, NONE),
+        OpcodeInfo::new(Ext, 6, "picture_data", 6, 6, BRANCHES),
+        OpcodeInfo::new(Ext, 7, "erase_picture", 6, 6, NONE),
+        OpcodeInfo::new(Ext, 8, "set_margins", 6, 6, NONE),
+        OpcodeInfo::new(Ext, 9, "save_undo", 5, 8, STORES),
+        OpcodeInfo::new(Ext, 10, "restore_undo", 5, 8, STORES),
+        OpcodeInfo::new(Ext, 11, "print_unicode", 5, 8, NONE),
+        OpcodeInfo::new(Ext, 12, "check_unicode", 5, 8, STORES),
+        OpcodeInfo::new(Ext, 13, "set_true_colour", 5, 8, NONE),
+        OpcodeInfo::new(Ext, 16, "move_window", 6, 6, NONE),
+        OpcodeInfo::new(Ext, 17, "window_size", 6, 6, NONE),
+        OpcodeInfo::new(Ext, 18, "window_style", 6, 6, NONE),
+        OpcodeInfo::new(Ext, 19, "get_wind_prop", 6, 6, STORES),
+        OpcodeInfo::new(Ext, 20, "scroll_window", 6, 6, NONE),
+        OpcodeInfo::new(Ext, 21, "pop_stack", 6, 6, NONE),
+        OpcodeInfo::new(Ext, 22, "read_mouse", 6, 6, NONE),
+        OpcodeInfo::new(Ext, 23, "mouse_window", 6, 6, NONE),
+        OpcodeInfo::new(Ext, 24, "push_stack", 6, 6, BRANCHES),
+        OpcodeInfo::new(Ext, 25, "put_wind_prop", 6, 6, NONE),
+        OpcodeInfo::new(Ext, 26, "print_form", 6, 6, NONE),
+        OpcodeInfo::new(Ext, 27, "make_menu", 6, 6, BRANCHES),
+        OpcodeInfo::new(Ext, 28, "picture_table", 6, 6, NONE),
+        OpcodeInfo::new(Ext, 29, "buffer_screen", 6, 6, STORES),
+    ]
+};
 
-pub struct ZProcessor<H, M, P, S, V>
+// The parts of the machine that a ZProcessor runs. They are built together,
+// as they share memory, the stack and the screen, and then handed over.
+pub struct ZComponents<A, H, I, M, O, P, S, Scr, V>
 where
+    A: SoundPlayer,
     H: Header,
+    I: InputStreams,
     M: Memory,
+    O: OutputStreams,
     P: PC,
-    S: Stack,
+    S: Stack + Clone,
+    Scr: Screen,
     V: Variables,
 {
     pub memory: Handle<M>,
@@ -21,41 +274,315 @@ where
     pub pc: P,
     pub stack: Handle<S>,
     pub variables: V,
+    pub objects: ZObjectTable<M>,
+    pub text: Handle<ZTextDecoder<M>>,
+    pub streams: O,
+    pub input: I,
+    pub sound: A,
+    pub screen: Handle<Scr>,
+}
+
+pub struct ZProcessor<A, H, I, M, O, P, S, Scr, V>
+where
+    A: SoundPlayer,
+    H: Header,
+    I: InputStreams,
+    M: Memory,
+    O: OutputStreams,
+    P: PC,
+    S: Stack + Clone,
+    Scr: Screen,
+    V: Variables,
+{
+    pub memory: Handle<M>,
+    pub header: H,
+    pub pc: P,
+    pub stack: Handle<S>,
+    pub variables: V,
+    pub objects: ZObjectTable<M>,
+    pub text: Handle<ZTextDecoder<M>>,
+    pub streams: O,
+    pub input: I,
+    pub sound: A,
+    pub screen: Handle<Scr>,
+
+    pub options: ZOptions,
+    pub random: ZRandom,
+    // Whether the story's checksum matches its header, for verify.
+    pub verified: bool,
+    // The log of memory accesses, if ZOptions::memory_audit asked for one.
+    pub audit: Option<Handle<ZAuditLog>>,
+    // How rule breaking by the game is handled, from ZOptions::strict.
+    pub violations: ZViolations,
+    // Where the save opcodes keep saved games. Without one, saves fail, as
+    // if the player cancelled them.
+    pub save_handler: Option<Box<dyn SaveHandler>>,
+
+    // The address of the instruction being executed, for error reporting.
+    instruction_pc: usize,
+
+    // The states saved by save_undo.
+    undo: ZUndoRing<S>,
+
+    // The font chosen with set_font.
+    font: u16,
+
+    // Where execution began, for restart.
+    start_pc: usize,
+    start_stack: S,
 }
 
-impl<H, M, P, S, V> ZProcessor<H, M, P, S, V>
+impl<A, H, I, M, O, P, S, Scr, V> ZProcessor<A, H, I, M, O, P, S, Scr, V>
 where
+    A: SoundPlayer,
     H: Header,
+    I: InputStreams,
     M: Memory,
+    O: OutputStreams,
     P: PC,
-    S: Stack,
+    S: Stack + Clone,
+    Scr: Screen,
     V: Variables,
 {
     pub fn new(
-        memory: Handle<M>,
-        header: H,
-        pc: P,
-        stack: Handle<S>,
-        variables: V,
-    ) -> ZProcessor<H, M, P, S, V> {
+        components: ZComponents<A, H, I, M, O, P, S, Scr, V>,
+    ) -> ZProcessor<A, H, I, M, O, P, S, Scr, V> {
+        let ZComponents {
+            memory,
+            header,
+            pc,
+            stack,
+            variables,
+            objects,
+            text,
+            streams,
+            input,
+            sound,
+            screen,
+        } = components;
+        let start_pc = pc.current_pc();
+        let start_stack = stack.borrow().clone();
         ZProcessor {
             memory,
             header,
             pc,
             stack,
             variables,
+            objects,
+            text,
+            streams,
+            input,
+            sound,
+            screen,
+
+            options: ZOptions::new(),
+            random: ZRandom::new(random::seed_from_time()),
+            verified: true,
+            audit: None,
+            violations: ZViolations::new(ErrorPolicy::default()),
+            save_handler: None,
+            instruction_pc: 0,
+            undo: ZUndoRing::new(),
+            font: 1,
+            start_pc,
+            start_stack,
+        }
+    }
+
+    // Advertise the interpreter's capabilities in the header. Call this at
+    // startup; restoring does so itself.
+    pub fn initialize_header(&mut self) -> Result<()> {
+        let screen = self.screen.borrow();
+        header::write_interpreter_fields(
+            &self.memory,
+            self.header.version_number(),
+            screen.supports_colour(),
+            self.input.supports_timed_input(),
+            screen.size(),
+            &self.options,
+        )
+    }
+
+    // Capture the machine's state. Restoring it continues from the next
+    // instruction.
+    pub fn snapshot(&self) -> ZSnapshot<S> {
+        ZSnapshot::capture(
+            &self.memory,
+            self.header.static_memory_base(),
+            &self.stack,
+            &self.pc,
+        )
+    }
+
+    // Return the machine to the state in a snapshot. As with restore_undo,
+    // the header fields that the interpreter owns are kept.
+    pub fn restore_snapshot(&mut self, snapshot: &ZSnapshot<S>) -> Result<()> {
+        let flags2 = header::read_flags2(&self.memory);
+        snapshot.restore(&self.memory, &self.stack, &mut self.pc)?;
+        self.finish_restore(flags2)
+    }
+
+    // Put back the Flags 2 bits that a restore must keep, and the header
+    // fields that the interpreter owns.
+    fn finish_restore(&mut self, flags2: u16) -> Result<()> {
+        header::restore_flags2(&self.memory, flags2)?;
+        self.text.borrow_mut().reset();
+        // A damaged tree will cause trouble later, so say so now.
+        for problem in self.objects.validate() {
+            warn!("after restore: {}", problem);
+        }
+        self.initialize_header()
+    }
+
+    // Write a Quetzal save with the save handler. The pc is at the save
+    // instruction's branch or store byte, where restoring will resume.
+    // Returns false if there is no handler or it fails, which the game sees
+    // as a failed save. (ZSpec 15 save)
+    fn save_game(&mut self) -> Result<bool> {
+        if self.save_handler.is_none() {
+            return Ok(false);
+        }
+        let info = self.save_info()?;
+        let current = snapshot::read_dynamic_memory(&self.memory, self.header.static_memory_base());
+        let data = QuetzalSave::new(
+            self.memory.borrow().original_dynamic(),
+            &current,
+            self.stack.borrow().quetzal_frames(),
+            self.pc.current_pc(),
+            self.options.memory_format,
+        )
+        .to_bytes();
+
+        match self.save_handler {
+            Some(ref mut handler) => match handler.save(&info, &data) {
+                Ok(()) => Ok(true),
+                Err(err) => {
+                    warn!("save failed: {}", err);
+                    Ok(false)
+                }
+            },
+            None => Ok(false),
+        }
+    }
+
+    // What the status line shows, to describe a save. Later versions have no
+    // status line, so they only get the time of the save.
+    fn save_info(&mut self) -> Result<SaveInfo> {
+        if self.header.version_number() > ZVersion::V3 {
+            return Ok(SaveInfo::new(0, 0, ""));
+        }
+        let room = match self.variables.read_variable(ZVariable::Global(0))? {
+            0 => String::new(),
+            num => self.objects.short_name(ObjectNumber::from(num))?,
+        };
+        if self.header.flags1() & FLAGS1_STATUS_TIME != 0 {
+            return Ok(SaveInfo::new(0, 0, &room));
+        }
+        let score = self.variables.read_variable(ZVariable::Global(1))?;
+        let turn = self.variables.read_variable(ZVariable::Global(2))?;
+        Ok(SaveInfo::new(turn, score as i16, &room))
+    }
+
+    // Read a save with the save handler and, if it belongs to this story,
+    // return the machine to the save instruction's branch or store byte.
+    // Returns false if there is no save, or it can't be used, which the game
+    // sees as a failed restore. (ZSpec 15 restore)
+    fn restore_game(&mut self) -> Result<bool> {
+        let data = match self.save_handler {
+            Some(ref mut handler) => handler.restore(),
+            None => return Ok(false),
+        };
+        let result = match data {
+            Ok(Some(data)) => self.load_save(&data),
+            Ok(None) => return Ok(false),
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(()) => Ok(true),
+            Err(err) => {
+                warn!("restore failed: {}", err);
+                Ok(false)
+            }
         }
     }
 
+    // Nothing is changed unless the save was made by this story and its
+    // stack frames can be read, so a bad save leaves the game running.
+    fn load_save(&mut self, data: &[u8]) -> Result<()> {
+        let save = quetzal::read_quetzal(data)?;
+        let dynamic = {
+            let memory = self.memory.borrow();
+            save.validate(memory.original_dynamic(), memory.memory_size())?
+        };
+        self.stack
+            .borrow_mut()
+            .restore_quetzal_frames(&save.stacks.data)?;
+
+        let flags2 = header::read_flags2(&self.memory);
+        snapshot::write_dynamic_memory(&self.memory, &dynamic)?;
+        self.pc.set_current_pc(save.header.pc as usize);
+        self.finish_restore(flags2)
+    }
+
+    // Step back to the state saved by the game's last save_undo, as the
+    // restore_undo opcode would, for a frontend's undo command. Returns
+    // false if there is nothing left to undo.
+    pub fn undo(&mut self) -> Result<bool> {
+        let state = match self.undo.pop() {
+            Some(state) => state,
+            None => return Ok(false),
+        };
+        let flags2 = header::read_flags2(&self.memory);
+        ext_op::resume_undo(
+            &self.memory,
+            &mut self.pc,
+            &self.stack,
+            &mut self.variables,
+            &state,
+        )?;
+        header::restore_flags2(&self.memory, flags2)?;
+        self.text.borrow_mut().reset();
+        self.initialize_header().map(|_| true)
+    }
+
+    // Begin the game again. Only the transcripting and fixed pitch bits of
+    // Flags 2 survive. (ZSpec 6.1.3)
+    pub fn restart(&mut self) -> Result<()> {
+        let flags2 = header::read_flags2(&self.memory);
+        self.memory.borrow_mut().reset_dynamic();
+        header::restore_flags2(&self.memory, flags2)?;
+        *self.stack.borrow_mut() = self.start_stack.clone();
+        self.pc.set_current_pc(self.start_pc);
+        self.undo = ZUndoRing::new();
+        self.font = 1;
+        self.text.borrow_mut().reset();
+        self.initialize_header()
+    }
+
     pub fn run(&mut self) -> Result<()> {
-        while self.execute_opcode()? {}
-        Ok(())
+        loop {
+            match self.execute_opcode() {
+                Ok(true) => (),
+                Ok(false) => break,
+                Err(err) => {
+                    self.log_audit();
+                    return Err(err);
+                }
+            }
+        }
+        self.screen.borrow_mut().flush()
     }
 
     // Result indicates whether or not we should continue.
     pub fn execute_opcode(&mut self) -> Result<bool> {
+        self.instruction_pc = self.pc.current_pc();
+        if let Some(ref audit) = self.audit {
+            audit.borrow_mut().set_pc(self.instruction_pc);
+        }
         let byte = self.pc.next_byte();
-        if byte == EXTENDED_OPCODE_SENTINEL && self.header.version_number() >= ZVersion::V5 {
+        if byte == EXTENDED_OPCODE_SENTINEL
+            && self.header.version_number().supports_extended_opcodes()
+        {
             self.execute_extended_opcode(byte)
         } else {
             // The top two bits indicate the opcode type.
@@ -67,8 +594,183 @@ where
         }
     }
 
-    fn execute_extended_opcode(&mut self, byte: u8) -> Result<bool> {
-        self.unimplemented("extended", byte)
+    fn execute_extended_opcode(&mut self, _byte: u8) -> Result<bool> {
+        // For extended opcodes, the opcode is in the second byte, and the
+        // operand types follow, as for var opcodes.
+        let opcode = self.pc.next_byte();
+        let operands = self.read_var_operands();
+
+        let info = self.opcode_info(OpcodeForm::Ext, opcode)?;
+        match opcode {
+            // Saving or restoring only a table isn't supported, so those
+            // forms fail.
+            0x00 => {
+                let saved = matches!(operands[0], ZOperand::Omitted) && self.save_game()?;
+                ext_op::o_0_save(&mut self.pc, &mut self.variables, operands, saved).to_true()
+            }
+            0x01 => {
+                let restored = matches!(operands[0], ZOperand::Omitted) && self.restore_game()?;
+                ext_op::o_1_restore(&mut self.pc, &mut self.variables, operands, restored).to_true()
+            }
+            0x02 => ext_op::o_2_log_shift(&mut self.pc, &mut self.variables, operands).to_true(),
+            0x03 => ext_op::o_3_art_shift(&mut self.pc, &mut self.variables, operands).to_true(),
+            0x04 => {
+                ext_op::o_4_set_font(&mut self.pc, &mut self.variables, &mut self.font, operands)
+                    .to_true()
+            }
+            0x09 => ext_op::o_9_save_undo(
+                &self.memory,
+                &mut self.pc,
+                &self.stack,
+                &mut self.variables,
+                &self.header,
+                &mut self.undo,
+                self.options.undo_levels,
+            )
+            .to_true(),
+            0x0a => {
+                let flags2 = header::read_flags2(&self.memory);
+                ext_op::o_10_restore_undo(
+                    &self.memory,
+                    &mut self.pc,
+                    &self.stack,
+                    &mut self.variables,
+                    &mut self.undo,
+                )?;
+                header::restore_flags2(&self.memory, flags2)?;
+                self.text.borrow_mut().reset();
+                self.initialize_header().to_true()
+            }
+            0x0b => ext_op::o_11_print_unicode(&mut self.variables, &mut self.streams, operands)
+                .to_true(),
+            0x0c => ext_op::o_12_check_unicode(
+                &self.memory,
+                &mut self.pc,
+                &mut self.variables,
+                operands,
+            )
+            .to_true(),
+            0x0d => ext_op::o_13_set_true_colour(
+                &mut self.variables,
+                &self.screen,
+                &mut self.violations,
+                operands,
+            )
+            .to_true(),
+            _ => self.unimplemented(info),
+        }
+    }
+
+    fn log_audit(&self) {
+        if let Some(ref audit) = self.audit {
+            let audit = audit.borrow();
+            error!("Memory accesses before the error, oldest first:");
+            for access in audit.accesses() {
+                error!("  {}", access);
+            }
+        }
+    }
+
+    fn show_status(&mut self) -> Result<()> {
+        zero_op::o_188_show_status(
+            &self.objects,
+            &mut self.variables,
+            &self.header,
+            &self.screen,
+            self.options.twenty_four_hour_clock,
+        )
+    }
+
+    // If the player resized the screen while typing, tell the game before it
+    // draws anything more. (ZSpec 8.4)
+    fn check_resize(&mut self) -> Result<()> {
+        let resized = self.screen.borrow_mut().check_resize()?;
+        if resized {
+            let size = self.screen.borrow().size();
+            header::write_screen_size(&self.memory, self.header.version_number(), size)?;
+        }
+        Ok(())
+    }
+
+    // Call a routine on the interpreter's behalf, as for timed input, and run
+    // it until it returns. Returns its result. (ZSpec 10.4.2)
+    fn call_interrupt(&mut self, routine: u16) -> Result<u16> {
+        let instruction_pc = self.instruction_pc;
+        let depth = self.stack.borrow().frame_depth();
+        opcode::call_routine(
+            &mut self.pc,
+            &self.stack,
+            &mut self.variables,
+            &self.header,
+            routine,
+            &[],
+            Some(ZVariable::Stack),
+        )?;
+        while self.stack.borrow().frame_depth() > depth {
+            if !self.execute_opcode()? {
+                return Err(ZErr::GenericError("Quit in an interrupt routine"));
+            }
+        }
+        self.instruction_pc = instruction_pc;
+        self.stack.borrow_mut().pop_word()
+    }
+
+    // Read a line, calling the timer routine each time it runs out. If the
+    // routine returns true, input stops with a terminator of 0. (ZSpec 10.4.2)
+    fn read_timed_line(
+        &mut self,
+        terminators: &[u8],
+        timer: Option<InputTimer>,
+    ) -> Result<(String, u8)> {
+        let timer = match timer {
+            Some(timer) if self.input.supports_timed_input() => timer,
+            _ => return self.input.read_line("", terminators, None),
+        };
+
+        let mut typed = String::new();
+        loop {
+            let (line, terminator) =
+                self.input
+                    .read_line(&typed, terminators, Some(timer.duration()))?;
+            if terminator != 0 {
+                return Ok((line, terminator));
+            }
+            typed = line;
+
+            let cursor = self.screen.borrow().get_cursor();
+            if self.call_interrupt(timer.routine)? != 0 {
+                return Ok((typed, 0));
+            }
+
+            // If the routine printed anything, the interpreter must show the
+            // text typed so far again. (ZSpec 10.4.2.1)
+            let mut screen = self.screen.borrow_mut();
+            screen.flush()?;
+            if screen.get_cursor() != cursor {
+                screen.print(&typed)?;
+                screen.flush()?;
+            }
+        }
+    }
+
+    // As read_timed_line, but for a single key. The key is 0 if the routine
+    // stopped the input.
+    fn read_timed_char(&mut self, timer: Option<InputTimer>) -> Result<u8> {
+        let timer = match timer {
+            Some(timer) if self.input.supports_timed_input() => timer,
+            _ => return self.input.read_char(None),
+        };
+
+        loop {
+            let key = self.input.read_char(Some(timer.duration()))?;
+            if key != 0 {
+                return Ok(key);
+            }
+            if self.call_interrupt(timer.routine)? != 0 {
+                return Ok(0);
+            }
+            self.screen.borrow_mut().flush()?;
+        }
     }
 
     fn execute_short_opcode(&mut self, byte: u8) -> Result<bool> {
@@ -79,6 +781,7 @@ where
         let operand = ZOperand::read_operand(&mut self.pc, optype.into());
 
         if let ZOperand::Omitted = operand {
+            let info = self.opcode_info(OpcodeForm::ZeroOp, opcode)?;
             match opcode {
                 0x00 => {
                     zero_op::o_176_rtrue(&mut self.pc, &self.stack, &mut self.variables).to_true()
@@ -86,28 +789,168 @@ where
                 0x01 => {
                     zero_op::o_177_rfalse(&mut self.pc, &self.stack, &mut self.variables).to_true()
                 }
-                0x02 => {
-                    zero_op::o_178_print(&self.memory, &mut self.pc, self.header.abbrev_location())
-                        .to_true()
+                0x02 => zero_op::o_178_print(&self.text, &mut self.pc, &mut self.streams).to_true(),
+                0x03 => zero_op::o_179_print_ret(
+                    &self.text,
+                    &mut self.pc,
+                    &self.stack,
+                    &mut self.variables,
+                    &mut self.streams,
+                )
+                .to_true(),
+                0x04 => zero_op::o_180_nop().to_true(),
+                0x05 => {
+                    let saved = self.save_game()?;
+                    zero_op::o_181_save(
+                        &mut self.pc,
+                        &self.stack,
+                        &mut self.variables,
+                        self.header.version_number(),
+                        saved,
+                    )
+                    .to_true()
                 }
-                0x0b => call_null(zero_op::o_187_new_line()),
-                _ => self.unimplemented("0op", opcode),
+                0x06 => {
+                    let restored = self.restore_game()?;
+                    zero_op::o_182_restore(
+                        &mut self.pc,
+                        &self.stack,
+                        &mut self.variables,
+                        self.header.version_number(),
+                        restored,
+                    )
+                    .to_true()
+                }
+                0x07 => self.restart().to_true(),
+                0x08 => zero_op::o_184_ret_popped(&mut self.pc, &self.stack, &mut self.variables)
+                    .to_true(),
+                0x09 if self.header.version_number() < ZVersion::V5 => {
+                    zero_op::o_185_pop(&self.stack).to_true()
+                }
+                0x09 => {
+                    zero_op::o_185_catch(&mut self.pc, &self.stack, &mut self.variables).to_true()
+                }
+                0x0a => {
+                    zero_op::o_186_quit();
+                    Ok(false)
+                }
+                0x0b => zero_op::o_187_new_line(&mut self.streams).to_true(),
+                0x0c => self.show_status().to_true(),
+                0x0d => zero_op::o_189_verify(
+                    &mut self.pc,
+                    &self.stack,
+                    &mut self.variables,
+                    self.verified,
+                )
+                .to_true(),
+                0x0f => zero_op::o_191_piracy(
+                    &mut self.pc,
+                    &self.stack,
+                    &mut self.variables,
+                    self.options.pirated,
+                )
+                .to_true(),
+                _ => self.unimplemented(info),
             }
         } else {
+            let info = self.opcode_info(OpcodeForm::OneOp, opcode)?;
             match opcode {
-                0x00 => one_op::o_128_jz(&mut self.pc, &mut self.variables, operand).to_true(),
+                0x00 => one_op::o_128_jz(&mut self.pc, &self.stack, &mut self.variables, operand)
+                    .to_true(),
+                0x01 => one_op::o_129_get_sibling(
+                    &self.objects,
+                    &mut self.pc,
+                    &self.stack,
+                    &mut self.variables,
+                    &mut self.violations,
+                    operand,
+                )
+                .to_true(),
+                0x02 => one_op::o_130_get_child(
+                    &self.objects,
+                    &mut self.pc,
+                    &self.stack,
+                    &mut self.variables,
+                    &mut self.violations,
+                    operand,
+                )
+                .to_true(),
+                0x03 => one_op::o_131_get_parent(
+                    &self.objects,
+                    &mut self.pc,
+                    &mut self.variables,
+                    &mut self.violations,
+                    operand,
+                )
+                .to_true(),
+                0x04 => one_op::o_132_get_prop_len(
+                    &self.objects,
+                    &mut self.pc,
+                    &mut self.variables,
+                    operand,
+                )
+                .to_true(),
+                0x05 => one_op::o_133_inc(&mut self.variables, operand).to_true(),
+                0x06 => one_op::o_134_dec(&mut self.variables, operand).to_true(),
+                0x07 => one_op::o_135_print_addr(
+                    &self.text,
+                    &mut self.variables,
+                    &mut self.streams,
+                    operand,
+                )
+                .to_true(),
+                0x08 => one_op::o_136_call_1s(
+                    &mut self.pc,
+                    &self.stack,
+                    &mut self.variables,
+                    &self.header,
+                    operand,
+                )
+                .to_true(),
+                0x09 => one_op::o_137_remove_obj(
+                    &self.objects,
+                    &mut self.variables,
+                    &mut self.violations,
+                    operand,
+                )
+                .to_true(),
+                0x0a => one_op::o_138_print_obj(
+                    &self.objects,
+                    &mut self.variables,
+                    &mut self.streams,
+                    &mut self.violations,
+                    operand,
+                )
+                .to_true(),
                 0x0b => one_op::o_139_ret(&mut self.pc, &self.stack, &mut self.variables, operand)
                     .to_true(),
                 0x0c => one_op::o_140_jump(&mut self.pc, &mut self.variables, operand).to_true(),
-                _ => self.unimplemented("1op", opcode),
+                0x0d => one_op::o_141_print_paddr(
+                    &self.text,
+                    &self.header,
+                    &mut self.variables,
+                    &mut self.streams,
+                    operand,
+                )
+                .to_true(),
+                0x0e => one_op::o_142_load(&mut self.pc, &mut self.variables, operand).to_true(),
+                0x0f if self.header.version_number() < ZVersion::V5 => {
+                    one_op::o_143_not(&mut self.pc, &mut self.variables, operand).to_true()
+                }
+                0x0f => one_op::o_143_call_1n(
+                    &mut self.pc,
+                    &self.stack,
+                    &mut self.variables,
+                    &self.header,
+                    operand,
+                )
+                .to_true(),
+                _ => self.unimplemented(info),
             }
         }
     }
 
-    fn execute_var_opcode(&mut self, byte: u8) -> Result<bool> {
-        // For var opcodes, the low 5 bits contain the opcode.
-        let opcode = byte & 0b11111;
-
+    fn read_var_operands(&mut self) -> [ZOperand; 4] {
         // The 4 opcode types are encoded in the next byte.
         let optypes = self.pc.next_byte();
 
@@ -120,28 +963,252 @@ where
                 o => operands[idx] = o,
             }
         }
+        operands
+    }
+
+    // call_vs2 and call_vn2 have two bytes of operand types, for up to eight
+    // operands. (ZSpec 4.4.3.1)
+    fn read_double_var_operands(&mut self) -> [ZOperand; 8] {
+        let optypes = self.pc.next_word();
+
+        let mut operands = <[ZOperand; 8]>::default();
+        for (idx, slot) in operands.iter_mut().enumerate() {
+            let optype = (optypes >> ((7 - idx) * 2)) as u8;
+            let operand = ZOperand::read_operand(&mut self.pc, optype.into());
+            match operand {
+                ZOperand::Omitted => break,
+                o => *slot = o,
+            }
+        }
+        operands
+    }
+
+    fn execute_var_opcode(&mut self, byte: u8) -> Result<bool> {
+        // For var opcodes, the low 5 bits contain the opcode.
+        let opcode = byte & 0b11111;
+        if byte == CALL_VS2_OPCODE || byte == CALL_VN2_OPCODE {
+            return self.execute_double_var_opcode(opcode);
+        }
+        let operands = self.read_var_operands();
 
         if byte & 0b0010_0000 == 0 {
-            self.match_long_opcode(opcode, [operands[0], operands[1]])
+            self.match_long_opcode(opcode, operands)
         } else {
+            let info = self.opcode_info(OpcodeForm::Var, opcode)?;
             match opcode {
                 0 => var_op::o_224_call(
                     &mut self.pc,
                     &self.stack,
                     &mut self.variables,
-                    self.header.version_number(),
+                    &self.header,
                     operands,
                 )
                 .to_true(),
                 1 => var_op::o_225_storew(&self.memory, &mut self.variables, operands).to_true(),
-                3 => call_null(var_op::o_227_put_prop(operands)),
-                5 => var_op::o_229_print_char(&mut self.variables, operands).to_true(),
-                6 => var_op::o_230_print_num(&mut self.variables, operands).to_true(),
-                _ => self.unimplemented("var", opcode),
+                2 => var_op::o_226_storeb(&self.memory, &mut self.variables, operands).to_true(),
+                3 => var_op::o_227_put_prop(
+                    &self.objects,
+                    &mut self.variables,
+                    &mut self.violations,
+                    operands,
+                )
+                .to_true(),
+                4 => {
+                    // In V1-3, the status line is redrawn before reading. (ZSpec 15 read)
+                    self.show_status()?;
+                    let request = var_op::o_228_read(
+                        &self.memory,
+                        &mut self.pc,
+                        &mut self.variables,
+                        &self.header,
+                        &self.screen,
+                        operands,
+                    )?;
+                    let (line, terminator) =
+                        self.read_timed_line(&request.terminators, request.timer)?;
+                    self.check_resize()?;
+                    var_op::echo_command(&self.input, &self.screen, &line)?;
+                    var_op::finish_read(
+                        &self.memory,
+                        &mut self.variables,
+                        &self.header,
+                        &mut self.streams,
+                        request,
+                        &line,
+                        terminator,
+                    )
+                    .to_true()
+                }
+                5 => var_op::o_229_print_char(
+                    &self.memory,
+                    &mut self.variables,
+                    &mut self.streams,
+                    &mut self.violations,
+                    self.header.version_number(),
+                    operands,
+                )
+                .to_true(),
+                6 => var_op::o_230_print_num(&mut self.variables, &mut self.streams, operands)
+                    .to_true(),
+                7 => var_op::o_231_random(
+                    &mut self.pc,
+                    &mut self.variables,
+                    &mut self.random,
+                    operands,
+                )
+                .to_true(),
+                8 => var_op::o_232_push(&mut self.variables, operands).to_true(),
+                9 => var_op::o_233_pull(
+                    &self.memory,
+                    &mut self.pc,
+                    &self.stack,
+                    &mut self.variables,
+                    self.header.version_number(),
+                    operands,
+                )
+                .to_true(),
+                10 => var_op::o_234_split_window(
+                    &mut self.variables,
+                    &self.screen,
+                    self.header.version_number(),
+                    operands,
+                )
+                .to_true(),
+                11 => {
+                    var_op::o_235_set_window(&mut self.variables, &self.screen, operands).to_true()
+                }
+                13 => var_op::o_237_erase_window(&mut self.variables, &self.screen, operands)
+                    .to_true(),
+                14 => {
+                    var_op::o_238_erase_line(&mut self.variables, &self.screen, operands).to_true()
+                }
+                15 => {
+                    var_op::o_239_set_cursor(&mut self.variables, &self.screen, operands).to_true()
+                }
+                16 => var_op::o_240_get_cursor(
+                    &self.memory,
+                    &mut self.variables,
+                    &self.screen,
+                    operands,
+                )
+                .to_true(),
+                17 => {
+                    var_op::o_241_set_text_style(&mut self.variables, &mut self.streams, operands)
+                        .to_true()
+                }
+                18 => {
+                    var_op::o_242_buffer_mode(&mut self.variables, &self.screen, operands).to_true()
+                }
+                19 => var_op::o_243_output_stream(&mut self.variables, &mut self.streams, operands)
+                    .to_true(),
+                20 => var_op::o_244_input_stream(&mut self.variables, &mut self.input, operands)
+                    .to_true(),
+                21 => var_op::o_245_sound_effect(
+                    &mut self.variables,
+                    &mut self.sound,
+                    &self.header,
+                    &mut self.violations,
+                    operands,
+                )
+                .to_true(),
+                22 => {
+                    let request = var_op::o_246_read_char(
+                        &mut self.pc,
+                        &mut self.variables,
+                        &self.screen,
+                        operands,
+                    )?;
+                    let key = self.read_timed_char(request.timer)?;
+                    self.check_resize()?;
+                    var_op::finish_read_char(
+                        &self.memory,
+                        &mut self.variables,
+                        &mut self.streams,
+                        request,
+                        key,
+                    )
+                    .to_true()
+                }
+                23 => var_op::o_247_scan_table(
+                    &self.memory,
+                    &mut self.pc,
+                    &self.stack,
+                    &mut self.variables,
+                    operands,
+                )
+                .to_true(),
+                24 => var_op::o_248_not(&mut self.pc, &mut self.variables, operands).to_true(),
+                25 => var_op::o_249_call_vn(
+                    &mut self.pc,
+                    &self.stack,
+                    &mut self.variables,
+                    &self.header,
+                    operands,
+                )
+                .to_true(),
+                27 => var_op::o_251_tokenise(
+                    &self.memory,
+                    &mut self.variables,
+                    &self.header,
+                    operands,
+                )
+                .to_true(),
+                28 => var_op::o_252_encode_text(
+                    &self.memory,
+                    &mut self.variables,
+                    self.header.version_number(),
+                    operands,
+                )
+                .to_true(),
+                29 => {
+                    var_op::o_253_copy_table(&self.memory, &mut self.variables, operands).to_true()
+                }
+                30 => var_op::o_254_print_table(
+                    &self.memory,
+                    &mut self.variables,
+                    &mut self.streams,
+                    &self.screen,
+                    &mut self.violations,
+                    self.header.version_number(),
+                    operands,
+                )
+                .to_true(),
+                31 => var_op::o_255_check_arg_count(
+                    &mut self.pc,
+                    &self.stack,
+                    &mut self.variables,
+                    operands,
+                )
+                .to_true(),
+                _ => self.unimplemented(info),
             }
         }
     }
 
+    fn execute_double_var_opcode(&mut self, opcode: u8) -> Result<bool> {
+        let operands = self.read_double_var_operands();
+        let info = self.opcode_info(OpcodeForm::Var, opcode)?;
+        match opcode {
+            12 => var_op::o_236_call_vs2(
+                &mut self.pc,
+                &self.stack,
+                &mut self.variables,
+                &self.header,
+                operands,
+            )
+            .to_true(),
+            26 => var_op::o_250_call_vn2(
+                &mut self.pc,
+                &self.stack,
+                &mut self.variables,
+                &self.header,
+                operands,
+            )
+            .to_true(),
+            _ => self.unimplemented(info),
+        }
+    }
+
     fn execute_long_opcode(&mut self, byte: u8) -> Result<bool> {
         let opcode = byte & 0b11111;
         let mut operands = <[ZOperand; 2]>::default();
@@ -162,16 +1229,80 @@ where
             ZOperand::read_operand(&mut self.pc, ZOperandType::VariableType)
         };
 
-        self.match_long_opcode(opcode, operands)
+        self.match_long_opcode(
+            opcode,
+            [
+                operands[0],
+                operands[1],
+                ZOperand::Omitted,
+                ZOperand::Omitted,
+            ],
+        )
     }
 
-    fn match_long_opcode(&mut self, opcode: u8, operands: [ZOperand; 2]) -> Result<bool> {
+    // 2OP opcodes, in either the long or the variable form. Only je uses more
+    // than two operands. (ZSpec 4.4.3)
+    fn match_long_opcode(&mut self, opcode: u8, all_operands: [ZOperand; 4]) -> Result<bool> {
+        let info = self.opcode_info(OpcodeForm::TwoOp, opcode)?;
+        let operands = [all_operands[0], all_operands[1]];
         match opcode {
-            0x01 => two_op::o_1_je(&mut self.pc, &mut self.variables, operands).to_true(),
-            0x05 => two_op::o_5_inc_chk(&mut self.pc, &mut self.variables, operands).to_true(),
+            0x01 => two_op::o_1_je(&mut self.pc, &self.stack, &mut self.variables, all_operands)
+                .to_true(),
+            0x02 => {
+                two_op::o_2_jl(&mut self.pc, &self.stack, &mut self.variables, operands).to_true()
+            }
+            0x03 => {
+                two_op::o_3_jg(&mut self.pc, &self.stack, &mut self.variables, operands).to_true()
+            }
+            0x04 => two_op::o_4_dec_chk(&mut self.pc, &self.stack, &mut self.variables, operands)
+                .to_true(),
+            0x05 => two_op::o_5_inc_chk(&mut self.pc, &self.stack, &mut self.variables, operands)
+                .to_true(),
+            0x06 => two_op::o_6_jin(
+                &self.objects,
+                &mut self.pc,
+                &self.stack,
+                &mut self.variables,
+                &mut self.violations,
+                operands,
+            )
+            .to_true(),
+            0x07 => {
+                two_op::o_7_test(&mut self.pc, &self.stack, &mut self.variables, operands).to_true()
+            }
+            0x08 => two_op::o_8_or(&mut self.pc, &mut self.variables, operands).to_true(),
             0x09 => two_op::o_9_and(&mut self.pc, &mut self.variables, operands).to_true(),
-            0x0a => call_null(two_op::o_10_test_attr(&mut self.pc, operands)),
+            0x0a => two_op::o_10_test_attr(
+                &self.objects,
+                &mut self.pc,
+                &self.stack,
+                &mut self.variables,
+                &mut self.violations,
+                operands,
+            )
+            .to_true(),
+            0x0b => two_op::o_11_set_attr(
+                &self.objects,
+                &mut self.variables,
+                &mut self.violations,
+                operands,
+            )
+            .to_true(),
+            0x0c => two_op::o_12_clear_attr(
+                &self.objects,
+                &mut self.variables,
+                &mut self.violations,
+                operands,
+            )
+            .to_true(),
             0x0d => two_op::o_13_store(&mut self.variables, operands).to_true(),
+            0x0e => two_op::o_14_insert_obj(
+                &self.objects,
+                &mut self.variables,
+                &mut self.violations,
+                operands,
+            )
+            .to_true(),
             0x0f => two_op::o_15_loadw(
                 &mut self.memory,
                 &mut self.pc,
@@ -186,22 +1317,127 @@ where
                 operands,
             )
             .to_true(),
+            0x11 => two_op::o_17_get_prop(
+                &self.objects,
+                &mut self.pc,
+                &mut self.variables,
+                &mut self.violations,
+                operands,
+            )
+            .to_true(),
+            0x12 => two_op::o_18_get_prop_addr(
+                &self.objects,
+                &mut self.pc,
+                &mut self.variables,
+                &mut self.violations,
+                operands,
+            )
+            .to_true(),
+            0x13 => two_op::o_19_get_next_prop(
+                &self.objects,
+                &mut self.pc,
+                &mut self.variables,
+                &mut self.violations,
+                operands,
+            )
+            .to_true(),
             0x14 => two_op::o_20_add(&mut self.pc, &mut self.variables, operands).to_true(),
             0x15 => two_op::o_21_sub(&mut self.pc, &mut self.variables, operands).to_true(),
-            _ => self.unimplemented("long", opcode),
+            0x16 => two_op::o_22_mul(&mut self.pc, &mut self.variables, operands).to_true(),
+            0x17 => two_op::o_23_div(&mut self.pc, &mut self.variables, operands).to_true(),
+            0x18 => two_op::o_24_mod(&mut self.pc, &mut self.variables, operands).to_true(),
+            0x19 => two_op::o_25_call_2s(
+                &mut self.pc,
+                &self.stack,
+                &mut self.variables,
+                &self.header,
+                operands,
+            )
+            .to_true(),
+            0x1a => two_op::o_26_call_2n(
+                &mut self.pc,
+                &self.stack,
+                &mut self.variables,
+                &self.header,
+                operands,
+            )
+            .to_true(),
+            0x1b => two_op::o_27_set_colour(
+                &mut self.variables,
+                &self.screen,
+                &mut self.violations,
+                operands,
+            )
+            .to_true(),
+            0x1c => two_op::o_28_throw(&mut self.pc, &self.stack, &mut self.variables, operands)
+                .to_true(),
+            _ => self.unimplemented(info),
         }
     }
 
-    fn unimplemented(&self, msg: &'static str, byte: u8) -> Result<bool> {
-        Err(ZErr::UnknownOpcode(msg, u16::from(byte)))
+    // Look up an opcode, failing if it isn't legal in this story's version.
+    fn opcode_info(&self, form: OpcodeForm, number: u8) -> Result<&'static OpcodeInfo> {
+        let version = self.header.version_number();
+        opcode_info(form, number, version).ok_or_else(|| {
+            ZErr::UnknownOpcode(form.name(), number, version as u8, self.instruction_pc)
+        })
     }
-}
 
-fn call_null(_n: ()) -> Result<bool> {
-    Ok(true)
+    fn unimplemented(&self, info: &OpcodeInfo) -> Result<bool> {
+        Err(ZErr::UnimplementedOpcode(info.name, self.instruction_pc))
+    }
 }
 
 #[cfg(test)]
 mod test {
-    // One day, you might want to figure out how to test this.
+    use super::*;
+
+    #[test]
+    fn test_opcode_info() {
+        let info = opcode_info(OpcodeForm::OneOp, 1, ZVersion::V3).unwrap();
+        assert_eq!("get_sibling", info.name);
+        assert!(info.stores());
+        assert!(info.branches());
+        assert!(!info.prints_text());
+
+        assert!(opcode_info(OpcodeForm::ZeroOp, 2, ZVersion::V5)
+            .unwrap()
+            .prints_text());
+    }
+
+    #[test]
+    fn test_opcode_info_depends_on_version() {
+        let sread = opcode_info(OpcodeForm::Var, 4, ZVersion::V3).unwrap();
+        assert_eq!("sread", sread.name);
+        assert!(!sread.stores());
+
+        let aread = opcode_info(OpcodeForm::Var, 4, ZVersion::V5).unwrap();
+        assert_eq!("aread", aread.name);
+        assert!(aread.stores());
+
+        assert_eq!(
+            "call_1n",
+            opcode_info(OpcodeForm::OneOp, 15, ZVersion::V5)
+                .unwrap()
+                .name
+        );
+        assert!(opcode_info(OpcodeForm::ZeroOp, 15, ZVersion::V3).is_none());
+        assert!(opcode_info(OpcodeForm::ZeroOp, 12, ZVersion::V5).is_none());
+        assert!(opcode_info(OpcodeForm::TwoOp, 0, ZVersion::V3).is_none());
+        assert!(opcode_info(OpcodeForm::Ext, 13, ZVersion::V5).is_some());
+    }
+
+    #[test]
+    fn test_opcode_map_has_no_overlaps() {
+        for (idx, info) in OPCODES.iter().enumerate() {
+            assert!(info.first_version <= info.last_version, "{}", info.name);
+            for other in OPCODES[idx + 1..].iter() {
+                let overlaps = info.form == other.form
+                    && info.number == other.number
+                    && info.first_version <= other.last_version
+                    && other.first_version <= info.last_version;
+                assert!(!overlaps, "{} overlaps {}", info.name, other.name);
+            }
+        }
+    }
 }
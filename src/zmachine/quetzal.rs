@@ -0,0 +1,574 @@
+use super::result::{Result, ZErr};
+
+// Dynamic memory in a Quetzal save file. (Quetzal 1.4, section 3)
+//
+// A save holds one of two chunks for it:
+//
+//   CMem: the current memory XORed with the story's original memory. Runs of
+//         zero bytes (memory that didn't change) are written as a zero
+//         followed by one less than the length of the run, up to 256 bytes.
+//         Trailing zeros are left off.
+//   UMem: the current memory, as is.
+//
+// CMem is much smaller, and is what we write unless asked otherwise. Both
+// are read, since some interpreters only write UMem.
+pub const CMEM_ID: [u8; 4] = *b"CMem";
+pub const UMEM_ID: [u8; 4] = *b"UMem";
+pub const IFHD_ID: [u8; 4] = *b"IFhd";
+pub const STKS_ID: [u8; 4] = *b"Stks";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemoryFormat {
+    #[default]
+    Compressed,
+    Uncompressed,
+}
+
+// The id and contents of an IFF chunk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chunk {
+    pub id: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+// Build the chunk for the current dynamic memory. `original` is the dynamic
+// memory from the story file.
+pub fn memory_chunk(original: &[u8], current: &[u8], format: MemoryFormat) -> Chunk {
+    match format {
+        MemoryFormat::Compressed => Chunk {
+            id: CMEM_ID,
+            data: compress_memory(original, current),
+        },
+        MemoryFormat::Uncompressed => Chunk {
+            id: UMEM_ID,
+            data: current.to_vec(),
+        },
+    }
+}
+
+// Recover dynamic memory from either kind of memory chunk.
+pub fn restore_memory(chunk: &Chunk, original: &[u8]) -> Result<Vec<u8>> {
+    match chunk.id {
+        CMEM_ID => decompress_memory(original, &chunk.data),
+        UMEM_ID => {
+            if chunk.data.len() != original.len() {
+                return Err(ZErr::BadSaveFile("UMem chunk is the wrong size"));
+            }
+            Ok(chunk.data.clone())
+        }
+        _ => Err(ZErr::BadSaveFile("not a memory chunk")),
+    }
+}
+
+// The IFhd chunk: which story a save belongs to, and where to resume.
+// (Quetzal 1.4, section 5)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SaveHeader {
+    pub release: u16,
+    pub serial: [u8; 6],
+    pub checksum: u16,
+    // Only 3 bytes in the file.
+    pub pc: u32,
+}
+
+// The chunks of a Quetzal file that are needed to restore it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuetzalSave {
+    pub header: SaveHeader,
+    // Either CMem or UMem.
+    pub memory: Chunk,
+    pub stacks: Chunk,
+}
+
+// Read a Quetzal file, as written by us or by other interpreters.
+//
+// Other interpreters don't all agree on the details, so this is forgiving
+// where it can be without guessing:
+//
+//   - Chunks with an odd length should be followed by a pad byte, but some
+//     files leave it off at the end of the file.
+//   - The FORM length is sometimes wrong. The chunks are read to the end of
+//     the data, or of the FORM, whichever is first.
+//   - Chunks we don't use (ANNO, AUTH, "(c) ", IntD, ...) are skipped.
+//   - Memory may be in CMem or UMem. If a file has both, the first is used.
+pub fn read_quetzal(bytes: &[u8]) -> Result<QuetzalSave> {
+    if bytes.len() < 12 || &bytes[0..4] != b"FORM" || &bytes[8..12] != b"IFZS" {
+        return Err(ZErr::BadSaveFile("not a Quetzal file"));
+    }
+    let form_end = (8 + u32_at(bytes, 4) as usize).min(bytes.len());
+
+    let mut header = None;
+    let mut memory = None;
+    let mut stacks = None;
+    let mut offset = 12;
+    while offset + 8 <= form_end {
+        let id = [
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ];
+        let len = u32_at(bytes, offset + 4) as usize;
+        let start = offset + 8;
+        let data = bytes
+            .get(start..start + len)
+            .ok_or(ZErr::BadSaveFile("chunk runs past the end of the file"))?;
+        let chunk = Chunk {
+            id,
+            data: data.to_vec(),
+        };
+        match id {
+            IFHD_ID => header = header.or(Some(read_save_header(&chunk)?)),
+            CMEM_ID | UMEM_ID => memory = memory.or(Some(chunk)),
+            STKS_ID => stacks = stacks.or(Some(chunk)),
+            _ => (),
+        }
+        offset = start + len + (len & 1);
+    }
+
+    Ok(QuetzalSave {
+        header: header.ok_or(ZErr::BadSaveFile("missing IFhd chunk"))?,
+        memory: memory.ok_or(ZErr::BadSaveFile("missing memory chunk"))?,
+        stacks: stacks.ok_or(ZErr::BadSaveFile("missing Stks chunk"))?,
+    })
+}
+
+impl QuetzalSave {
+    // A save of the current state. `original` is the dynamic memory from the
+    // story file, which holds the header that identifies the story. `stacks`
+    // is the Stks chunk's data, and pc is where to resume: the save
+    // instruction's store or branch byte. (Quetzal 1.4, section 5.8)
+    pub fn new(
+        original: &[u8],
+        current: &[u8],
+        stacks: Vec<u8>,
+        pc: usize,
+        format: MemoryFormat,
+    ) -> QuetzalSave {
+        let mut serial = [0; 6];
+        serial.copy_from_slice(&original[0x12..0x18]);
+        QuetzalSave {
+            header: SaveHeader {
+                release: u16_at(original, 0x02),
+                serial,
+                checksum: u16_at(original, 0x1c),
+                pc: pc as u32,
+            },
+            memory: memory_chunk(original, current, format),
+            stacks: Chunk {
+                id: STKS_ID,
+                data: stacks,
+            },
+        }
+    }
+
+    // The save as a Quetzal file: a FORM of type IFZS holding the IFhd,
+    // memory, and Stks chunks, each padded to an even length.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut body = b"IFZS".to_vec();
+        for chunk in &[
+            write_save_header(&self.header),
+            self.memory.clone(),
+            self.stacks.clone(),
+        ] {
+            body.extend_from_slice(&chunk.id);
+            body.extend_from_slice(&(chunk.data.len() as u32).to_be_bytes());
+            body.extend_from_slice(&chunk.data);
+            if chunk.data.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        let mut bytes = b"FORM".to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    // Check that the save belongs to the story whose dynamic memory, as
+    // loaded, is `story`, and that its chunks make sense for it. The PC must
+    // be within the first story_length bytes. Returns the restored dynamic
+    // memory. Nothing is changed when this fails, so a bad save can be
+    // reported and the game carried on.
+    pub fn validate(&self, story: &[u8], story_length: usize) -> Result<Vec<u8>> {
+        if story.len() < 0x40 {
+            return Err(ZErr::BadSaveFile("story file is too short"));
+        }
+        let mut serial = [0; 6];
+        serial.copy_from_slice(&story[0x12..0x18]);
+        let release = u16_at(story, 0x02);
+        if self.header.release != release || self.header.serial != serial {
+            return Err(ZErr::SaveStoryMismatch(
+                self.header.release,
+                self.header.serial,
+                release,
+                serial,
+            ));
+        }
+        // The earliest stories have no checksum in the header, and some
+        // interpreters write 0 when they don't know it.
+        let checksum = u16_at(story, 0x1c);
+        if checksum != 0 && self.header.checksum != 0 && self.header.checksum != checksum {
+            return Err(ZErr::SaveChecksumMismatch(self.header.checksum, checksum));
+        }
+        // The PC can't be in the header, and must be in the story.
+        if self.header.pc < 0x40 || self.header.pc as usize >= story_length {
+            return Err(ZErr::BadSavePC(self.header.pc, story_length));
+        }
+        validate_stacks(&self.stacks.data)?;
+
+        let dynamic_size = usize::from(u16_at(story, 0x0e)).min(story.len());
+        restore_memory(&self.memory, &story[..dynamic_size])
+    }
+}
+
+fn write_save_header(header: &SaveHeader) -> Chunk {
+    let mut data = header.release.to_be_bytes().to_vec();
+    data.extend_from_slice(&header.serial);
+    data.extend_from_slice(&header.checksum.to_be_bytes());
+    data.extend_from_slice(&header.pc.to_be_bytes()[1..]);
+    Chunk { id: IFHD_ID, data }
+}
+
+fn read_save_header(chunk: &Chunk) -> Result<SaveHeader> {
+    let data = &chunk.data;
+    if data.len() < 13 {
+        return Err(ZErr::BadSaveFile("IFhd chunk is too short"));
+    }
+    let mut serial = [0; 6];
+    serial.copy_from_slice(&data[2..8]);
+    Ok(SaveHeader {
+        release: u16_at(data, 0),
+        serial,
+        checksum: u16_at(data, 8),
+        pc: (u32::from(data[10]) << 16) | (u32::from(data[11]) << 8) | u32::from(data[12]),
+    })
+}
+
+// Walk the frames in a Stks chunk, to check that they fit it exactly. Each
+// frame is a 3 byte return PC, a flags byte (the number of locals in the low
+// nibble), the result variable, a byte of argument flags, a 16-bit count of
+// evaluation stack words, and then the locals and stack words. (Quetzal 1.4,
+// section 4)
+fn validate_stacks(data: &[u8]) -> Result<()> {
+    let mut offset = 0;
+    while offset < data.len() {
+        if offset + 8 > data.len() {
+            return Err(ZErr::BadSaveFile("Stks chunk ends inside a frame"));
+        }
+        let num_locals = usize::from(data[offset + 3] & 0x0f);
+        let num_words = usize::from(u16_at(data, offset + 6));
+        offset += 8 + 2 * (num_locals + num_words);
+    }
+    if offset != data.len() {
+        return Err(ZErr::BadSaveFile("Stks chunk ends inside a frame"));
+    }
+    Ok(())
+}
+
+fn u16_at(bytes: &[u8], idx: usize) -> u16 {
+    (u16::from(bytes[idx]) << 8) | u16::from(bytes[idx + 1])
+}
+
+fn u32_at(bytes: &[u8], idx: usize) -> u32 {
+    u32::from_be_bytes([bytes[idx], bytes[idx + 1], bytes[idx + 2], bytes[idx + 3]])
+}
+
+// The CMem encoding of the difference between two images of dynamic memory.
+// It's compact whenever little has changed, so it serves for undo states and
+// comparing snapshots as well as save files.
+pub fn compress_memory(original: &[u8], current: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut zeros = 0usize;
+    for (orig, cur) in original.iter().zip(current) {
+        let byte = orig ^ cur;
+        if byte == 0 {
+            zeros += 1;
+            continue;
+        }
+        write_zero_run(&mut data, zeros);
+        zeros = 0;
+        data.push(byte);
+    }
+    data
+}
+
+fn write_zero_run(data: &mut Vec<u8>, mut zeros: usize) {
+    while zeros > 0 {
+        let run = zeros.min(256);
+        data.push(0);
+        data.push((run - 1) as u8);
+        zeros -= run;
+    }
+}
+
+// Apply a diff made by compress_memory to the original image.
+pub fn decompress_memory(original: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let mut memory = original.to_vec();
+    let mut offset = 0;
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte == 0 {
+            let count = bytes
+                .next()
+                .ok_or(ZErr::BadSaveFile("CMem chunk ends inside a run"))?;
+            offset += usize::from(*count) + 1;
+        } else {
+            let orig = memory
+                .get_mut(offset)
+                .ok_or(ZErr::BadSaveFile("CMem chunk is longer than memory"))?;
+            *orig ^= byte;
+            offset += 1;
+        }
+    }
+    if offset > memory.len() {
+        return Err(ZErr::BadSaveFile("CMem chunk is longer than memory"));
+    }
+    Ok(memory)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let original = vec![0u8; 600];
+        let mut current = original.clone();
+        current[1] = 7;
+        current[2] = 9;
+        current[500] = 1;
+
+        let chunk = memory_chunk(&original, &current, MemoryFormat::Compressed);
+        assert_eq!(CMEM_ID, chunk.id);
+        // 1 zero, two changes, 497 zeros (as 256 + 241), then a change.
+        // Trailing zeros are left off.
+        assert_eq!(vec![0, 0, 7, 9, 0, 255, 0, 240, 1], chunk.data);
+        assert_eq!(current, restore_memory(&chunk, &original).unwrap());
+    }
+
+    #[test]
+    fn test_uncompressed_round_trip() {
+        let original = vec![1, 2, 3, 4];
+        let current = vec![1, 5, 3, 4];
+
+        let chunk = memory_chunk(&original, &current, MemoryFormat::Uncompressed);
+        assert_eq!(UMEM_ID, chunk.id);
+        assert_eq!(current, chunk.data);
+        assert_eq!(current, restore_memory(&chunk, &original).unwrap());
+    }
+
+    fn story() -> Vec<u8> {
+        let mut story = vec![0u8; 0x80];
+        story[0x03] = 88; // Release.
+        story[0x0f] = 0x40; // Static memory base.
+        story[0x12..0x18].copy_from_slice(b"840726");
+        story[0x1c] = 0x12; // Checksum.
+        story[0x1d] = 0x34;
+        story
+    }
+
+    fn chunk_bytes(id: &[u8; 4], data: &[u8], pad: bool) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(data);
+        if pad && data.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    fn form(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        let mut bytes = b"FORM".to_vec();
+        bytes.extend_from_slice(&(body.len() as u32 + 4).to_be_bytes());
+        bytes.extend_from_slice(b"IFZS");
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    fn ifhd() -> Vec<u8> {
+        let mut data = vec![0, 88];
+        data.extend_from_slice(b"840726");
+        data.extend_from_slice(&[0x12, 0x34, 0x00, 0x00, 0x50]);
+        chunk_bytes(b"IFhd", &data, true)
+    }
+
+    // One frame, with one local and one stack word.
+    fn stks() -> Vec<u8> {
+        chunk_bytes(b"Stks", &[0, 0, 0, 1, 0, 0, 0, 1, 0, 5, 0, 6], true)
+    }
+
+    #[test]
+    fn test_read_other_interpreters() {
+        let story = story();
+
+        // An annotation with an odd length, padded, and CMem.
+        let save = form(&[
+            ifhd(),
+            chunk_bytes(b"ANNO", b"Frotz", true),
+            chunk_bytes(b"CMem", &[0, 1, 3], true),
+            stks(),
+        ]);
+        let save = read_quetzal(&save).unwrap();
+        assert_eq!(88, save.header.release);
+        assert_eq!(0x1234, save.header.checksum);
+        assert_eq!(0x50, save.header.pc);
+        let memory = save.validate(&story, story.len()).unwrap();
+        assert_eq!(0x40, memory.len());
+        assert_eq!(3, memory[2]);
+
+        // UMem, an author chunk, and the last pad byte left off.
+        let mut umem = story[..0x40].to_vec();
+        umem[5] = 9;
+        let mut save = form(&[
+            chunk_bytes(b"AUTH", b"me", true),
+            ifhd(),
+            stks(),
+            chunk_bytes(b"UMem", &umem, true),
+            chunk_bytes(b"ANNO", b"odd", false),
+        ]);
+        // With the FORM length still counting the pad byte.
+        let len = save.len() as u32 - 7;
+        save[4..8].copy_from_slice(&len.to_be_bytes());
+        let memory = read_quetzal(&save)
+            .unwrap()
+            .validate(&story, story.len())
+            .unwrap();
+        assert_eq!(umem, memory);
+    }
+
+    #[test]
+    fn test_write_quetzal() {
+        let story = story();
+        let mut current = story[..0x40].to_vec();
+        current[0x30] = 7;
+        let stacks = vec![0, 0, 0, 1, 0, 0, 0, 1, 0, 5, 0, 6];
+
+        for &format in &[MemoryFormat::Compressed, MemoryFormat::Uncompressed] {
+            let save = QuetzalSave::new(&story, &current, stacks.clone(), 0x50, format);
+            let bytes = save.to_bytes();
+            assert_eq!(b"FORM", &bytes[..4]);
+            assert_eq!(bytes.len() - 8, u32_at(&bytes, 4) as usize);
+
+            // The IFhd chunk is 13 bytes, padded to 14.
+            assert_eq!(ifhd(), bytes[12..34].to_vec());
+
+            let save = read_quetzal(&bytes).unwrap();
+            assert_eq!(0x50, save.header.pc);
+            assert_eq!(stacks, save.stacks.data);
+            assert_eq!(current, save.validate(&story, story.len()).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_reject_bad_saves() {
+        let story = story();
+        let cmem = chunk_bytes(b"CMem", &[], true);
+
+        let bad_files = vec![
+            b"FORM\0\0\0\x04IFRS".to_vec(),
+            form(&[cmem.clone(), stks()]),
+            form(&[ifhd(), stks()]),
+            form(&[ifhd(), cmem.clone()]),
+            form(&[ifhd(), chunk_bytes(b"CMem", &[0, 1], true)[..9].to_vec()]),
+        ];
+        for bytes in bad_files {
+            match read_quetzal(&bytes) {
+                Err(ZErr::BadSaveFile(_)) => (),
+                _ => panic!("Missing error for {:?}", bytes),
+            }
+        }
+
+        let bad_stacks = chunk_bytes(b"Stks", &[0, 0, 0, 2, 0, 0, 0, 0, 0, 5], true);
+        let cases = vec![
+            (form(&[ifhd(), cmem.clone(), bad_stacks]), story.clone()),
+            (
+                form(&[ifhd(), chunk_bytes(b"UMem", &[0; 3], true), stks()]),
+                story.clone(),
+            ),
+        ];
+        for (bytes, story) in cases {
+            let save = read_quetzal(&bytes).unwrap();
+            match save.validate(&story, story.len()) {
+                Err(ZErr::BadSaveFile(_)) => (),
+                _ => panic!("Missing error for {:?}", save),
+            }
+        }
+    }
+
+    #[test]
+    fn test_save_for_other_story() {
+        let save = read_quetzal(&form(&[ifhd(), chunk_bytes(b"CMem", &[], true), stks()])).unwrap();
+
+        let mut story = story();
+        story[0x17] = b'7';
+        match save.validate(&story, story.len()) {
+            Err(ZErr::SaveStoryMismatch(88, serial, 88, other)) => {
+                assert_eq!(b"840726", &serial);
+                assert_eq!(b"840727", &other);
+            }
+            _ => panic!("Missing error for serial"),
+        }
+
+        let mut story = self::story();
+        story[0x03] = 119;
+        match save.validate(&story, story.len()) {
+            Err(ZErr::SaveStoryMismatch(88, _, 119, _)) => (),
+            _ => panic!("Missing error for release"),
+        }
+
+        let mut story = self::story();
+        story[0x1d] = 0x35;
+        match save.validate(&story, story.len()) {
+            Err(ZErr::SaveChecksumMismatch(0x1234, 0x1235)) => (),
+            _ => panic!("Missing error for checksum"),
+        }
+        // No checksum in the story's header.
+        story[0x1c] = 0;
+        story[0x1d] = 0;
+        assert!(save.validate(&story, story.len()).is_ok());
+
+        let story = self::story();
+        let mut save = save;
+        for &pc in &[0x3f, 0x80] {
+            save.header.pc = pc;
+            match save.validate(&story, story.len()) {
+                Err(ZErr::BadSavePC(bad, 0x80)) => assert_eq!(pc, bad),
+                _ => panic!("Missing error for pc {}", pc),
+            }
+        }
+    }
+
+    #[test]
+    fn test_bad_memory_chunks() {
+        let original = vec![0u8; 4];
+        let bad_chunks = vec![
+            Chunk {
+                id: UMEM_ID,
+                data: vec![0; 3],
+            },
+            Chunk {
+                id: CMEM_ID,
+                data: vec![1, 0],
+            },
+            Chunk {
+                id: CMEM_ID,
+                data: vec![0, 3, 1],
+            },
+            Chunk {
+                id: CMEM_ID,
+                data: vec![0, 9],
+            },
+            Chunk {
+                id: *b"Stks",
+                data: vec![],
+            },
+        ];
+        for chunk in bad_chunks {
+            match restore_memory(&chunk, &original) {
+                Err(ZErr::BadSaveFile(_)) => (),
+                _ => panic!("Missing error for {:?}", chunk),
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Below this, a seed from the game asks for the predictable sequence
+// 1, 2, ..., seed, 1, 2, ... rather than seeding the generator. (ZSpec 2.4.1)
+const COUNTING_SEED_LIMIT: u16 = 1000;
+
+// The random number generator behind the random opcode. (ZSpec 2.4)
+//
+// Numbers come from an xorshift generator. It is seeded once when the story
+// starts, and everything after that follows from the seed: even when the
+// game asks to be reseeded "randomly", the new seed is drawn from the
+// generator itself. So a session can be replayed exactly by starting from
+// the same seed and giving the same input.
+pub struct ZRandom {
+    state: u64,
+    // While set, the game asked for the predictable sequence: (seed, last).
+    counting: Option<(u16, u16)>,
+}
+
+impl ZRandom {
+    pub fn new(seed: u64) -> ZRandom {
+        ZRandom {
+            state: mix(seed),
+            counting: None,
+        }
+    }
+
+    // A number between 1 and range, inclusive.
+    pub fn next(&mut self, range: u16) -> u16 {
+        if range == 0 {
+            return 0;
+        }
+        if let Some((seed, last)) = self.counting {
+            let value = if last >= seed { 1 } else { last + 1 };
+            self.counting = Some((seed, value));
+            return (value - 1) % range + 1;
+        }
+        let value = self.next_u64() >> 32;
+        (value % u64::from(range)) as u16 + 1
+    }
+
+    // The game's own seed, from random with a negative range.
+    pub fn seed(&mut self, seed: u16) {
+        if seed < COUNTING_SEED_LIMIT {
+            self.counting = Some((seed.max(1), 0));
+        } else {
+            self.counting = None;
+            self.state = mix(u64::from(seed));
+        }
+    }
+
+    // Go back to unpredictable numbers, as for random 0.
+    pub fn reseed(&mut self) {
+        self.counting = None;
+        let seed = self.next_u64();
+        self.state = mix(seed);
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+// A seed for when the player didn't give one.
+pub fn seed_from_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() ^ u64::from(d.subsec_nanos()))
+        .unwrap_or(0)
+}
+
+// Spread the seed's bits, since xorshift does badly from small seeds, and
+// can't start from zero.
+fn mix(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    match z ^ (z >> 31) {
+        0 => 1,
+        z => z,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_range() {
+        let mut random = ZRandom::new(0);
+        for _ in 0..1000 {
+            let value = random.next(6);
+            assert!(value >= 1 && value <= 6);
+        }
+        assert_eq!(1, random.next(1));
+    }
+
+    #[test]
+    fn test_same_seed_same_numbers() {
+        let mut first = ZRandom::new(1234);
+        let mut second = ZRandom::new(1234);
+        let numbers: Vec<u16> = (0..20).map(|_| first.next(100)).collect();
+        assert_eq!(
+            numbers,
+            (0..20).map(|_| second.next(100)).collect::<Vec<_>>()
+        );
+
+        // Reseeding is repeatable too.
+        first.reseed();
+        second.reseed();
+        assert_eq!(first.next(30000), second.next(30000));
+    }
+
+    #[test]
+    fn test_counting_sequence() {
+        let mut random = ZRandom::new(0);
+        random.seed(3);
+        let numbers: Vec<u16> = (0..7).map(|_| random.next(100)).collect();
+        assert_eq!(vec![1, 2, 3, 1, 2, 3, 1], numbers);
+
+        // Larger seeds seed the generator predictably.
+        random.seed(5000);
+        let first = random.next(30000);
+        random.seed(5000);
+        assert_eq!(first, random.next(30000));
+    }
+}
@@ -2,18 +2,37 @@ use std::fmt;
 use std::io;
 use std::result;
 
+use super::violations::Violation;
+
 pub type Result<T> = result::Result<T, ZErr>;
 
 #[derive(Debug)]
 pub enum ZErr {
+    BadAttribute(u8),
+    BadObject(u16),
+    BadProperty(u8),
+    BadSaveFile(&'static str),
+    BadSavePC(u32, usize), // Saved PC, story length.
     BadVariableIndex(&'static str, u8),
+    DivisionByZero,
     LocalOutOfRange(u8, u8), // Requested local, num_locals.
     MissingOperand,
+    MissingProperty(u8),
     NullObject,
+    ReadViolation(usize),
+    SaveChecksumMismatch(u16, u16), // Saved checksum, story's checksum.
+    SaveStoryMismatch(u16, [u8; 6], u16, [u8; 6]), // Saved release and serial, story's.
     StackOverflow(&'static str),
     StackUnderflow(&'static str),
-    UnknownOpcode(&'static str, u16),
+    StoryTooLarge(usize, usize),  // File's length, version's maximum.
+    StoryTruncated(usize, usize), // File's length, header's.
+    UnknownInputStream(u8),
+    UnimplementedOpcode(&'static str, usize), // Opcode name, pc.
+    UnknownOpcode(&'static str, u8, u8, usize), // Form, opcode number, version, pc.
+    UnknownOutputStream(u8),
     UnknownVersionNumber(u8),
+    UnknownWindow(u16),
+    Violation(Violation),
     WriteViolation(usize),
 
     GenericError(&'static str),
@@ -37,11 +56,31 @@ impl From<io::Error> for ZErr {
     }
 }
 
+#[cfg(feature = "terminal")]
+impl From<crossterm::ErrorKind> for ZErr {
+    fn from(err: crossterm::ErrorKind) -> ZErr {
+        match err {
+            crossterm::ErrorKind::IoError(err) => ZErr::IO(err),
+            err => ZErr::IO(io::Error::other(err.to_string())),
+        }
+    }
+}
+
 impl fmt::Display for ZErr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::ZErr::*;
         match *self {
+            BadAttribute(attr) => write!(f, "Bad attribute number: {}", attr),
+            BadObject(obj) => write!(f, "Bad object number: {}", obj),
+            BadProperty(prop) => write!(f, "Bad property number: {}", prop),
+            BadSaveFile(msg) => write!(f, "Bad save file: {}", msg),
+            BadSavePC(pc, len) => write!(
+                f,
+                "Save resumes at pc 0x{:x}, outside the story (0x{:x} bytes)",
+                pc, len
+            ),
             BadVariableIndex(msg, index) => write!(f, "Bad {} variable index: {}", msg, index),
+            DivisionByZero => write!(f, "Division by zero."),
             GenericError(msg) => write!(f, "Generic error: {}", msg),
             LocalOutOfRange(req, num) => write!(
                 f,
@@ -49,11 +88,53 @@ impl fmt::Display for ZErr {
                 req, num
             ),
             MissingOperand => write!(f, "Missing operand."),
+            MissingProperty(prop) => write!(f, "Object has no property {}.", prop),
             NullObject => write!(f, "Null object reference."),
+            ReadViolation(offset) => {
+                write!(
+                    f,
+                    "Attempt to read past the end of memory at offset '{}'",
+                    offset
+                )
+            }
+            SaveChecksumMismatch(saved, story) => write!(
+                f,
+                "Save is for a story with checksum 0x{:04x}, but this story's is 0x{:04x}",
+                saved, story
+            ),
+            SaveStoryMismatch(saved_release, ref saved_serial, release, ref serial) => write!(
+                f,
+                "Save is for release {} serial {}, but this story is release {} serial {}",
+                saved_release,
+                String::from_utf8_lossy(saved_serial),
+                release,
+                String::from_utf8_lossy(serial)
+            ),
             StackOverflow(msg) => write!(f, "Stack overflow: {}", msg),
             StackUnderflow(msg) => write!(f, "Stack underflow: {}", msg),
-            UnknownOpcode(msg, opcode) => write!(f, "Unknown {} opcode: 0x{:02x}", msg, opcode),
+            StoryTooLarge(length, max) => write!(
+                f,
+                "Story file is {} bytes, but its version allows at most {}",
+                length, max
+            ),
+            StoryTruncated(length, expected) => write!(
+                f,
+                "Story file is {} bytes, but its header says {}",
+                length, expected
+            ),
+            UnknownInputStream(stream) => write!(f, "Unknown input stream: {}", stream),
+            UnimplementedOpcode(name, pc) => {
+                write!(f, "Unimplemented opcode '{}' at pc 0x{:x}", name, pc)
+            }
+            UnknownOpcode(form, opcode, version, pc) => write!(
+                f,
+                "Unknown {} opcode {} (0x{:02x}) in V{} story at pc 0x{:x}",
+                form, opcode, opcode, version, pc
+            ),
+            UnknownOutputStream(stream) => write!(f, "Unknown output stream: {}", stream),
             UnknownVersionNumber(vers) => write!(f, "Unknown version number: '{}'", vers),
+            UnknownWindow(window) => write!(f, "Unknown window: {}", window),
+            Violation(violation) => write!(f, "Strict mode: {}", violation),
             WriteViolation(offset) => write!(
                 f,
                 "Attempt to write to read-only memory at offset '{}'",
@@ -0,0 +1,237 @@
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::result::{Result, ZErr};
+use super::traits::SaveHandler;
+
+// Save files written by FileSaveHandler start with this, then a big-endian
+// u32 giving the length of the metadata that follows. The save data itself
+// comes after the metadata.
+const SAVE_MAGIC: &[u8; 4] = b"RZMS";
+const SAVE_EXTENSION: &str = "sav";
+
+// What a save file says about the game it holds.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SaveInfo {
+    // Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub turn: u16,
+    pub score: i16,
+    pub room: String,
+}
+
+impl SaveInfo {
+    // Info for a save made now.
+    pub fn new(turn: u16, score: i16, room: &str) -> SaveInfo {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        SaveInfo {
+            timestamp,
+            turn,
+            score,
+            room: room.to_string(),
+        }
+    }
+
+    // One "key=value" line per field.
+    fn to_text(&self) -> String {
+        format!(
+            "timestamp={}\nturn={}\nscore={}\nroom={}\n",
+            self.timestamp,
+            self.turn,
+            self.score,
+            self.room.replace('\n', " ")
+        )
+    }
+
+    // Unknown keys are ignored, and missing ones left at their defaults, so
+    // that fields can be added later.
+    fn from_text(text: &str) -> SaveInfo {
+        let mut info = SaveInfo::default();
+        for line in text.lines() {
+            let mut parts = line.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key, value),
+                _ => continue,
+            };
+            match key {
+                "timestamp" => info.timestamp = value.parse().unwrap_or(0),
+                "turn" => info.turn = value.parse().unwrap_or(0),
+                "score" => info.score = value.parse().unwrap_or(0),
+                "room" => info.room = value.to_string(),
+                _ => (),
+            }
+        }
+        info
+    }
+}
+
+// A save file found by list_saves.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SaveEntry {
+    pub path: PathBuf,
+    pub info: SaveInfo,
+}
+
+// Keeps save files in a directory, named for the story and the turn they
+// were made on: "zork1-0042.sav". Saving on a turn that was saved before
+// replaces the earlier save.
+//
+// Restoring reads the newest save for the story. Frontends that let the
+// player pick a save can show list_saves, and then call restore_from.
+pub struct FileSaveHandler {
+    dir: PathBuf,
+    story_name: String,
+}
+
+impl FileSaveHandler {
+    // The story name is taken from the story file's name, without the
+    // extension.
+    pub fn new<D, S>(dir: D, story_path: S) -> FileSaveHandler
+    where
+        D: AsRef<Path>,
+        S: AsRef<Path>,
+    {
+        let story_name = story_path
+            .as_ref()
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_lowercase())
+            .unwrap_or_else(|| "story".to_string());
+        FileSaveHandler {
+            dir: dir.as_ref().to_path_buf(),
+            story_name,
+        }
+    }
+
+    pub fn default_file_name(&self, turn: u16) -> String {
+        format!("{}-{:04}.{}", self.story_name, turn, SAVE_EXTENSION)
+    }
+
+    // The story's saves, oldest first. Files that can't be read are skipped.
+    pub fn list_saves(&self) -> Result<Vec<SaveEntry>> {
+        let prefix = format!("{}-", self.story_name);
+        let mut saves = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let is_save = path.extension() == Some(OsStr::new(SAVE_EXTENSION))
+                && path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with(&prefix));
+            if !is_save {
+                continue;
+            }
+            if let Ok((info, _)) = read_save_file(&path) {
+                saves.push(SaveEntry { path, info });
+            }
+        }
+        saves.sort_by(|a, b| (a.info.timestamp, &a.path).cmp(&(b.info.timestamp, &b.path)));
+        Ok(saves)
+    }
+
+    pub fn restore_from<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>> {
+        read_save_file(path.as_ref()).map(|(_, data)| data)
+    }
+}
+
+impl SaveHandler for FileSaveHandler {
+    fn save(&mut self, info: &SaveInfo, data: &[u8]) -> Result<()> {
+        let metadata = info.to_text();
+        let path = self.dir.join(self.default_file_name(info.turn));
+        let mut file = File::create(path)?;
+        file.write_all(SAVE_MAGIC)?;
+        file.write_all(&(metadata.len() as u32).to_be_bytes())?;
+        file.write_all(metadata.as_bytes())?;
+        file.write_all(data)?;
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<Option<Vec<u8>>> {
+        match self.list_saves()?.pop() {
+            Some(entry) => self.restore_from(entry.path).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+fn read_save_file(path: &Path) -> Result<(SaveInfo, Vec<u8>)> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    if bytes.len() < 8 || &bytes[0..4] != SAVE_MAGIC {
+        return Err(ZErr::BadSaveFile("missing save file header"));
+    }
+    let len = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+    let metadata = bytes
+        .get(8..8 + len)
+        .ok_or(ZErr::BadSaveFile("save file header is truncated"))?;
+    let info = SaveInfo::from_text(&String::from_utf8_lossy(metadata));
+    Ok((info, bytes[8 + len..].to_vec()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_default_file_name() {
+        let handler = FileSaveHandler::new("saves", "games/Zork1.z3");
+        assert_eq!("zork1-0042.sav", handler.default_file_name(42));
+    }
+
+    #[test]
+    fn test_save_and_list() {
+        let dir = test_dir("rzm2_test_save_and_list");
+        let mut handler = FileSaveHandler::new(&dir, "zork1.z3");
+        assert_eq!(None, handler.restore().unwrap());
+
+        let mut first = SaveInfo::new(7, 10, "West of House");
+        first.timestamp = 100;
+        handler.save(&first, b"first").unwrap();
+        let mut second = SaveInfo::new(12, 25, "Living Room");
+        second.timestamp = 200;
+        handler.save(&second, b"second").unwrap();
+        // Saves for other stories, and other files, aren't listed.
+        FileSaveHandler::new(&dir, "trinity.z4")
+            .save(&first, b"other")
+            .unwrap();
+        fs::write(dir.join("zork1-notes.txt"), "notes").unwrap();
+
+        let saves = handler.list_saves().unwrap();
+        assert_eq!(2, saves.len());
+        assert_eq!(dir.join("zork1-0007.sav"), saves[0].path);
+        assert_eq!(first, saves[0].info);
+        assert_eq!(second, saves[1].info);
+
+        // Restoring picks the newest.
+        assert_eq!(Some(b"second".to_vec()), handler.restore().unwrap());
+        assert_eq!(
+            b"first".to_vec(),
+            handler.restore_from(&saves[0].path).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_bad_save_file() {
+        let dir = test_dir("rzm2_test_bad_save_file");
+        let path = dir.join("zork1-0001.sav");
+        fs::write(&path, b"RZMS\0\0\0\x20short").unwrap();
+
+        let handler = FileSaveHandler::new(&dir, "zork1.z3");
+        match handler.restore_from(&path) {
+            Err(ZErr::BadSaveFile(_)) => (),
+            _ => panic!("Missing error"),
+        }
+        assert!(handler.list_saves().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,894 @@
+use std::io::{self, Write};
+
+use super::header::{SCREEN_HEIGHT_LINES, SCREEN_WIDTH_CHARS};
+use super::pager::Pager;
+use super::result::{Result, ZErr};
+use super::status;
+use super::traits::Screen;
+use super::version::ZVersion;
+use super::wrap::WordWrapper;
+
+// Window numbers. (ZSpec 8.7)
+pub const LOWER_WINDOW: u16 = 0;
+pub const UPPER_WINDOW: u16 = 1;
+
+// V6 has eight windows, numbered 0-7. (ZSpec 8.8)
+pub const V6_NUM_WINDOWS: usize = 8;
+
+// Text styles may be combined, so each style is a bit. (ZSpec 8.7.1)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TextStyle(u8);
+
+impl TextStyle {
+    pub const ROMAN: TextStyle = TextStyle(0b0000);
+    pub const REVERSE: TextStyle = TextStyle(0b0001);
+    pub const BOLD: TextStyle = TextStyle(0b0010);
+    pub const ITALIC: TextStyle = TextStyle(0b0100);
+    pub const FIXED: TextStyle = TextStyle(0b1000);
+
+    pub fn from_raw(bits: u8) -> TextStyle {
+        TextStyle(bits & 0b1111)
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(self, style: TextStyle) -> bool {
+        style.0 != 0 && self.0 & style.0 == style.0
+    }
+
+    // Applying Roman clears all styles. Other styles are added to the current
+    // ones. (ZSpec 8.7.1.1)
+    pub fn apply(self, style: TextStyle) -> TextStyle {
+        if style == TextStyle::ROMAN {
+            TextStyle::ROMAN
+        } else {
+            TextStyle(self.0 | style.0)
+        }
+    }
+}
+
+// The colours available to set_colour and set_true_colour. (ZSpec 8.3)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Colour {
+    Current,
+    Default,
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    LightGrey,
+    MediumGrey,
+    DarkGrey,
+    // A 15-bit colour: 0bbbbbgggggrrrrr (ZSpec 8.3.7)
+    True(u16),
+}
+
+impl Colour {
+    // Decode a colour number as used by set_colour. (ZSpec 8.3.1)
+    pub fn from_palette(number: u16) -> Option<Colour> {
+        use self::Colour::*;
+        match number {
+            0 => Some(Current),
+            1 => Some(Default),
+            2 => Some(Black),
+            3 => Some(Red),
+            4 => Some(Green),
+            5 => Some(Yellow),
+            6 => Some(Blue),
+            7 => Some(Magenta),
+            8 => Some(Cyan),
+            9 => Some(White),
+            10 => Some(LightGrey),
+            11 => Some(MediumGrey),
+            12 => Some(DarkGrey),
+            _ => None,
+        }
+    }
+
+    // Decode a colour word as used by set_true_colour. The colour under the
+    // cursor (-3) and transparency (-4) are V6 features that aren't
+    // supported, so they give None, as do the other negative values.
+    // (ZSpec 15 set_true_colour)
+    pub fn from_true(word: u16) -> Option<Colour> {
+        match word {
+            0xffff => Some(Colour::Default),
+            0xfffe => Some(Colour::Current),
+            0xfffd | 0xfffc => None,
+            w if w & 0x8000 == 0 => Some(Colour::True(w)),
+            _ => None,
+        }
+    }
+
+    // The number used in the header and by set_colour, if this is a
+    // palette colour.
+    pub fn palette_number(self) -> Option<u8> {
+        use self::Colour::*;
+        match self {
+            Current => Some(0),
+            Default => Some(1),
+            Black => Some(2),
+            Red => Some(3),
+            Green => Some(4),
+            Yellow => Some(5),
+            Blue => Some(6),
+            Magenta => Some(7),
+            Cyan => Some(8),
+            White => Some(9),
+            LightGrey => Some(10),
+            MediumGrey => Some(11),
+            DarkGrey => Some(12),
+            True(_) => None,
+        }
+    }
+
+    // The 15-bit equivalent of a palette colour. (ZSpec 8.3.7)
+    // Current and Default have no fixed value.
+    pub fn true_colour(self) -> Option<u16> {
+        use self::Colour::*;
+        match self {
+            Current | Default => None,
+            Black => Some(0x0000),
+            Red => Some(0x001d),
+            Green => Some(0x0340),
+            Yellow => Some(0x03bd),
+            Blue => Some(0x59a0),
+            Magenta => Some(0x7c1f),
+            Cyan => Some(0x77a0),
+            White => Some(0x7fff),
+            LightGrey => Some(0x5ad6),
+            MediumGrey => Some(0x4631),
+            DarkGrey => Some(0x2d6b),
+            True(word) => Some(word),
+        }
+    }
+
+    // Expand a 15-bit colour into 8-bit red, green and blue components.
+    pub fn rgb(self) -> Option<(u8, u8, u8)> {
+        self.true_colour().map(|word| {
+            let expand = |five_bits: u16| ((five_bits & 0x1f) * 255 / 31) as u8;
+            (expand(word), expand(word >> 5), expand(word >> 10))
+        })
+    }
+}
+
+// The V3+ window layout: the upper window (1) occupies the top lines of the
+// screen, and the lower window (0) gets the rest. (ZSpec 8.6, 8.7)
+//
+// Screen implementations can use this to track which window is selected,
+// and where the cursor is in each window. Cursor positions are (line, column),
+// counting from (1, 1) at the top left of the window. (ZSpec 8.7.2.3)
+//
+// In V6, there are eight windows, and the cursor may be moved in any of
+// them. (ZSpec 8.8)
+//
+// The size is the whole screen, as (lines, columns). The upper window can
+// never be taller than the screen.
+#[derive(Debug)]
+pub struct WindowModel {
+    size: (u16, u16),
+    upper_lines: u16,
+    current: u16,
+    cursors: Vec<(u16, u16)>,
+    // Foreground and background for each window.
+    colours: Vec<(Colour, Colour)>,
+}
+
+impl Default for WindowModel {
+    fn default() -> WindowModel {
+        WindowModel::with_windows(2)
+    }
+}
+
+impl WindowModel {
+    pub fn new() -> WindowModel {
+        WindowModel::default()
+    }
+
+    pub fn for_version(version: ZVersion) -> WindowModel {
+        if version == ZVersion::V6 {
+            WindowModel::with_windows(V6_NUM_WINDOWS)
+        } else {
+            WindowModel::new()
+        }
+    }
+
+    fn with_windows(count: usize) -> WindowModel {
+        WindowModel {
+            size: (
+                u16::from(SCREEN_HEIGHT_LINES),
+                u16::from(SCREEN_WIDTH_CHARS),
+            ),
+            upper_lines: 0,
+            current: LOWER_WINDOW,
+            cursors: vec![(1, 1); count],
+            colours: vec![(Colour::Default, Colour::Default); count],
+        }
+    }
+
+    fn is_v6(&self) -> bool {
+        self.cursors.len() == V6_NUM_WINDOWS
+    }
+
+    pub fn size(&self) -> (u16, u16) {
+        self.size
+    }
+
+    pub fn set_size(&mut self, lines: u16, columns: u16) {
+        self.size = (lines, columns);
+        self.upper_lines = self.upper_lines.min(lines);
+    }
+
+    pub fn upper_lines(&self) -> u16 {
+        self.upper_lines
+    }
+
+    pub fn current_window(&self) -> u16 {
+        self.current
+    }
+
+    // Zero lines removes the upper window.
+    pub fn split(&mut self, lines: u16) {
+        self.upper_lines = lines.min(self.size.0);
+    }
+
+    pub fn select(&mut self, window: u16) -> Result<()> {
+        if usize::from(window) >= self.cursors.len() {
+            return Err(ZErr::UnknownWindow(window));
+        }
+        self.current = window;
+        // Before V6, selecting the upper window homes its cursor. (ZSpec 8.7.2)
+        if window == UPPER_WINDOW && !self.is_v6() {
+            self.cursors[usize::from(UPPER_WINDOW)] = (1, 1);
+        }
+        Ok(())
+    }
+
+    // Erasing a window homes its cursor.
+    pub fn erase(&mut self, window: u16) {
+        if let Some(cursor) = self.cursors.get_mut(usize::from(window)) {
+            *cursor = (1, 1);
+        }
+    }
+
+    pub fn cursor(&self) -> (u16, u16) {
+        self.cursors[usize::from(self.current)]
+    }
+
+    // Before V6, the cursor may only be moved in the upper window.
+    // (ZSpec 8.7.2.3) Returns whether the cursor was moved.
+    pub fn set_cursor(&mut self, line: u16, column: u16) -> bool {
+        if self.current == UPPER_WINDOW || self.is_v6() {
+            self.cursors[usize::from(self.current)] = (line, column);
+            true
+        } else {
+            false
+        }
+    }
+
+    // The current window's foreground and background.
+    pub fn colours(&self) -> (Colour, Colour) {
+        self.colours[usize::from(self.current)]
+    }
+
+    // Colour::Current leaves that colour as it was.
+    pub fn set_colours(&mut self, foreground: Colour, background: Colour) {
+        let colours = &mut self.colours[usize::from(self.current)];
+        if foreground != Colour::Current {
+            colours.0 = foreground;
+        }
+        if background != Colour::Current {
+            colours.1 = background;
+        }
+    }
+
+    // Move the current window's cursor past some printed text.
+    pub fn advance(&mut self, text: &str) {
+        let cursor = &mut self.cursors[usize::from(self.current)];
+        for ch in text.chars() {
+            if ch == '\n' {
+                *cursor = (cursor.0 + 1, 1);
+            } else {
+                cursor.1 += 1;
+            }
+        }
+    }
+}
+
+// A Screen that writes to stdout, using ANSI escapes for text styles.
+//
+// There is no cursor control, so text for the upper window is simply printed
+// in sequence with everything else. The exception is the V1-3 status line,
+// which is kept on the terminal's top line by scrolling only the lines below.
+//
+// Text in the lower window pauses with a [MORE] prompt when a page has been
+// printed since the last input. Turn this off for non-interactive runs.
+pub struct StdoutScreen {
+    windows: WindowModel,
+    pager: Pager,
+
+    // Set once the status line has reserved the top line of the terminal.
+    status_shown: bool,
+
+    // Present when buffering is on. Only the lower window is buffered.
+    wrapper: Option<WordWrapper>,
+
+    style: TextStyle,
+}
+
+impl StdoutScreen {
+    const DEFAULT_HEIGHT: u16 = 24;
+
+    pub fn new(version: ZVersion) -> StdoutScreen {
+        let windows = WindowModel::for_version(version);
+        let width = usize::from(windows.size().1);
+        StdoutScreen {
+            windows,
+            pager: Pager::new(Some(StdoutScreen::DEFAULT_HEIGHT - 1)),
+            status_shown: false,
+            // Buffering is on by default. (ZSpec 7.2.1)
+            wrapper: Some(WordWrapper::new(width)),
+            style: TextStyle::ROMAN,
+        }
+    }
+
+    pub fn set_paging(&mut self, on: bool) {
+        self.pager.set_page_lines(if on { Some(0) } else { None });
+        self.update_page_lines();
+    }
+
+    // A page is whatever fits below the upper window and the status line,
+    // leaving a line for the prompt. The terminal's height is unknown unless
+    // it was set, since the screen is otherwise reported as infinitely tall.
+    fn update_page_lines(&mut self) {
+        if self.pager.page_lines().is_some() {
+            let height = match self.windows.size().0 {
+                lines if lines >= u16::from(SCREEN_HEIGHT_LINES) => StdoutScreen::DEFAULT_HEIGHT,
+                lines => lines,
+            };
+            let reserved = self.windows.upper_lines() + if self.status_shown { 1 } else { 0 };
+            let lines = height.saturating_sub(reserved + 1);
+            self.pager.set_page_lines(Some(lines));
+        }
+    }
+
+    fn flush_wrapper(&mut self) -> Result<()> {
+        let text = match self.wrapper {
+            Some(ref mut wrapper) => wrapper.flush(),
+            None => return Ok(()),
+        };
+        self.display_lower(&text)
+    }
+
+    // Print text in the lower window, stopping for the player after each page.
+    fn display_lower(&mut self, text: &str) -> Result<()> {
+        for (idx, page) in self.pager.page(text).iter().enumerate() {
+            if idx > 0 {
+                print!("[MORE]");
+                io::stdout().flush()?;
+                io::stdin().read_line(&mut String::new())?;
+                // Remove the prompt, and the newline that dismissed it.
+                print!("\x1b[1A\r\x1b[K");
+            }
+            print!("{}", page);
+        }
+        Ok(())
+    }
+
+    fn apply_attributes(&mut self) -> Result<()> {
+        let (foreground, background) = self.windows.colours();
+        let escape = attribute_escape(self.style, foreground, background);
+        io::stdout().write_all(escape.as_bytes())?;
+        Ok(())
+    }
+}
+
+impl Screen for StdoutScreen {
+    fn print(&mut self, text: &str) -> Result<()> {
+        self.windows.advance(text);
+        if self.windows.current_window() != LOWER_WINDOW {
+            print!("{}", text);
+            return Ok(());
+        }
+        let text = match self.wrapper {
+            Some(ref mut wrapper) => wrapper.push(text),
+            None => text.to_string(),
+        };
+        self.display_lower(&text)
+    }
+
+    // The screen is flushed before each read, so this starts a new page.
+    fn flush(&mut self) -> Result<()> {
+        self.flush_wrapper()?;
+        io::stdout().flush()?;
+        self.pager.reset();
+        Ok(())
+    }
+
+    fn set_buffer_mode(&mut self, buffered: bool) -> Result<()> {
+        self.flush_wrapper()?;
+        self.wrapper = if buffered {
+            Some(WordWrapper::new(usize::from(self.windows.size().1)))
+        } else {
+            None
+        };
+        Ok(())
+    }
+
+    fn set_text_style(&mut self, style: TextStyle) -> Result<()> {
+        self.flush_wrapper()?;
+        self.style = style;
+        self.apply_attributes()
+    }
+
+    fn set_colour(&mut self, foreground: Colour, background: Colour) -> Result<()> {
+        self.flush_wrapper()?;
+        self.windows.set_colours(foreground, background);
+        self.apply_attributes()
+    }
+
+    fn supports_colour(&self) -> bool {
+        true
+    }
+
+    fn split_window(&mut self, lines: u16) -> Result<()> {
+        self.windows.split(lines);
+        self.update_page_lines();
+        Ok(())
+    }
+
+    fn set_window(&mut self, window: u16) -> Result<()> {
+        self.flush_wrapper()?;
+        self.windows.select(window)?;
+        // Each window keeps its own colours.
+        self.apply_attributes()
+    }
+
+    fn erase_window(&mut self, window: u16) -> Result<()> {
+        self.windows.erase(window);
+        Ok(())
+    }
+
+    fn erase_line(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_cursor(&mut self, line: u16, column: u16) -> Result<()> {
+        self.windows.set_cursor(line, column);
+        Ok(())
+    }
+
+    fn get_cursor(&self) -> (u16, u16) {
+        self.windows.cursor()
+    }
+
+    fn size(&self) -> (u16, u16) {
+        self.windows.size()
+    }
+
+    // Text is wrapped to the width. Set the size before the header is
+    // initialized, since there is no way to notice a resized terminal.
+    fn set_size(&mut self, lines: u16, columns: u16) -> Result<()> {
+        self.flush_wrapper()?;
+        self.windows.set_size(lines, columns);
+        if let Some(ref mut wrapper) = self.wrapper {
+            wrapper.set_width(usize::from(columns));
+        }
+        self.update_page_lines();
+        Ok(())
+    }
+
+    // The status line is drawn in reverse video across the top of the
+    // terminal, leaving the cursor where it was. The first time, the
+    // scrolling region is set to start below it, so that game text never
+    // scrolls it away.
+    fn show_status(&mut self, location: &str, right: &str) -> Result<()> {
+        self.flush_wrapper()?;
+        if !self.status_shown {
+            print!("\x1b7\x1b[2r\x1b8");
+            self.status_shown = true;
+            self.update_page_lines();
+        }
+        let width = usize::from(self.windows.size().1);
+        let line = status::status_line(location, right, width);
+        print!("\x1b7\x1b[1;1H\x1b[0;7m{}\x1b8", line);
+        self.apply_attributes()?;
+        io::stdout().flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for StdoutScreen {
+    // Give the whole terminal back to the shell.
+    fn drop(&mut self) {
+        if self.status_shown {
+            print!("\x1b7\x1b[r\x1b8");
+            let _ = io::stdout().flush();
+        }
+    }
+}
+
+// A Screen for non-interactive use: plain text, with no escapes, no cursor
+// control and no paging, so that output can be piped or compared.
+//
+// Everything printed, in any window, goes to the writer in order. Styles,
+// colours and the status line are dropped.
+pub struct DumbScreen<W>
+where
+    W: Write,
+{
+    out: W,
+    windows: WindowModel,
+
+    // Present when buffering is on. Only the lower window is buffered.
+    wrapper: Option<WordWrapper>,
+}
+
+impl<W> DumbScreen<W>
+where
+    W: Write,
+{
+    pub fn new(version: ZVersion, out: W) -> DumbScreen<W> {
+        let windows = WindowModel::for_version(version);
+        let width = usize::from(windows.size().1);
+        DumbScreen {
+            out,
+            windows,
+            wrapper: Some(WordWrapper::new(width)),
+        }
+    }
+
+    fn flush_wrapper(&mut self) -> Result<()> {
+        if let Some(ref mut wrapper) = self.wrapper {
+            self.out.write_all(wrapper.flush().as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl<W> Screen for DumbScreen<W>
+where
+    W: Write,
+{
+    fn print(&mut self, text: &str) -> Result<()> {
+        self.windows.advance(text);
+        match self.wrapper {
+            Some(ref mut wrapper) if self.windows.current_window() == LOWER_WINDOW => {
+                self.out.write_all(wrapper.push(text).as_bytes())?
+            }
+            _ => self.out.write_all(text.as_bytes())?,
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.flush_wrapper()?;
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn set_buffer_mode(&mut self, buffered: bool) -> Result<()> {
+        self.flush_wrapper()?;
+        self.wrapper = if buffered {
+            Some(WordWrapper::new(usize::from(self.windows.size().1)))
+        } else {
+            None
+        };
+        Ok(())
+    }
+
+    fn set_text_style(&mut self, _style: TextStyle) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_colour(&mut self, _foreground: Colour, _background: Colour) -> Result<()> {
+        Ok(())
+    }
+
+    fn supports_colour(&self) -> bool {
+        false
+    }
+
+    fn split_window(&mut self, lines: u16) -> Result<()> {
+        self.windows.split(lines);
+        Ok(())
+    }
+
+    fn set_window(&mut self, window: u16) -> Result<()> {
+        self.flush_wrapper()?;
+        self.windows.select(window)
+    }
+
+    fn erase_window(&mut self, window: u16) -> Result<()> {
+        self.windows.erase(window);
+        Ok(())
+    }
+
+    fn erase_line(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_cursor(&mut self, line: u16, column: u16) -> Result<()> {
+        self.windows.set_cursor(line, column);
+        Ok(())
+    }
+
+    fn get_cursor(&self) -> (u16, u16) {
+        self.windows.cursor()
+    }
+
+    fn size(&self) -> (u16, u16) {
+        self.windows.size()
+    }
+
+    fn set_size(&mut self, lines: u16, columns: u16) -> Result<()> {
+        self.flush_wrapper()?;
+        self.windows.set_size(lines, columns);
+        if let Some(ref mut wrapper) = self.wrapper {
+            wrapper.set_width(usize::from(columns));
+        }
+        Ok(())
+    }
+
+    fn show_status(&mut self, _location: &str, _right: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+// The ANSI escape to select a colour. Base is 30 for foreground colours, and
+// 40 for background colours.
+// Reset, then turn on the style's attributes and the colours.
+// Italic is shown as underline, which more terminals support. Every
+// character on a terminal is the same width, so fixed pitch needs nothing.
+fn attribute_escape(style: TextStyle, foreground: Colour, background: Colour) -> String {
+    let mut escape = "\x1b[0".to_string();
+    if style.contains(TextStyle::BOLD) {
+        escape.push_str(";1");
+    }
+    if style.contains(TextStyle::ITALIC) {
+        escape.push_str(";4");
+    }
+    if style.contains(TextStyle::REVERSE) {
+        escape.push_str(";7");
+    }
+    escape.push('m');
+    if let Some(fg) = ansi_colour(foreground, 30) {
+        escape.push_str(&fg);
+    }
+    if let Some(bg) = ansi_colour(background, 40) {
+        escape.push_str(&bg);
+    }
+    escape
+}
+
+fn ansi_colour(colour: Colour, base: u8) -> Option<String> {
+    use self::Colour::*;
+    let code = match colour {
+        Current => return None,
+        Default => 9,
+        Black => 0,
+        Red => 1,
+        Green => 2,
+        Yellow => 3,
+        Blue => 4,
+        Magenta => 5,
+        Cyan => 6,
+        White => 7,
+        _ => {
+            let (r, g, b) = colour.rgb()?;
+            return Some(format!("\x1b[{};2;{};{};{}m", base + 8, r, g, b));
+        }
+    };
+    Some(format!("\x1b[{}m", base + code))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_apply_style() {
+        let style = TextStyle::ROMAN.apply(TextStyle::BOLD);
+        assert_eq!(TextStyle::BOLD, style);
+
+        let style = style.apply(TextStyle::REVERSE);
+        assert!(style.contains(TextStyle::BOLD));
+        assert!(style.contains(TextStyle::REVERSE));
+        assert!(!style.contains(TextStyle::ITALIC));
+
+        let style = style.apply(TextStyle::ROMAN);
+        assert_eq!(TextStyle::ROMAN, style);
+    }
+
+    #[test]
+    fn test_colour_palette() {
+        assert_eq!(Some(Colour::Current), Colour::from_palette(0));
+        assert_eq!(Some(Colour::Red), Colour::from_palette(3));
+        assert_eq!(Some(Colour::DarkGrey), Colour::from_palette(12));
+        assert_eq!(None, Colour::from_palette(13));
+
+        assert_eq!(Some(5), Colour::Yellow.palette_number());
+        assert_eq!(None, Colour::True(0x1234).palette_number());
+    }
+
+    #[test]
+    fn test_true_colour() {
+        assert_eq!(Some(Colour::Default), Colour::from_true(0xffff));
+        assert_eq!(Some(Colour::Current), Colour::from_true(0xfffe));
+        assert_eq!(None, Colour::from_true(0xfffd));
+        assert_eq!(None, Colour::from_true(0xfffc));
+        assert_eq!(None, Colour::from_true(0x8000));
+        assert_eq!(Some(Colour::True(0x001d)), Colour::from_true(0x001d));
+        assert_eq!(Some(Colour::True(0x7fff)), Colour::from_true(0x7fff));
+
+        assert_eq!(Some(0x7c1f), Colour::Magenta.true_colour());
+        assert_eq!(None, Colour::Default.true_colour());
+
+        assert_eq!(Some((255, 255, 255)), Colour::White.rgb());
+        assert_eq!(Some((0, 0, 255)), Colour::True(0x7c00).rgb());
+    }
+
+    #[test]
+    fn test_ansi_colour() {
+        assert_eq!(None, ansi_colour(Colour::Current, 30));
+        assert_eq!(
+            Some("\x1b[39m".to_string()),
+            ansi_colour(Colour::Default, 30)
+        );
+        assert_eq!(Some("\x1b[41m".to_string()), ansi_colour(Colour::Red, 40));
+        assert_eq!(
+            Some("\x1b[38;2;255;0;0m".to_string()),
+            ansi_colour(Colour::True(0x001f), 30)
+        );
+    }
+
+    #[test]
+    fn test_window_model() {
+        let mut windows = WindowModel::new();
+        assert_eq!(0, windows.upper_lines());
+        assert_eq!(LOWER_WINDOW, windows.current_window());
+
+        windows.split(3);
+        windows.select(UPPER_WINDOW).unwrap();
+        assert_eq!(3, windows.upper_lines());
+        assert_eq!(UPPER_WINDOW, windows.current_window());
+
+        match windows.select(2) {
+            Err(ZErr::UnknownWindow(2)) => (),
+            _ => panic!("Missing error"),
+        }
+    }
+
+    #[test]
+    fn test_window_size() {
+        let mut windows = WindowModel::new();
+        assert_eq!((255, 80), windows.size());
+
+        windows.set_size(24, 100);
+        windows.split(30);
+        assert_eq!(24, windows.upper_lines());
+
+        // Shrinking the screen shrinks the upper window with it.
+        windows.set_size(10, 40);
+        assert_eq!((10, 40), windows.size());
+        assert_eq!(10, windows.upper_lines());
+    }
+
+    #[test]
+    fn test_v6_windows() {
+        let mut windows = WindowModel::for_version(ZVersion::V6);
+        windows.select(7).unwrap();
+        assert_eq!(7, windows.current_window());
+        assert!(windows.set_cursor(3, 4));
+        assert!(windows.select(8).is_err());
+
+        // The cursor can be moved in any window, and isn't homed on selection.
+        windows.select(LOWER_WINDOW).unwrap();
+        assert!(windows.set_cursor(5, 5));
+        windows.select(7).unwrap();
+        assert_eq!((3, 4), windows.cursor());
+
+        assert!(WindowModel::for_version(ZVersion::V5).select(2).is_err());
+    }
+
+    #[test]
+    fn test_window_cursors() {
+        let mut windows = WindowModel::new();
+        windows.split(2);
+
+        // Cursor can't be moved in the lower window.
+        assert!(!windows.set_cursor(5, 5));
+        windows.advance("abc\nde");
+        assert_eq!((2, 3), windows.cursor());
+
+        windows.select(UPPER_WINDOW).unwrap();
+        assert_eq!((1, 1), windows.cursor());
+        assert!(windows.set_cursor(2, 10));
+        windows.advance("xy");
+        assert_eq!((2, 12), windows.cursor());
+
+        // Each window keeps its own cursor.
+        windows.select(LOWER_WINDOW).unwrap();
+        assert_eq!((2, 3), windows.cursor());
+        windows.erase(LOWER_WINDOW);
+        assert_eq!((1, 1), windows.cursor());
+
+        // Reselecting the upper window homes its cursor.
+        windows.select(UPPER_WINDOW).unwrap();
+        assert_eq!((1, 1), windows.cursor());
+    }
+
+    #[test]
+    fn test_window_colours() {
+        let mut windows = WindowModel::new();
+        assert_eq!((Colour::Default, Colour::Default), windows.colours());
+        windows.set_colours(Colour::Yellow, Colour::Blue);
+
+        windows.select(UPPER_WINDOW).unwrap();
+        assert_eq!((Colour::Default, Colour::Default), windows.colours());
+        windows.set_colours(Colour::Black, Colour::White);
+        windows.set_colours(Colour::Current, Colour::Cyan);
+        assert_eq!((Colour::Black, Colour::Cyan), windows.colours());
+
+        // Each window keeps its own colours.
+        windows.select(LOWER_WINDOW).unwrap();
+        assert_eq!((Colour::Yellow, Colour::Blue), windows.colours());
+    }
+
+    #[test]
+    fn test_attribute_escape() {
+        assert_eq!(
+            "\x1b[0m",
+            attribute_escape(TextStyle::ROMAN, Colour::Current, Colour::Current)
+        );
+        assert_eq!(
+            "\x1b[0;1;7m\x1b[32m\x1b[49m",
+            attribute_escape(
+                TextStyle::BOLD.apply(TextStyle::REVERSE),
+                Colour::Green,
+                Colour::Default
+            )
+        );
+    }
+
+    #[test]
+    fn test_dumb_screen() {
+        let mut screen = DumbScreen::new(ZVersion::V3, Vec::new());
+        screen.set_text_style(TextStyle::BOLD).unwrap();
+        screen.print("West of House\n").unwrap();
+        screen
+            .show_status("West of House", "Score: 0  Moves: 1")
+            .unwrap();
+        screen.print("You are standing").unwrap();
+        screen.flush().unwrap();
+
+        assert_eq!(
+            "West of House\nYou are standing",
+            String::from_utf8(screen.out).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_dumb_screen_set_size() {
+        let mut screen = DumbScreen::new(ZVersion::V5, Vec::new());
+        screen.set_size(24, 12).unwrap();
+        assert_eq!((24, 12), screen.size());
+
+        screen.print("Behind the white house").unwrap();
+        screen.flush().unwrap();
+        assert_eq!(
+            "Behind the\nwhite house",
+            String::from_utf8(screen.out).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_from_raw() {
+        assert_eq!(0b0110, TextStyle::from_raw(0b1111_0110).bits());
+    }
+}
@@ -0,0 +1,258 @@
+use std::io::{BufRead, Write};
+use std::time::Duration;
+
+use super::result::{Result, ZErr};
+use super::streams::trim_line_ending;
+use super::traits::Keyboard;
+
+// A session file holds what it takes to replay a run of a story exactly:
+// the random seed, and everything the keyboard returned, in order.
+//
+//   rzm2 session
+//   seed 1234
+//   timed 1
+//   line 13 open the mailbox
+//   line 0 op
+//   char 97
+//
+// "timed" is whether the keyboard supported timed input, since the header
+// tells the game so. Each "line" holds the terminator and then the text.
+// A timeout is recorded as input ending with terminator 0, so interrupt
+// routines run at the same points when the session is replayed. Replay
+// doesn't wait for the time to pass.
+const SESSION_HEADER: &str = "rzm2 session";
+
+// A keyboard that records everything read from another keyboard.
+pub struct SessionRecorder {
+    keyboard: Box<dyn Keyboard>,
+    writer: Box<dyn Write>,
+}
+
+impl SessionRecorder {
+    pub fn new(
+        keyboard: Box<dyn Keyboard>,
+        mut writer: Box<dyn Write>,
+        seed: u64,
+    ) -> Result<SessionRecorder> {
+        writeln!(writer, "{}", SESSION_HEADER)?;
+        writeln!(writer, "seed {}", seed)?;
+        writeln!(writer, "timed {}", keyboard.supports_timed_input() as u8)?;
+        writer.flush()?;
+        Ok(SessionRecorder { keyboard, writer })
+    }
+}
+
+impl Keyboard for SessionRecorder {
+    fn read_line(
+        &mut self,
+        typed: &str,
+        terminators: &[u8],
+        timeout: Option<Duration>,
+    ) -> Result<(String, u8)> {
+        let (line, terminator) = self.keyboard.read_line(typed, terminators, timeout)?;
+        writeln!(self.writer, "line {} {}", terminator, line)?;
+        self.writer.flush()?;
+        Ok((line, terminator))
+    }
+
+    fn read_char(&mut self, timeout: Option<Duration>) -> Result<u8> {
+        let key = self.keyboard.read_char(timeout)?;
+        writeln!(self.writer, "char {}", key)?;
+        self.writer.flush()?;
+        Ok(key)
+    }
+
+    fn supports_timed_input(&self) -> bool {
+        self.keyboard.supports_timed_input()
+    }
+}
+
+// A keyboard that gives back a recorded session, and then reads from
+// another keyboard once the recording runs out.
+//
+// The story must ask for input in the same order as when the session was
+// recorded. It will, if it is the same story started with the same seed.
+pub struct SessionReplay {
+    reader: Option<Box<dyn BufRead>>,
+    keyboard: Box<dyn Keyboard>,
+    seed: u64,
+    timed_input: bool,
+}
+
+impl SessionReplay {
+    pub fn new(mut reader: Box<dyn BufRead>, keyboard: Box<dyn Keyboard>) -> Result<SessionReplay> {
+        if next_line(&mut reader)?.as_deref() != Some(SESSION_HEADER) {
+            return Err(ZErr::GenericError("Not a session file"));
+        }
+        let seed = header_value(&mut reader, "seed")?
+            .parse()
+            .map_err(|_| ZErr::GenericError("Bad seed in session file"))?;
+        let timed_input = header_value(&mut reader, "timed")? == "1";
+        Ok(SessionReplay {
+            reader: Some(reader),
+            keyboard,
+            seed,
+            timed_input,
+        })
+    }
+
+    // The random seed that the session was recorded with.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // The next recorded input, as (kind, rest of the line). None once the
+    // recording is used up.
+    fn next_event(&mut self) -> Result<Option<(String, String)>> {
+        let line = match self.reader {
+            Some(ref mut reader) => next_line(reader)?,
+            None => None,
+        };
+        match line {
+            Some(line) => {
+                let mut parts = line.splitn(2, ' ');
+                let kind = parts.next().unwrap_or("").to_string();
+                Ok(Some((kind, parts.next().unwrap_or("").to_string())))
+            }
+            None => {
+                self.reader = None;
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl Keyboard for SessionReplay {
+    fn read_line(
+        &mut self,
+        typed: &str,
+        terminators: &[u8],
+        timeout: Option<Duration>,
+    ) -> Result<(String, u8)> {
+        match self.next_event()? {
+            None => self.keyboard.read_line(typed, terminators, timeout),
+            Some((ref kind, ref rest)) if kind == "line" => {
+                let mut parts = rest.splitn(2, ' ');
+                let terminator = parse_number(parts.next())?;
+                Ok((parts.next().unwrap_or("").to_string(), terminator))
+            }
+            Some(_) => Err(ZErr::GenericError("Session replay is out of step")),
+        }
+    }
+
+    fn read_char(&mut self, timeout: Option<Duration>) -> Result<u8> {
+        match self.next_event()? {
+            None => self.keyboard.read_char(timeout),
+            Some((ref kind, ref rest)) if kind == "char" => parse_number(Some(rest)),
+            Some(_) => Err(ZErr::GenericError("Session replay is out of step")),
+        }
+    }
+
+    // As when the session was recorded, since the game was told so.
+    fn supports_timed_input(&self) -> bool {
+        self.timed_input
+    }
+}
+
+fn next_line(reader: &mut Box<dyn BufRead>) -> Result<Option<String>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(trim_line_ending(line)))
+}
+
+fn header_value(reader: &mut Box<dyn BufRead>, key: &str) -> Result<String> {
+    let line = next_line(reader)?.unwrap_or_default();
+    let mut parts = line.splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(k), Some(value)) if k == key => Ok(value.to_string()),
+        _ => Err(ZErr::GenericError("Missing field in session file")),
+    }
+}
+
+fn parse_number(text: Option<&str>) -> Result<u8> {
+    text.and_then(|text| text.parse().ok())
+        .ok_or(ZErr::GenericError("Bad input in session file"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::zmachine::streams::LineKeyboard;
+
+    use std::fs::File;
+    use std::io::{BufReader, Cursor};
+
+    fn keyboard(text: &str) -> Box<dyn Keyboard> {
+        Box::new(LineKeyboard::new(Box::new(Cursor::new(text.to_string()))))
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let path = std::env::temp_dir().join("rzm2_test_record_and_replay.session");
+        {
+            let file = File::create(&path).unwrap();
+            let mut recorder =
+                SessionRecorder::new(keyboard("north\n yes \nsouth\n"), Box::new(file), 42)
+                    .unwrap();
+            assert_eq!(
+                ("north".to_string(), 13),
+                recorder.read_line("", &[], None).unwrap()
+            );
+            recorder.read_char(None).unwrap();
+            recorder.read_line("", &[], None).unwrap();
+        }
+
+        let reader = BufReader::new(File::open(&path).unwrap());
+        let mut replay = SessionReplay::new(Box::new(reader), keyboard("after\n")).unwrap();
+        assert_eq!(42, replay.seed());
+        assert!(!replay.supports_timed_input());
+        assert_eq!(
+            ("north".to_string(), 13),
+            replay.read_line("", &[], None).unwrap()
+        );
+        assert_eq!(b' ', replay.read_char(None).unwrap());
+        assert_eq!(
+            ("south".to_string(), 13),
+            replay.read_line("", &[], None).unwrap()
+        );
+        // Then the keyboard takes over.
+        assert_eq!(
+            ("after".to_string(), 13),
+            replay.read_line("", &[], None).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_replay_timeouts() {
+        let session = "rzm2 session\nseed 7\ntimed 1\nline 0 op\nline 13 open door\nchar 0\n";
+        let mut replay = SessionReplay::new(Box::new(Cursor::new(session)), keyboard("")).unwrap();
+        assert!(replay.supports_timed_input());
+        assert_eq!(
+            ("op".to_string(), 0),
+            replay.read_line("", &[], None).unwrap()
+        );
+        assert_eq!(
+            ("open door".to_string(), 13),
+            replay.read_line("op", &[], None).unwrap()
+        );
+        assert_eq!(0, replay.read_char(None).unwrap());
+    }
+
+    #[test]
+    fn test_replay_out_of_step() {
+        let session = "rzm2 session\nseed 7\ntimed 0\nchar 97\n";
+        let mut replay = SessionReplay::new(Box::new(Cursor::new(session)), keyboard("")).unwrap();
+        match replay.read_line("", &[], None) {
+            Err(ZErr::GenericError(_)) => (),
+            _ => panic!("Missing error"),
+        }
+    }
+
+    #[test]
+    fn test_not_a_session() {
+        let result = SessionReplay::new(Box::new(Cursor::new("north\n")), keyboard(""));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,118 @@
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+use super::addressing::{ByteAddress, ZOffset};
+use super::handle::Handle;
+use super::result::Result;
+use super::traits::{Memory, Stack, PC};
+
+// The state of a running machine: dynamic memory, the stack, and the pc.
+// Nothing else changes as a game runs, so restoring a snapshot returns the
+// machine to exactly where it was. The contents are opaque, and a snapshot
+// should only be restored into the machine that it came from.
+//
+// With the "serialize" feature, a snapshot can be stored with any serde
+// format, for embedders that keep game state somewhere other than a save
+// file.
+//
+// save_undo keeps one, whose pc is that of save_undo's store byte, so that
+// restore_undo can find the variable that should receive 2.
+// (ZSpec 15: save_undo)
+#[derive(Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct ZSnapshot<S>
+where
+    S: Stack + Clone,
+{
+    memory: Vec<u8>,
+    stack: S,
+    pc: usize,
+}
+
+impl<S> ZSnapshot<S>
+where
+    S: Stack + Clone,
+{
+    pub fn capture<M, P>(
+        memory: &Handle<M>,
+        static_memory_base: ByteAddress,
+        stack: &Handle<S>,
+        pc: &P,
+    ) -> ZSnapshot<S>
+    where
+        M: Memory,
+        P: PC,
+    {
+        ZSnapshot {
+            memory: read_dynamic_memory(memory, static_memory_base),
+            stack: stack.borrow().clone(),
+            pc: pc.current_pc(),
+        }
+    }
+
+    pub fn restore<M, P>(&self, memory: &Handle<M>, stack: &Handle<S>, pc: &mut P) -> Result<()>
+    where
+        M: Memory,
+        P: PC,
+    {
+        write_dynamic_memory(memory, &self.memory)?;
+        *stack.borrow_mut() = self.stack.clone();
+        pc.set_current_pc(self.pc);
+        Ok(())
+    }
+}
+
+// A copy of dynamic memory, which ends at the static memory base.
+pub fn read_dynamic_memory<M>(memory: &Handle<M>, static_memory_base: ByteAddress) -> Vec<u8>
+where
+    M: Memory,
+{
+    let memory = memory.borrow();
+    let origin = ZOffset::from(ByteAddress::from_raw(0));
+    (0..ZOffset::from(static_memory_base).value())
+        .map(|offset| memory.read_byte(origin.inc_by(offset)))
+        .collect()
+}
+
+// Replace dynamic memory, from the start, with `dynamic`.
+pub fn write_dynamic_memory<M>(memory: &Handle<M>, dynamic: &[u8]) -> Result<()>
+where
+    M: Memory,
+{
+    let origin = ZOffset::from(ByteAddress::from_raw(0));
+    let mut memory = memory.borrow_mut();
+    for (offset, byte) in dynamic.iter().enumerate() {
+        memory.write_interpreter_byte(origin.inc_by(offset), *byte)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::zmachine::fixtures::{TestMemory, TestPC, TestStack};
+    use crate::zmachine::handle::new_handle;
+
+    #[test]
+    fn test_capture_and_restore() {
+        let memory = new_handle(TestMemory::new(0x20));
+        let stack = new_handle(TestStack::new(0));
+        let mut pc = TestPC::new(0x100, vec![]);
+        memory.borrow_mut().bytes[0x04] = 3;
+        stack.borrow_mut().push_byte(7).unwrap();
+
+        let snapshot = ZSnapshot::capture(&memory, ByteAddress::from_raw(0x10), &stack, &pc);
+
+        memory.borrow_mut().bytes[0x04] = 4;
+        memory.borrow_mut().bytes[0x18] = 5;
+        stack.borrow_mut().push_byte(8).unwrap();
+        pc.set_current_pc(0x200);
+
+        snapshot.restore(&memory, &stack, &mut pc).unwrap();
+        assert_eq!(3, memory.borrow().bytes[0x04]);
+        // Only dynamic memory is captured.
+        assert_eq!(5, memory.borrow().bytes[0x18]);
+        assert_eq!(vec![7], stack.borrow().arr);
+        assert_eq!(0x100, pc.current_pc());
+    }
+}
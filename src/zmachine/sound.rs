@@ -0,0 +1,81 @@
+use std::io::{self, Write};
+
+use log::debug;
+
+use super::addressing::RoutinePackedAddress;
+use super::result::Result;
+use super::traits::SoundPlayer;
+
+// Sound numbers 1 and 2 are built-in "bleeps". All other numbers refer to
+// sampled sounds supplied with the story. (ZSpec 9.2)
+pub const HIGH_BLEEP: u16 = 1;
+pub const LOW_BLEEP: u16 = 2;
+
+// The effect operand of sound_effect. (ZSpec 9.2.1)
+pub const EFFECT_PREPARE: u16 = 1;
+pub const EFFECT_START: u16 = 2;
+pub const EFFECT_STOP: u16 = 3;
+pub const EFFECT_FINISH_WITH: u16 = 4;
+
+// The volume operand packs the volume (1-8, or 255 for loudest) in the low
+// byte and, in V5+, the number of repeats in the high byte. (ZSpec 9.2.3)
+pub fn unpack_volume(word: u16) -> (u8, u8) {
+    ((word & 0xff) as u8, (word >> 8) as u8)
+}
+
+// A SoundPlayer for frontends without sound support.
+//
+// Bleeps ring the terminal bell. Sampled sounds are silently ignored, which
+// is permitted by the spec. (ZSpec 9.6)
+#[derive(Default)]
+pub struct BellSoundPlayer {}
+
+impl BellSoundPlayer {
+    pub fn new() -> BellSoundPlayer {
+        BellSoundPlayer::default()
+    }
+}
+
+impl SoundPlayer for BellSoundPlayer {
+    fn bleep(&mut self, _high: bool) -> Result<()> {
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x07")?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    fn prepare(&mut self, number: u16) -> Result<()> {
+        debug!("Ignoring request to prepare sound {}", number);
+        Ok(())
+    }
+
+    fn start(
+        &mut self,
+        number: u16,
+        _volume: u8,
+        _repeats: u8,
+        _routine: Option<RoutinePackedAddress>,
+    ) -> Result<()> {
+        debug!("Ignoring request to start sound {}", number);
+        Ok(())
+    }
+
+    fn stop(&mut self, _number: u16) -> Result<()> {
+        Ok(())
+    }
+
+    fn finish_with(&mut self, _number: u16) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unpack_volume() {
+        assert_eq!((8, 0), unpack_volume(0x0008));
+        assert_eq!((0xff, 3), unpack_volume(0x03ff));
+    }
+}
@@ -1,8 +1,14 @@
+#[cfg(feature = "serialize")]
+use serde::de::{self, Deserialize, Deserializer};
+#[cfg(feature = "serialize")]
+use serde::ser::{Serialize, Serializer};
+
 use super::constants;
 use super::opcode::ZVariable;
 use super::result::{Result, ZErr};
 use super::traits::{bytes, Stack};
 
+#[derive(Clone)]
 pub struct ZStack {
     stack: [u8; constants::STACK_SIZE],
 
@@ -20,7 +26,10 @@ pub struct ZStack {
 //                     (The top frame has STACK_SIZE here.)
 //   return_pc:  u32 - Next pc value after returning.
 //   return_var: u8  - Encoded ZVariable for return value.
-//   num_locals: u8  - Number of local variables on the stack. (0-14)
+//   num_locals: u8  - Number of local variables on the stack (0-15) in the low
+//                     nibble, and number of arguments passed (0-7) in the next
+//                     three bits. The top bit is set if the result is discarded,
+//                     in which case return_var is unused.
 //   locals: u16     - One of these for each local, so up to 14.
 //
 // NOTE: we can probably save one byte/frame (and preserve word-alignment) by
@@ -33,6 +42,8 @@ impl ZStack {
     const NUM_LOCALS_OFFSET: usize = 7;
     const LOCAL_VAR_OFFSET: usize = 8;
 
+    const DISCARD_RESULT_BIT: u8 = 0x80;
+
     pub fn new() -> ZStack {
         let mut zs = ZStack {
             stack: [0; constants::STACK_SIZE],
@@ -71,7 +82,7 @@ impl ZStack {
     }
 
     pub fn num_locals(&self) -> u8 {
-        bytes::byte_from_slice(&self.stack, self.fp + ZStack::NUM_LOCALS_OFFSET).into()
+        bytes::byte_from_slice(&self.stack, self.fp + ZStack::NUM_LOCALS_OFFSET) & 0x0f
     }
 
     fn push_addr(&mut self, addr: usize) -> Result<()> {
@@ -102,6 +113,23 @@ impl Stack for ZStack {
         }
     }
 
+    fn peek_word(&self) -> Result<u16> {
+        if self.sp >= self.s0 + 2 {
+            Ok(bytes::word_from_slice(&self.stack, self.sp - 2))
+        } else {
+            Err(ZErr::StackUnderflow("Peeked word on empty stack."))
+        }
+    }
+
+    fn poke_word(&mut self, word: u16) -> Result<()> {
+        if self.sp >= self.s0 + 2 {
+            bytes::word_to_slice(&mut self.stack, self.sp - 2, word);
+            Ok(())
+        } else {
+            Err(ZErr::StackUnderflow("Poked word on empty stack."))
+        }
+    }
+
     fn read_local(&self, l: u8) -> Result<u16> {
         if l >= self.num_locals() {
             Err(ZErr::LocalOutOfRange(l, self.num_locals()))
@@ -130,16 +158,41 @@ impl Stack for ZStack {
         bytes::long_word_from_slice(&self.stack, self.fp + ZStack::RETURN_PC_OFFSET) as usize
     }
 
-    fn return_variable(&self) -> ZVariable {
-        bytes::byte_from_slice(&self.stack, self.fp + ZStack::RETURN_VAR_OFFSET).into()
+    fn return_variable(&self) -> Option<ZVariable> {
+        let num_locals = bytes::byte_from_slice(&self.stack, self.fp + ZStack::NUM_LOCALS_OFFSET);
+        if num_locals & ZStack::DISCARD_RESULT_BIT != 0 {
+            return None;
+        }
+        Some(bytes::byte_from_slice(&self.stack, self.fp + ZStack::RETURN_VAR_OFFSET).into())
+    }
+
+    fn num_args(&self) -> u8 {
+        (bytes::byte_from_slice(&self.stack, self.fp + ZStack::NUM_LOCALS_OFFSET) >> 4) & 0x07
+    }
+
+    fn frame_depth(&self) -> usize {
+        let mut depth = 0;
+        let mut fp = self.fp;
+        loop {
+            let saved_fp = usize::from(bytes::word_from_slice(
+                &self.stack,
+                fp + ZStack::SAVED_PC_OFFSET,
+            ));
+            if saved_fp >= constants::STACK_SIZE {
+                return depth;
+            }
+            depth += 1;
+            fp = saved_fp;
+        }
     }
 
     fn push_frame(
         &mut self,
         return_pc: usize,
         num_locals: u8,
-        return_var: ZVariable,
+        return_var: Option<ZVariable>,
         operands: &[u16],
+        num_args: u8,
     ) -> Result<()> {
         // Steps:
         // - save sp to new_fp
@@ -157,8 +210,17 @@ impl Stack for ZStack {
         self.fp = new_fp;
         self.push_addr(return_pc)?;
         // TODO: figure out that AsRef thing here.
-        self.push_byte(u8::from(return_var))?;
-        self.push_byte(num_locals)?;
+        let discard = match return_var {
+            Some(variable) => {
+                self.push_byte(u8::from(variable))?;
+                0
+            }
+            None => {
+                self.push_byte(0)?;
+                ZStack::DISCARD_RESULT_BIT
+            }
+        };
+        self.push_byte(num_locals | num_args << 4 | discard)?;
         for _ in 0..num_locals {
             self.push_word(0)?;
         }
@@ -196,6 +258,158 @@ impl Stack for ZStack {
 
         Ok(())
     }
+
+    // Each Quetzal frame has a 3-byte return pc; the number of locals, with
+    // bit 4 set if the result is discarded; the result variable; a byte with a
+    // bit set for each argument supplied; the number of words on the
+    // routine's stack; then the locals and the stack. The base frame is
+    // Quetzal's dummy frame, which has no return pc or result.
+    fn quetzal_frames(&self) -> Vec<u8> {
+        // The frames are linked from the newest, so find them all first.
+        let mut fps = vec![];
+        let mut fp = self.fp;
+        while fp < constants::STACK_SIZE {
+            fps.push(fp);
+            fp = usize::from(bytes::word_from_slice(
+                &self.stack,
+                fp + ZStack::SAVED_PC_OFFSET,
+            ));
+        }
+        fps.reverse();
+
+        let mut data = vec![];
+        for (idx, &fp) in fps.iter().enumerate() {
+            let flags = self.stack[fp + ZStack::NUM_LOCALS_OFFSET];
+            let num_locals = flags & 0x0f;
+            let num_args = (flags >> 4) & 0x07;
+            let discard = flags & ZStack::DISCARD_RESULT_BIT != 0;
+            let return_pc = bytes::long_word_from_slice(&self.stack, fp + ZStack::RETURN_PC_OFFSET);
+
+            data.extend_from_slice(&return_pc.to_be_bytes()[1..]);
+            data.push(num_locals | if discard { 0x10 } else { 0 });
+            data.push(if discard || idx == 0 {
+                0
+            } else {
+                self.stack[fp + ZStack::RETURN_VAR_OFFSET]
+            });
+            data.push(((1u16 << num_args) - 1) as u8);
+
+            let locals = fp + ZStack::LOCAL_VAR_OFFSET;
+            let end = fps.get(idx + 1).copied().unwrap_or(self.sp);
+            let num_words = (end - locals) / 2 - usize::from(num_locals);
+            data.extend_from_slice(&(num_words as u16).to_be_bytes());
+            data.extend_from_slice(&self.stack[locals..end]);
+        }
+        data
+    }
+
+    fn restore_quetzal_frames(&mut self, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            return Err(ZErr::BadSaveFile("Stks chunk has no frames"));
+        }
+
+        // Build a new stack, so that a bad chunk leaves this one as it was.
+        let mut stack = ZStack::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            if offset + 8 > data.len() {
+                return Err(ZErr::BadSaveFile("Stks chunk ends inside a frame"));
+            }
+            let frame = &data[offset..];
+            let num_locals = frame[3] & 0x0f;
+            let num_words = usize::from(num_locals) + usize::from(bytes::word_from_slice(frame, 6));
+            let end = offset + 8 + 2 * num_words;
+            if end > data.len() {
+                return Err(ZErr::BadSaveFile("Stks chunk ends inside a frame"));
+            }
+            let words = data[offset + 8..end]
+                .chunks(2)
+                .map(|word| bytes::word_from_slice(word, 0))
+                .collect::<Vec<_>>();
+            let (locals, values) = words.split_at(usize::from(num_locals));
+
+            if offset == 0 {
+                if num_locals != 0 {
+                    return Err(ZErr::BadSaveFile("Stks dummy frame has locals"));
+                }
+            } else {
+                let return_pc = bytes::long_word_from_slice(&[0, frame[0], frame[1], frame[2]], 0);
+                let return_var = if frame[3] & 0x10 != 0 {
+                    None
+                } else {
+                    Some(ZVariable::from(frame[4]))
+                };
+                let num_args = (frame[5].trailing_ones() as u8).min(7);
+                stack.push_frame(return_pc as usize, num_locals, return_var, locals, num_args)?;
+            }
+            for value in values {
+                stack.push_word(*value)?;
+            }
+            offset = end;
+        }
+
+        *self = stack;
+        Ok(())
+    }
+}
+
+// Only the part of the stack in use is serialized. The frame pointers are
+// checked when deserializing, so that a damaged snapshot can't point outside
+// the stack.
+#[cfg(feature = "serialize")]
+#[derive(serde::Serialize)]
+struct ZStackRef<'a> {
+    bytes: &'a [u8],
+    fp: usize,
+    s0: usize,
+}
+
+#[cfg(feature = "serialize")]
+#[derive(serde::Deserialize)]
+struct ZStackData {
+    bytes: Vec<u8>,
+    fp: usize,
+    s0: usize,
+}
+
+#[cfg(feature = "serialize")]
+impl Serialize for ZStack {
+    fn serialize<Ser>(&self, serializer: Ser) -> std::result::Result<Ser::Ok, Ser::Error>
+    where
+        Ser: Serializer,
+    {
+        ZStackRef {
+            bytes: &self.stack[..self.sp],
+            fp: self.fp,
+            s0: self.s0,
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<'de> Deserialize<'de> for ZStack {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<ZStack, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let data = ZStackData::deserialize(deserializer)?;
+        let sp = data.bytes.len();
+        if sp > constants::STACK_SIZE
+            || data.s0 > sp
+            || data.fp + ZStack::LOCAL_VAR_OFFSET > data.s0
+        {
+            return Err(de::Error::custom("stack frame pointers are out of range"));
+        }
+        let mut stack = ZStack {
+            stack: [0; constants::STACK_SIZE],
+            fp: data.fp,
+            s0: data.s0,
+            sp,
+        };
+        stack.stack[..sp].copy_from_slice(&data.bytes);
+        Ok(stack)
+    }
 }
 
 #[cfg(test)]
@@ -239,12 +453,13 @@ mod test {
         let old_fp = stack.fp;
 
         stack
-            .push_frame(0xbabef00d, 5, ZVariable::Global(3), &[34, 38])
+            .push_frame(0xbabef00d, 5, Some(ZVariable::Global(3)), &[34, 38], 2)
             .unwrap();
 
         assert_eq!(old_fp, stack.saved_fp());
+        assert_eq!(2, stack.num_args());
         assert_eq!(0xbabef00d, stack.return_pc());
-        assert_eq!(ZVariable::Global(3), stack.return_variable());
+        assert_eq!(Some(ZVariable::Global(3)), stack.return_variable());
         assert_eq!(5, stack.num_locals());
         assert_eq!(34, stack.read_local(0).unwrap());
         assert_eq!(38, stack.read_local(1).unwrap());
@@ -258,7 +473,7 @@ mod test {
         let mut stack = ZStack::new();
 
         stack
-            .push_frame(0xbabef00d, 2, ZVariable::Stack, &[11, 24, 36, 48])
+            .push_frame(0xbabef00d, 2, Some(ZVariable::Stack), &[11, 24, 36, 48], 0)
             .unwrap();
 
         assert_eq!(2, stack.num_locals());
@@ -271,7 +486,7 @@ mod test {
         let mut stack = ZStack::new();
 
         stack
-            .push_frame(0xbabef00d, 1, ZVariable::Stack, &[22])
+            .push_frame(0xbabef00d, 1, Some(ZVariable::Stack), &[22], 0)
             .unwrap();
 
         assert_eq!(22, stack.read_local(0).unwrap());
@@ -288,17 +503,17 @@ mod test {
 
         let saved_fp1 = stack.fp;
         stack
-            .push_frame(0xbabef00d, 5, ZVariable::Global(3), &[34, 38])
+            .push_frame(0xbabef00d, 5, Some(ZVariable::Global(3)), &[34, 38], 0)
             .unwrap();
 
         let saved_fp2 = stack.fp;
         stack
-            .push_frame(0x12345678, 7, ZVariable::Local(5), &[1, 3, 5])
+            .push_frame(0x12345678, 7, Some(ZVariable::Local(5)), &[1, 3, 5], 0)
             .unwrap();
 
         assert_eq!(saved_fp2, stack.saved_fp());
         assert_eq!(0x12345678, stack.return_pc());
-        assert_eq!(ZVariable::Local(5), stack.return_variable());
+        assert_eq!(Some(ZVariable::Local(5)), stack.return_variable());
         assert_eq!(7, stack.num_locals());
         assert_eq!(1, stack.read_local(0).unwrap());
         assert_eq!(3, stack.read_local(1).unwrap());
@@ -312,7 +527,7 @@ mod test {
 
         assert_eq!(saved_fp1, stack.saved_fp());
         assert_eq!(0xbabef00d, stack.return_pc());
-        assert_eq!(ZVariable::Global(3), stack.return_variable());
+        assert_eq!(Some(ZVariable::Global(3)), stack.return_variable());
         assert_eq!(5, stack.num_locals());
         assert_eq!(34, stack.read_local(0).unwrap());
         assert_eq!(38, stack.read_local(1).unwrap());
@@ -323,19 +538,46 @@ mod test {
         stack.pop_frame().unwrap();
     }
 
+    #[test]
+    fn test_discarded_result() {
+        let mut stack = ZStack::new();
+        stack.push_frame(0xbabef00d, 3, None, &[34, 38], 7).unwrap();
+        assert_eq!(None, stack.return_variable());
+        assert_eq!(3, stack.num_locals());
+        assert_eq!(7, stack.num_args());
+        assert_eq!(38, stack.read_local(1).unwrap());
+    }
+
+    #[test]
+    fn test_frame_depth() {
+        let mut stack = ZStack::new();
+        assert_eq!(0, stack.frame_depth());
+
+        stack
+            .push_frame(0xbabef00d, 5, Some(ZVariable::Global(3)), &[34, 38], 0)
+            .unwrap();
+        stack
+            .push_frame(0x12345678, 0, Some(ZVariable::Stack), &[], 0)
+            .unwrap();
+        assert_eq!(2, stack.frame_depth());
+
+        stack.pop_frame().unwrap();
+        assert_eq!(1, stack.frame_depth());
+    }
+
     #[test]
     fn test_push_pop_stack_values() {
         let mut stack = ZStack::new();
 
         stack
-            .push_frame(0xbabef00d, 5, ZVariable::Global(3), &[34, 38])
+            .push_frame(0xbabef00d, 5, Some(ZVariable::Global(3)), &[34, 38], 0)
             .unwrap();
         stack.push_word(34).unwrap();
         stack.push_word(4832).unwrap();
         stack.push_word(137).unwrap();
 
         stack
-            .push_frame(0x12345678, 7, ZVariable::Local(5), &[1, 3, 5])
+            .push_frame(0x12345678, 7, Some(ZVariable::Local(5)), &[1, 3, 5], 0)
             .unwrap();
         stack.push_word(99).unwrap();
         stack.push_word(1293).unwrap();
@@ -356,6 +598,26 @@ mod test {
         assert_eq!(34, stack.pop_word().unwrap());
     }
 
+    #[test]
+    fn test_peek_and_poke() {
+        let mut stack = ZStack::new();
+
+        stack
+            .push_frame(0xbabef00d, 2, Some(ZVariable::Global(3)), &[], 0)
+            .unwrap();
+        assert!(stack.peek_word().is_err());
+        assert!(stack.poke_word(1).is_err());
+
+        stack.push_word(34).unwrap();
+        stack.push_word(4832).unwrap();
+        assert_eq!(4832, stack.peek_word().unwrap());
+
+        stack.poke_word(137).unwrap();
+        assert_eq!(137, stack.peek_word().unwrap());
+        assert_eq!(137, stack.pop_word().unwrap());
+        assert_eq!(34, stack.pop_word().unwrap());
+    }
+
     #[test]
     fn test_pop_missing_stack_frame() {
         let mut stack = ZStack::new();
@@ -372,10 +634,12 @@ mod test {
 
         // 42 stack frames is as many as fit on the current sized frame.
         for _ in 0..42 {
-            stack.push_frame(0x1000, 8, ZVariable::Stack, &[]).unwrap();
+            stack
+                .push_frame(0x1000, 8, Some(ZVariable::Stack), &[], 0)
+                .unwrap();
         }
 
-        match stack.push_frame(0x2000, 8, ZVariable::Stack, &[]) {
+        match stack.push_frame(0x2000, 8, Some(ZVariable::Stack), &[], 0) {
             Err(ZErr::StackOverflow(_)) => {}
             Err(e) => panic!("Wrong error: {:?}", e),
             Ok(_) => panic!("Missing error"),
@@ -388,7 +652,9 @@ mod test {
 
         // 42 stack frames is as many as fit on the current sized frame.
         for _ in 0..42 {
-            stack.push_frame(0x1000, 8, ZVariable::Stack, &[]).unwrap();
+            stack
+                .push_frame(0x1000, 8, Some(ZVariable::Stack), &[], 0)
+                .unwrap();
         }
 
         // Then, we can fit 4 more words.
@@ -415,7 +681,7 @@ mod test {
         assert_eq!(stack.sp, stack.s0 + 4);
 
         stack
-            .push_frame(0xabcdef00, 4, ZVariable::Stack, &[])
+            .push_frame(0xabcdef00, 4, Some(ZVariable::Stack), &[], 0)
             .unwrap();
         stack.pop_frame().unwrap();
 
@@ -436,7 +702,7 @@ mod test {
         let mut stack = ZStack::new();
 
         stack
-            .push_frame(0x12213443, 4, ZVariable::Stack, &[])
+            .push_frame(0x12213443, 4, Some(ZVariable::Stack), &[], 0)
             .unwrap();
 
         stack.write_local(0, 0x11).unwrap();
@@ -461,4 +727,62 @@ mod test {
             Ok(_) => panic!("Missing error"),
         }
     }
+
+    #[test]
+    fn test_quetzal_frames() {
+        let mut stack = ZStack::new();
+        stack.push_word(0x0102).unwrap();
+        stack
+            .push_frame(0x012345, 2, Some(ZVariable::Global(3)), &[34], 1)
+            .unwrap();
+        stack.push_word(0x0304).unwrap();
+        stack.push_frame(0x543210, 1, None, &[5, 6], 2).unwrap();
+
+        let data = stack.quetzal_frames();
+        #[rustfmt::skip]
+        assert_eq!(
+            vec![
+                0, 0, 0, 0, 0, 0, 0, 1, 0x01, 0x02,
+                0x01, 0x23, 0x45, 0x02, 0x13, 0x01, 0, 1, 0, 34, 0, 0, 0x03, 0x04,
+                0x54, 0x32, 0x10, 0x11, 0, 0x03, 0, 0, 0, 5,
+            ],
+            data
+        );
+
+        let mut restored = ZStack::new();
+        restored.restore_quetzal_frames(&data).unwrap();
+        assert_eq!(2, restored.frame_depth());
+        assert_eq!(None, restored.return_variable());
+        assert_eq!(0x543210, restored.return_pc());
+        assert_eq!(2, restored.num_args());
+        assert_eq!(5, restored.read_local(0).unwrap());
+
+        restored.pop_frame().unwrap();
+        assert_eq!(Some(ZVariable::Global(3)), restored.return_variable());
+        assert_eq!(1, restored.num_args());
+        assert_eq!(34, restored.read_local(0).unwrap());
+        assert_eq!(0x0304, restored.pop_word().unwrap());
+
+        restored.pop_frame().unwrap();
+        assert_eq!(0x0102, restored.pop_word().unwrap());
+    }
+
+    #[test]
+    fn test_bad_quetzal_frames() {
+        let mut stack = ZStack::new();
+        stack
+            .push_frame(0x012345, 1, Some(ZVariable::Stack), &[7], 1)
+            .unwrap();
+
+        // A truncated chunk, or a dummy frame with locals, changes nothing.
+        for data in &[
+            vec![],
+            vec![0, 0, 0, 0, 0, 0, 0, 1],
+            vec![0, 0, 0, 1, 0, 0, 0, 0, 0, 9],
+        ] {
+            assert!(stack.restore_quetzal_frames(data).is_err());
+            assert_eq!(1, stack.frame_depth());
+            assert_eq!(7, stack.read_local(0).unwrap());
+        }
+    }
 }
@@ -0,0 +1,74 @@
+// The V1-3 status line. (ZSpec 8.2)
+//
+// The left side shows the name of the object in global 0 (the location).
+// The right side shows either the score and number of moves, or, in time
+// games, the time of day.
+
+pub fn score_text(score: i16, moves: u16) -> String {
+    format!("Score: {}  Moves: {}", score, moves)
+}
+
+// Time games keep the hour (0-23) in global 1, and the minutes in global 2.
+// (ZSpec 8.2.3.2)
+pub fn time_text(hours: u16, minutes: u16, twenty_four_hour: bool) -> String {
+    let hours = hours % 24;
+    if twenty_four_hour {
+        format!("Time: {:02}:{:02}", hours, minutes)
+    } else {
+        let suffix = if hours < 12 { "am" } else { "pm" };
+        let hours = match hours % 12 {
+            0 => 12,
+            h => h,
+        };
+        format!("Time: {}:{:02} {}", hours, minutes, suffix)
+    }
+}
+
+// Lay out a complete status line of exactly width characters. The location
+// is truncated if there isn't room for both sides.
+pub fn status_line(location: &str, right: &str, width: usize) -> String {
+    let right_len = right.chars().count();
+    let room = width.saturating_sub(right_len + 3);
+    let location: String = location.chars().take(room).collect();
+
+    let mut line = format!(" {}", location);
+    let padding = width.saturating_sub(line.chars().count() + right_len + 1);
+    line.push_str(&" ".repeat(padding));
+    line.push_str(right);
+    line.push(' ');
+    line
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_score_text() {
+        assert_eq!("Score: 10  Moves: 3", score_text(10, 3));
+        assert_eq!("Score: -5  Moves: 0", score_text(-5, 0));
+    }
+
+    #[test]
+    fn test_time_text() {
+        assert_eq!("Time: 12:05 am", time_text(0, 5, false));
+        assert_eq!("Time: 9:30 am", time_text(9, 30, false));
+        assert_eq!("Time: 12:00 pm", time_text(12, 0, false));
+        assert_eq!("Time: 11:59 pm", time_text(23, 59, false));
+
+        assert_eq!("Time: 00:05", time_text(0, 5, true));
+        assert_eq!("Time: 23:59", time_text(23, 59, true));
+    }
+
+    #[test]
+    fn test_status_line() {
+        assert_eq!(
+            " West of House        Score: 0  Moves: 1 ",
+            status_line("West of House", "Score: 0  Moves: 1", 41)
+        );
+        assert_eq!(
+            " West  Time: 1:00 pm ",
+            status_line("West of House", "Time: 1:00 pm", 21)
+        );
+    }
+}
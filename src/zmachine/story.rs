@@ -1,24 +1,253 @@
-use std::io::Read;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Stdout};
 
-use super::addressing::ZPC;
+use super::addressing::{ByteAddress, ZOffset, ZPC};
+use super::audit::ZAuditLog;
+use super::check::{self, ZProblem};
+use super::dictionary::ZDictionary;
+use super::disasm;
 use super::handle::new_handle;
 use super::header::ZHeader;
 use super::memory::ZMemory;
-use super::processor::ZProcessor;
+use super::objects::ZObjectTable;
+use super::opcode::ZVariable;
+use super::options::ZOptions;
+use super::processor::{ZComponents, ZProcessor};
+use super::random::{self, ZRandom};
 use super::result::Result;
+use super::screen::{DumbScreen, StdoutScreen};
+use super::session::{SessionRecorder, SessionReplay};
+use super::sound::BellSoundPlayer;
 use super::stack::ZStack;
-use super::traits::Header;
+use super::streams::{
+    ZInputStreams, ZOutputStreams, COMMAND_STREAM, DEFAULT_COMMAND_SCRIPT_FILE,
+    DEFAULT_TRANSCRIPT_FILE, TRANSCRIPT_STREAM,
+};
+#[cfg(feature = "terminal")]
+use super::terminal::{TerminalKeyboard, TerminalScreen};
+use super::traits::{Header, Memory, OutputStreams, Screen, Stack, PC};
 use super::variables::ZVariables;
+use super::version::ZVersion;
+use super::violations::ZViolations;
+use super::zscii::ZTextDecoder;
 
+use log::warn;
+
+// A processor built from the standard components, drawing on the screen Scr.
+pub type StoryProcessor<Scr> = ZProcessor<
+    BellSoundPlayer,
+    ZHeader,
+    ZInputStreams,
+    ZMemory,
+    ZOutputStreams<ZMemory, Scr>,
+    ZPC<ZMemory>,
+    ZStack,
+    Scr,
+    ZVariables<ZMemory, ZStack>,
+>;
+
+// Plain text on stdout, with commands read a line at a time from stdin.
 pub fn new_story_processor<T: Read>(
     rdr: &mut T,
-) -> Result<ZProcessor<ZHeader, ZMemory, ZPC<ZMemory>, ZStack, ZVariables<ZMemory, ZStack>>> {
+    options: ZOptions,
+) -> Result<StoryProcessor<StdoutScreen>> {
+    let input = ZInputStreams::new(Box::new(BufReader::new(io::stdin())));
+    build_processor(
+        rdr,
+        options,
+        |version| Ok(StdoutScreen::new(version)),
+        input,
+    )
+}
+
+// Plain text only, for piping walkthroughs through the interpreter.
+pub fn new_dumb_story_processor<T: Read>(
+    rdr: &mut T,
+    options: ZOptions,
+) -> Result<StoryProcessor<DumbScreen<Stdout>>> {
+    let input = ZInputStreams::new(Box::new(BufReader::new(io::stdin())));
+    build_processor(
+        rdr,
+        options,
+        |version| Ok(DumbScreen::new(version, io::stdout())),
+        input,
+    )
+}
+
+// A full screen terminal, with keys read as they are pressed.
+#[cfg(feature = "terminal")]
+pub fn new_terminal_story_processor<T: Read>(
+    rdr: &mut T,
+    options: ZOptions,
+) -> Result<StoryProcessor<TerminalScreen>> {
+    let input = ZInputStreams::with_keyboard(Box::new(TerminalKeyboard::new()));
+    build_processor(rdr, options, TerminalScreen::new, input)
+}
+
+// The screen can't be made until the story's version is known.
+fn build_processor<T, Scr, F>(
+    rdr: &mut T,
+    options: ZOptions,
+    new_screen: F,
+    mut input: ZInputStreams,
+) -> Result<StoryProcessor<Scr>>
+where
+    T: Read,
+    Scr: Screen,
+    F: FnOnce(ZVersion) -> Result<Scr>,
+{
     let (story_h, header) = ZMemory::new(rdr)?;
-    // TODO: For V6, you will need to treat the start_pc as a PackedAddress.
-    let pc = ZPC::new(&story_h, header.start_pc());
+    let checksum = story_h.borrow().checksum();
+    let header_checksum = header.checksum();
+    if header_checksum != 0 && header_checksum != checksum {
+        warn!(
+            "Story checksum is 0x{:04x}, but the header says 0x{:04x}",
+            checksum, header_checksum
+        );
+    }
+    let mut pc = ZPC::new(&story_h, header.start_pc());
     let stack_h = new_handle(ZStack::new());
 
+    // In V6, execution starts by calling the main routine, with no arguments.
+    // It is illegal to return from it, so the frame's return values don't matter.
+    // (ZSpec 5.5)
+    if header.version_number() == ZVersion::V6 {
+        pc = ZPC::new(&story_h, header.main_routine());
+        let num_locals = pc.next_byte();
+        stack_h
+            .borrow_mut()
+            .push_frame(0, num_locals, Some(ZVariable::Stack), &[0; 15], 0)?;
+    }
+
     let variables = ZVariables::new(header.global_location(), story_h.clone(), stack_h.clone());
+    let text = new_handle(ZTextDecoder::new(&header, &story_h));
+    let objects = ZObjectTable::new(&header, &story_h, &text);
+
+    let mut screen = new_screen(header.version_number())?;
+    if let Some((lines, columns)) = options.screen_size {
+        screen.set_size(lines, columns)?;
+    }
+    let screen_h = new_handle(screen);
+    let mut streams = ZOutputStreams::new(story_h.clone(), screen_h.clone());
+    match options.transcript {
+        Some(ref path) => {
+            streams.set_transcript_file(path);
+            streams.select_stream(TRANSCRIPT_STREAM, None)?;
+        }
+        None => streams.set_transcript_file(DEFAULT_TRANSCRIPT_FILE),
+    }
+    match options.command_script {
+        Some(ref path) => {
+            streams.set_command_script_file(path);
+            streams.select_stream(COMMAND_STREAM, None)?;
+        }
+        None => streams.set_command_script_file(DEFAULT_COMMAND_SCRIPT_FILE),
+    }
+    if let Some(ref path) = options.command_file {
+        input.play_back(path)?;
+    }
+    let sound = BellSoundPlayer::new();
+
+    // A replayed session brings its own seed. Recording while replaying
+    // copies the replayed input into the new session.
+    let mut seed = options.random_seed.unwrap_or_else(random::seed_from_time);
+    if let Some(ref path) = options.replay_session {
+        let reader = BufReader::new(File::open(path)?);
+        input = input.map_keyboard(|keyboard| {
+            let replay = SessionReplay::new(Box::new(reader), keyboard)?;
+            seed = replay.seed();
+            Ok(Box::new(replay))
+        })?;
+    }
+    if let Some(ref path) = options.record_session {
+        let writer = File::create(path)?;
+        input = input.map_keyboard(|keyboard| {
+            Ok(Box::new(SessionRecorder::new(
+                keyboard,
+                Box::new(writer),
+                seed,
+            )?))
+        })?;
+    }
+
+    let audit = if options.memory_audit > 0 {
+        let audit = new_handle(ZAuditLog::new(options.memory_audit));
+        story_h.borrow_mut().set_audit(Some(audit.clone()));
+        Some(audit)
+    } else {
+        None
+    };
+
+    let mut processor = ZProcessor::new(ZComponents {
+        memory: story_h,
+        header,
+        pc,
+        stack: stack_h,
+        variables,
+        objects,
+        text,
+        streams,
+        input,
+        sound,
+        screen: screen_h,
+    });
+    processor.audit = audit;
+    processor.violations = ZViolations::new(options.strict);
+    processor.options = options;
+    processor.random = ZRandom::new(seed);
+    processor.verified = header_checksum == checksum;
+    processor.initialize_header()?;
+    Ok(processor)
+}
+
+// A description of a story's header, for tools that show it.
+pub fn story_header<T: Read>(rdr: &mut T) -> Result<String> {
+    let (_, header) = ZMemory::new(rdr)?;
+    Ok(header.report())
+}
+
+// A listing of a story's dictionary, for tools that show it.
+pub fn story_dictionary<T: Read>(rdr: &mut T) -> Result<String> {
+    let (story_h, header) = ZMemory::new(rdr)?;
+    let dictionary = ZDictionary::from_header(&header, &*story_h.borrow());
+    dictionary.report(&header, &story_h)
+}
+
+// A disassembly of the routines reachable from the start of a story, for
+// tools that show it.
+pub fn story_disassembly<T: Read>(rdr: &mut T) -> Result<String> {
+    let (story_h, header) = ZMemory::new(rdr)?;
+    let routines = disasm::disassemble(&header, &story_h)?;
+    Ok(routines
+        .iter()
+        .map(|routine| routine.to_string())
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+// A listing of a story's objects, for tools that show them.
+pub fn story_objects<T: Read>(rdr: &mut T) -> Result<String> {
+    let (story_h, header) = ZMemory::new(rdr)?;
+    let text = new_handle(ZTextDecoder::new(&header, &story_h));
+    ZObjectTable::new(&header, &story_h, &text).report()
+}
 
-    Ok(ZProcessor::new(story_h, header, pc, stack_h, variables))
+// Everything that a static look over a story finds wrong with it, for tools
+// that validate stories.
+pub fn story_problems<T: Read>(rdr: &mut T) -> Result<Vec<ZProblem>> {
+    let (story_h, header) = ZMemory::new(rdr)?;
+    Ok(check::check(&header, &story_h))
+}
+
+// The strings in a story's high memory, with their addresses, for tools
+// that list them.
+pub fn story_strings<T: Read>(rdr: &mut T) -> Result<Vec<(usize, String)>> {
+    let (story_h, header) = ZMemory::new(rdr)?;
+    let start = ZOffset::from(header.high_memory_base());
+    let end = ZOffset::from(ByteAddress::from_raw(0)).inc_by(story_h.borrow().memory_size());
+    let strings = ZTextDecoder::new(&header, &story_h).scan_strings(start, end)?;
+    Ok(strings
+        .into_iter()
+        .map(|(offset, text)| (offset.value(), text))
+        .collect())
 }
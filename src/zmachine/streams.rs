@@ -0,0 +1,763 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::warn;
+
+use super::addressing::ByteAddress;
+use super::handle::Handle;
+use super::header::{self, FLAGS2_FORCE_FIXED_PITCH, FLAGS2_TRANSCRIPTING};
+use super::result::{Result, ZErr};
+use super::screen::TextStyle;
+use super::traits::{InputStreams, Keyboard, Memory, OutputStreams, Screen};
+use super::zscii::{char_to_zscii, key_to_zscii, zscii_to_char, ZSCII_NEWLINE};
+
+// Output stream numbers. (ZSpec 7.1.1)
+pub const SCREEN_STREAM: u8 = 1;
+pub const TRANSCRIPT_STREAM: u8 = 2;
+pub const MEMORY_STREAM: u8 = 3;
+pub const COMMAND_STREAM: u8 = 4;
+
+// Input stream numbers. (ZSpec 10.2)
+pub const KEYBOARD_STREAM: u8 = 0;
+pub const COMMAND_FILE_STREAM: u8 = 1;
+
+// Where the transcript and the command script go, unless the user picks
+// other files.
+pub const DEFAULT_TRANSCRIPT_FILE: &str = "transcript.txt";
+pub const DEFAULT_COMMAND_SCRIPT_FILE: &str = "commands.rec";
+
+// Output stream 3 redirects output into a table in dynamic memory.
+// The first word of the table holds the number of characters written.
+// The characters themselves follow. (ZSpec 7.1.2.1)
+//
+// Stream 3 may be selected again while it is already selected, and the
+// new table is used until it is deselected. Nesting is limited to 16
+// levels. (ZSpec 7.1.2.1.1)
+const MAX_MEMORY_STREAMS: usize = 16;
+
+struct MemoryStream {
+    table: ByteAddress,
+    count: u16,
+}
+
+// Manages the four output streams, and routes text to the selected ones.
+// See ZSpec 7.
+//
+//   1: the screen
+//   2: the transcript
+//   3: a table in memory
+//   4: the command script (only player input is sent to this stream)
+//
+// The command script holds one command per line, so that it can be played
+// back through input stream 1. A key read by read_char is recorded as a line
+// holding just that key, and the return key as an empty line.
+//
+// While stream 3 is selected, text is sent _only_ to stream 3. (ZSpec 7.1.2.2)
+//
+// The transcript's selection mirrors bit 0 of Flags 2, which the game may
+// flip directly, so the bit is checked before any text is sent. (ZSpec 7.3)
+// Bit 1 of Flags 2 is checked the same way, and while it is set the screen
+// shows text in fixed pitch whatever the style. (ZSpec 11.1.2)
+pub struct ZOutputStreams<M, Scr>
+where
+    M: Memory,
+    Scr: Screen,
+{
+    memory: Handle<M>,
+    screen: Handle<Scr>,
+
+    screen_selected: bool,
+    transcript_selected: bool,
+    memory_streams: Vec<MemoryStream>,
+    command_selected: bool,
+
+    transcript: Option<Box<dyn Write>>,
+    transcript_path: Option<PathBuf>,
+    command_script: Option<Box<dyn Write>>,
+    command_script_path: Option<PathBuf>,
+
+    text_style: TextStyle,
+    // The style last sent to the screen, including any forced fixed pitch.
+    screen_style: TextStyle,
+}
+
+impl<M, Scr> ZOutputStreams<M, Scr>
+where
+    M: Memory,
+    Scr: Screen,
+{
+    pub fn new(memory: Handle<M>, screen: Handle<Scr>) -> ZOutputStreams<M, Scr> {
+        ZOutputStreams {
+            memory,
+            screen,
+
+            // Only the screen is selected at startup. (ZSpec 7.1.1)
+            screen_selected: true,
+            transcript_selected: false,
+            memory_streams: Vec::new(),
+            command_selected: false,
+
+            transcript: None,
+            transcript_path: None,
+            command_script: None,
+            command_script_path: None,
+
+            text_style: TextStyle::ROMAN,
+            screen_style: TextStyle::ROMAN,
+        }
+    }
+
+    pub fn text_style(&self) -> TextStyle {
+        self.text_style
+    }
+
+    // Text sent to stream 2 will be written to this writer.
+    pub fn set_transcript_writer(&mut self, writer: Box<dyn Write>) {
+        self.transcript = Some(writer);
+    }
+
+    // The transcript file is opened, for appending, the first time stream 2
+    // is selected. (ZSpec 7.1.1.2)
+    pub fn set_transcript_file<T: AsRef<Path>>(&mut self, path: T) {
+        self.transcript_path = Some(path.as_ref().to_path_buf());
+    }
+
+    // Player input sent to stream 4 will be written to this writer.
+    pub fn set_command_script_writer(&mut self, writer: Box<dyn Write>) {
+        self.command_script = Some(writer);
+    }
+
+    // Like the transcript, the command script is opened for appending the
+    // first time stream 4 is selected.
+    pub fn set_command_script_file<T: AsRef<Path>>(&mut self, path: T) {
+        self.command_script_path = Some(path.as_ref().to_path_buf());
+    }
+
+    pub fn is_selected(&self, stream: u8) -> bool {
+        match stream {
+            SCREEN_STREAM => self.screen_selected,
+            TRANSCRIPT_STREAM => self.transcript_selected,
+            MEMORY_STREAM => !self.memory_streams.is_empty(),
+            COMMAND_STREAM => self.command_selected,
+            _ => false,
+        }
+    }
+
+    fn print_to_memory(&mut self, text: &str) -> Result<()> {
+        // Only the most recently selected table receives text.
+        if let Some(ms) = self.memory_streams.last_mut() {
+            let mut memory = self.memory.borrow_mut();
+            for ch in text.chars() {
+                // Newlines are stored as ZSCII 13. (ZSpec 7.1.2.2.1)
+                let zscii = char_to_zscii(&*memory, ch).unwrap_or(b'?');
+                memory.write_byte(ms.table.inc_by(2 + ms.count), zscii)?;
+                ms.count += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_transcript_selected(&mut self, selected: bool) -> Result<()> {
+        if selected && self.transcript.is_none() {
+            if let Some(ref path) = self.transcript_path {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                self.transcript = Some(Box::new(file));
+            }
+        }
+        self.transcript_selected = selected;
+        header::set_flags2(&self.memory, FLAGS2_TRANSCRIPTING, selected)
+    }
+
+    fn set_command_selected(&mut self, selected: bool) -> Result<()> {
+        if selected && self.command_script.is_none() {
+            if let Some(ref path) = self.command_script_path {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                self.command_script = Some(Box::new(file));
+            }
+        }
+        self.command_selected = selected;
+        Ok(())
+    }
+
+    // Pick up any change that the game made to the transcripting bit.
+    fn sync_transcript(&mut self) -> Result<()> {
+        let bit_set = header::read_flags2(&self.memory) & FLAGS2_TRANSCRIPTING != 0;
+        if bit_set != self.transcript_selected {
+            self.set_transcript_selected(bit_set)?;
+        }
+        Ok(())
+    }
+
+    // Send the style to the screen, if it changed or the game flipped the
+    // fixed pitch bit.
+    fn sync_style(&mut self) -> Result<()> {
+        let mut style = self.text_style;
+        if header::read_flags2(&self.memory) & FLAGS2_FORCE_FIXED_PITCH != 0 {
+            style = style.apply(TextStyle::FIXED);
+        }
+        if style != self.screen_style {
+            self.screen.borrow_mut().set_text_style(style)?;
+            self.screen_style = style;
+        }
+        Ok(())
+    }
+
+    fn print_to_transcript(&mut self, text: &str) -> Result<()> {
+        match self.transcript {
+            Some(ref mut writer) => writer.write_all(text.as_bytes())?,
+            None => warn!("Transcript stream selected, but no transcript file is available."),
+        }
+        Ok(())
+    }
+}
+
+impl<M, Scr> OutputStreams for ZOutputStreams<M, Scr>
+where
+    M: Memory,
+    Scr: Screen,
+{
+    fn select_stream(&mut self, stream: u8, table: Option<ByteAddress>) -> Result<()> {
+        match stream {
+            SCREEN_STREAM => self.screen_selected = true,
+            TRANSCRIPT_STREAM => self.set_transcript_selected(true)?,
+            MEMORY_STREAM => {
+                let table = table.ok_or(ZErr::MissingOperand)?;
+                if self.memory_streams.len() >= MAX_MEMORY_STREAMS {
+                    return Err(ZErr::StackOverflow("memory streams"));
+                }
+                self.memory_streams.push(MemoryStream { table, count: 0 });
+            }
+            COMMAND_STREAM => self.set_command_selected(true)?,
+            _ => return Err(ZErr::UnknownOutputStream(stream)),
+        }
+        Ok(())
+    }
+
+    fn deselect_stream(&mut self, stream: u8) -> Result<()> {
+        match stream {
+            SCREEN_STREAM => self.screen_selected = false,
+            TRANSCRIPT_STREAM => self.set_transcript_selected(false)?,
+            MEMORY_STREAM => {
+                if let Some(ms) = self.memory_streams.pop() {
+                    self.memory.borrow_mut().write_word(ms.table, ms.count)?;
+                }
+            }
+            COMMAND_STREAM => self.set_command_selected(false)?,
+            _ => return Err(ZErr::UnknownOutputStream(stream)),
+        }
+        Ok(())
+    }
+
+    fn set_text_style(&mut self, style: TextStyle) -> Result<()> {
+        self.text_style = self.text_style.apply(style);
+        self.sync_style()
+    }
+
+    fn print_str(&mut self, text: &str) -> Result<()> {
+        self.sync_transcript()?;
+        if !self.memory_streams.is_empty() {
+            return self.print_to_memory(text);
+        }
+
+        if self.screen_selected {
+            self.sync_style()?;
+            self.screen.borrow_mut().print(text)?;
+        }
+        if self.transcript_selected {
+            self.print_to_transcript(text)?;
+        }
+        Ok(())
+    }
+
+    fn record_line(&mut self, line: &str) -> Result<()> {
+        if !self.command_selected {
+            return Ok(());
+        }
+        match self.command_script {
+            Some(ref mut writer) => {
+                writer.write_all(line.as_bytes())?;
+                writer.write_all(b"\n")?;
+                writer.flush()?;
+            }
+            None => warn!("Command stream selected, but no command script is available."),
+        }
+        Ok(())
+    }
+
+    fn record_key(&mut self, key: u8) -> Result<()> {
+        if key == ZSCII_NEWLINE {
+            self.record_line("")
+        } else {
+            let ch = zscii_to_char(&*self.memory.borrow(), u16::from(key));
+            self.record_line(&ch.to_string())
+        }
+    }
+}
+
+// A keyboard that reads whole lines from a reader, such as stdin.
+//
+// Input is line based, so function keys can never be typed, and input is
+// always terminated by a newline. Blocking reads can't time out.
+pub struct LineKeyboard {
+    reader: Box<dyn BufRead>,
+}
+
+impl LineKeyboard {
+    pub fn new(reader: Box<dyn BufRead>) -> LineKeyboard {
+        LineKeyboard { reader }
+    }
+}
+
+impl Keyboard for LineKeyboard {
+    fn read_line(
+        &mut self,
+        typed: &str,
+        _terminators: &[u8],
+        _timeout: Option<Duration>,
+    ) -> Result<(String, u8)> {
+        let mut line = typed.to_string();
+        self.reader.read_line(&mut line)?;
+        Ok((trim_line_ending(line), ZSCII_NEWLINE))
+    }
+
+    // Keypresses aren't available, so this reads a whole line, and returns
+    // its first character.
+    fn read_char(&mut self, timeout: Option<Duration>) -> Result<u8> {
+        let (line, _) = self.read_line("", &[], timeout)?;
+        Ok(first_char(&line))
+    }
+
+    // Reading blocks until a whole line arrives.
+    fn supports_timed_input(&self) -> bool {
+        false
+    }
+}
+
+// Manages the two input streams. (ZSpec 10.2)
+//
+//   0: the keyboard
+//   1: a file of commands, one per line
+//
+// When the command file runs out, input reverts to the keyboard. Any text
+// file with one command per line can be played back, including a command
+// script recorded through output stream 4.
+pub struct ZInputStreams {
+    current: u8,
+
+    keyboard: Box<dyn Keyboard>,
+    command_file: Option<Box<dyn BufRead>>,
+}
+
+impl ZInputStreams {
+    // Read the keyboard a line at a time from reader.
+    pub fn new(reader: Box<dyn BufRead>) -> ZInputStreams {
+        ZInputStreams::with_keyboard(Box::new(LineKeyboard::new(reader)))
+    }
+
+    pub fn with_keyboard(keyboard: Box<dyn Keyboard>) -> ZInputStreams {
+        ZInputStreams {
+            current: KEYBOARD_STREAM,
+            keyboard,
+            command_file: None,
+        }
+    }
+
+    // Replace the keyboard with one built around it, such as a recorder.
+    pub fn map_keyboard<F>(mut self, f: F) -> Result<ZInputStreams>
+    where
+        F: FnOnce(Box<dyn Keyboard>) -> Result<Box<dyn Keyboard>>,
+    {
+        self.keyboard = f(self.keyboard)?;
+        Ok(self)
+    }
+
+    // Commands will be read from this reader whenever stream 1 is selected.
+    pub fn set_command_file(&mut self, reader: Box<dyn BufRead>) {
+        self.command_file = Some(reader);
+    }
+
+    // Start reading commands from the file at path.
+    pub fn play_back<T: AsRef<Path>>(&mut self, path: T) -> Result<()> {
+        let file = File::open(path)?;
+        self.set_command_file(Box::new(BufReader::new(file)));
+        self.select_input_stream(COMMAND_FILE_STREAM)
+    }
+
+    // Read the next command, if stream 1 is selected. When the command file
+    // is exhausted, go back to the keyboard.
+    fn read_from_command_file(&mut self) -> Result<Option<String>> {
+        if self.current != COMMAND_FILE_STREAM {
+            return Ok(None);
+        }
+        if let Some(ref mut reader) = self.command_file {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? > 0 {
+                return Ok(Some(trim_line_ending(line)));
+            }
+        }
+        self.command_file = None;
+        self.current = KEYBOARD_STREAM;
+        Ok(None)
+    }
+}
+
+impl InputStreams for ZInputStreams {
+    fn select_input_stream(&mut self, stream: u8) -> Result<()> {
+        match stream {
+            KEYBOARD_STREAM => self.current = KEYBOARD_STREAM,
+            COMMAND_FILE_STREAM => {
+                if self.command_file.is_some() {
+                    self.current = COMMAND_FILE_STREAM;
+                } else {
+                    warn!("No command file available. Reading from the keyboard.");
+                }
+            }
+            _ => return Err(ZErr::UnknownInputStream(stream)),
+        }
+        Ok(())
+    }
+
+    fn current_stream(&self) -> u8 {
+        self.current
+    }
+
+    // Lines in the command file always end with a newline.
+    fn read_line(
+        &mut self,
+        typed: &str,
+        terminators: &[u8],
+        timeout: Option<Duration>,
+    ) -> Result<(String, u8)> {
+        match self.read_from_command_file()? {
+            Some(line) => Ok((format!("{}{}", typed, line), ZSCII_NEWLINE)),
+            None => self.keyboard.read_line(typed, terminators, timeout),
+        }
+    }
+
+    // Each line of the command file holds one key.
+    fn read_char(&mut self, timeout: Option<Duration>) -> Result<u8> {
+        match self.read_from_command_file()? {
+            Some(line) => Ok(first_char(&line)),
+            None => self.keyboard.read_char(timeout),
+        }
+    }
+
+    fn supports_timed_input(&self) -> bool {
+        self.keyboard.supports_timed_input()
+    }
+}
+
+// The key that a line of text stands for: its first character, or a newline
+// if it is empty. Characters that can't be typed as ZSCII become '?'.
+fn first_char(line: &str) -> u8 {
+    match line.chars().next() {
+        Some(ch) => key_to_zscii(ch).unwrap_or(b'?'),
+        None => ZSCII_NEWLINE,
+    }
+}
+
+pub fn trim_line_ending(mut line: String) -> String {
+    while line.ends_with('\n') || line.ends_with('\r') {
+        line.pop();
+    }
+    line
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::zmachine::fixtures::{TestMemory, TestScreen};
+    use crate::zmachine::handle::new_handle;
+
+    use std::cell::RefCell;
+    use std::io::{self, Cursor};
+    use std::rc::Rc;
+
+    // A Write that can be inspected after it is handed to the streams.
+    #[derive(Clone, Default)]
+    struct SharedWriter(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn make_streams() -> (
+        Handle<TestMemory>,
+        Handle<TestScreen>,
+        ZOutputStreams<TestMemory, TestScreen>,
+    ) {
+        let memory = new_handle(TestMemory::new(0x100));
+        let screen = new_handle(TestScreen::new());
+        let streams = ZOutputStreams::new(memory.clone(), screen.clone());
+        (memory, screen, streams)
+    }
+
+    #[test]
+    fn test_initial_selection() {
+        let (_, _, streams) = make_streams();
+
+        assert!(streams.is_selected(SCREEN_STREAM));
+        assert!(!streams.is_selected(TRANSCRIPT_STREAM));
+        assert!(!streams.is_selected(MEMORY_STREAM));
+        assert!(!streams.is_selected(COMMAND_STREAM));
+    }
+
+    #[test]
+    fn test_memory_stream() {
+        let (memory, _, mut streams) = make_streams();
+        let table = ByteAddress::from_raw(0x40);
+
+        streams.select_stream(MEMORY_STREAM, Some(table)).unwrap();
+        streams.print_str("ab\ncé").unwrap();
+        streams.deselect_stream(MEMORY_STREAM).unwrap();
+
+        assert_eq!(5, memory.borrow().read_word(table));
+        assert_eq!(
+            vec![b'a', b'b', 13, b'c', 170],
+            memory.borrow().bytes[0x42..0x47].to_vec()
+        );
+        assert!(!streams.is_selected(MEMORY_STREAM));
+    }
+
+    #[test]
+    fn test_memory_stream_requires_table() {
+        let (_, _, mut streams) = make_streams();
+        match streams.select_stream(MEMORY_STREAM, None) {
+            Err(ZErr::MissingOperand) => (),
+            _ => panic!("Missing error"),
+        }
+    }
+
+    #[test]
+    fn test_memory_stream_nesting() {
+        let (memory, _, mut streams) = make_streams();
+        let outer = ByteAddress::from_raw(0x40);
+        let inner = ByteAddress::from_raw(0x60);
+
+        streams.select_stream(MEMORY_STREAM, Some(outer)).unwrap();
+        streams.print_str("ab").unwrap();
+        streams.select_stream(MEMORY_STREAM, Some(inner)).unwrap();
+        streams.print_str("xyz").unwrap();
+        streams.deselect_stream(MEMORY_STREAM).unwrap();
+        assert!(streams.is_selected(MEMORY_STREAM));
+        streams.print_str("c").unwrap();
+        streams.deselect_stream(MEMORY_STREAM).unwrap();
+        assert!(!streams.is_selected(MEMORY_STREAM));
+
+        let memory = memory.borrow();
+        assert_eq!(3, memory.read_word(outer));
+        assert_eq!(b"abc".to_vec(), memory.bytes[0x42..0x45].to_vec());
+        assert_eq!(3, memory.read_word(inner));
+        assert_eq!(b"xyz".to_vec(), memory.bytes[0x62..0x65].to_vec());
+    }
+
+    #[test]
+    fn test_memory_stream_nesting_limit() {
+        let (_, _, mut streams) = make_streams();
+        for i in 0..MAX_MEMORY_STREAMS {
+            let table = ByteAddress::from_raw(0x20 + i as u16 * 8);
+            streams.select_stream(MEMORY_STREAM, Some(table)).unwrap();
+        }
+        match streams.select_stream(MEMORY_STREAM, Some(ByteAddress::from_raw(0xc0))) {
+            Err(ZErr::StackOverflow(_)) => (),
+            _ => panic!("Missing error"),
+        }
+    }
+
+    #[test]
+    fn test_memory_stream_captures_exclusively() {
+        let (_, _, mut streams) = make_streams();
+        let writer = SharedWriter::default();
+        streams.set_transcript_writer(Box::new(writer.clone()));
+        streams.select_stream(TRANSCRIPT_STREAM, None).unwrap();
+
+        streams.print_str("one").unwrap();
+        streams
+            .select_stream(MEMORY_STREAM, Some(ByteAddress::from_raw(0x40)))
+            .unwrap();
+        streams.print_str("two").unwrap();
+        streams.deselect_stream(MEMORY_STREAM).unwrap();
+        streams.print_str("three").unwrap();
+
+        assert_eq!(b"onethree".to_vec(), *writer.0.borrow());
+    }
+
+    #[test]
+    fn test_transcript_sets_flags2() {
+        let (memory, _, mut streams) = make_streams();
+        let writer = SharedWriter::default();
+        streams.set_transcript_writer(Box::new(writer.clone()));
+
+        streams.select_stream(TRANSCRIPT_STREAM, None).unwrap();
+        assert_eq!(FLAGS2_TRANSCRIPTING, header::read_flags2(&memory));
+        streams.print_str("one").unwrap();
+
+        streams.deselect_stream(TRANSCRIPT_STREAM).unwrap();
+        assert_eq!(0, header::read_flags2(&memory));
+        streams.print_str("two").unwrap();
+
+        assert_eq!(b"one".to_vec(), *writer.0.borrow());
+    }
+
+    #[test]
+    fn test_transcript_follows_flags2() {
+        let (memory, _, mut streams) = make_streams();
+        let writer = SharedWriter::default();
+        streams.set_transcript_writer(Box::new(writer.clone()));
+
+        header::set_flags2(&memory, FLAGS2_TRANSCRIPTING, true).unwrap();
+        streams.print_str("on").unwrap();
+        assert!(streams.is_selected(TRANSCRIPT_STREAM));
+
+        header::set_flags2(&memory, FLAGS2_TRANSCRIPTING, false).unwrap();
+        streams.print_str("off").unwrap();
+        assert!(!streams.is_selected(TRANSCRIPT_STREAM));
+
+        assert_eq!(b"on".to_vec(), *writer.0.borrow());
+    }
+
+    #[test]
+    fn test_command_stream() {
+        let (_, screen, mut streams) = make_streams();
+        let writer = SharedWriter::default();
+        streams.set_command_script_writer(Box::new(writer.clone()));
+
+        streams.record_line("ignored").unwrap();
+        streams.select_stream(COMMAND_STREAM, None).unwrap();
+        streams.print_str("Output is not recorded.").unwrap();
+        streams.record_line("open mailbox").unwrap();
+        streams.record_key(b'y').unwrap();
+        streams.record_key(ZSCII_NEWLINE).unwrap();
+        streams.deselect_stream(COMMAND_STREAM).unwrap();
+        streams.record_line("ignored").unwrap();
+
+        assert_eq!(b"open mailbox\ny\n\n".to_vec(), *writer.0.borrow());
+        assert_eq!("Output is not recorded.", screen.borrow().output);
+    }
+
+    #[test]
+    fn test_screen_stream() {
+        let (_, screen, mut streams) = make_streams();
+
+        streams.print_str("shown").unwrap();
+        streams.deselect_stream(SCREEN_STREAM).unwrap();
+        streams.print_str("hidden").unwrap();
+
+        assert_eq!("shown", screen.borrow().output);
+    }
+
+    #[test]
+    fn test_text_style() {
+        let (_, screen, mut streams) = make_streams();
+
+        streams.set_text_style(TextStyle::BOLD).unwrap();
+        streams.set_text_style(TextStyle::ITALIC).unwrap();
+        assert_eq!(0b0110, streams.text_style().bits());
+        assert_eq!(0b0110, screen.borrow().style.bits());
+
+        streams.set_text_style(TextStyle::ROMAN).unwrap();
+        assert_eq!(TextStyle::ROMAN, screen.borrow().style);
+    }
+
+    #[test]
+    fn test_forced_fixed_pitch() {
+        let (memory, screen, mut streams) = make_streams();
+        streams.set_text_style(TextStyle::BOLD).unwrap();
+
+        header::set_flags2(&memory, FLAGS2_FORCE_FIXED_PITCH, true).unwrap();
+        streams.print_str("fixed").unwrap();
+        assert_eq!(0b1010, screen.borrow().style.bits());
+        // The game's own style doesn't include the forced fixed pitch.
+        assert_eq!(TextStyle::BOLD, streams.text_style());
+
+        streams.set_text_style(TextStyle::ROMAN).unwrap();
+        assert_eq!(TextStyle::FIXED, screen.borrow().style);
+
+        header::set_flags2(&memory, FLAGS2_FORCE_FIXED_PITCH, false).unwrap();
+        streams.print_str("proportional").unwrap();
+        assert_eq!(TextStyle::ROMAN, screen.borrow().style);
+    }
+
+    #[test]
+    fn test_unknown_stream() {
+        let (_, _, mut streams) = make_streams();
+        match streams.select_stream(5, None) {
+            Err(ZErr::UnknownOutputStream(5)) => (),
+            _ => panic!("Missing error"),
+        }
+    }
+
+    #[test]
+    fn test_input_defaults_to_keyboard() {
+        let mut input = ZInputStreams::new(Box::new(Cursor::new("look\n")));
+        assert_eq!(KEYBOARD_STREAM, input.current_stream());
+        assert_eq!("look", input.read_line("", &[], None).unwrap().0);
+    }
+
+    #[test]
+    fn test_read_line_continues_typed_text() {
+        let mut input = ZInputStreams::new(Box::new(Cursor::new("th\n")));
+        assert!(!input.supports_timed_input());
+        assert_eq!(
+            ("north".to_string(), ZSCII_NEWLINE),
+            input.read_line("nor", &[], None).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_read_char() {
+        let mut input = ZInputStreams::new(Box::new(Cursor::new("yes\n\nÑo\n")));
+        assert_eq!(b'y', input.read_char(None).unwrap());
+        // The rest of the line is discarded, and an empty line is a newline.
+        assert_eq!(ZSCII_NEWLINE, input.read_char(None).unwrap());
+        assert_eq!(209, input.read_char(None).unwrap());
+    }
+
+    #[test]
+    fn test_command_file_falls_back_to_keyboard() {
+        let mut input = ZInputStreams::new(Box::new(Cursor::new("inventory\n")));
+        input.set_command_file(Box::new(Cursor::new("north\r\nopen door\n")));
+        input.select_input_stream(COMMAND_FILE_STREAM).unwrap();
+
+        assert_eq!("north", input.read_line("", &[], None).unwrap().0);
+        assert_eq!("open door", input.read_line("", &[], None).unwrap().0);
+        assert_eq!(COMMAND_FILE_STREAM, input.current_stream());
+
+        assert_eq!("inventory", input.read_line("", &[], None).unwrap().0);
+        assert_eq!(KEYBOARD_STREAM, input.current_stream());
+    }
+
+    #[test]
+    fn test_play_back() {
+        let path = std::env::temp_dir().join("rzm2_test_play_back.rec");
+        std::fs::write(&path, "take lamp\n").unwrap();
+
+        let mut input = ZInputStreams::new(Box::new(Cursor::new("quit\n")));
+        input.play_back(&path).unwrap();
+        assert_eq!(COMMAND_FILE_STREAM, input.current_stream());
+        assert_eq!("take lamp", input.read_line("", &[], None).unwrap().0);
+        assert_eq!("quit", input.read_line("", &[], None).unwrap().0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_command_file_missing() {
+        let mut input = ZInputStreams::new(Box::new(Cursor::new("")));
+        input.select_input_stream(COMMAND_FILE_STREAM).unwrap();
+        assert_eq!(KEYBOARD_STREAM, input.current_stream());
+
+        match input.select_input_stream(2) {
+            Err(ZErr::UnknownInputStream(2)) => (),
+            _ => panic!("Missing error"),
+        }
+    }
+}
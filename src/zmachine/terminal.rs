@@ -0,0 +1,541 @@
+use std::io::{self, Stdout, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::{MoveLeft, MoveTo, RestorePosition, SavePosition};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{
+    Attribute, Color, Print, SetAttribute, SetBackgroundColor, SetForegroundColor,
+};
+use crossterm::terminal::{self, Clear, ClearType};
+use crossterm::{execute, queue};
+
+use super::editor::LineEditor;
+use super::pager::Pager;
+use super::result::Result;
+use super::screen::{Colour, TextStyle, WindowModel, LOWER_WINDOW, UPPER_WINDOW};
+use super::status;
+use super::traits::{Keyboard, Screen};
+use super::version::ZVersion;
+use super::wrap::WordWrapper;
+use super::zscii::{key_to_zscii, ZKey, ZSCII_CURSOR_UP, ZSCII_NEWLINE};
+
+// A Screen that drives the terminal with crossterm.
+//
+// The terminal is divided into rows, from the top:
+//
+//   the status line (V1-3 only)
+//   the upper window, which never scrolls, and is addressed by set_cursor
+//   the lower window, which scrolls, and is set as the scrolling region
+//
+// The terminal remembers the cursor position in the lower window while text
+// is sent to the upper window.
+pub struct TerminalScreen {
+    out: Stdout,
+
+    windows: WindowModel,
+    pager: Pager,
+    wrapper: Option<WordWrapper>,
+
+    // 1 if the top row holds the status line.
+    status_rows: u16,
+    // Whether the size follows the terminal's, or was set by set_size.
+    follow_terminal: bool,
+
+    style: TextStyle,
+}
+
+impl TerminalScreen {
+    pub fn new(version: ZVersion) -> Result<TerminalScreen> {
+        let (columns, lines) = terminal::size()?;
+        let mut windows = WindowModel::for_version(version);
+        windows.set_size(lines, columns);
+
+        let mut screen = TerminalScreen {
+            out: io::stdout(),
+            windows,
+            pager: Pager::new(Some(0)),
+            // Buffering is on by default. (ZSpec 7.2.1)
+            wrapper: Some(WordWrapper::new(usize::from(columns))),
+            status_rows: if version <= ZVersion::V3 { 1 } else { 0 },
+            follow_terminal: true,
+            style: TextStyle::ROMAN,
+        };
+        execute!(screen.out, Clear(ClearType::All))?;
+        screen.layout()?;
+        let lower_top = screen.lower_top();
+        execute!(screen.out, MoveTo(0, lower_top))?;
+        Ok(screen)
+    }
+
+    pub fn set_paging(&mut self, on: bool) {
+        self.pager.set_page_lines(if on { Some(0) } else { None });
+        self.update_page_lines();
+    }
+
+    // The first terminal row of the lower window.
+    fn lower_top(&self) -> u16 {
+        self.status_rows + self.windows.upper_lines()
+    }
+
+    // Only the lower window scrolls. This also moves the cursor, so save it.
+    fn layout(&mut self) -> Result<()> {
+        let lines = self.windows.size().0;
+        let top = self.lower_top().min(lines.saturating_sub(1));
+        queue!(
+            self.out,
+            SavePosition,
+            Print(format!("\x1b[{};{}r", top + 1, lines)),
+            RestorePosition
+        )?;
+        self.update_page_lines();
+        Ok(())
+    }
+
+    // Lay the windows out again for a new size. What is already on the
+    // screen stays where the terminal put it.
+    fn resize(&mut self, lines: u16, columns: u16) -> Result<()> {
+        self.flush_wrapper()?;
+        self.windows.set_size(lines, columns);
+        if let Some(ref mut wrapper) = self.wrapper {
+            wrapper.set_width(usize::from(columns));
+        }
+        self.layout()
+    }
+
+    // A page is the whole lower window, less a line for the prompt.
+    fn update_page_lines(&mut self) {
+        if self.pager.page_lines().is_some() {
+            let lines = self.windows.size().0;
+            let page = lines.saturating_sub(self.lower_top() + 1);
+            self.pager.set_page_lines(Some(page));
+        }
+    }
+
+    fn flush_wrapper(&mut self) -> Result<()> {
+        let text = match self.wrapper {
+            Some(ref mut wrapper) => wrapper.flush(),
+            None => return Ok(()),
+        };
+        self.display_lower(&text)
+    }
+
+    // Print text in the lower window, stopping for the player after each page.
+    fn display_lower(&mut self, text: &str) -> Result<()> {
+        for (idx, page) in self.pager.page(text).iter().enumerate() {
+            if idx > 0 {
+                queue!(self.out, Print("[MORE]"))?;
+                self.out.flush()?;
+                read_key(None)?;
+                queue!(self.out, Print("\r"), Clear(ClearType::CurrentLine))?;
+            }
+            // The terminal isn't in raw mode, but be explicit about returns.
+            queue!(self.out, Print(page.replace('\n', "\r\n")))?;
+        }
+        Ok(())
+    }
+
+    // Upper window text is placed at the window's cursor. It never scrolls,
+    // so text past the bottom of the window is lost.
+    fn display_upper(&mut self, text: &str) -> Result<()> {
+        for (idx, line) in text.split('\n').enumerate() {
+            if idx > 0 {
+                self.windows.advance("\n");
+            }
+            let (row, column) = self.windows.cursor();
+            if row > self.windows.upper_lines() {
+                continue;
+            }
+            let y = self.status_rows + row.saturating_sub(1);
+            queue!(self.out, MoveTo(column.saturating_sub(1), y), Print(line))?;
+            self.windows.advance(line);
+        }
+        Ok(())
+    }
+
+    // The terminal's font is always fixed pitch, so TextStyle::FIXED needs
+    // no attribute.
+    fn apply_attributes(&mut self) -> Result<()> {
+        queue!(self.out, SetAttribute(Attribute::Reset))?;
+        if self.style.contains(TextStyle::BOLD) {
+            queue!(self.out, SetAttribute(Attribute::Bold))?;
+        }
+        if self.style.contains(TextStyle::ITALIC) {
+            queue!(self.out, SetAttribute(Attribute::Italic))?;
+        }
+        if self.style.contains(TextStyle::REVERSE) {
+            queue!(self.out, SetAttribute(Attribute::Reverse))?;
+        }
+        // Resetting the attributes also resets the colours.
+        let (foreground, background) = self.windows.colours();
+        queue!(
+            self.out,
+            SetForegroundColor(terminal_colour(foreground)),
+            SetBackgroundColor(terminal_colour(background))
+        )?;
+        Ok(())
+    }
+
+    fn clear_rows(&mut self, first: u16, count: u16) -> Result<()> {
+        for row in first..first + count {
+            queue!(self.out, MoveTo(0, row), Clear(ClearType::CurrentLine))?;
+        }
+        Ok(())
+    }
+}
+
+impl Screen for TerminalScreen {
+    fn print(&mut self, text: &str) -> Result<()> {
+        if self.windows.current_window() != LOWER_WINDOW {
+            return self.display_upper(text);
+        }
+        self.windows.advance(text);
+        let text = match self.wrapper {
+            Some(ref mut wrapper) => wrapper.push(text),
+            None => text.to_string(),
+        };
+        self.display_lower(&text)
+    }
+
+    // The screen is flushed before each read, so this starts a new page.
+    fn flush(&mut self) -> Result<()> {
+        self.flush_wrapper()?;
+        self.out.flush()?;
+        self.pager.reset();
+        Ok(())
+    }
+
+    fn set_buffer_mode(&mut self, buffered: bool) -> Result<()> {
+        self.flush_wrapper()?;
+        self.wrapper = if buffered {
+            Some(WordWrapper::new(usize::from(self.windows.size().1)))
+        } else {
+            None
+        };
+        Ok(())
+    }
+
+    fn set_text_style(&mut self, style: TextStyle) -> Result<()> {
+        self.flush_wrapper()?;
+        self.style = style;
+        self.apply_attributes()
+    }
+
+    fn set_colour(&mut self, foreground: Colour, background: Colour) -> Result<()> {
+        self.flush_wrapper()?;
+        self.windows.set_colours(foreground, background);
+        self.apply_attributes()
+    }
+
+    fn supports_colour(&self) -> bool {
+        true
+    }
+
+    fn split_window(&mut self, lines: u16) -> Result<()> {
+        self.flush_wrapper()?;
+        let available = self.windows.size().0.saturating_sub(self.status_rows);
+        self.windows.split(lines.min(available));
+        self.layout()
+    }
+
+    fn set_window(&mut self, window: u16) -> Result<()> {
+        self.flush_wrapper()?;
+        let from = self.windows.current_window();
+        self.windows.select(window)?;
+        // Keep the lower window's place while the upper window is in use.
+        match (from, window) {
+            (LOWER_WINDOW, UPPER_WINDOW) => queue!(self.out, SavePosition)?,
+            (UPPER_WINDOW, LOWER_WINDOW) => queue!(self.out, RestorePosition)?,
+            _ => (),
+        }
+        // Each window keeps its own colours.
+        self.apply_attributes()
+    }
+
+    fn erase_window(&mut self, window: u16) -> Result<()> {
+        self.flush_wrapper()?;
+        self.windows.erase(window);
+        match window {
+            LOWER_WINDOW => {
+                let top = self.lower_top();
+                let count = self.windows.size().0.saturating_sub(top);
+                self.clear_rows(top, count)?;
+                queue!(self.out, MoveTo(0, top))?;
+            }
+            UPPER_WINDOW => {
+                let upper_lines = self.windows.upper_lines();
+                queue!(self.out, SavePosition)?;
+                self.clear_rows(self.status_rows, upper_lines)?;
+                queue!(self.out, RestorePosition)?;
+            }
+            _ => (),
+        }
+        // Each window keeps its own colours.
+        self.apply_attributes()
+    }
+
+    fn erase_line(&mut self) -> Result<()> {
+        self.flush_wrapper()?;
+        queue!(self.out, Clear(ClearType::UntilNewLine))?;
+        Ok(())
+    }
+
+    fn set_cursor(&mut self, line: u16, column: u16) -> Result<()> {
+        self.flush_wrapper()?;
+        self.windows.set_cursor(line, column);
+        Ok(())
+    }
+
+    fn get_cursor(&self) -> (u16, u16) {
+        self.windows.cursor()
+    }
+
+    fn size(&self) -> (u16, u16) {
+        self.windows.size()
+    }
+
+    fn set_size(&mut self, lines: u16, columns: u16) -> Result<()> {
+        self.follow_terminal = false;
+        self.resize(lines, columns)
+    }
+
+    fn check_resize(&mut self) -> Result<bool> {
+        if !self.follow_terminal {
+            return Ok(false);
+        }
+        let (columns, lines) = terminal::size()?;
+        if (lines, columns) == self.windows.size() {
+            return Ok(false);
+        }
+        self.resize(lines, columns)?;
+        Ok(true)
+    }
+
+    fn show_status(&mut self, location: &str, right: &str) -> Result<()> {
+        self.flush_wrapper()?;
+        let width = usize::from(self.windows.size().1);
+        let line = status::status_line(location, right, width);
+        queue!(
+            self.out,
+            SavePosition,
+            MoveTo(0, 0),
+            SetAttribute(Attribute::Reverse),
+            Print(line)
+        )?;
+        self.apply_attributes()?;
+        queue!(self.out, RestorePosition)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for TerminalScreen {
+    // Give the whole terminal back to the shell.
+    fn drop(&mut self) {
+        let _ = execute!(
+            self.out,
+            SetAttribute(Attribute::Reset),
+            Print("\x1b[r"),
+            Print("\r\n")
+        );
+    }
+}
+
+fn terminal_colour(colour: Colour) -> Color {
+    use self::Colour::*;
+    match colour {
+        Current | Default => Color::Reset,
+        Black => Color::Black,
+        Red => Color::DarkRed,
+        Green => Color::DarkGreen,
+        Yellow => Color::DarkYellow,
+        Blue => Color::DarkBlue,
+        Magenta => Color::DarkMagenta,
+        Cyan => Color::DarkCyan,
+        White => Color::White,
+        LightGrey => Color::Grey,
+        DarkGrey => Color::DarkGrey,
+        _ => match colour.rgb() {
+            Some((r, g, b)) => Color::Rgb { r, g, b },
+            None => Color::Reset,
+        },
+    }
+}
+
+// Convert a key event into the ZSCII code that a game would see, if it has
+// one. (ZSpec 10.5.2)
+//
+// crossterm doesn't tell keypad digits from the others, so they arrive as
+// ordinary characters, and ZKey::Keypad is never seen here.
+fn zscii_key(event: KeyEvent) -> Option<u8> {
+    if event.modifiers.contains(KeyModifiers::CONTROL) {
+        return None;
+    }
+    let key = match event.code {
+        KeyCode::Char(ch) => return key_to_zscii(ch),
+        KeyCode::Enter => return Some(ZSCII_NEWLINE),
+        KeyCode::Backspace | KeyCode::Delete => ZKey::Delete,
+        KeyCode::Esc => ZKey::Escape,
+        KeyCode::Up => ZKey::CursorUp,
+        KeyCode::Down => ZKey::CursorDown,
+        KeyCode::Left => ZKey::CursorLeft,
+        KeyCode::Right => ZKey::CursorRight,
+        KeyCode::F(n) => ZKey::Function(n),
+        _ => return None,
+    };
+    key.zscii()
+}
+
+// Wait for a key to be pressed. Returns None if the timeout passes.
+// The terminal must be in raw mode, or keys won't arrive until return is
+// pressed.
+fn read_raw_event(timeout: Option<Duration>) -> Result<Option<KeyEvent>> {
+    loop {
+        if let Some(timeout) = timeout {
+            if !event::poll(timeout)? {
+                return Ok(None);
+            }
+        }
+        if let Event::Key(key) = event::read()? {
+            return Ok(Some(key));
+        }
+    }
+}
+
+// As read_raw_event, but only for keys that have a ZSCII code.
+fn read_raw_key(timeout: Option<Duration>) -> Result<Option<u8>> {
+    loop {
+        match read_raw_event(timeout)? {
+            None => return Ok(None),
+            Some(event) => {
+                if let Some(zscii) = zscii_key(event) {
+                    return Ok(Some(zscii));
+                }
+            }
+        }
+    }
+}
+
+// Function keys only end input if they are in the terminating characters
+// table. 255 in the table stands for all of them. (ZSpec 11.1.8)
+fn is_terminator(key: u8, terminators: &[u8]) -> bool {
+    key >= ZSCII_CURSOR_UP && (terminators.contains(&key) || terminators.contains(&255))
+}
+
+// As read_raw_key, but switch into raw mode for the duration.
+fn read_key(timeout: Option<Duration>) -> Result<Option<u8>> {
+    terminal::enable_raw_mode()?;
+    let key = read_raw_key(timeout);
+    terminal::disable_raw_mode()?;
+    key
+}
+
+// A Keyboard that reads keys as they are pressed. Raw mode is only on while
+// waiting for input, so that printing works normally.
+//
+// Lines can be edited as they are typed. The arrow keys move the cursor, or
+// step through the lines typed earlier in the session, unless the game wants
+// them as terminators.
+#[derive(Default)]
+pub struct TerminalKeyboard {
+    editor: LineEditor,
+}
+
+impl TerminalKeyboard {
+    pub fn new() -> TerminalKeyboard {
+        TerminalKeyboard {
+            editor: LineEditor::new(),
+        }
+    }
+
+    // Apply keys to the line until it is finished. Returns the terminator,
+    // or 0 if the timeout passes first. Keypresses don't restart the timer.
+    fn edit_line(&mut self, terminators: &[u8], timeout: Option<Duration>) -> Result<u8> {
+        let mut out = io::stdout();
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let event = match read_raw_event(remaining)? {
+                Some(event) => event,
+                None => return Ok(0),
+            };
+            if let Some(key) = zscii_key(event) {
+                if is_terminator(key, terminators) {
+                    return Ok(key);
+                }
+            }
+
+            let old_cursor = self.editor.cursor();
+            match event.code {
+                KeyCode::Enter => return Ok(ZSCII_NEWLINE),
+                KeyCode::Char(ch)
+                    if key_to_zscii(ch).is_some()
+                        && !event.modifiers.contains(KeyModifiers::CONTROL) =>
+                {
+                    self.editor.insert(ch)
+                }
+                KeyCode::Backspace => self.editor.backspace(),
+                KeyCode::Delete => self.editor.delete(),
+                KeyCode::Left => self.editor.left(),
+                KeyCode::Right => self.editor.right(),
+                KeyCode::Home => self.editor.home(),
+                KeyCode::End => self.editor.end(),
+                KeyCode::Up => self.editor.history_back(),
+                KeyCode::Down => self.editor.history_forward(),
+                _ => continue,
+            }
+            self.redraw(&mut out, old_cursor)?;
+        }
+    }
+
+    // Redraw the line from where it starts on the screen, which is
+    // old_cursor characters to the left.
+    fn redraw(&self, out: &mut Stdout, old_cursor: usize) -> Result<()> {
+        if old_cursor > 0 {
+            queue!(out, MoveLeft(old_cursor as u16))?;
+        }
+        let text = self.editor.text();
+        queue!(out, Print(&text), Clear(ClearType::UntilNewLine))?;
+        let back = text.chars().count() - self.editor.cursor();
+        if back > 0 {
+            queue!(out, MoveLeft(back as u16))?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+impl Keyboard for TerminalKeyboard {
+    fn read_line(
+        &mut self,
+        typed: &str,
+        terminators: &[u8],
+        timeout: Option<Duration>,
+    ) -> Result<(String, u8)> {
+        for ch in typed.chars() {
+            self.editor.insert(ch);
+        }
+
+        terminal::enable_raw_mode()?;
+        let terminator = self.edit_line(terminators, timeout);
+        terminal::disable_raw_mode()?;
+
+        // Only complete commands are worth recalling.
+        let terminator = terminator?;
+        let line = if terminator == ZSCII_NEWLINE {
+            execute!(io::stdout(), Print("\r\n"))?;
+            self.editor.finish()
+        } else {
+            self.editor.take()
+        };
+        Ok((line, terminator))
+    }
+
+    fn read_char(&mut self, timeout: Option<Duration>) -> Result<u8> {
+        Ok(read_key(timeout)?.unwrap_or(0))
+    }
+
+    fn supports_timed_input(&self) -> bool {
+        true
+    }
+}
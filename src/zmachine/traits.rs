@@ -1,9 +1,15 @@
-use super::addressing::{ByteAddress, ZOffset};
+use std::time::Duration;
+
+use super::addressing::{ByteAddress, RoutinePackedAddress, StringPackedAddress, ZOffset};
 use super::opcode::ZVariable;
 use super::result::Result;
+use super::saves::SaveInfo;
+use super::screen::{Colour, TextStyle};
 use super::version::ZVersion;
 
 pub mod bytes {
+    use super::super::result::{Result, ZErr};
+
     // TODO: range check all of this.
 
     #[inline]
@@ -11,6 +17,11 @@ pub mod bytes {
         slice[idx]
     }
 
+    // As byte_from_slice, for offsets that might be outside the slice.
+    pub fn checked_byte_from_slice(slice: &[u8], idx: usize) -> Result<u8> {
+        slice.get(idx).copied().ok_or(ZErr::ReadViolation(idx))
+    }
+
     #[inline]
     pub fn byte_to_slice(slice: &mut [u8], idx: usize, val: u8) {
         slice[idx] = val;
@@ -25,6 +36,12 @@ pub mod bytes {
         (high_byte << 8) + low_byte
     }
 
+    pub fn checked_word_from_slice(slice: &[u8], idx: usize) -> Result<u16> {
+        let high_byte = u16::from(checked_byte_from_slice(slice, idx)?);
+        let low_byte = u16::from(checked_byte_from_slice(slice, idx + 1)?);
+        Ok((high_byte << 8) + low_byte)
+    }
+
     #[inline]
     pub fn word_to_slice(slice: &mut [u8], idx: usize, val: u16) {
         let high_byte = ((val >> 8) & 0xff) as u8;
@@ -49,11 +66,35 @@ pub mod bytes {
 
 pub trait Header {
     fn abbrev_location(&self) -> ByteAddress;
+    fn dictionary_location(&self) -> ByteAddress;
+    fn flags1(&self) -> u8;
     fn global_location(&self) -> ByteAddress;
     fn high_memory_base(&self) -> ByteAddress;
     fn static_memory_base(&self) -> ByteAddress;
     fn otable_location(&self) -> ByteAddress;
+    // Offsets added to packed routine and string addresses in V6-7, in units
+    // of 8 bytes. (ZSpec 1.2.3)
+    fn routines_offset(&self) -> u16;
+    fn strings_offset(&self) -> u16;
+    // The table of extra characters that can terminate input, if any. (ZSpec 11.1.8)
+    fn terminating_chars_location(&self) -> Option<ByteAddress>;
     fn version_number(&self) -> ZVersion;
+
+    // Unpack the address of a routine, as used by the call opcodes.
+    fn routine_address(&self, packed: u16) -> RoutinePackedAddress {
+        RoutinePackedAddress::new(
+            self.version_number()
+                .make_packed_address(packed, self.routines_offset()),
+        )
+    }
+
+    // Unpack the address of a string, as used by print_paddr.
+    fn string_address(&self, packed: u16) -> StringPackedAddress {
+        StringPackedAddress::new(
+            self.version_number()
+                .make_packed_address(packed, self.strings_offset()),
+        )
+    }
 }
 
 pub trait PC {
@@ -79,10 +120,45 @@ pub trait Memory {
     where
         T: Into<ZOffset> + Copy;
 
+    // As read_byte, but returns ReadViolation if `at` is past the end of
+    // memory, for reads that a malformed story could send anywhere.
+    fn try_read_byte<T>(&self, at: T) -> Result<u8>
+    where
+        T: Into<ZOffset> + Copy;
+
     fn write_byte<T>(&mut self, at: T, val: u8) -> Result<()>
     where
         T: Into<ZOffset> + Copy;
 
+    // The total number of bytes in memory.
+    fn memory_size(&self) -> usize;
+
+    // Dynamic memory as the story file has it. Quetzal saves record their
+    // changes against this.
+    fn original_dynamic(&self) -> &[u8];
+
+    // Writes made by the interpreter itself, which may also change the header
+    // fields that the game must not. (ZSpec 11)
+    fn write_interpreter_byte<T>(&mut self, at: T, val: u8) -> Result<()>
+    where
+        T: Into<ZOffset> + Copy,
+    {
+        self.write_byte(at, val)
+    }
+
+    fn write_interpreter_word<T>(&mut self, at: T, val: u16) -> Result<()>
+    where
+        T: Into<ZOffset> + Copy,
+    {
+        let offset = at.into();
+        self.write_interpreter_byte(offset, (val >> 8) as u8)?;
+        self.write_interpreter_byte(offset.inc_by(1), val as u8)
+    }
+
+    // Return dynamic memory to its state when the story was loaded.
+    // (ZSpec 15: restart)
+    fn reset_dynamic(&mut self);
+
     fn read_word<T>(&self, at: T) -> u16
     where
         T: Into<ZOffset> + Copy,
@@ -92,6 +168,15 @@ pub trait Memory {
         (high_byte << 8) + low_byte
     }
 
+    fn try_read_word<T>(&self, at: T) -> Result<u16>
+    where
+        T: Into<ZOffset> + Copy,
+    {
+        let high_byte = u16::from(self.try_read_byte(at.into())?);
+        let low_byte = u16::from(self.try_read_byte(at.into().inc_by(1))?);
+        Ok((high_byte << 8) + low_byte)
+    }
+
     // May fail if word is outside dynamic memory.
     fn write_word<T>(&mut self, at: T, val: u16) -> Result<()>
     where
@@ -116,13 +201,25 @@ pub trait Stack {
         &mut self,
         return_pc: usize,
         num_locals: u8,
-        return_var: ZVariable,
+        return_var: Option<ZVariable>,
         operands: &[u16],
+        num_args: u8,
     ) -> Result<()>;
     fn pop_frame(&mut self) -> Result<()>;
 
     fn return_pc(&self) -> usize;
-    fn return_variable(&self) -> ZVariable;
+    // None if the routine's result is thrown away, as for call_vn.
+    fn return_variable(&self) -> Option<ZVariable>;
+    // The number of arguments passed to the current routine. (ZSpec 6.4.5)
+    fn num_args(&self) -> u8;
+    // The number of routine frames above the base frame. The interpreter uses
+    // this to tell when a routine that it called has returned.
+    fn frame_depth(&self) -> usize;
+
+    // The frames, oldest first, as the data of a Quetzal Stks chunk, and the
+    // reverse. A failed restore leaves the stack unchanged. (Quetzal 1.4, 4)
+    fn quetzal_frames(&self) -> Vec<u8>;
+    fn restore_quetzal_frames(&mut self, data: &[u8]) -> Result<()>;
 
     fn push_word(&mut self, word: u16) -> Result<()> {
         self.push_byte((word >> 8 & 0xff) as u8)?;
@@ -136,6 +233,78 @@ pub trait Stack {
 
         Ok((high_byte << 8) + low_byte)
     }
+
+    // Read or replace the top word of the stack without popping it.
+    fn peek_word(&self) -> Result<u16>;
+    fn poke_word(&mut self, word: u16) -> Result<()>;
+}
+
+// The screen backend. Frontends implement this to display the game.
+pub trait Screen {
+    fn print(&mut self, text: &str) -> Result<()>;
+    // Display any buffered text.
+    fn flush(&mut self) -> Result<()>;
+
+    // When buffering, text in the lower window is wrapped at word boundaries.
+    fn set_buffer_mode(&mut self, buffered: bool) -> Result<()>;
+    fn set_text_style(&mut self, style: TextStyle) -> Result<()>;
+
+    // Colour::Current leaves that colour unchanged.
+    fn set_colour(&mut self, foreground: Colour, background: Colour) -> Result<()>;
+    fn supports_colour(&self) -> bool;
+
+    // Give the upper window this many lines. Zero removes the upper window.
+    fn split_window(&mut self, lines: u16) -> Result<()>;
+    fn set_window(&mut self, window: u16) -> Result<()>;
+    fn erase_window(&mut self, window: u16) -> Result<()>;
+    // Erase from the cursor to the end of the line.
+    fn erase_line(&mut self) -> Result<()>;
+
+    // Cursor positions are (line, column) in the current window, from (1, 1).
+    fn set_cursor(&mut self, line: u16, column: u16) -> Result<()>;
+    fn get_cursor(&self) -> (u16, u16);
+
+    // The size of the whole screen, as (lines, columns). This is reported to
+    // the game in the header. (ZSpec 8.4)
+    fn size(&self) -> (u16, u16);
+    // Use this size, rather than whatever the screen would choose. Frontends
+    // that follow the size of a terminal stop doing so.
+    fn set_size(&mut self, lines: u16, columns: u16) -> Result<()>;
+    // Returns true if the screen has changed size since the last check, as a
+    // terminal window may at any time. size() then reports the new size.
+    fn check_resize(&mut self) -> Result<bool> {
+        Ok(false)
+    }
+
+    // Draw the V1-3 status line, with the location on the left, and the
+    // score or time on the right. (ZSpec 8.2)
+    fn show_status(&mut self, location: &str, right: &str) -> Result<()>;
+}
+
+// Frontends implement this to play the sounds requested by sound_effect.
+pub trait SoundPlayer {
+    fn bleep(&mut self, high: bool) -> Result<()>;
+
+    fn prepare(&mut self, number: u16) -> Result<()>;
+    // If present, the routine should be called when the sound finishes. (V5+)
+    fn start(
+        &mut self,
+        number: u16,
+        volume: u8,
+        repeats: u8,
+        routine: Option<RoutinePackedAddress>,
+    ) -> Result<()>;
+    fn stop(&mut self, number: u16) -> Result<()>;
+    fn finish_with(&mut self, number: u16) -> Result<()>;
+}
+
+// Frontends implement this to decide where saved games go. The data is an
+// opaque save file, written as is.
+pub trait SaveHandler {
+    fn save(&mut self, info: &SaveInfo, data: &[u8]) -> Result<()>;
+
+    // None if there is no save to restore, or the player chose none.
+    fn restore(&mut self) -> Result<Option<Vec<u8>>>;
 }
 
 pub trait Variables {
@@ -145,6 +314,79 @@ pub trait Variables {
 
     // TODO: range check variable sub-values. (MAX_LOCAL, MAX_GLOBAL)
     fn write_variable(&mut self, var: ZVariable, val: u16) -> Result<()>;
+
+    // Opcodes that name a variable by number (inc, dec, inc_chk, dec_chk,
+    // load, store, pull) access the stack in place, reading or replacing the
+    // top value rather than popping or pushing it. (ZSpec 6.3.4)
+    fn peek_variable(&mut self, var: ZVariable) -> Result<u16>;
+    fn poke_variable(&mut self, var: ZVariable, val: u16) -> Result<()>;
+}
+
+// All player input comes through here, so that the opcodes never touch
+// std::io. Frontends, tests, and command files implement it.
+pub trait InputStreams {
+    fn select_input_stream(&mut self, stream: u8) -> Result<()>;
+
+    // The stream that the next input will come from. (ZSpec 10.2)
+    fn current_stream(&self) -> u8;
+
+    // Read one line of input from the current stream, without the line ending.
+    // Input also ends if one of the terminators (function key codes) is typed.
+    // Returns the text and the character that ended it (13 for newline).
+    // If the timeout passes first, the terminator is 0, and the text is
+    // whatever had been typed so far.
+    //
+    // typed is text that the player has already typed, and which is already
+    // on the screen, as when a read is resumed after a timeout. The line
+    // carries on from it, and the result includes it.
+    fn read_line(
+        &mut self,
+        typed: &str,
+        terminators: &[u8],
+        timeout: Option<Duration>,
+    ) -> Result<(String, u8)>;
+
+    // Read a single keypress, as a ZSCII code, or 0 if the timeout passes.
+    fn read_char(&mut self, timeout: Option<Duration>) -> Result<u8>;
+
+    // Whether the timeouts are honoured. If not, input waits indefinitely.
+    // (ZSpec 11.1 Flags 1 bit 7)
+    fn supports_timed_input(&self) -> bool;
+}
+
+// The source of typed input behind input stream 0. Frontends implement it,
+// either line by line, or key by key.
+pub trait Keyboard {
+    // As InputStreams::read_line.
+    fn read_line(
+        &mut self,
+        typed: &str,
+        terminators: &[u8],
+        timeout: Option<Duration>,
+    ) -> Result<(String, u8)>;
+
+    // As InputStreams::read_char.
+    fn read_char(&mut self, timeout: Option<Duration>) -> Result<u8>;
+
+    // As InputStreams::supports_timed_input.
+    fn supports_timed_input(&self) -> bool;
+}
+
+pub trait OutputStreams {
+    // Stream 3 requires a table address. It is ignored for other streams.
+    fn select_stream(&mut self, stream: u8, table: Option<ByteAddress>) -> Result<()>;
+    fn deselect_stream(&mut self, stream: u8) -> Result<()>;
+
+    // Styles are combined with the current style. Roman clears them all.
+    fn set_text_style(&mut self, style: TextStyle) -> Result<()>;
+
+    // Send text to all of the currently selected streams.
+    fn print_str(&mut self, text: &str) -> Result<()>;
+
+    // Send player input to the command script, if stream 4 is selected.
+    // Lines come from read, and single keys from read_char. (ZSpec 7.1.2.3)
+    fn record_line(&mut self, line: &str) -> Result<()>;
+    fn record_key(&mut self, key: u8) -> Result<()>;
 }
 
 #[cfg(test)]
@@ -222,6 +464,13 @@ mod test {
             self.val[at.into().value()]
         }
 
+        fn try_read_byte<T>(&self, at: T) -> Result<u8>
+        where
+            T: Into<ZOffset> + Copy,
+        {
+            bytes::checked_byte_from_slice(&self.val, at.into().value())
+        }
+
         fn write_byte<T>(&mut self, at: T, val: u8) -> Result<()>
         where
             T: Into<ZOffset> + Copy,
@@ -229,6 +478,16 @@ mod test {
             self.val[at.into().value()] = val;
             Ok(())
         }
+
+        fn memory_size(&self) -> usize {
+            self.val.len()
+        }
+
+        fn original_dynamic(&self) -> &[u8] {
+            &[]
+        }
+
+        fn reset_dynamic(&mut self) {}
     }
 
     #[test]
@@ -264,13 +523,32 @@ mod test {
                 .ok_or(ZErr::GenericError("Popping in BareStack"))
         }
 
+        fn peek_word(&self) -> Result<u16> {
+            panic!("unimplemented")
+        }
+        fn poke_word(&mut self, _word: u16) -> Result<()> {
+            panic!("unimplemented")
+        }
+
         fn pop_frame(&mut self) -> Result<()> {
             Ok(())
         }
         fn return_pc(&self) -> usize {
             panic!("unimplemented")
         }
-        fn return_variable(&self) -> ZVariable {
+        fn return_variable(&self) -> Option<ZVariable> {
+            panic!("unimplemented")
+        }
+        fn num_args(&self) -> u8 {
+            panic!("unimplemented")
+        }
+        fn frame_depth(&self) -> usize {
+            panic!("unimplemented")
+        }
+        fn quetzal_frames(&self) -> Vec<u8> {
+            panic!("unimplemented")
+        }
+        fn restore_quetzal_frames(&mut self, _data: &[u8]) -> Result<()> {
             panic!("unimplemented")
         }
 
@@ -285,8 +563,9 @@ mod test {
             &mut self,
             _return_pc: usize,
             _num_locals: u8,
-            _return_var: ZVariable,
+            _return_var: Option<ZVariable>,
             _operands: &[u16],
+            _num_args: u8,
         ) -> Result<()> {
             Ok(())
         }
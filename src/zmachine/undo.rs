@@ -0,0 +1,46 @@
+use std::collections::VecDeque;
+
+use super::snapshot::ZSnapshot;
+use super::traits::Stack;
+
+// The states saved by save_undo, newest last. Each restore_undo takes the
+// newest, so repeated calls step further back. Once the limit is reached,
+// saving drops the oldest state. (ZSpec 15: save_undo)
+pub struct ZUndoRing<S>
+where
+    S: Stack + Clone,
+{
+    states: VecDeque<ZSnapshot<S>>,
+}
+
+impl<S> Default for ZUndoRing<S>
+where
+    S: Stack + Clone,
+{
+    fn default() -> ZUndoRing<S> {
+        ZUndoRing {
+            states: VecDeque::new(),
+        }
+    }
+}
+
+impl<S> ZUndoRing<S>
+where
+    S: Stack + Clone,
+{
+    pub fn new() -> ZUndoRing<S> {
+        ZUndoRing::default()
+    }
+
+    // Keep at most `limit` states.
+    pub fn push(&mut self, state: ZSnapshot<S>, limit: usize) {
+        self.states.push_back(state);
+        while self.states.len() > limit {
+            self.states.pop_front();
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<ZSnapshot<S>> {
+        self.states.pop_back()
+    }
+}
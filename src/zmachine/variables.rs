@@ -99,6 +99,20 @@ where
             Global(g) => self.write_global(g, val),
         }
     }
+
+    fn peek_variable(&mut self, var: ZVariable) -> Result<u16> {
+        match var {
+            ZVariable::Stack => self.stack_h.borrow().peek_word(),
+            _ => self.read_variable(var),
+        }
+    }
+
+    fn poke_variable(&mut self, var: ZVariable, val: u16) -> Result<()> {
+        match var {
+            ZVariable::Stack => self.stack_h.borrow_mut().poke_word(val),
+            _ => self.write_variable(var, val),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -199,4 +213,19 @@ mod test {
             variables.read_variable(ZVariable::Global(3)).unwrap()
         );
     }
+
+    #[test]
+    fn test_peek_and_poke_stack() {
+        let mut variables = make_test_setup();
+
+        variables.write_variable(ZVariable::Stack, 0x3579).unwrap();
+        variables.write_variable(ZVariable::Stack, 0x4677).unwrap();
+
+        assert_eq!(0x4677, variables.peek_variable(ZVariable::Stack).unwrap());
+        variables.poke_variable(ZVariable::Stack, 0xabcd).unwrap();
+        assert_eq!(0xabcd, variables.peek_variable(ZVariable::Stack).unwrap());
+
+        assert_eq!(0xabcd, variables.read_variable(ZVariable::Stack).unwrap());
+        assert_eq!(0x3579, variables.read_variable(ZVariable::Stack).unwrap());
+    }
 }
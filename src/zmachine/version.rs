@@ -3,51 +3,142 @@ use super::result::{Result, ZErr};
 
 #[derive(Clone, Copy, Debug, PartialOrd, PartialEq, Eq)]
 pub enum ZVersion {
-    //    V1 = 1,
-    //    V2 = 2,
+    V1 = 1,
+    V2 = 2,
     V3 = 3,
-    //    V4 = 4,
+    V4 = 4,
     V5 = 5,
-    //    V6 = 6,
+    V6 = 6,
+    V7 = 7,
+    V8 = 8,
 }
 
 impl ZVersion {
     pub fn new(byte: u8) -> Result<ZVersion> {
         use self::ZVersion::*;
         match byte {
-            //            1 => Ok(V1),
-            //            2 => Ok(V2),
+            1 => Ok(V1),
+            2 => Ok(V2),
             3 => Ok(V3),
-            //            4 => Ok(V4),
+            4 => Ok(V4),
             5 => Ok(V5),
-            //            6 => Ok(V6),
+            6 => Ok(V6),
+            7 => Ok(V7),
+            8 => Ok(V8),
             _ => Err(ZErr::UnknownVersionNumber(byte)),
         }
     }
 
-    pub fn make_packed_address(&self, val: u16) -> PackedAddress {
+    // Unpack an address, with the routine or string offset from the header,
+    // which only applies in V6-7. (ZSpec 1.2.3)
+    pub fn make_packed_address(&self, val: u16, offset: u16) -> PackedAddress {
         use self::ZVersion::*;
-        PackedAddress::new(
-            val,
-            match self {
-                // V1...
-                V3 => 2,
-                // V4...
-                V5 => 4,
-                // V6 is special.
-            },
-        )
+        match self {
+            V1 | V2 | V3 => PackedAddress::new(val, 2),
+            V4 | V5 => PackedAddress::new(val, 4),
+            V6 | V7 => PackedAddress::with_offset(val, 4, offset),
+            V8 => PackedAddress::new(val, 8),
+        }
+    }
+
+    // Whether opcode 0xbe introduces an EXT opcode. (ZSpec 4.3.4)
+    pub fn supports_extended_opcodes(self) -> bool {
+        self >= ZVersion::V5
+    }
+
+    // The size in bytes of an object table entry. (ZSpec 12.3)
+    pub fn object_entry_size(self) -> u16 {
+        if self > ZVersion::V3 {
+            14
+        } else {
+            9
+        }
+    }
+
+    // Objects are numbered with a byte in V1-3, and a word in V4+. (ZSpec 12.3)
+    pub fn max_objects(self) -> u16 {
+        if self > ZVersion::V3 {
+            65535
+        } else {
+            255
+        }
+    }
+
+    pub fn attribute_count(self) -> u8 {
+        if self > ZVersion::V3 {
+            48
+        } else {
+            32
+        }
+    }
+
+    // The number of words in the property defaults table. (ZSpec 12.2)
+    pub fn property_defaults_count(self) -> u16 {
+        (1 << self.property_number_bits()) - 1
+    }
+
+    // Property numbers fit in the low bits of the size byte. (ZSpec 12.4.1, 12.4.2)
+    pub fn property_number_bits(self) -> u8 {
+        if self > ZVersion::V3 {
+            6
+        } else {
+            5
+        }
+    }
+
+    // The number of z-chars in an encoded dictionary word. (ZSpec 13.3)
+    pub fn dictionary_word_length(self) -> usize {
+        if self > ZVersion::V3 {
+            9
+        } else {
+            6
+        }
+    }
+
+    // The largest story file allowed. (ZSpec 1.1.4)
+    pub fn max_story_length(self) -> usize {
+        use self::ZVersion::*;
+        match self {
+            V1 | V2 | V3 => 128 * 1024,
+            V4 | V5 => 256 * 1024,
+            V6 | V7 | V8 => 512 * 1024,
+        }
     }
 
     pub fn convert_file_length(&self, raw_length: u16) -> usize {
         use self::ZVersion::*;
         (match self {
-            //            V1 | V2 |
-            V3 => 2,
-            //            V4 |
-            V5 => 4,
-            //            V6 => 8,
+            V1 | V2 | V3 => 2,
+            V4 | V5 => 4,
+            V6 | V7 | V8 => 8,
         }) as usize
             * raw_length as usize
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_capabilities() {
+        let v3 = ZVersion::V3;
+        assert!(!v3.supports_extended_opcodes());
+        assert_eq!(9, v3.object_entry_size());
+        assert_eq!(255, v3.max_objects());
+        assert_eq!(32, v3.attribute_count());
+        assert_eq!(31, v3.property_defaults_count());
+        assert_eq!(6, v3.dictionary_word_length());
+
+        let v5 = ZVersion::V5;
+        assert!(v5.supports_extended_opcodes());
+        assert_eq!(14, v5.object_entry_size());
+        assert_eq!(65535, v5.max_objects());
+        assert_eq!(48, v5.attribute_count());
+        assert_eq!(63, v5.property_defaults_count());
+        assert_eq!(9, v5.dictionary_word_length());
+
+        assert!(!ZVersion::V4.supports_extended_opcodes());
+        assert_eq!(14, ZVersion::V4.object_entry_size());
+    }
+}
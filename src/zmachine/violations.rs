@@ -0,0 +1,149 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::mem::{self, Discriminant};
+use std::str::FromStr;
+
+use log::warn;
+
+use super::result::{Result, ZErr};
+
+// What to do when a game breaks a rule in a way that the interpreter can
+// recover from. The choices are those of Frotz's -s option.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    Ignore,
+    Warn,
+    // Warn only the first time each kind of violation happens.
+    #[default]
+    WarnOnce,
+    Fatal,
+}
+
+impl FromStr for ErrorPolicy {
+    type Err = ZErr;
+
+    fn from_str(s: &str) -> Result<ErrorPolicy> {
+        match s {
+            "ignore" => Ok(ErrorPolicy::Ignore),
+            "warn" => Ok(ErrorPolicy::Warn),
+            "once" => Ok(ErrorPolicy::WarnOnce),
+            "fatal" => Ok(ErrorPolicy::Fatal),
+            _ => Err(ZErr::GenericError(
+                "Error policy must be one of ignore, warn, once, or fatal",
+            )),
+        }
+    }
+}
+
+// The rules that a game may break without stopping it, and what was done
+// instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Violation {
+    IllegalColour(u16, u16),
+    // An object opcode, named here, was given object 0.
+    NullObject(&'static str),
+    UndefinedZsciiOutput(u16),
+    UnknownSoundEffect(u16),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Violation::*;
+        match *self {
+            IllegalColour(foreground, background) => write!(
+                f,
+                "illegal colours {} and {} were ignored",
+                foreground, background
+            ),
+            NullObject(opcode) => write!(f, "{} of object 0 was ignored", opcode),
+            UndefinedZsciiOutput(zscii) => write!(
+                f,
+                "ZSCII {} is not defined for output, and printed nothing",
+                zscii
+            ),
+            UnknownSoundEffect(effect) => {
+                write!(f, "sound_effect with unknown effect {} was ignored", effect)
+            }
+        }
+    }
+}
+
+// Applies the error policy to each violation as opcodes report it.
+pub struct ZViolations {
+    policy: ErrorPolicy,
+    reported: HashSet<Discriminant<Violation>>,
+}
+
+impl ZViolations {
+    pub fn new(policy: ErrorPolicy) -> ZViolations {
+        ZViolations {
+            policy,
+            reported: HashSet::new(),
+        }
+    }
+
+    // Returns an error only under the Fatal policy. Otherwise, the opcode
+    // should carry on as the violation describes.
+    pub fn report(&mut self, violation: Violation) -> Result<()> {
+        match self.policy {
+            ErrorPolicy::Ignore => (),
+            ErrorPolicy::Warn => warn!("{}", violation),
+            ErrorPolicy::WarnOnce => {
+                if self.reported.insert(mem::discriminant(&violation)) {
+                    warn!("{} (further reports of this are suppressed)", violation);
+                }
+            }
+            ErrorPolicy::Fatal => return Err(ZErr::Violation(violation)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(ErrorPolicy::Ignore, "ignore".parse().unwrap());
+        assert_eq!(ErrorPolicy::Warn, "warn".parse().unwrap());
+        assert_eq!(ErrorPolicy::WarnOnce, "once".parse().unwrap());
+        assert_eq!(ErrorPolicy::Fatal, "fatal".parse().unwrap());
+        assert!("sometimes".parse::<ErrorPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_report() {
+        for &policy in &[
+            ErrorPolicy::Ignore,
+            ErrorPolicy::Warn,
+            ErrorPolicy::WarnOnce,
+        ] {
+            let mut violations = ZViolations::new(policy);
+            violations
+                .report(Violation::NullObject("print_obj"))
+                .unwrap();
+            violations
+                .report(Violation::NullObject("print_obj"))
+                .unwrap();
+        }
+
+        let mut violations = ZViolations::new(ErrorPolicy::Fatal);
+        match violations.report(Violation::UnknownSoundEffect(7)) {
+            Err(ZErr::Violation(Violation::UnknownSoundEffect(7))) => (),
+            _ => panic!("Fatal policy should return an error"),
+        }
+    }
+
+    #[test]
+    fn test_report_once_per_kind() {
+        let mut violations = ZViolations::new(ErrorPolicy::WarnOnce);
+        violations.report(Violation::IllegalColour(1, 99)).unwrap();
+        violations.report(Violation::IllegalColour(99, 1)).unwrap();
+        violations
+            .report(Violation::NullObject("get_prop"))
+            .unwrap();
+        violations.report(Violation::NullObject("jin")).unwrap();
+        assert_eq!(2, violations.reported.len());
+    }
+}
@@ -0,0 +1,174 @@
+// Buffers text so that it can be wrapped at word boundaries. (ZSpec 8.4.2)
+//
+// Text is pushed in as it is printed. Complete words are released as soon as
+// the end of the word is seen, with a newline inserted in front of any word
+// that would not fit on the current line. Spaces at the point of a line break
+// are dropped.
+//
+// The final (possibly incomplete) word is held until more text arrives or the
+// buffer is flushed, so flush before reading input or moving the cursor.
+//
+// Tabs, which only V6 prints, become spaces up to the next tab stop.
+const TAB_WIDTH: usize = 8;
+
+pub struct WordWrapper {
+    width: usize,
+    column: usize,
+
+    spaces: usize,
+    word: String,
+}
+
+impl WordWrapper {
+    pub fn new(width: usize) -> WordWrapper {
+        WordWrapper {
+            width,
+            column: 0,
+            spaces: 0,
+            word: String::new(),
+        }
+    }
+
+    // Text already released keeps its line breaks. The new width applies
+    // from the next word.
+    pub fn set_width(&mut self, width: usize) {
+        self.width = width;
+    }
+
+    // Add text to the buffer, and return the text that is ready to be displayed.
+    pub fn push(&mut self, text: &str) -> String {
+        let mut out = String::new();
+        for ch in text.chars() {
+            match ch {
+                ' ' => {
+                    self.release_word(&mut out);
+                    self.spaces += 1;
+                }
+                '\t' => {
+                    self.release_word(&mut out);
+                    self.spaces += TAB_WIDTH - (self.column + self.spaces) % TAB_WIDTH;
+                }
+                '\n' => {
+                    self.release_word(&mut out);
+                    self.spaces = 0;
+                    self.column = 0;
+                    out.push('\n');
+                }
+                ch => self.word.push(ch),
+            }
+        }
+        out
+    }
+
+    // Return everything held in the buffer.
+    pub fn flush(&mut self) -> String {
+        let mut out = String::new();
+        self.release_word(&mut out);
+        for _ in 0..self.spaces {
+            out.push(' ');
+        }
+        self.column += self.spaces;
+        self.spaces = 0;
+        out
+    }
+
+    fn release_word(&mut self, out: &mut String) {
+        if self.word.is_empty() {
+            return;
+        }
+
+        let word_len = self.word.chars().count();
+        if self.column > 0 && self.column + self.spaces + word_len > self.width {
+            out.push('\n');
+            self.column = 0;
+        } else {
+            for _ in 0..self.spaces {
+                out.push(' ');
+            }
+            self.column += self.spaces;
+        }
+        self.spaces = 0;
+
+        out.push_str(&self.word);
+        self.column += word_len;
+        self.word.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_short_text_is_unchanged() {
+        let mut wrapper = WordWrapper::new(20);
+        let mut out = wrapper.push("West of House\nYou are");
+        out.push_str(&wrapper.flush());
+        assert_eq!("West of House\nYou are", out);
+    }
+
+    #[test]
+    fn test_wrap_at_word_boundary() {
+        let mut wrapper = WordWrapper::new(10);
+        let mut out = wrapper.push("the quick brown fox jumps");
+        out.push_str(&wrapper.flush());
+        assert_eq!("the quick\nbrown fox\njumps", out);
+    }
+
+    #[test]
+    fn test_partial_word_is_held() {
+        let mut wrapper = WordWrapper::new(10);
+        assert_eq!("", wrapper.push("mail"));
+        assert_eq!("mailbox", wrapper.push("box "));
+        assert_eq!(" ", wrapper.flush());
+    }
+
+    #[test]
+    fn test_text_split_across_pushes() {
+        let mut wrapper = WordWrapper::new(8);
+        let mut out = wrapper.push("open the ");
+        out.push_str(&wrapper.push("small mailbox"));
+        out.push_str(&wrapper.flush());
+        assert_eq!("open the\nsmall\nmailbox", out);
+    }
+
+    #[test]
+    fn test_set_width() {
+        let mut wrapper = WordWrapper::new(20);
+        let mut out = wrapper.push("one two ");
+        wrapper.set_width(9);
+        out.push_str(&wrapper.push("three four"));
+        out.push_str(&wrapper.flush());
+        assert_eq!("one two\nthree\nfour", out);
+    }
+
+    #[test]
+    fn test_tabs() {
+        let mut wrapper = WordWrapper::new(40);
+        let mut out = wrapper.push("ab\tc\t\td\n\te");
+        out.push_str(&wrapper.flush());
+        assert_eq!(
+            format!(
+                "ab{}c{}d\n{}e",
+                " ".repeat(6),
+                " ".repeat(15),
+                " ".repeat(8)
+            ),
+            out
+        );
+
+        // Like spaces, tabs are dropped at a line break.
+        let mut wrapper = WordWrapper::new(20);
+        let mut out = wrapper.push("abc\t\t\tx");
+        out.push_str(&wrapper.flush());
+        assert_eq!("abc\nx", out);
+    }
+
+    #[test]
+    fn test_long_word() {
+        let mut wrapper = WordWrapper::new(4);
+        let mut out = wrapper.push("a abcdefg b");
+        out.push_str(&wrapper.flush());
+        assert_eq!("a\nabcdefg\nb", out);
+    }
+}
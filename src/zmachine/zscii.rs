@@ -1,9 +1,15 @@
 use super::addressing::{ByteAddress, WordAddress, ZOffset};
 use super::handle::Handle;
+use super::header::{self, HEXT_UNICODE_TABLE};
 use super::result::Result;
-use super::traits::{Memory, PC};
+use super::traits::{Header, Memory, OutputStreams, PC};
+use super::version::ZVersion;
+use super::violations::{Violation, ZViolations};
 
-// TODO: make this a struct to avoid so much param passing.
+use log::warn;
+
+// ZSCII code for newline. (ZSpec 3.8.2.4)
+pub const ZSCII_NEWLINE: u8 = 13;
 
 const V2_TO_4_TABLE: [char; 78] = [
     'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's',
@@ -14,104 +20,556 @@ const V2_TO_4_TABLE: [char; 78] = [
     '\'', '"', '/', '\\', '-', ':', '(', ')',
 ];
 
-// TODO: all of these ByteAddresses should be B: Into<ZOffset>
-pub fn read_zstr_from_pc<M, P>(
-    memory: &Handle<M>,
-    abbrev_offset: ByteAddress,
-    pc: &mut P,
-) -> Result<String>
+// In V1, A2 has no newline (z-char 1 is newline instead), and includes '<'.
+// The first entry is the ZSCII escape. (ZSpec 3.5.4)
+const V1_A2_TABLE: [char; 26] = [
+    ' ', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', '.', ',', '!', '?', '_', '#', '\'', '"',
+    '/', '\\', '<', '-', ':', '(', ')',
+];
+
+// Z-char 6 in A2 starts a ZSCII escape: the next two z-chars hold the top and
+// bottom five bits of a ZSCII code. (ZSpec 3.4)
+const ZSCII_ESCAPE: u8 = 6;
+
+// The three alphabets used to decode and encode Z-strings. (ZSpec 3.5)
+#[derive(Clone, Copy)]
+pub struct ZAlphabet {
+    chars: [char; 78],
+}
+
+impl ZAlphabet {
+    // The standard alphabets, which differ in V1.
+    pub fn default_for(version: ZVersion) -> ZAlphabet {
+        let mut chars = V2_TO_4_TABLE;
+        if version == ZVersion::V1 {
+            chars[52..].copy_from_slice(&V1_A2_TABLE);
+        }
+        ZAlphabet { chars }
+    }
+
+    // In V5+, a story may give its own alphabets: 78 ZSCII codes at the
+    // address in the header, A0 then A1 then A2. Even then, A2 z-chars 6 and
+    // 7 are the ZSCII escape and newline. (ZSpec 3.5.5)
+    pub fn from_story<M>(memory: &M, version: ZVersion) -> ZAlphabet
+    where
+        M: Memory,
+    {
+        if version < ZVersion::V5 {
+            return ZAlphabet::default_for(version);
+        }
+        let table = memory.read_word(ByteAddress::from_raw(header::HOF_ALPHABET_TABLE));
+        if table == 0 {
+            return ZAlphabet::default_for(version);
+        }
+        let table = ByteAddress::from_raw(table);
+        let mut chars = V2_TO_4_TABLE;
+        for (idx, ch) in chars.iter_mut().enumerate() {
+            *ch = zscii_to_char(
+                memory,
+                u16::from(memory.read_byte(table.inc_by(idx as u16))),
+            );
+        }
+        chars[53] = '\n';
+        ZAlphabet { chars }
+    }
+
+    fn char(&self, alphabet: usize, zchar: u8) -> char {
+        self.chars[alphabet * 26 + usize::from(zchar - 6)]
+    }
+}
+
+// ZSCII 155-251 are the "extra characters", which a story may use for
+// accented letters and the like. (ZSpec 3.8.5)
+const FIRST_EXTRA_CHAR: u8 = 155;
+const LAST_EXTRA_CHAR: u8 = 251;
+
+// The extra characters, unless the story supplies its own Unicode
+// translation table. Only 155-223 are defined. (ZSpec 3.8.5.3)
+const DEFAULT_UNICODE_TABLE: [char; 69] = [
+    'ä', 'ö', 'ü', 'Ä', 'Ö', 'Ü', 'ß', '»', '«', 'ë', 'ï', 'ÿ', 'Ë', 'Ï', 'á', 'é', 'í', 'ó', 'ú',
+    'ý', 'Á', 'É', 'Í', 'Ó', 'Ú', 'Ý', 'à', 'è', 'ì', 'ò', 'ù', 'À', 'È', 'Ì', 'Ò', 'Ù', 'â', 'ê',
+    'î', 'ô', 'û', 'Â', 'Ê', 'Î', 'Ô', 'Û', 'å', 'Å', 'ø', 'Ø', 'ã', 'ñ', 'õ', 'Ã', 'Ñ', 'Õ', 'æ',
+    'Æ', 'ç', 'Ç', 'þ', 'ð', 'Þ', 'Ð', '£', 'œ', 'Œ', '¡', '¿',
+];
+
+// Look up an extra character. A story's own table is found through the
+// header extension, and starts with the number of entries, followed by a
+// word of Unicode for each. (ZSpec 3.8.5.2)
+fn extra_char<M>(memory: &M, zscii: u8) -> Option<char>
 where
     M: Memory,
-    P: PC,
 {
-    read_zstr(memory, abbrev_offset, || Ok(pc.next_word()))
+    let idx = zscii - FIRST_EXTRA_CHAR;
+    match header::header_extension_word(memory, HEXT_UNICODE_TABLE) {
+        Some(table) if table != 0 => {
+            let table = ByteAddress::from_raw(table);
+            if idx >= memory.read_byte(table) {
+                return None;
+            }
+            let unicode = memory.read_word(table.inc_by(1 + 2 * u16::from(idx)));
+            std::char::from_u32(u32::from(unicode))
+        }
+        _ => DEFAULT_UNICODE_TABLE.get(usize::from(idx)).copied(),
+    }
 }
 
-pub fn read_abbrev<M>(
-    mem: &Handle<M>,
-    abbrev_offset: ByteAddress,
-    abbrev_table: u8,
-    abbrev_number: u8,
-) -> Result<String>
+// Convert a ZSCII code to a character. Extra characters that the story
+// hasn't defined become '?'. (ZSpec 3.8.5.4)
+pub fn zscii_to_char<M>(memory: &M, zscii: u16) -> char
 where
     M: Memory,
 {
-    let entry_number = 32 * (abbrev_table - 1) + abbrev_number;
-    let entry_address = abbrev_offset.inc_by(u16::from(entry_number) * 2);
-    //    let entry_address = ByteAddress::from_raw(u16::from(entry_number) * 2).inc_by(abbrev_offset);
-    let abbrev_address = WordAddress::from_raw(mem.borrow().read_word(entry_address));
+    if zscii == u16::from(ZSCII_NEWLINE) {
+        '\n'
+    } else if zscii >= u16::from(FIRST_EXTRA_CHAR) && zscii <= u16::from(LAST_EXTRA_CHAR) {
+        extra_char(memory, zscii as u8).unwrap_or('?')
+    } else {
+        (zscii as u8) as char
+    }
+}
 
-    read_zstr_from_memory(mem, abbrev_offset, abbrev_address)
+// A sentence space is wider than a word space, which on a terminal means two
+// spaces. (ZSpec 3.8.2.3)
+const SENTENCE_SPACE: &str = "  ";
+
+// Convert a ZSCII code to the text to output, encoded in buf, or None if it
+// prints nothing. Only some codes are defined for output: 0 is nothing at
+// all, and tab (9) and sentence space (11) are only for V6. Tabs are left for
+// the screen to expand. Printing any other code is reported to violations.
+// (ZSpec 3.8)
+pub fn zscii_for_output<'a, M>(
+    memory: &M,
+    version: ZVersion,
+    zscii: u16,
+    violations: &mut ZViolations,
+    buf: &'a mut [u8; 4],
+) -> Result<Option<&'a str>>
+where
+    M: Memory,
+{
+    let text = output_text(memory, version, zscii, buf);
+    if text.is_none() && zscii != 0 {
+        violations.report(Violation::UndefinedZsciiOutput(zscii))?;
+    }
+    Ok(text)
 }
 
-pub fn read_zstr_from_memory<M, O>(
-    mem: &Handle<M>,
-    abbrev_offset: ByteAddress,
-    offset: O,
-) -> Result<String>
+// The text for a ZSCII code, or None if it isn't defined for output.
+fn output_text<'a, M>(
+    memory: &M,
+    version: ZVersion,
+    zscii: u16,
+    buf: &'a mut [u8; 4],
+) -> Option<&'a str>
 where
     M: Memory,
-    O: Into<ZOffset> + Copy,
 {
-    let mut zoffset = offset.into();
-    read_zstr(mem, abbrev_offset, || {
-        let word = mem.borrow().read_word(zoffset);
-        zoffset = zoffset.inc_by(2);
-        Ok(word)
-    })
+    match zscii {
+        9 if version == ZVersion::V6 => Some("\t"),
+        11 if version == ZVersion::V6 => Some(SENTENCE_SPACE),
+        13 | 32..=126 => Some(zscii_to_char(memory, zscii).encode_utf8(buf)),
+        _ if zscii >= u16::from(FIRST_EXTRA_CHAR) && zscii <= u16::from(LAST_EXTRA_CHAR) => {
+            Some(zscii_to_char(memory, zscii).encode_utf8(buf))
+        }
+        _ => None,
+    }
+}
+
+// Convert a character to ZSCII for input, or for output stream 3, if the
+// story can represent it.
+pub fn char_to_zscii<M>(memory: &M, ch: char) -> Option<u8>
+where
+    M: Memory,
+{
+    match ch {
+        '\n' => Some(ZSCII_NEWLINE),
+        ' '..='~' => Some(ch as u8),
+        _ => (FIRST_EXTRA_CHAR..=LAST_EXTRA_CHAR)
+            .find(|&zscii| extra_char(memory, zscii) == Some(ch)),
+    }
+}
+
+// Keys that aren't characters, which games see as ZSCII codes of their own.
+// (ZSpec 10.5.2)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ZKey {
+    Delete,
+    Escape,
+    CursorUp,
+    CursorDown,
+    CursorLeft,
+    CursorRight,
+    // F1 to F12.
+    Function(u8),
+    // The digits on the keypad, 0 to 9.
+    Keypad(u8),
+}
+
+// The first of the cursor, function and keypad keys, which may end a read.
+pub const ZSCII_CURSOR_UP: u8 = 129;
+
+impl ZKey {
+    pub fn zscii(self) -> Option<u8> {
+        match self {
+            ZKey::Delete => Some(8),
+            ZKey::Escape => Some(27),
+            ZKey::CursorUp => Some(ZSCII_CURSOR_UP),
+            ZKey::CursorDown => Some(130),
+            ZKey::CursorLeft => Some(131),
+            ZKey::CursorRight => Some(132),
+            ZKey::Function(n @ 1..=12) => Some(132 + n),
+            ZKey::Keypad(n @ 0..=9) => Some(145 + n),
+            _ => None,
+        }
+    }
+}
+
+// Keyboards don't see the story, so they convert typed characters with the
+// default table.
+pub fn key_to_zscii(ch: char) -> Option<u8> {
+    match ch {
+        ' '..='~' => Some(ch as u8),
+        _ => DEFAULT_UNICODE_TABLE
+            .iter()
+            .position(|&extra| extra == ch)
+            .map(|idx| FIRST_EXTRA_CHAR + idx as u8),
+    }
 }
 
-fn read_zstr<F, M>(
-    memory: &Handle<M>,
-    abbrev_offset: ByteAddress,
-    mut next_word: F,
-) -> Result<String>
+// Move a key from key_to_zscii into the story's own table.
+pub fn translate_key<M>(memory: &M, key: u8) -> u8
 where
-    F: FnMut() -> Result<u16>,
     M: Memory,
 {
-    let mut zstr = "".to_string();
-    let mut next_char_offset = 0;
-    let mut abbrev_table = 0;
-    loop {
-        let word = next_word()?;
-        let (done, bytes) = break_apart_word(word);
-
-        for byte in bytes.iter() {
-            // TODO: range check.
-            let char_offset = next_char_offset;
-            next_char_offset = 0;
-
-            if abbrev_table > 0 {
-                let r = zstr.push_str(&read_abbrev(memory, abbrev_offset, abbrev_table, *byte)?);
-                abbrev_table = 0;
-                r
+    if key < FIRST_EXTRA_CHAR {
+        return key;
+    }
+    match DEFAULT_UNICODE_TABLE.get(usize::from(key - FIRST_EXTRA_CHAR)) {
+        Some(&ch) => char_to_zscii(memory, ch).unwrap_or(b'?'),
+        None => key,
+    }
+}
+
+// There are 3 tables of 32 abbreviations. (ZSpec 3.3)
+const NUM_ABBREVIATIONS: usize = 96;
+
+// Decodes the story's Z-strings, wherever they are found.
+//
+// Abbreviations are decoded as they are first used, since they turn up in
+// nearly every string printed. They are decoded from memory, which restoring
+// or restarting replaces, so call reset after either.
+pub struct ZTextDecoder<M> {
+    memory: Handle<M>,
+    version: ZVersion,
+    abbrev_table: ByteAddress,
+    alphabets: ZAlphabet,
+    abbrev_cache: Vec<Option<String>>,
+}
+
+impl<M> ZTextDecoder<M>
+where
+    M: Memory,
+{
+    pub fn new<H>(header: &H, memory: &Handle<M>) -> ZTextDecoder<M>
+    where
+        H: Header,
+    {
+        let version = header.version_number();
+        ZTextDecoder {
+            memory: memory.clone(),
+            version,
+            abbrev_table: header.abbrev_location(),
+            alphabets: ZAlphabet::from_story(&*memory.borrow(), version),
+            abbrev_cache: vec![None; NUM_ABBREVIATIONS],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.alphabets = ZAlphabet::from_story(&*self.memory.borrow(), self.version);
+        for abbrev in self.abbrev_cache.iter_mut() {
+            *abbrev = None;
+        }
+    }
+
+    // Print a string inline in the code, as for print and print_ret. Text
+    // goes to the streams as it is decoded, without building a String.
+    pub fn print_from_pc<O, P>(&mut self, pc: &mut P, streams: &mut O) -> Result<()>
+    where
+        O: OutputStreams,
+        P: PC,
+    {
+        self.decode(true, || Ok(pc.next_word()), &mut |text| {
+            streams.print_str(text)
+        })?;
+        Ok(())
+    }
+
+    pub fn print_from_memory<O, Z>(&mut self, offset: Z, streams: &mut O) -> Result<()>
+    where
+        O: OutputStreams,
+        Z: Into<ZOffset> + Copy,
+    {
+        let next_word = words_from(&self.memory, offset.into());
+        self.decode(true, next_word, &mut |text| streams.print_str(text))?;
+        Ok(())
+    }
+
+    pub fn decode_from_memory<Z>(&mut self, offset: Z) -> Result<String>
+    where
+        Z: Into<ZOffset> + Copy,
+    {
+        self.decode_string(offset.into(), true)
+            .map(|(zstr, _)| zstr)
+    }
+
+    // Abbreviation `number` from table 1, 2 or 3.
+    pub fn abbreviation(&mut self, table: u8, number: u8) -> Result<&str> {
+        let entry = usize::from(32 * (table - 1) + number);
+        if self.abbrev_cache[entry].is_none() {
+            let entry_address = self.abbrev_table.inc_by(entry as u16 * 2);
+            let abbrev_address =
+                WordAddress::from_raw(self.memory.borrow().read_word(entry_address));
+
+            // Abbreviations may not themselves contain abbreviations.
+            // (ZSpec 3.3.1)
+            let (abbrev, _) = self.decode_string(ZOffset::from(abbrev_address), false)?;
+            self.abbrev_cache[entry] = Some(abbrev);
+        }
+        Ok(self.abbrev_cache[entry].as_deref().unwrap_or_default())
+    }
+
+    // Find the Z-strings from start to end, like the "strings" output of txd
+    // and infodump, returning each one's address and text.
+    //
+    // Strings are aligned as packed addresses are. A candidate is kept if it
+    // ends before `end`, decodes without problems, and is printable text.
+    // Code and tables sometimes pass too, so expect some noise.
+    pub fn scan_strings(&mut self, start: ZOffset, end: ZOffset) -> Result<Vec<(ZOffset, String)>> {
+        let align = usize::from(self.version.make_packed_address(1, 0));
+        let aligned = |offset: ZOffset| offset.inc_by((align - offset.value() % align) % align);
+
+        let mut strings = Vec::new();
+        let mut at = aligned(start);
+        while at.value() + 2 <= end.value() {
+            // Without an end bit, nothing after here can end either.
+            let next = match self.string_end(at, end) {
+                Some(next) => next,
+                None => break,
+            };
+            let (text, well_formed) = self.decode_string(at, true)?;
+            if well_formed && is_printable_text(&text) {
+                strings.push((at, text));
+                at = aligned(next);
             } else {
-                match byte {
-                    0 => zstr.push(' '),
-
-                    // TODO: learn the pattern notation to clean this up.
-                    1 => abbrev_table = 1,
-                    2 => abbrev_table = 2,
-                    3 => abbrev_table = 3,
-
-                    4 => next_char_offset = 26,
-                    5 => next_char_offset = 52,
-                    6...31 => {
-                        zstr.push(V2_TO_4_TABLE[usize::from(char_offset + byte - 6)]);
+                at = at.inc_by(align);
+            }
+        }
+        Ok(strings)
+    }
+
+    // Where the string at `at` ends, if it does before `end`.
+    fn string_end(&self, at: ZOffset, end: ZOffset) -> Option<ZOffset> {
+        let memory = self.memory.borrow();
+        let mut offset = at;
+        while offset.value() + 2 <= end.value() {
+            let word = memory.try_read_word(offset).ok()?;
+            offset = offset.inc_by(2);
+            if word & 0x8000 != 0 {
+                return Some(offset);
+            }
+        }
+        None
+    }
+
+    fn decode_string(&mut self, offset: ZOffset, allow_abbrevs: bool) -> Result<(String, bool)> {
+        let next_word = words_from(&self.memory, offset);
+        let mut zstr = String::new();
+        let well_formed = self.decode(allow_abbrevs, next_word, &mut |text| {
+            zstr.push_str(text);
+            Ok(())
+        })?;
+        Ok((zstr, well_formed))
+    }
+
+    // Decode a Z-string. (ZSpec 3)
+    //
+    // The meaning of z-chars 1-5 depends on the version:
+    //
+    //   V1:  1 is newline, 2/3 shift, and 4/5 shift lock.
+    //   V2:  1 is an abbreviation, 2/3 shift, and 4/5 shift lock.
+    //   V3+: 1-3 are abbreviations, and 4/5 shift.
+    //
+    // In V1-2, shifts are relative: 2 and 4 move to the next alphabet, 3 and 5 to
+    // the previous one. In V3+, 4 shifts to A1 and 5 to A2. (ZSpec 3.2)
+    //
+    // allow_abbrevs is false while decoding an abbreviation, since they can't
+    // nest. The text is passed to `out` a character or an abbreviation at a
+    // time. Returns false if the string was malformed: if it ended part way
+    // through an abbreviation or ZSCII escape, or used one it shouldn't.
+    fn decode<F, S>(&mut self, allow_abbrevs: bool, mut next_word: F, out: &mut S) -> Result<bool>
+    where
+        F: FnMut() -> Result<u16>,
+        S: FnMut(&str) -> Result<()>,
+    {
+        let version = self.version;
+        let mut buf = [0; 4];
+        let mut locked_alphabet = 0;
+        let mut shifted_alphabet: Option<usize> = None;
+        let mut abbrev_table = 0;
+        // Some(None) after the escape, then Some(Some(top five bits)).
+        let mut escape: Option<Option<u8>> = None;
+        let mut well_formed = true;
+        loop {
+            let word = next_word()?;
+            let (done, bytes) = break_apart_word(word);
+
+            for byte in bytes.iter() {
+                match escape.take() {
+                    Some(None) => {
+                        escape = Some(Some(*byte));
+                        continue;
+                    }
+                    Some(Some(top)) => {
+                        let zscii = (u16::from(top) << 5) + u16::from(*byte);
+                        let text = output_text(&*self.memory.borrow(), version, zscii, &mut buf);
+                        match text {
+                            Some(text) => out(text)?,
+                            None => well_formed &= zscii == 0,
+                        }
+                        continue;
+                    }
+                    None => {}
+                }
+
+                if abbrev_table > 0 {
+                    if allow_abbrevs {
+                        out(self.abbreviation(abbrev_table, *byte)?)?;
+                    } else {
+                        warn!("Abbreviation inside an abbreviation: {}", abbrev_table);
+                        well_formed = false;
+                    }
+                    abbrev_table = 0;
+                    continue;
+                }
+
+                let alphabet = shifted_alphabet.take().unwrap_or(locked_alphabet);
+                match (byte, version) {
+                    (0, _) => out(" ")?,
+
+                    (1, ZVersion::V1) => out("\n")?,
+                    (1, ZVersion::V2) => abbrev_table = 1,
+                    (2, ZVersion::V1) | (2, ZVersion::V2) => {
+                        shifted_alphabet = Some((locked_alphabet + 1) % 3)
+                    }
+                    (3, ZVersion::V1) | (3, ZVersion::V2) => {
+                        shifted_alphabet = Some((locked_alphabet + 2) % 3)
+                    }
+                    (4, ZVersion::V1) | (4, ZVersion::V2) => {
+                        locked_alphabet = (locked_alphabet + 1) % 3
                     }
-                    v => {
-                        println!("V: {}", v);
+                    (5, ZVersion::V1) | (5, ZVersion::V2) => {
+                        locked_alphabet = (locked_alphabet + 2) % 3
                     }
+
+                    (1..=3, _) => abbrev_table = *byte,
+                    (4, _) => shifted_alphabet = Some(1),
+                    (5, _) => shifted_alphabet = Some(2),
+
+                    (&ZSCII_ESCAPE, _) if alphabet == 2 => escape = Some(None),
+                    (6..=31, _) => {
+                        let ch = self.alphabets.char(alphabet, *byte);
+                        out(ch.encode_utf8(&mut buf))?;
+                    }
+                    (v, _) => warn!("Illegal z-char: {}", v),
                 }
             }
+
+            if done {
+                break;
+            }
         }
+        Ok(well_formed && escape.is_none() && abbrev_table == 0)
+    }
+}
 
-        if done {
-            break;
+// Read words from memory, for decoding.
+fn words_from<M>(memory: &Handle<M>, offset: ZOffset) -> impl FnMut() -> Result<u16>
+where
+    M: Memory,
+{
+    let memory = memory.clone();
+    let mut offset = offset;
+    move || {
+        let word = memory.borrow().try_read_word(offset)?;
+        offset = offset.inc_by(2);
+        Ok(word)
+    }
+}
+
+fn is_printable_text(text: &str) -> bool {
+    !text.trim().is_empty() && text.chars().all(|ch| ch == '\n' || !ch.is_control())
+}
+
+// Encode with the standard alphabets. The interpreter itself always uses the
+// story's alphabets, so this is only for tests.
+#[cfg(test)]
+pub fn encode_zstr(text: &str, num_zchars: usize, version: ZVersion) -> Vec<u8> {
+    encode_zstr_in(&ZAlphabet::default_for(version), text, num_zchars, version)
+}
+
+// Encode text as a Z-string of exactly num_zchars z-characters, padding with
+// 5s or truncating as needed. This is the form used for dictionary words.
+// (ZSpec 3.7)
+//
+// Characters in the story's alphabets are encoded directly, or after a shift
+// to A1 or A2. Everything else uses a ZSCII escape. Dictionary words are
+// lowercase, and read lowercases the player's input, so in practice only
+// lowercase text is looked up.
+pub fn encode_zstr_in(
+    alphabets: &ZAlphabet,
+    text: &str,
+    num_zchars: usize,
+    version: ZVersion,
+) -> Vec<u8> {
+    // Shifting from A0 is relative in V1-2, where A1 is "shift up" and A2
+    // "shift down". (ZSpec 3.2.2)
+    let (a1_shift, a2_shift) = if version < ZVersion::V3 {
+        (2, 3)
+    } else {
+        (4, 5)
+    };
+
+    let mut zchars = Vec::<u8>::new();
+    for ch in text.chars() {
+        if let Some(zchar) = (6..32).find(|&z| alphabets.char(0, z) == ch) {
+            zchars.push(zchar);
+        } else if let Some(zchar) = (6..32).find(|&z| alphabets.char(1, z) == ch) {
+            zchars.push(a1_shift);
+            zchars.push(zchar);
+        } else if let Some(zchar) = (7..32).find(|&z| alphabets.char(2, z) == ch && ch != '\n') {
+            zchars.push(a2_shift);
+            zchars.push(zchar);
+        } else {
+            let zscii = key_to_zscii(ch).unwrap_or(b'?');
+            zchars.push(a2_shift);
+            zchars.push(ZSCII_ESCAPE);
+            zchars.push(zscii >> 5);
+            zchars.push(zscii & 0b1_1111);
         }
     }
-    Ok(zstr)
+    zchars.resize(num_zchars, 5);
+
+    let mut bytes = Vec::<u8>::new();
+    for (idx, chunk) in zchars.chunks(3).enumerate() {
+        let mut word =
+            (u16::from(chunk[0]) << 10) + (u16::from(chunk[1]) << 5) + u16::from(chunk[2]);
+        if (idx + 1) * 3 >= num_zchars {
+            word |= 0b1000_0000_0000_0000;
+        }
+        bytes.push((word >> 8) as u8);
+        bytes.push((word & 0xff) as u8);
+    }
+    bytes
 }
 
 fn break_apart_word(word: u16) -> (bool, [u8; 3]) {
@@ -122,3 +580,384 @@ fn break_apart_word(word: u16) -> (bool, [u8; 3]) {
 
     (done, [byte1 as u8, byte2 as u8, byte3 as u8])
 }
+
+#[cfg(test)]
+mod test {
+    use super::super::fixtures::{TestHeader, TestMemory, TestOutputStreams};
+    use super::super::handle::new_handle;
+    use super::super::result::ZErr;
+    use super::super::violations::ErrorPolicy;
+    use super::*;
+
+    // Pack z-chars three to a word, setting the end bit on the last word.
+    fn pack_zchars(zchars: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let num_words = zchars.len() / 3;
+        for (idx, chunk) in zchars.chunks(3).enumerate() {
+            let mut word =
+                (u16::from(chunk[0]) << 10) + (u16::from(chunk[1]) << 5) + u16::from(chunk[2]);
+            if idx + 1 == num_words {
+                word |= 0b1000_0000_0000_0000;
+            }
+            bytes.push((word >> 8) as u8);
+            bytes.push((word & 0xff) as u8);
+        }
+        bytes
+    }
+
+    // A decoder using the abbreviations table at 0x40.
+    fn decoder(version: ZVersion, memory: &Handle<TestMemory>) -> ZTextDecoder<TestMemory> {
+        let mut header = TestHeader::new(version);
+        header.abbrev = ByteAddress::from_raw(0x40);
+        ZTextDecoder::new(&header, memory)
+    }
+
+    fn decode(version: ZVersion, zchars: &[u8]) -> String {
+        let mut memory = TestMemory::new(0x100);
+        let packed = pack_zchars(zchars);
+        memory.bytes[0x80..0x80 + packed.len()].copy_from_slice(&packed);
+
+        // A single abbreviation, "ab", at entry 0 of the table at 0x40.
+        memory.bytes[0x40..0x42].copy_from_slice(&[0x00, 0x28]);
+        memory.bytes[0x50..0x52].copy_from_slice(&pack_zchars(&[6, 7, 5]));
+
+        decoder(version, &new_handle(memory))
+            .decode_from_memory(ByteAddress::from_raw(0x80))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_decode_v1_newline() {
+        assert_eq!("h\ni", decode(ZVersion::V1, &[13, 1, 14]));
+        // In V2, z-char 1 is an abbreviation.
+        assert_eq!("hab", decode(ZVersion::V2, &[13, 1, 0]));
+    }
+
+    #[test]
+    fn test_decode_v1_shift_lock() {
+        // 4 locks A1, 5 locks back to A0.
+        assert_eq!("ABa", decode(ZVersion::V1, &[4, 6, 7, 5, 6, 5]));
+        // 2 and 3 only shift the next character.
+        assert_eq!("Ab", decode(ZVersion::V2, &[2, 6, 7]));
+        // Shifts are relative to the locked alphabet.
+        assert_eq!("Ba", decode(ZVersion::V2, &[4, 7, 3, 6, 5, 5]));
+    }
+
+    #[test]
+    fn test_decode_v1_alphabet() {
+        // A2 differs between V1 and later versions.
+        assert_eq!("<", decode(ZVersion::V1, &[3, 27, 5]));
+        assert_eq!("\\", decode(ZVersion::V2, &[3, 27, 5]));
+        assert_eq!("\\", decode(ZVersion::V3, &[5, 27, 5]));
+    }
+
+    #[test]
+    fn test_decode_v1_v2_shift_lock_details() {
+        // The lock lasts across words, until another lock.
+        assert_eq!("0123a", decode(ZVersion::V1, &[5, 7, 8, 9, 10, 4, 6, 5, 5]));
+        // From a locked A2, 2 shifts up to A0 for one character.
+        assert_eq!("0a1", decode(ZVersion::V2, &[5, 8, 2, 6, 9, 5]));
+        // A2 character 7 is newline in V2, but '0' in V1.
+        assert_eq!("\n", decode(ZVersion::V2, &[3, 7, 5]));
+        assert_eq!("0", decode(ZVersion::V1, &[3, 7, 5]));
+        // The ZSCII escape works from a locked A2 too.
+        assert_eq!("@", decode(ZVersion::V2, &[5, 6, 2, 0, 4, 5]));
+    }
+
+    #[test]
+    fn test_decode_abbreviations() {
+        // V2 has only the one abbreviation table, so 2 is a shift.
+        assert_eq!("Ab", decode(ZVersion::V2, &[2, 6, 7]));
+        assert_eq!("ab", decode(ZVersion::V3, &[1, 0, 5]));
+    }
+
+    #[test]
+    fn test_decode_v1_has_no_abbreviations() {
+        // 1 is newline, and 2 and 3 are shifts, so the table is never read.
+        assert_eq!("\nA0a", decode(ZVersion::V1, &[1, 2, 6, 3, 7, 6]));
+        // Only 1 is an abbreviation in V2.
+        assert_eq!("ab", decode(ZVersion::V2, &[1, 0, 5]));
+    }
+
+    #[test]
+    fn test_abbreviations_do_not_nest() {
+        let mut memory = TestMemory::new(0x100);
+        memory.bytes[0x40..0x42].copy_from_slice(&[0x00, 0x28]);
+        // The abbreviation is "a", then an attempt to use itself, then "b".
+        memory.bytes[0x50..0x54].copy_from_slice(&pack_zchars(&[6, 1, 0, 7, 5, 5]));
+        memory.bytes[0x80..0x82].copy_from_slice(&pack_zchars(&[1, 0, 5]));
+
+        let zstr = decoder(ZVersion::V3, &new_handle(memory))
+            .decode_from_memory(ByteAddress::from_raw(0x80))
+            .unwrap();
+        assert_eq!("ab", zstr);
+    }
+
+    #[test]
+    fn test_decode_zscii_escape() {
+        assert_eq!("\"", decode(ZVersion::V3, &[5, 6, 1, 2, 5, 5]));
+        // 155 is an extra character.
+        assert_eq!("ä", decode(ZVersion::V3, &[5, 6, 4, 27, 5, 5]));
+    }
+
+    #[test]
+    fn test_decode_zscii_escape_details() {
+        // The escape may span words: '~' is 126, or 3 and 30.
+        assert_eq!("a~b", decode(ZVersion::V3, &[6, 5, 6, 3, 30, 7]));
+        // After a locking shift to A2 in V1-2, z-char 6 is still the escape.
+        assert_eq!("~0", decode(ZVersion::V2, &[5, 6, 3, 30, 8, 5]));
+        assert_eq!("~", decode(ZVersion::V1, &[3, 6, 3, 30, 5, 5]));
+        // z-char 6 only escapes in A2.
+        assert_eq!("aA", decode(ZVersion::V3, &[6, 4, 6, 5, 5, 5]));
+        // An escape cut off by the end of the string prints nothing.
+        assert_eq!("a", decode(ZVersion::V3, &[6, 5, 6]));
+    }
+
+    #[test]
+    fn test_default_extra_characters() {
+        let memory = TestMemory::new(0x40);
+        assert_eq!('ä', zscii_to_char(&memory, 155));
+        assert_eq!('¿', zscii_to_char(&memory, 223));
+        // Undefined extra characters.
+        assert_eq!('?', zscii_to_char(&memory, 224));
+
+        assert_eq!(Some(155), char_to_zscii(&memory, 'ä'));
+        assert_eq!(Some(b'a'), char_to_zscii(&memory, 'a'));
+        assert_eq!(None, char_to_zscii(&memory, 'ł'));
+
+        assert_eq!(Some(201), key_to_zscii('å'));
+        assert_eq!(201, translate_key(&memory, 201));
+    }
+
+    #[test]
+    fn test_zscii_for_output() {
+        let memory = TestMemory::new(0x40);
+        let mut violations = ZViolations::new(ErrorPolicy::Ignore);
+        let mut output = |version, zscii| {
+            zscii_for_output(&memory, version, zscii, &mut violations, &mut [0; 4])
+                .unwrap()
+                .map(str::to_string)
+        };
+        assert_eq!(None, output(ZVersion::V5, 0));
+        assert_eq!(Some("\n".to_string()), output(ZVersion::V5, 13));
+        assert_eq!(Some("~".to_string()), output(ZVersion::V5, 126));
+        assert_eq!(Some("ä".to_string()), output(ZVersion::V5, 155));
+        assert_eq!(Some("?".to_string()), output(ZVersion::V5, 251));
+
+        // Tab and sentence space are only for V6.
+        assert_eq!(None, output(ZVersion::V5, 9));
+        assert_eq!(None, output(ZVersion::V5, 11));
+        assert_eq!(Some("\t".to_string()), output(ZVersion::V6, 9));
+        assert_eq!(Some("  ".to_string()), output(ZVersion::V6, 11));
+
+        // Input-only and undefined codes print nothing.
+        for &zscii in &[7, 8, 27, 127, 129, 154, 252, 255, 1000] {
+            assert_eq!(None, output(ZVersion::V5, zscii));
+        }
+
+        // Which is a violation.
+        let mut violations = ZViolations::new(ErrorPolicy::Fatal);
+        match zscii_for_output(&memory, ZVersion::V5, 1000, &mut violations, &mut [0; 4]) {
+            Err(ZErr::Violation(Violation::UndefinedZsciiOutput(1000))) => (),
+            _ => panic!("Missing violation"),
+        }
+    }
+
+    #[test]
+    fn test_special_keys() {
+        assert_eq!(Some(8), ZKey::Delete.zscii());
+        assert_eq!(Some(27), ZKey::Escape.zscii());
+        assert_eq!(Some(129), ZKey::CursorUp.zscii());
+        assert_eq!(Some(132), ZKey::CursorRight.zscii());
+        assert_eq!(Some(133), ZKey::Function(1).zscii());
+        assert_eq!(Some(144), ZKey::Function(12).zscii());
+        assert_eq!(Some(145), ZKey::Keypad(0).zscii());
+        assert_eq!(Some(154), ZKey::Keypad(9).zscii());
+
+        assert_eq!(None, ZKey::Function(0).zscii());
+        assert_eq!(None, ZKey::Function(13).zscii());
+        assert_eq!(None, ZKey::Keypad(10).zscii());
+    }
+
+    #[test]
+    fn test_story_unicode_table() {
+        let mut memory = TestMemory::new(0x80);
+        memory.bytes[0] = 5;
+        memory.bytes[0x37] = 0x40; // Header extension at 0x40,
+        memory.bytes[0x41] = 3; // with three words.
+        memory.bytes[0x47] = 0x50; // Unicode table at 0x50,
+        memory.bytes[0x50] = 2; // with two characters.
+        memory.bytes[0x51..0x55].copy_from_slice(&[0x01, 0x42, 0x00, 0xe5]); // ł, å
+
+        assert_eq!('ł', zscii_to_char(&memory, 155));
+        assert_eq!('å', zscii_to_char(&memory, 156));
+        assert_eq!('?', zscii_to_char(&memory, 157));
+
+        assert_eq!(Some(155), char_to_zscii(&memory, 'ł'));
+        assert_eq!(None, char_to_zscii(&memory, 'ä'));
+        // å is 201 in the default table.
+        assert_eq!(156, translate_key(&memory, 201));
+        assert_eq!(b'?', translate_key(&memory, 155));
+    }
+
+    #[test]
+    fn test_encode_v1() {
+        // '<' is only in the V1 A2 row, and V1 shifts to A2 with 3.
+        let encoded = encode_zstr("<", 6, ZVersion::V1);
+        assert_eq!(pack_zchars(&[3, 27, 5, 5, 5, 5]), encoded);
+    }
+
+    #[test]
+    fn test_encode_lowercase() {
+        // "mailbo": m=18, a=6, i=14, l=17, b=7, o=20
+        let encoded = encode_zstr("mailbox", 6, ZVersion::V3);
+        assert_eq!(vec![0x48, 0xce, 0xc4, 0xf4], encoded);
+    }
+
+    #[test]
+    fn test_encode_padding() {
+        // "go" padded with 5s, with the end bit on the last word.
+        let encoded = encode_zstr("go", 6, ZVersion::V3);
+        assert_eq!(vec![0x32, 0x85, 0x94, 0xa5], encoded);
+
+        let encoded = encode_zstr("go", 9, ZVersion::V3);
+        assert_eq!(vec![0x32, 0x85, 0x14, 0xa5, 0x94, 0xa5], encoded);
+    }
+
+    #[test]
+    fn test_encode_punctuation() {
+        // '.' is A2 character 18.
+        let encoded = encode_zstr(".", 6, ZVersion::V3);
+        assert_eq!(vec![0x16, 0x45, 0x94, 0xa5], encoded);
+    }
+
+    #[test]
+    fn test_encode_shifts() {
+        // A1 is reached with 4 in V3+, and with 2 (shift up) in V1-2.
+        assert_eq!(
+            pack_zchars(&[4, 6, 7, 5, 5, 5]),
+            encode_zstr("Ab", 6, ZVersion::V3)
+        );
+        assert_eq!(
+            pack_zchars(&[2, 6, 7, 5, 5, 5]),
+            encode_zstr("Ab", 6, ZVersion::V2)
+        );
+        // Anything else is a ZSCII escape: '@' is 64, and 'ä' is 155.
+        assert_eq!(
+            pack_zchars(&[5, 6, 2, 0, 5, 5]),
+            encode_zstr("@", 6, ZVersion::V3)
+        );
+        assert_eq!(
+            pack_zchars(&[5, 6, 4, 27, 5, 5, 5, 5, 5]),
+            encode_zstr("ä", 9, ZVersion::V5)
+        );
+        // Encoding and decoding agree.
+        let encoded = encode_zstr("Go North, 42!", 30, ZVersion::V5);
+        let mut memory = TestMemory::new(0x100);
+        memory.bytes[0x80..0x80 + encoded.len()].copy_from_slice(&encoded);
+        let decoded = decoder(ZVersion::V5, &new_handle(memory))
+            .decode_from_memory(ByteAddress::from_raw(0x80))
+            .unwrap();
+        assert_eq!("Go North, 42!", decoded.trim_end_matches(|c| c == ' '));
+    }
+
+    #[test]
+    fn test_print_from_memory() {
+        let mut memory = TestMemory::new(0x100);
+        memory.bytes[0x40..0x42].copy_from_slice(&[0x00, 0x28]);
+        memory.bytes[0x50..0x52].copy_from_slice(&pack_zchars(&[6, 7, 5]));
+        // An abbreviation, an escaped '@', then "c".
+        let packed = pack_zchars(&[1, 0, 5, 6, 2, 0, 8, 5, 5]);
+        memory.bytes[0x80..0x80 + packed.len()].copy_from_slice(&packed);
+        let memory = new_handle(memory);
+        let mut streams = TestOutputStreams::new();
+
+        decoder(ZVersion::V3, &memory)
+            .print_from_memory(ByteAddress::from_raw(0x80), &mut streams)
+            .unwrap();
+        assert_eq!("ab@c", streams.output);
+    }
+
+    #[test]
+    fn test_scan_strings() {
+        let mut memory = TestMemory::new(0x100);
+        let mut put = |at: usize, zchars: &[u8]| {
+            let packed = pack_zchars(zchars);
+            memory.bytes[at..at + packed.len()].copy_from_slice(&packed);
+        };
+        put(0x80, &[6, 7, 5]); // "ab"
+        put(0x82, &[8, 9, 10, 5, 5, 5]); // "cde"
+                                         // An escape to ZSCII 129, which is only for input. From 0x88, it ends
+                                         // part way through an abbreviation, as does the string at 0x8a.
+        put(0x86, &[5, 6, 4, 1, 5, 1]);
+        put(0x8a, &[6, 7, 1]);
+        put(0x8c, &[7, 8, 9]); // "bcd"
+        put(0x8e, &[6, 6, 6]); // No end bit before the end of the scan.
+        memory.bytes[0x8e] &= 0x7f;
+        let memory = new_handle(memory);
+
+        let strings = decoder(ZVersion::V3, &memory)
+            .scan_strings(
+                ByteAddress::from_raw(0x7f).into(),
+                ByteAddress::from_raw(0x92).into(),
+            )
+            .unwrap();
+        let found: Vec<_> = strings
+            .iter()
+            .map(|(offset, text)| (offset.value(), text.as_str()))
+            .collect();
+        assert_eq!(vec![(0x80, "ab"), (0x82, "cde"), (0x8c, "bcd")], found);
+    }
+
+    #[test]
+    fn test_abbreviation_cache() {
+        let mut memory = TestMemory::new(0x100);
+        memory.bytes[0x40..0x42].copy_from_slice(&[0x00, 0x28]);
+        memory.bytes[0x50..0x52].copy_from_slice(&pack_zchars(&[6, 7, 5]));
+        let memory = new_handle(memory);
+        let mut decoder = decoder(ZVersion::V3, &memory);
+
+        assert_eq!("ab", decoder.abbreviation(1, 0).unwrap());
+        // Changing memory doesn't change the cached string, until reset.
+        memory.borrow_mut().bytes[0x50..0x52].copy_from_slice(&pack_zchars(&[8, 9, 5]));
+        assert_eq!("ab", decoder.abbreviation(1, 0).unwrap());
+        decoder.reset();
+        assert_eq!("cd", decoder.abbreviation(1, 0).unwrap());
+    }
+
+    #[test]
+    fn test_custom_alphabet() {
+        let mut memory = TestMemory::new(0x100);
+        memory.bytes[0] = 5;
+        // An alphabet table at 0xa0. A0 is the standard A1, A1 the standard
+        // A0, and A2 is digits, then '*' for the rest.
+        memory.bytes[0x35] = 0xa0;
+        for idx in 0..26 {
+            memory.bytes[0xa0 + idx] = b'A' + idx as u8;
+            memory.bytes[0xba + idx] = b'a' + idx as u8;
+            memory.bytes[0xd4 + idx] = if idx < 10 { b'0' + idx as u8 } else { b'*' };
+        }
+        let packed = pack_zchars(&[6, 4, 6, 5, 8, 5, 7, 5, 6, 2, 0, 5]);
+        memory.bytes[0x80..0x80 + packed.len()].copy_from_slice(&packed);
+        let memory = new_handle(memory);
+
+        // A2 z-chars 6 and 7 keep their meanings.
+        let decoded = decoder(ZVersion::V5, &memory)
+            .decode_from_memory(ByteAddress::from_raw(0x80))
+            .unwrap();
+        assert_eq!("Aa2\n@", decoded);
+
+        let alphabets = ZAlphabet::from_story(&*memory.borrow(), ZVersion::V5);
+        assert_eq!(
+            pack_zchars(&[4, 6, 6, 5, 8, 5, 5, 5, 5]),
+            encode_zstr_in(&alphabets, "aA2", 9, ZVersion::V5)
+        );
+
+        // Before V5, the header field isn't used.
+        let alphabets = ZAlphabet::from_story(&*memory.borrow(), ZVersion::V3);
+        assert_eq!(
+            encode_zstr("aA2", 6, ZVersion::V3),
+            encode_zstr_in(&alphabets, "aA2", 6, ZVersion::V3)
+        );
+    }
+}